@@ -1,8 +1,10 @@
-
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+#[path = "src/gpu/codegen.rs"]
+mod codegen;
+
 // The Directory where the Kernels are stored
 const KERNEL_DIR: &str = "src/gpu/kernels/";
 
@@ -10,9 +12,20 @@ fn main() {
     // make sure this build script will be reran upon changes
     println!("cargo:rerun-if-changed=build.rs");
 
+    // Generate the kernel sources first, so they can be inspected without nvcc
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let sources = generate(&out_path);
+
+    // The `emulated` feature builds against a driver that runs the kernels on the host, for tests without a GPU
+    if env::var_os("CARGO_FEATURE_EMULATED").is_some() {
+        write_registry(&declare(&out_path, &sources), &out_path.join("ptx_registry.rs"));
+        return
+    }
+
     // Get the GT_CUDA_SRC env variable
+    println!("cargo:rerun-if-env-changed=GT_CUDA_SRC");
     let root = env::var("GT_CUDA_SRC")
-        .expect("Failed to get GT_CUDA_SRC");
+        .expect("Failed to get GT_CUDA_SRC! Set it to a CUDA installation, or enable the emulated feature to test without one.");
 
     // tell cargo where to look for compiled cuda binaries
     println!("cargo:rustc-link-search={}/lib64/", root);
@@ -25,60 +38,166 @@ fn main() {
         .expect("Unable to generate cuda driver bindings!");
 
     // Write the bindings to the $OUT_DIR/bindings.rs file.
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     bindings
     .write_to_file(out_path.join("driver_bindings.rs"))
         .expect("Couldn't write cuda driver bindings!");
 
-    // Create a directory for compiled PTXs'
-    let _ = std::fs::create_dir(out_path.join("ptx/"));
-
     // Location of NVCC Installation
     let nvcc = &format!("{}/bin/nvcc", root.clone());
-    // Output directory for the compiled PTXs
-    let out_dir = out_path.join("ptx/").to_str().unwrap().to_owned();
     // Include directory to search for headers
     let include = root.clone() + "/include/";
 
-    // Compile for all gpus sm_52+
-    #[cfg(feature = "gpu")]
-    compile("sm_52", &include, &out_dir, nvcc);
+    // Every compiled PTX, as (module, dtype, sm, path)
+    let mut registry = Vec::new();
+
+    if !PathBuf::from(nvcc).exists() {
+        panic!("nvcc was not found at {}, so the kernels cannot be compiled!", nvcc);
+    }
+
+    for (sm, sources) in sources.iter() {
+        // Output directory for the compiled PTXs
+        let out_dir = out_path.join("ptx").join(sm);
+        let _ = std::fs::create_dir_all(&out_dir);
+
+        for (op, dtype, path) in sources {
+            compile(sm, &include, &out_dir, nvcc, path);
 
-    // f16 is a feature of sm_70+
-    #[cfg(feature = "f16")]
-    compile("sm_70", &include, &out_dir, nvcc);
+            registry.push((
+                op.clone(),
+                dtype.to_string(),
+                sm["sm_".len()..].parse().unwrap(),
+                out_dir.join(format!("{}.ptx", codegen::kernel_name(op, dtype))),
+            ));
+        }
+    }
+
+    write_registry(&registry, &out_path.join("ptx_registry.rs"));
+}
+
+/// The (op, dtype, path) of every generated kernel source, per SM level.
+type Sources = Vec<(&'static str, Vec<(String, &'static str, PathBuf)>)>;
+
+/// The SM levels kernels are compiled for, with the dtypes each one adds.
+fn levels() -> Vec<(&'static str, &'static [&'static str])> {
+    vec![
+        // Compile for all gpus sm_52+
+        #[cfg(feature = "gpu")]
+        ("sm_52", &["f64", "f32"][..]),
 
-    // bf16 is a feature of sm_80+
-    #[cfg(feature = "bf16")]
-    compile("sm_80", &include, &out_dir, nvcc);
+        // f16 is a feature of sm_70+
+        #[cfg(feature = "f16")]
+        ("sm_70", &["f16"][..]),
+
+        // bf16 is a feature of sm_80+
+        #[cfg(feature = "bf16")]
+        ("sm_80", &["bf16"][..]),
+    ]
 }
 
-/// Compile sm_52, sm_70, or sm_80.
-pub fn compile(sm: &str, include: &str, out_dir: &str, nvcc: &str) {
-
-    // The Directory that the Kernels are stored
-    let read_dir = std::fs::read_dir(format!("{}{}", KERNEL_DIR, sm))
-        .expect("Failed to read directory to compile kernels");
-
-    // For every file in the dir,
-    for file in read_dir {
-        // get the path
-        let file = file.unwrap();
-        let path = file.path();
-
-        // Launch a bash process to run NVCC
-        // nvcc --ptx -arch=sm_xx -I$GT_CUDA_SRC/include/ -odir $OUT_DIR/ptx/ <name>
-        let status = Command::new(nvcc)
-            .arg("--ptx")
-            .arg(format!("-arch={}",sm))
-            .arg(format!("-I{}", include))
-            .arg(format!("-odir {}", out_dir))
-            .arg(path)
-            .status();
-
-        // send an error if compilation fails.
-        if let Err(e) = status {
-            panic!("Failed to compile with error: {e}")
+/// Instantiate every templated kernel in `KERNEL_DIR` for the dtypes of every SM level, into
+/// `$OUT_DIR/kernels/<sm>/<op>_<dtype>.cu`. Returns the (op, dtype, path) of every source per level.
+fn generate(out_path: &Path) -> Sources {
+    let mut templates: Vec<PathBuf> = std::fs::read_dir(KERNEL_DIR)
+        .expect("Failed to read directory to generate kernels")
+        .map(|file| file.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "cu"))
+        .collect();
+
+    templates.sort();
+
+    println!("cargo:rerun-if-changed={}", KERNEL_DIR);
+    println!("cargo:rerun-if-changed=src/gpu/codegen.rs");
+
+    for template in templates.iter() {
+        println!("cargo:rerun-if-changed={}", template.display());
+    }
+
+    levels().into_iter().map(|(sm, dtypes)| {
+        let dir = out_path.join("kernels").join(sm);
+        let _ = std::fs::create_dir_all(&dir);
+
+        let mut sources = Vec::new();
+
+        for template in templates.iter() {
+            let op = template.file_stem().unwrap().to_str().unwrap().to_owned();
+            let text = std::fs::read_to_string(template)
+                .expect("Failed to read kernel template");
+
+            for dtype in dtypes.iter() {
+                let source = codegen::instantiate(&text, &op, dtype)
+                    .unwrap_or_else(|e| panic!("Failed to generate {}: {}", template.display(), e));
+
+                let path = dir.join(format!("{}.cu", codegen::kernel_name(&op, dtype)));
+                std::fs::write(&path, source)
+                    .expect("Failed to write generated kernel");
+
+                sources.push((op.clone(), *dtype, path));
+            }
         }
+
+        (sm, sources)
+    }).collect()
+}
+
+/// Write the registry of every compiled PTX, embedded with `include_str!`.
+pub fn write_registry(registry: &[(String, String, u32, PathBuf)], path: &PathBuf) {
+    let mut out = String::from("pub static PTX: &[Ptx] = &[\n");
+
+    for (module, dtype, sm, ptx) in registry {
+        out += &format!(
+            "    Ptx {{ module: {:?}, dtype: {:?}, sm: {}, source: include_str!({:?}) }},\n",
+            module, dtype, sm, ptx.to_str().unwrap()
+        );
+    }
+
+    out += "];\n";
+
+    std::fs::write(path, out)
+        .expect("Failed to write the PTX registry!");
+}
+
+/// Declare the entry of every generated kernel in PTX without compiling it, for the emulated driver.
+fn declare(out_path: &Path, sources: &Sources) -> Vec<(String, String, u32, PathBuf)> {
+    let mut registry = Vec::new();
+
+    for (sm, sources) in sources.iter() {
+        let out_dir = out_path.join("ptx").join(sm);
+        let _ = std::fs::create_dir_all(&out_dir);
+
+        for (op, dtype, path) in sources {
+            let source = std::fs::read_to_string(path)
+                .expect("Failed to read generated kernel");
+            let ptx = codegen::declare(&source, op, dtype, sm)
+                .unwrap_or_else(|e| panic!("Failed to declare {}: {}", path.display(), e));
+
+            let ptx_path = out_dir.join(format!("{}.ptx", codegen::kernel_name(op, dtype)));
+            std::fs::write(&ptx_path, ptx)
+                .expect("Failed to write declared PTX");
+
+            registry.push((op.clone(), dtype.to_string(), sm["sm_".len()..].parse().unwrap(), ptx_path));
+        }
+    }
+
+    registry
+}
+
+/// Compile a generated kernel for sm_52, sm_70, or sm_80.
+pub fn compile(sm: &str, include: &str, out_dir: &Path, nvcc: &str, path: &Path) {
+    // Launch a bash process to run NVCC
+    // nvcc --ptx -arch=sm_xx -I$GT_CUDA_SRC/include/ -odir $OUT_DIR/ptx/sm_xx/ <name>
+    let status = Command::new(nvcc)
+        .arg("--ptx")
+        .arg(format!("-arch={}",sm))
+        .arg(format!("-I{}", include))
+        .arg("-odir")
+        .arg(out_dir)
+        .arg(path)
+        .status();
+
+    // send an error if compilation fails.
+    match status {
+        Ok(status) if !status.success() => panic!("Failed to compile {}: nvcc exited with {status}", path.display()),
+        Err(e) => panic!("Failed to compile with error: {e}"),
+        _ => {}
     }
 }
@@ -48,3 +48,4 @@ Add this line to any location in your `bashrc`. gTensor will use the `GT_CUDA_SR
 
 If you install cuda with `Environment Modules` or manually, just set `GT_CUDA_SRC` to the path you need.
 
+The build fails if `GT_CUDA_SRC` is not set, or if `nvcc` is missing from it. To run the tests without cuda, enable the `emulated` feature (`cargo test --features emulated`), which builds against an emulated driver that runs every kernel on the host.
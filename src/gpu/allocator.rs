@@ -0,0 +1,332 @@
+//! # Caching Allocator
+//!
+//! Device memory is requested from the driver in size classes and kept in
+//! bins when it is freed, so the next allocation of the same class on the
+//! same stream can reuse it without a call into the driver. Work on a stream
+//! runs in order, so a block freed on a stream can be handed out again on
+//! that stream straight away. A block used on other streams, as recorded by
+//! `record_stream`, is only reused once an event recorded on each of them when
+//! it was freed has completed. The blocks cached for a stream are given back
+//! when the stream is destroyed.
+//!
+//! A graph replays the pointers it was captured with, so the blocks freed while
+//! capturing one are pinned. They can be reused by the rest of the captured work,
+//! which is ordered on the stream, but are taken out of the cache when the capture
+//! ends, and stay allocated until the graph is dropped. Memory cannot be requested
+//! from the driver while capturing, so the tensors allocated meanwhile must come
+//! from the cache.
+
+use std::sync::Mutex;
+
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use super::cu;
+use super::stream::Stream;
+
+/// Allocations up to this size are rounded up to a multiple of `SMALL_ALIGN`.
+const SMALL_SIZE: usize = 1 << 20;
+const SMALL_ALIGN: usize = 512;
+/// Larger allocations are rounded up to a multiple of `LARGE_ALIGN`.
+const LARGE_ALIGN: usize = 2 << 20;
+
+/// The size class of an allocation of `bytes`.
+pub(crate) fn size_class(bytes: usize) -> usize {
+    let align = if bytes <= SMALL_SIZE { SMALL_ALIGN } else { LARGE_ALIGN };
+    bytes.max(1).div_ceil(align) * align
+}
+
+/// Bytes held by an Allocator.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Handed out and not yet freed.
+    pub allocated: usize,
+    /// Requested from the driver, including the cached blocks.
+    pub reserved: usize,
+    /// The most that was ever allocated at once.
+    pub peak: usize,
+}
+
+/// A block of device memory from an Allocator.
+#[derive(Copy, Clone)]
+pub struct Block {
+    pub(crate) ptr: cu::DevicePtr,
+    size: usize,
+    stream: usize,
+}
+
+impl Block {
+    /// The size of the block, which can be larger than what was requested.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Free blocks keyed by stream and size class.
+#[derive(Default)]
+pub(crate) struct Bins {
+    free: HashMap<(usize, usize), Vec<cu::DevicePtr>>,
+    /// The streams each allocated block was used on besides its own, by address.
+    uses: HashMap<cu::sys::CUdeviceptr, Vec<cu::Stream>>,
+    /// Freed blocks, with the events that must complete before they are cached.
+    pending: Vec<(Block, Vec<cu::Event>)>,
+    /// The blocks freed while pinning and not reused since.
+    pinned: Option<Vec<Block>>,
+    stats: MemoryStats,
+}
+
+impl Bins {
+    /// Take a cached block of `size` for `stream`, if there is one.
+    pub fn take(&mut self, size: usize, stream: usize) -> Option<Block> {
+        self.collect();
+
+        let ptr = self.free.get_mut(&(stream, size))?.pop()?;
+        self.allocated(size);
+
+        if let Some(pinned) = self.pinned.as_mut() {
+            pinned.retain(|block| block.ptr.ptr != ptr.ptr);
+        }
+
+        Some(Block { ptr, size, stream })
+    }
+
+    /// Record a block that was freshly requested from the driver.
+    pub fn insert(&mut self, ptr: cu::DevicePtr, size: usize, stream: usize) -> Block {
+        self.stats.reserved += size;
+        self.allocated(size);
+        Block { ptr, size, stream }
+    }
+
+    /// Record that `block` is used on `stream`, which is not the stream it was allocated for.
+    pub fn record(&mut self, block: &Block, stream: cu::Stream) {
+        let uses = self.uses.entry(block.ptr.ptr).or_default();
+
+        if !uses.iter().any(|used| used.ptr == stream.ptr) {
+            uses.push(stream);
+        }
+    }
+
+    /// The other streams `block` was used on, which are forgotten.
+    pub fn uses(&mut self, block: &Block) -> Vec<cu::Stream> {
+        self.uses.remove(&block.ptr.ptr).unwrap_or_default()
+    }
+
+    /// Return a block to its bin, once every one of `events` has completed.
+    pub fn put(&mut self, block: Block, events: Vec<cu::Event>) {
+        self.stats.allocated -= block.size;
+
+        match events.is_empty() {
+            true => self.cache(block),
+            false => self.pending.push((block, events)),
+        }
+    }
+
+    fn cache(&mut self, block: Block) {
+        self.free.entry((block.stream, block.size)).or_default().push(block.ptr);
+    }
+
+    /// Cache the pending blocks whose events have all completed.
+    fn collect(&mut self) {
+        let completed = self.pending.extract_if(.., |(_, events)| {
+            events.iter().all(|event| cu::event::query(event).unwrap_or(false))
+        });
+
+        for (block, events) in completed.collect::<Vec<_>>() {
+            for event in events {
+                let _ = cu::event::destroy(event);
+            }
+
+            self.cache(block);
+        }
+    }
+
+    /// Take the blocks freed since pinning started out of the cache, and stop pinning.
+    /// Those still waiting for another stream are waited for.
+    pub fn unpin(&mut self) -> Vec<Block> {
+        let blocks = self.pinned.take().unwrap_or_default();
+
+        for block in &blocks {
+            if let Some(ptrs) = self.free.get_mut(&(block.stream, block.size)) {
+                ptrs.retain(|ptr| ptr.ptr != block.ptr.ptr);
+            }
+
+            for (_, events) in self.pending.extract_if(.., |(pending, _)| pending.ptr.ptr == block.ptr.ptr) {
+                for event in events {
+                    let _ = cu::event::synchronize(&event);
+                    let _ = cu::event::destroy(event);
+                }
+            }
+
+            self.allocated(block.size);
+        }
+
+        blocks
+    }
+
+    /// Forget that `stream` used any block, once it is destroyed and its work is completed.
+    pub fn forget(&mut self, stream: &cu::Stream) {
+        for uses in self.uses.values_mut() {
+            uses.retain(|used| used.ptr != stream.ptr);
+        }
+    }
+
+    /// Remove every cached block, returning them with their sizes.
+    pub fn drain(&mut self) -> Vec<(cu::DevicePtr, usize)> {
+        self.drain_where(|_| true)
+    }
+
+    /// Remove the cached blocks of `stream`, returning them with their sizes.
+    pub fn drain_stream(&mut self, stream: usize) -> Vec<(cu::DevicePtr, usize)> {
+        self.drain_where(|key| key == stream)
+    }
+
+    /// Pending blocks are waited for first.
+    fn drain_where(&mut self, f: impl Fn(usize) -> bool) -> Vec<(cu::DevicePtr, usize)> {
+        let pending: Vec<_> = self.pending.extract_if(.., |(block, _)| f(block.stream)).collect();
+
+        for (block, events) in pending {
+            for event in events {
+                let _ = cu::event::synchronize(&event);
+                let _ = cu::event::destroy(event);
+            }
+
+            self.cache(block);
+        }
+
+        let blocks: Vec<_> = self.free.extract_if(|(stream, _), _| f(*stream))
+            .flat_map(|((_, size), ptrs)| ptrs.into_iter().map(move |ptr| (ptr, size)))
+            .collect();
+
+        self.stats.reserved -= blocks.iter().map(|(_, size)| size).sum::<usize>();
+        blocks
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        self.stats
+    }
+
+    fn allocated(&mut self, size: usize) {
+        self.stats.allocated += size;
+        self.stats.peak = self.stats.peak.max(self.stats.allocated);
+    }
+}
+
+/// A caching allocator for the memory of one Device.
+#[derive(Default)]
+pub struct Allocator {
+    bins: Mutex<Bins>,
+}
+
+impl Allocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate at least `bytes` for use on `stream`.
+    ///
+    /// When the driver runs out of memory, the cache is emptied and the allocation is tried again.
+    pub fn alloc(&self, bytes: usize, stream: &Stream) -> Result<Block> {
+        let size = size_class(bytes);
+        let key = stream.stream.ptr as usize;
+
+        if let Some(block) = self.bins.lock().unwrap().take(size, key) {
+            return Ok(block)
+        }
+
+        let ptr = match cu::mem::alloc::<u8>(size) {
+            Ok(ptr) => ptr,
+            Err(_) => {
+                self.empty_cache()?;
+                cu::mem::alloc::<u8>(size)?
+            }
+        };
+
+        Ok(self.bins.lock().unwrap().insert(ptr, size, key))
+    }
+
+    /// Record that `block` is used on `stream`, so once it is freed, it is not reused before
+    /// the work enqueued on `stream` until then completes. Does nothing for its own stream.
+    pub fn record_stream(&self, block: &Block, stream: &Stream) {
+        if stream.stream.ptr as usize != block.stream {
+            self.bins.lock().unwrap().record(block, stream.stream);
+        }
+    }
+
+    /// Return a block to the cache. It is not given back to the driver until `empty_cache()`.
+    /// The context of the device must be bound if the block was used on other streams.
+    pub fn free(&self, block: Block) {
+        let mut bins = self.bins.lock().unwrap();
+        let uses = bins.uses(&block);
+
+        // Without an event, wait for the other stream instead.
+        let events = uses.iter()
+            .filter_map(|stream| {
+                let event = cu::event::create(true).ok()?;
+
+                match cu::event::record(&event, stream) {
+                    Ok(_) => Some(event),
+                    Err(_) => {
+                        let _ = cu::event::destroy(event);
+                        let _ = cu::stream::synchronize(stream);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        bins.put(block, events);
+
+        if let Some(pinned) = bins.pinned.as_mut() {
+            pinned.push(block);
+        }
+    }
+
+    /// Pin the blocks freed from now on, until `unpin`.
+    pub(crate) fn pin(&self) {
+        self.bins.lock().unwrap().pinned.get_or_insert_with(Vec::new);
+    }
+
+    /// Stop pinning, returning the blocks freed since `pin` and not reused.
+    /// They are allocated until they are freed again.
+    pub(crate) fn unpin(&self) -> Vec<Block> {
+        self.bins.lock().unwrap().unpin()
+    }
+
+    /// Give every cached block back to the driver.
+    pub fn empty_cache(&self) -> Result<()> {
+        let blocks = self.bins.lock().unwrap().drain();
+
+        for (ptr, _) in blocks {
+            cu::mem::free(ptr)?;
+        }
+
+        Ok(())
+    }
+
+    /// Give the cached blocks of `stream` back to the driver, once the stream is destroyed
+    /// and its work is completed, as a new stream can be created with the same handle.
+    pub(crate) fn release(&self, stream: &cu::Stream) -> Result<()> {
+        let blocks = {
+            let mut bins = self.bins.lock().unwrap();
+            bins.forget(stream);
+            bins.drain_stream(stream.ptr as usize)
+        };
+
+        for (ptr, _) in blocks {
+            cu::mem::free(ptr)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        self.bins.lock().unwrap().stats()
+    }
+}
+
+impl Drop for Allocator {
+    /// The Device empties the cache first with its context bound, so this only frees what is left.
+    fn drop(&mut self) {
+        let _ = self.empty_cache();
+    }
+}
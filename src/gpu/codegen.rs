@@ -0,0 +1,239 @@
+//! # Kernel Generation
+//!
+//! Every operator has one templated kernel source in `src/gpu/kernels/<op>.cu`,
+//! holding `__device__ void` function templates over the element type `T`. The build
+//! script instantiates each of them for every dtype as an `extern "C"` kernel named
+//! `<kern>_<dtype>`, matching the `<kern>_<Float::NAME>` lookup of `NodeBuilder::with_kernel`.
+//! The template named after the file must exist, and the others are loaded from the same module.
+//!
+//! Templates over `<typename T, typename I>` also take the integer type `I` of an index,
+//! and are instantiated for every index type as `<kern>_<dtype>_<index>`, matching
+//! `NodeBuilder::with_indexed_kernel`.
+//!
+//! The generated sources are written to `$OUT_DIR/kernels/<sm>/<op>_<dtype>.cu`.
+//! This module is shared with the build script, so it only uses `std`.
+
+/// The dtypes kernels are instantiated for, with their C type and header.
+pub const DTYPES: [(&str, &str, Option<&str>); 4] = [
+    ("f64", "double", None),
+    ("f32", "float", None),
+    ("f16", "__half", Some("cuda_fp16.h")),
+    ("bf16", "__nv_bfloat16", Some("cuda_bf16.h")),
+];
+
+/// The index types templates over `I` are instantiated for, with their C type.
+pub const INDICES: [(&str, &str); 3] = [
+    ("i64", "long long"),
+    ("i32", "int"),
+    ("u8", "unsigned char"),
+];
+
+/// The name of the kernel of `op` for `dtype`.
+pub fn kernel_name(op: &str, dtype: &str) -> String {
+    format!("{}_{}", op, dtype)
+}
+
+/// A `__device__ void` template of a kernel source.
+struct Template<'a> {
+    name: &'a str,
+    params: Vec<&'a str>,
+    indexed: bool,
+}
+
+/// Every `template <typename T>` or `template <typename T, typename I>` 
+/// of a `__device__ void` function in `source`. Other templates are helpers.
+fn templates(source: &str) -> Result<Vec<Template<'_>>, String> {
+    let mut out = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("template <") {
+        rest = &rest[start + "template <".len()..];
+
+        let close = rest.find('>')
+            .ok_or("Unterminated template parameter list!")?;
+
+        let types: Vec<&str> = rest[..close].split(',')
+            .map(|param| param.trim().trim_start_matches("typename").trim())
+            .collect();
+
+        rest = rest[close + 1..].trim_start();
+
+        let Some(function) = rest.strip_prefix("__device__ void ") else {
+            continue
+        };
+
+        let indexed = match types.as_slice() {
+            ["T"] => false,
+            ["T", "I"] => true,
+            _ => return Err(format!("Kernel templates must be over <T> or <T, I>, not <{}>!", types.join(", "))),
+        };
+
+        let open = function.find('(')
+            .ok_or("Missing parameter list of a kernel template!")?;
+        let end = function.find(')')
+            .ok_or("Unterminated parameter list of a kernel template!")?;
+
+        out.push(Template {
+            name: function[..open].trim(),
+            params: function[open + 1..end]
+                .split(',')
+                .map(str::trim)
+                .filter(|param| !param.is_empty())
+                .collect(),
+            indexed,
+        });
+
+        rest = &function[end..];
+    }
+
+    Ok(out)
+}
+
+/// Instantiate every kernel template in the `template` source of `op` for `dtype`.
+pub fn instantiate(template: &str, op: &str, dtype: &str) -> Result<String, String> {
+    let (_, ctype, header) = DTYPES.iter()
+        .find(|(name, _, _)| *name == dtype)
+        .ok_or(format!("Unknown dtype {}!", dtype))?;
+
+    let templates = templates(template)?;
+
+    if !templates.iter().any(|t| t.name == op) {
+        return Err(format!("No `__device__ void {}(` in the template of {}!", op, op))
+    }
+
+    let mut out = String::new();
+
+    if let Some(header) = header {
+        out += &format!("#include <{}>\n\n", header);
+    }
+
+    out += template.trim_end();
+
+    for t in templates.iter() {
+        let names = t.params.iter()
+            .map(|param| param.rsplit(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let indices: &[(&str, &str)] = if t.indexed { &INDICES } else { &[("", "")] };
+
+        for (index, itype) in indices {
+            let params = t.params.iter()
+                .map(|param| replace_types(param, &[("T", ctype), ("I", itype)]))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let (name, args) = match t.indexed {
+                true => (kernel_name(&kernel_name(t.name, dtype), index), format!("{}, {}", ctype, itype)),
+                false => (kernel_name(t.name, dtype), ctype.to_string()),
+            };
+
+            out += &format!(
+                "\n\nextern \"C\" __global__ void {}({}) {{\n    {}<{}>({});\n}}",
+                name, params, t.name, args, names
+            );
+        }
+    }
+
+    out += "\n";
+
+    Ok(out)
+}
+
+/// Replace every identifier in `param` named in `types` with its C type.
+fn replace_types(param: &str, types: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    let mut ident = String::new();
+
+    for c in param.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() || c == '_' {
+            ident.push(c);
+            continue
+        }
+
+        out += types.iter().find(|(name, _)| *name == ident).map_or(ident.as_str(), |(_, ctype)| ctype);
+        ident.clear();
+        out.push(c);
+    }
+
+    out.pop();
+    out
+}
+
+/// The PTX of the kernels of `op` for `dtype` in the instantiated `source`, declaring their entries
+/// the way nvcc does, with an empty body. Builds without nvcc embed it in place of compiled PTX,
+/// so kernels are found and their launches checked, while the emulated driver runs them on the host.
+pub fn declare(source: &str, op: &str, dtype: &str, sm: &str) -> Result<String, String> {
+    let signature = "extern \"C\" __global__ void ";
+    let mut entries = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find(signature) {
+        rest = &rest[start + signature.len()..];
+
+        let open = rest.find('(')
+            .ok_or("Missing parameter list of a kernel!")?;
+        let end = rest.find(')')
+            .ok_or("Unterminated parameter list of a kernel!")?;
+        let name = &rest[..open];
+
+        let params = rest[open + 1..end]
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty())
+            .enumerate()
+            .map(|(i, param)| ptx_param(param, &format!("{}_param_{}", name, i)).map(|param| format!("\t{}", param)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        entries.push((name, params.join(",\n")));
+        rest = &rest[end..];
+    }
+
+    let name = kernel_name(op, dtype);
+    let declared = |entry: &str| entry == name || INDICES.iter().any(|(index, _)| entry == kernel_name(&name, index));
+
+    if !entries.iter().any(|(entry, _)| declared(entry)) {
+        return Err(format!("No kernel {} in the source!", name))
+    }
+
+    let entries = entries.iter()
+        .map(|(name, params)| format!(".visible .entry {}(\n{}\n)\n{{\n\tret;\n}}\n", name, params))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(format!(".version 7.8\n.target {}\n.address_size 64\n\n{}", sm, entries))
+}
+
+/// The PTX `.param` nvcc declares for the C parameter `param`. Pointers
+/// are declared with `.ptr` and the alignment of the elements they point to.
+fn ptx_param(param: &str, name: &str) -> Result<String, String> {
+    let ident = param.rsplit(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap();
+    let ctype = param[..param.len() - ident.len()]
+        .split_whitespace()
+        .filter(|token| *token != "const" && *token != "__restrict__")
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if let Some(element) = ctype.strip_suffix('*') {
+        let (_, size) = ptx_type(element.trim())?;
+        return Ok(format!(".param .u64 .ptr .global .align {} {}", size, name))
+    }
+
+    match ptx_type(&ctype)? {
+        (".b16", _) => Ok(format!(".param .align 2 .b8 {}[2]", name)),
+        (ptype, _) => Ok(format!(".param {} {}", ptype, name)),
+    }
+}
+
+/// The PTX type of the C type `ctype`, and its size in bytes.
+fn ptx_type(ctype: &str) -> Result<(&'static str, usize), String> {
+    Ok(match ctype {
+        "double" => (".f64", 8),
+        "float" => (".f32", 4),
+        "__half" | "__nv_bfloat16" => (".b16", 2),
+        "size_t" | "long long" | "unsigned long long" | "int64_t" | "uint64_t" => (".u64", 8),
+        "int" | "unsigned int" | "unsigned" | "int32_t" | "uint32_t" => (".u32", 4),
+        "bool" | "char" | "unsigned char" | "uint8_t" => (".u8", 1),
+        ctype => return Err(format!("No PTX type for the parameter type {}!", ctype)),
+    })
+}
@@ -0,0 +1,91 @@
+//! # Contexts
+//!
+//! A Device runs in the primary context of its device, which is retained once
+//! when the Device is created and released when the last owner of it is dropped.
+//! The primary context is the one shared by every user of the driver in the
+//! process, including the runtime API, so memory and modules can be shared with them.
+//!
+//! A context is current per thread. `bind` makes it current on the calling
+//! thread if it is not already, so a Device and its tensors can be used from
+//! any thread.
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use super::cu;
+use super::kernel::LaunchConfig;
+
+/// A retained primary context, released when dropped.
+pub(crate) struct Context {
+    device: cu::Device,
+    context: cu::Context,
+}
+
+impl Context {
+    /// Retain the primary context of `device`.
+    pub fn retain(device: cu::Device) -> Result<Arc<Self>> {
+        let context = cu::primary::retain(&device)?;
+        Ok(Arc::new(Self { device, context }))
+    }
+
+    /// Make this context current on the calling thread, if it is not already.
+    pub fn bind(&self) -> Result<()> {
+        match cu::ctx::try_get_current()? {
+            Some(current) if current.ptr == self.context.ptr => Ok(()),
+            _ => cu::ctx::set_current(&self.context),
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        let _ = cu::primary::release(&self.device);
+    }
+}
+
+/// A module loaded into a context. It is unloaded once the Device and
+/// every Kernel from it are dropped, and keeps its context until then.
+pub(crate) struct Module {
+    pub module: cu::Module,
+    pub context: Arc<Context>,
+    /// The launch configurations of its kernels, by name.
+    pub configs: RwLock<HashMap<String, LaunchConfig>>,
+    /// The functions of its kernels, by name.
+    functions: RwLock<HashMap<String, cu::Function>>,
+}
+
+impl Module {
+    /// Load `ptx` into `context`.
+    pub fn load(context: &Arc<Context>, ptx: &str) -> Result<Arc<Self>> {
+        context.bind()?;
+
+        Ok(Arc::new(Self {
+            module: cu::module::load_data(ptx)?,
+            context: context.clone(),
+            configs: RwLock::new(HashMap::new()),
+            functions: RwLock::new(HashMap::new()),
+        }))
+    }
+
+    /// The function `name`, looked up in the module on the first call and cached after.
+    /// The context of the module must be bound.
+    pub fn function(&self, name: &str) -> Result<cu::Function> {
+        if let Some(function) = self.functions.read().unwrap().get(name) {
+            return Ok(*function)
+        }
+
+        let function = cu::module::get_function(&self.module, name)?;
+        self.functions.write().unwrap().insert(name.to_owned(), function);
+
+        Ok(function)
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        let _ = self.context.bind()
+            .and_then(|_| cu::module::unload(self.module));
+    }
+}
@@ -113,9 +113,15 @@ pub fn get_texture_1d_linear_max_width() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn get_uuid() {
-    
+/// Returns the UUID of the device.
+pub fn get_uuid(device: &Device) -> Result<[u8; 16]> {
+    let mut uuid = sys::CUuuid { bytes: [0; 16] };
+
+    unsafe {
+        check(sys::cuDeviceGetUuid_v2(&mut uuid, device.ptr))?;
+    }
+
+    Ok(uuid.bytes.map(|b| b as u8))
 }
 
 /// Sets the current memory pool of a device.
@@ -146,9 +152,14 @@ pub fn get_by_pci_bus_id() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn get_pci_bus_id() {
+/// Returns the PCI bus id of the device, as `domain:bus:device.function`.
+pub fn get_pci_bus_id(device: &Device) -> Result<String> {
+    let mut id = [0 as std::ffi::c_char; 64];
 
+    unsafe {
+        check(sys::cuDeviceGetPCIBusId(id.as_mut_ptr(), id.len() as i32, device.ptr))?;
+        Ok(std::ffi::CStr::from_ptr(id.as_ptr()).to_str()?.to_owned())
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
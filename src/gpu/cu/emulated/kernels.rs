@@ -0,0 +1,403 @@
+//! Host implementations of the kernels in `src/gpu/kernels`, looked up by the
+//! `<op>_<dtype>` or `<op>_<dtype>_<index>` name of their entry. Each one does
+//! what its template does for every index of the grid stride loop.
+
+use half::{bf16, f16};
+use num_traits::AsPrimitive;
+
+use crate::storage::{Float, Integer};
+
+/// A kernel, run with the arguments it was launched with.
+pub type Kernel = fn(&Args);
+
+/// The arguments of a launch, as the bytes of every parameter.
+pub struct Args<'a>(pub(super) &'a [Vec<u8>]);
+
+impl Args<'_> {
+    /// The parameter at `i`, read as a `T`.
+    pub fn value<T: Copy>(&self, i: usize) -> T {
+        assert_eq!(self.0[i].len(), std::mem::size_of::<T>(), "Parameter {} has another size!", i);
+        unsafe { std::ptr::read_unaligned(self.0[i].as_ptr().cast()) }
+    }
+
+    /// The device pointer at `i`. Device memory is host memory in the emulated driver.
+    pub fn ptr<T>(&self, i: usize) -> *mut T {
+        self.value::<u64>(i) as usize as *mut T
+    }
+
+    /// The trailing length passed by `Kernel::launch_n`.
+    pub fn elements(&self) -> usize {
+        self.value::<u64>(self.0.len() - 1) as usize
+    }
+}
+
+/// The host implementation of the entry `name`.
+pub fn find(name: &str) -> Option<Kernel> {
+    let (op, dtype) = name.rsplit_once('_')?;
+
+    match dtype {
+        "f64" => float::<f64>(op),
+        "f32" => float::<f32>(op),
+        "f16" => float::<f16>(op),
+        "bf16" => float::<bf16>(op),
+        "i64" => index::<i64>(op),
+        "i32" => index::<i32>(op),
+        "u8" => index::<u8>(op),
+        _ => None,
+    }
+}
+
+fn index<I: Integer>(name: &str) -> Option<Kernel> {
+    let (op, dtype) = name.rsplit_once('_')?;
+
+    match dtype {
+        "f64" => indexed::<f64, I>(op),
+        "f32" => indexed::<f32, I>(op),
+        "f16" => indexed::<f16, I>(op),
+        "bf16" => indexed::<bf16, I>(op),
+        _ => None,
+    }
+}
+
+fn float<T: Float>(op: &str) -> Option<Kernel> {
+    Some(match op {
+        "mul" => mul::<T, false>,
+        "mul_add" => mul::<T, true>,
+        "permute" => permute::<T, false>,
+        "permute_add" => permute::<T, true>,
+        "pad" => pad::<T>,
+        "pad_wrt" => pad_wrt::<T>,
+        "masked_fill" => masked_fill::<T>,
+        "masked_fill_wrt" => masked_fill_wrt::<T>,
+        "strided" => strided::<T, 0>,
+        "strided_scatter" => strided::<T, 1>,
+        "strided_add" => strided::<T, 2>,
+        "unscale" => unscale::<T>,
+        _ => return None,
+    })
+}
+
+fn indexed<T: Float, I: Integer>(op: &str) -> Option<Kernel> {
+    Some(match op {
+        "cross_entropy" => cross_entropy::<T, I>,
+        "cross_entropy_tangent" => cross_entropy_tangent::<T, I>,
+        "cross_entropy_wrt" => cross_entropy_wrt::<T, I>,
+        "gather" => gather::<T, I, false>,
+        "gather_add" => gather::<T, I, true>,
+        "scatter" => scatter::<T, I, false>,
+        "scatter_add" => scatter::<T, I, true>,
+        "scatter_mark" => scatter_mark::<I>,
+        "index_select" => index_select::<T, I, false>,
+        "index_select_add" => index_select::<T, I, true>,
+        "index_select_wrt" => index_select_wrt::<T, I>,
+        _ => return None,
+    })
+}
+
+// y (+)= x1 * x2
+fn mul<T: Float, const ADD: bool>(args: &Args) {
+    let (x1, x2, y) = (args.ptr::<T>(0), args.ptr::<T>(1), args.ptr::<T>(2));
+
+    for i in 0..args.elements() {
+        unsafe {
+            let v = *x1.add(i) * *x2.add(i);
+            *y.add(i) = if ADD { *y.add(i) + v } else { v };
+        }
+    }
+}
+
+// y (+)= x of shape [n, c, h, w] with its axes reordered
+fn permute<T: Float, const ADD: bool>(args: &Args) {
+    let (x, y) = (args.ptr::<T>(0), args.ptr::<T>(1));
+    let dims: [usize; 4] = std::array::from_fn(|i| args.value::<u64>(2 + i) as usize);
+    let axes = args.value::<u32>(6);
+
+    for index in 0..args.elements() {
+        let mut rest = index;
+        let mut coords = [0; 4];
+
+        for k in (0..4).rev() {
+            let axis = (axes >> (2 * k)) as usize & 3;
+            coords[axis] = rest % dims[axis];
+            rest /= dims[axis];
+        }
+
+        let offset = ((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3];
+
+        unsafe {
+            let v = *x.add(offset);
+            *y.add(index) = if ADD { *y.add(index) + v } else { v };
+        }
+    }
+}
+
+/// The index into an input axis of `size` for `i`, or None in the border of a constant pad.
+fn pad_source(i: i64, size: i64, mode: u32) -> Option<usize> {
+    let last = size - 1;
+
+    match mode {
+        1 => Some(if i < 0 { -i } else if i > last { 2 * last - i } else { i } as usize),
+        2 => Some(i.clamp(0, last) as usize),
+        _ => (0..=last).contains(&i).then_some(i as usize),
+    }
+}
+
+/// Calls `f` with the offset of every element of the padded Y and of the element of X it is copied from,
+/// for the pad kernels taking `(.., h, w, top, left, oh, ow, mode, ..)`.
+fn for_each_padded(args: &Args, len: usize, mut f: impl FnMut(usize, Option<usize>)) {
+    let [h, w, top, left, oh, ow] = std::array::from_fn(|i| args.value::<u64>(2 + i) as i64);
+    let mode = args.value::<u32>(8);
+
+    for index in 0..len as i64 {
+        let nc = index / (oh * ow);
+        let i = pad_source(index / ow % oh - top, h, mode);
+        let j = pad_source(index % ow - left, w, mode);
+
+        f(index as usize, i.zip(j).map(|(i, j)| (nc as usize * h as usize + i) * w as usize + j));
+    }
+}
+
+// y = x of shape [.., h, w] padded into [.., oh, ow], starting at (top, left)
+fn pad<T: Float>(args: &Args) {
+    let (x, y) = (args.ptr::<T>(0), args.ptr::<T>(1));
+    let value = T::from_f64_nearest(args.value::<f64>(9));
+
+    for_each_padded(args, args.elements(), |i, source| unsafe {
+        *y.add(i) = source.map_or(value, |j| *x.add(j));
+    });
+}
+
+// g += the sum of gy over every element of y copied from each element of x
+fn pad_wrt<T: Float>(args: &Args) {
+    let (gy, g) = (args.ptr::<T>(0), args.ptr::<T>(1));
+    let (h, w, oh, ow) = (args.value::<u64>(2), args.value::<u64>(3), args.value::<u64>(6), args.value::<u64>(7));
+    let len = args.elements() / (h * w) as usize * (oh * ow) as usize;
+
+    for_each_padded(args, len, |i, source| unsafe {
+        if let Some(j) = source {
+            *g.add(j) = *g.add(j) + *gy.add(i);
+        }
+    });
+}
+
+/// Calls `f` with the offset of (n, 0, h, w), the max logit, the sum of exp(x - max) and 
+/// the target class of every pixel, for the cross-entropy kernels taking `(x, target, ..)`.
+fn for_each_pixel<T: Float, I: Integer>(args: &Args, classes: usize, hw: usize, mut f: impl FnMut(usize, f64, f64, usize)) {
+    let (x, target) = (args.ptr::<T>(0), args.ptr::<I>(1));
+
+    for index in 0..args.elements() {
+        let offset = index / hw * classes * hw + index % hw;
+        let logits = (0..classes).map(|k| unsafe { AsPrimitive::<f64>::as_(*x.add(offset + k * hw)) });
+        let max = logits.clone().fold(f64::NEG_INFINITY, f64::max);
+        let sum = logits.map(|v| (v - max).exp()).sum();
+
+        f(offset, max, sum, unsafe { *target.add(index) }.as_index());
+    }
+}
+
+// y[0] = mean(log(sum(exp(x))) - x[target])
+fn cross_entropy<T: Float, I: Integer>(args: &Args) {
+    let (x, y) = (args.ptr::<T>(0), args.ptr::<T>(2));
+    let (classes, hw) = (args.value::<u64>(3) as usize, args.value::<u64>(4) as usize);
+    let mut loss = 0.0;
+
+    for_each_pixel::<T, I>(args, classes, hw, |offset, max, sum, class| {
+        loss += match class < classes {
+            true => sum.ln() + max - unsafe { AsPrimitive::<f64>::as_(*x.add(offset + class * hw)) },
+            false => f64::NAN,
+        };
+    });
+
+    unsafe { *y = T::from_f64_nearest(loss / args.elements() as f64) };
+}
+
+// ty[0] = mean((softmax(x) - onehot(target)) . tx)
+fn cross_entropy_tangent<T: Float, I: Integer>(args: &Args) {
+    let (x, tx, ty) = (args.ptr::<T>(0), args.ptr::<T>(2), args.ptr::<T>(3));
+    let (classes, hw) = (args.value::<u64>(4) as usize, args.value::<u64>(5) as usize);
+    let mut tangent = 0.0;
+
+    for_each_pixel::<T, I>(args, classes, hw, |offset, max, sum, class| {
+        for k in 0..classes {
+            let (v, t): (f64, f64) = unsafe { ((*x.add(offset + k * hw)).as_(), (*tx.add(offset + k * hw)).as_()) };
+            tangent += ((v - max).exp() / sum - if k == class { 1.0 } else { 0.0 }) * t;
+        }
+    });
+
+    unsafe { *ty = T::from_f64_nearest(tangent / args.elements() as f64) };
+}
+
+// g += (softmax(x) - onehot(target)) * gy[0] / len
+fn cross_entropy_wrt<T: Float, I: Integer>(args: &Args) {
+    let (x, gy, g) = (args.ptr::<T>(0), args.ptr::<T>(2), args.ptr::<T>(3));
+    let (classes, hw) = (args.value::<u64>(4) as usize, args.value::<u64>(5) as usize);
+    let scale = unsafe { AsPrimitive::<f64>::as_(*gy) } / args.elements() as f64;
+
+    for_each_pixel::<T, I>(args, classes, hw, |offset, max, sum, class| {
+        for k in 0..classes {
+            unsafe {
+                let (v, g) = (*x.add(offset + k * hw), g.add(offset + k * hw));
+                let grad = ((AsPrimitive::<f64>::as_(v) - max).exp() / sum - if k == class { 1.0 } else { 0.0 }) * scale;
+                *g = T::from_f64_nearest(AsPrimitive::<f64>::as_(*g) + grad);
+            }
+        }
+    });
+}
+
+// y = mask ? value : x
+fn masked_fill<T: Float>(args: &Args) {
+    let (x, mask, y) = (args.ptr::<T>(0), args.ptr::<bool>(1), args.ptr::<T>(2));
+    let value = T::from_f64_nearest(args.value::<f64>(3));
+
+    for i in 0..args.elements() {
+        unsafe {
+            *y.add(i) = if *mask.add(i) { value } else { *x.add(i) };
+        }
+    }
+}
+
+// x *= factor, setting found_inf[0] to 1 if any element of x was inf or NaN
+fn unscale<T: Float>(args: &Args) {
+    let (x, found_inf) = (args.ptr::<T>(0), args.ptr::<i32>(1));
+    let factor = args.value::<f64>(2);
+
+    for i in 0..args.elements() {
+        unsafe {
+            let v: f64 = (*x.add(i)).as_();
+
+            if !v.is_finite() {
+                *found_inf = 1;
+            }
+
+            *x.add(i) = T::from_f64_nearest(v * factor);
+        }
+    }
+}
+
+// g += mask ? 0 : gy
+fn masked_fill_wrt<T: Float>(args: &Args) {
+    let (gy, mask, g) = (args.ptr::<T>(0), args.ptr::<bool>(1), args.ptr::<T>(2));
+
+    for i in 0..args.elements() {
+        unsafe {
+            if !*mask.add(i) {
+                *g.add(i) = *g.add(i) + *gy.add(i);
+            }
+        }
+    }
+}
+
+// y[i] = x[offset(i)] for MODE 0, y[offset(i)] = x[i] for MODE 1 and y[offset(i)] += x[i] for MODE 2,
+// where offset(i) is the offset of the element at i through a layout of shape [n, c, h, w]
+fn strided<T: Float, const MODE: u8>(args: &Args) {
+    let (x, y) = (args.ptr::<T>(0), args.ptr::<T>(1));
+    let [_, c, h, w, sn, sc, sh, sw, offset]: [usize; 9] = std::array::from_fn(|i| args.value::<u64>(2 + i) as usize);
+
+    for i in 0..args.elements() {
+        let strided = offset + i / (c * h * w) * sn + i / (h * w) % c * sc + i / w % h * sh + i % w * sw;
+
+        unsafe {
+            match MODE {
+                0 => *y.add(i) = *x.add(strided),
+                1 => *y.add(strided) = *x.add(i),
+                _ => *y.add(strided) = *y.add(strided) + *x.add(i),
+            }
+        }
+    }
+}
+
+/// Calls `f` with every element of an index and the offset into X it points to, or None if it is
+/// out of range, for the gather kernels taking `(.., index, .., n, c, h, w, ic, ih, iw, axis, len)`.
+fn for_each_position<I: Integer>(args: &Args, index: usize, mut f: impl FnMut(usize, Option<usize>)) {
+    let index = args.ptr::<I>(index);
+    let first = args.0.len() - 9;
+    let dims: [usize; 4] = std::array::from_fn(|i| args.value::<u64>(first + i) as usize);
+    let [ic, ih, iw, axis] = std::array::from_fn(|i| args.value::<u64>(first + 4 + i) as usize);
+
+    for i in 0..args.elements() {
+        let mut coords = [i / (ic * ih * iw), i / (ih * iw) % ic, i / iw % ih, i % iw];
+        coords[axis] = unsafe { *index.add(i) }.as_index();
+
+        let offset = ((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3];
+        f(i, (coords[axis] < dims[axis]).then_some(offset));
+    }
+}
+
+// y[i] (+)= x[position(i)]
+fn gather<T: Float, I: Integer, const ADD: bool>(args: &Args) {
+    let (x, y) = (args.ptr::<T>(0), args.ptr::<T>(2));
+
+    for_each_position::<I>(args, 1, |i, p| unsafe {
+        let v = p.map_or(T::ZERO, |p| *x.add(p));
+        *y.add(i) = if ADD { *y.add(i) + v } else { v };
+    });
+}
+
+// y[position(i)] (+)= x[i]
+fn scatter<T: Float, I: Integer, const ADD: bool>(args: &Args) {
+    let (x, y) = (args.ptr::<T>(0), args.ptr::<T>(2));
+
+    for_each_position::<I>(args, 1, |i, p| unsafe {
+        if let Some(p) = p {
+            *y.add(p) = if ADD { *y.add(p) + *x.add(i) } else { *x.add(i) };
+        }
+    });
+}
+
+// mask[position(i)] = true
+fn scatter_mark<I: Integer>(args: &Args) {
+    let mask = args.ptr::<bool>(1);
+
+    for_each_position::<I>(args, 0, |_, p| unsafe {
+        if let Some(p) = p {
+            *mask.add(p) = true;
+        }
+    });
+}
+
+/// Calls `f` with every element of Y and the offset into X it is selected from, or None if it is
+/// out of range, for the index_select kernels taking `(.., index, .., n, c, h, w, k, axis, len)`.
+fn for_each_selected<I: Integer>(args: &Args, mut f: impl FnMut(usize, Option<usize>)) {
+    let index = args.ptr::<I>(1);
+    let dims: [usize; 4] = std::array::from_fn(|i| args.value::<u64>(3 + i) as usize);
+    let (k, axis) = (args.value::<u64>(7) as usize, args.value::<u64>(8) as usize);
+
+    let mut shape = dims;
+    shape[axis] = k;
+
+    for i in 0..args.elements() {
+        let mut rest = i;
+        let mut coords = [0; 4];
+
+        for a in (0..4).rev() {
+            coords[a] = rest % shape[a];
+            rest /= shape[a];
+        }
+
+        coords[axis] = unsafe { *index.add(coords[axis]) }.as_index();
+
+        let offset = ((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3];
+        f(i, (coords[axis] < dims[axis]).then_some(offset));
+    }
+}
+
+// y[i] (+)= x[selected(i)]
+fn index_select<T: Float, I: Integer, const ADD: bool>(args: &Args) {
+    let (x, y) = (args.ptr::<T>(0), args.ptr::<T>(2));
+
+    for_each_selected::<I>(args, |i, p| unsafe {
+        let v = p.map_or(T::ZERO, |p| *x.add(p));
+        *y.add(i) = if ADD { *y.add(i) + v } else { v };
+    });
+}
+
+// g[selected(i)] += gy[i]
+fn index_select_wrt<T: Float, I: Integer>(args: &Args) {
+    let (gy, g) = (args.ptr::<T>(0), args.ptr::<T>(2));
+
+    for_each_selected::<I>(args, |i, p| unsafe {
+        if let Some(p) = p {
+            *g.add(p) = *g.add(p) + *gy.add(i);
+        }
+    });
+}
@@ -0,0 +1,1609 @@
+//! # Emulated Driver
+//!
+//! A host implementation of the part of the driver API this crate uses, built
+//! in place of the bindings to `cuda.h` when `GT_CUDA_SRC` is not set. It has
+//! the same items as the bindings, so the rest of the crate and its tests build
+//! and run without CUDA.
+//!
+//! Device memory is host memory, filled with `0xff` bytes when allocated. Work
+//! enqueued on a stream is queued, and only runs once something waits on it, so
+//! work that is not ordered by a stream, an event or a synchronization reads stale
+//! data instead of passing by chance. The legacy null stream runs its work straight
+//! away, after the work of every blocking stream of its context. Kernels run the
+//! host implementations in `kernels`, looked up by entry name.
+//!
+//! Captures, contexts and handles are checked like the driver does, so calls that
+//! are not allowed during a capture invalidate it, and calls without a current
+//! context fail. The driver does not have to be initialized.
+
+#![allow(dead_code, non_camel_case_types, non_snake_case, non_upper_case_globals, clippy::missing_safety_doc)]
+
+mod kernels;
+
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::{CStr, c_char, c_int, c_uchar, c_uint, c_ulonglong, c_ushort, c_void};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::ThreadId;
+use std::time::Instant;
+
+use hashbrown::HashMap;
+use once_cell::sync::Lazy;
+
+use crate::gpu::ptx;
+
+pub type cudaError_enum = c_uint;
+pub type CUresult = cudaError_enum;
+pub type CUdevice = c_int;
+pub type CUdeviceptr = c_ulonglong;
+
+pub const cudaError_enum_CUDA_SUCCESS: cudaError_enum = 0;
+pub const cudaError_enum_CUDA_ERROR_INVALID_VALUE: cudaError_enum = 1;
+pub const cudaError_enum_CUDA_ERROR_OUT_OF_MEMORY: cudaError_enum = 2;
+pub const cudaError_enum_CUDA_ERROR_INVALID_DEVICE: cudaError_enum = 101;
+pub const cudaError_enum_CUDA_ERROR_INVALID_CONTEXT: cudaError_enum = 201;
+pub const cudaError_enum_CUDA_ERROR_INVALID_PTX: cudaError_enum = 218;
+pub const cudaError_enum_CUDA_ERROR_FILE_NOT_FOUND: cudaError_enum = 301;
+pub const cudaError_enum_CUDA_ERROR_INVALID_HANDLE: cudaError_enum = 400;
+pub const cudaError_enum_CUDA_ERROR_ILLEGAL_STATE: cudaError_enum = 401;
+pub const cudaError_enum_CUDA_ERROR_NOT_FOUND: cudaError_enum = 500;
+pub const cudaError_enum_CUDA_ERROR_NOT_READY: cudaError_enum = 600;
+pub const cudaError_enum_CUDA_ERROR_NOT_SUPPORTED: cudaError_enum = 801;
+pub const cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_UNSUPPORTED: cudaError_enum = 900;
+pub const cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_INVALIDATED: cudaError_enum = 901;
+pub const cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_IMPLICIT: cudaError_enum = 906;
+pub const cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_WRONG_THREAD: cudaError_enum = 908;
+pub const cudaError_enum_CUDA_ERROR_GRAPH_EXEC_UPDATE_FAILURE: cudaError_enum = 910;
+pub const cudaError_enum_CUDA_ERROR_UNKNOWN: cudaError_enum = 999;
+
+macro_rules! opaque {
+    ($($st:ident => $t:ident),*) => {
+        $(
+            #[repr(C)]
+            pub struct $st {
+                _private: [u8; 0],
+            }
+
+            pub type $t = *mut $st;
+        )*
+    };
+}
+
+opaque!(
+    CUarray_st => CUarray,
+    CUctx_st => CUcontext,
+    CUevent_st => CUevent,
+    CUextMemory_st => CUexternalMemory,
+    CUfunc_st => CUfunction,
+    CUgraph_st => CUgraph,
+    CUgraphExec_st => CUgraphExec,
+    CUgraphNode_st => CUgraphNode,
+    CUgraphicsResource_st => CUgraphicsResource,
+    CUmemPoolHandle_st => CUmemoryPool,
+    CUmipmappedArray_st => CUmipmappedArray,
+    CUmod_st => CUmodule,
+    CUstream_st => CUstream,
+    CUsurfref_st => CUsurfref,
+    CUtexref_st => CUtexref,
+    CUuserObject_st => CUuserObject
+);
+
+pub type CUkernelNodeAttrID = c_uint;
+pub type CUstreamAttrID = c_uint;
+pub type CUsurfObject = c_ulonglong;
+pub type CUtexObject = c_ulonglong;
+pub type CUsharedconfig = c_uint;
+pub type CUlimit = c_uint;
+pub type CUmoduleLoadingMode = c_uint;
+pub type CUdevice_attribute = c_uint;
+
+pub type CUstreamCaptureMode_enum = c_uint;
+pub type CUstreamCaptureMode = CUstreamCaptureMode_enum;
+pub const CUstreamCaptureMode_enum_CU_STREAM_CAPTURE_MODE_GLOBAL: CUstreamCaptureMode_enum = 0;
+pub const CUstreamCaptureMode_enum_CU_STREAM_CAPTURE_MODE_THREAD_LOCAL: CUstreamCaptureMode_enum = 1;
+pub const CUstreamCaptureMode_enum_CU_STREAM_CAPTURE_MODE_RELAXED: CUstreamCaptureMode_enum = 2;
+
+pub type CUstreamCaptureStatus_enum = c_uint;
+pub type CUstreamCaptureStatus = CUstreamCaptureStatus_enum;
+pub const CUstreamCaptureStatus_enum_CU_STREAM_CAPTURE_STATUS_NONE: CUstreamCaptureStatus_enum = 0;
+pub const CUstreamCaptureStatus_enum_CU_STREAM_CAPTURE_STATUS_ACTIVE: CUstreamCaptureStatus_enum = 1;
+pub const CUstreamCaptureStatus_enum_CU_STREAM_CAPTURE_STATUS_INVALIDATED: CUstreamCaptureStatus_enum = 2;
+
+pub type CUgraphExecUpdateResult_enum = c_uint;
+pub type CUgraphExecUpdateResult = CUgraphExecUpdateResult_enum;
+pub const CUgraphExecUpdateResult_enum_CU_GRAPH_EXEC_UPDATE_SUCCESS: CUgraphExecUpdateResult_enum = 0;
+pub const CUgraphExecUpdateResult_enum_CU_GRAPH_EXEC_UPDATE_ERROR_TOPOLOGY_CHANGED: CUgraphExecUpdateResult_enum = 2;
+pub const CUgraphExecUpdateResult_enum_CU_GRAPH_EXEC_UPDATE_ERROR_FUNCTION_CHANGED: CUgraphExecUpdateResult_enum = 4;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union CUstreamAttrValue_v1 {
+    pub priority: c_int,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct CUuuid_st {
+    pub bytes: [c_char; 16],
+}
+
+pub type CUuuid = CUuuid_st;
+pub type CUoccupancyB2DSize = Option<unsafe extern "C" fn(blockSize: c_int) -> usize>;
+
+const SUCCESS: CUresult = cudaError_enum_CUDA_SUCCESS;
+const INVALID_VALUE: CUresult = cudaError_enum_CUDA_ERROR_INVALID_VALUE;
+const OUT_OF_MEMORY: CUresult = cudaError_enum_CUDA_ERROR_OUT_OF_MEMORY;
+const INVALID_DEVICE: CUresult = cudaError_enum_CUDA_ERROR_INVALID_DEVICE;
+const INVALID_CONTEXT: CUresult = cudaError_enum_CUDA_ERROR_INVALID_CONTEXT;
+const INVALID_PTX: CUresult = cudaError_enum_CUDA_ERROR_INVALID_PTX;
+const FILE_NOT_FOUND: CUresult = cudaError_enum_CUDA_ERROR_FILE_NOT_FOUND;
+const INVALID_HANDLE: CUresult = cudaError_enum_CUDA_ERROR_INVALID_HANDLE;
+const ILLEGAL_STATE: CUresult = cudaError_enum_CUDA_ERROR_ILLEGAL_STATE;
+const NOT_FOUND: CUresult = cudaError_enum_CUDA_ERROR_NOT_FOUND;
+const NOT_READY: CUresult = cudaError_enum_CUDA_ERROR_NOT_READY;
+const NOT_SUPPORTED: CUresult = cudaError_enum_CUDA_ERROR_NOT_SUPPORTED;
+const CAPTURE_UNSUPPORTED: CUresult = cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_UNSUPPORTED;
+const CAPTURE_INVALIDATED: CUresult = cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_INVALIDATED;
+const CAPTURE_IMPLICIT: CUresult = cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_IMPLICIT;
+const CAPTURE_WRONG_THREAD: CUresult = cudaError_enum_CUDA_ERROR_STREAM_CAPTURE_WRONG_THREAD;
+const UPDATE_FAILURE: CUresult = cudaError_enum_CUDA_ERROR_GRAPH_EXEC_UPDATE_FAILURE;
+
+/// The name and description of every error the emulated driver returns.
+const ERRORS: [(CUresult, &str, &str); 19] = [
+    (SUCCESS, "CUDA_SUCCESS\0", "no error\0"),
+    (INVALID_VALUE, "CUDA_ERROR_INVALID_VALUE\0", "invalid argument\0"),
+    (OUT_OF_MEMORY, "CUDA_ERROR_OUT_OF_MEMORY\0", "out of memory\0"),
+    (INVALID_DEVICE, "CUDA_ERROR_INVALID_DEVICE\0", "invalid device ordinal\0"),
+    (INVALID_CONTEXT, "CUDA_ERROR_INVALID_CONTEXT\0", "invalid device context\0"),
+    (INVALID_PTX, "CUDA_ERROR_INVALID_PTX\0", "a PTX JIT compilation failed\0"),
+    (FILE_NOT_FOUND, "CUDA_ERROR_FILE_NOT_FOUND\0", "file not found\0"),
+    (INVALID_HANDLE, "CUDA_ERROR_INVALID_HANDLE\0", "invalid resource handle\0"),
+    (ILLEGAL_STATE, "CUDA_ERROR_ILLEGAL_STATE\0", "the operation is not permitted in the current state\0"),
+    (NOT_FOUND, "CUDA_ERROR_NOT_FOUND\0", "named symbol not found\0"),
+    (NOT_READY, "CUDA_ERROR_NOT_READY\0", "device not ready\0"),
+    (NOT_SUPPORTED, "CUDA_ERROR_NOT_SUPPORTED\0", "operation not supported\0"),
+    (CAPTURE_UNSUPPORTED, "CUDA_ERROR_STREAM_CAPTURE_UNSUPPORTED\0", "operation not permitted when stream is capturing\0"),
+    (CAPTURE_INVALIDATED, "CUDA_ERROR_STREAM_CAPTURE_INVALIDATED\0", "operation failed due to a previous error during capture\0"),
+    (CAPTURE_IMPLICIT, "CUDA_ERROR_STREAM_CAPTURE_IMPLICIT\0", "operation would make the legacy stream depend on a capturing blocking stream\0"),
+    (CAPTURE_WRONG_THREAD, "CUDA_ERROR_STREAM_CAPTURE_WRONG_THREAD\0", "attempt to terminate a thread-local capture sequence from another thread\0"),
+    (UPDATE_FAILURE, "CUDA_ERROR_GRAPH_EXEC_UPDATE_FAILURE\0", "the graph update was not performed because it included changes which violated constraints specific to instantiated graph update\0"),
+    (cudaError_enum_CUDA_ERROR_UNKNOWN, "CUDA_ERROR_UNKNOWN\0", "unknown error\0"),
+    (3, "CUDA_ERROR_NOT_INITIALIZED\0", "initialization error\0"),
+];
+
+/// An emulated device.
+struct Spec {
+    name: &'static str,
+    compute_capability: (c_int, c_int),
+    multiprocessors: c_int,
+    memory: usize,
+    pci_bus_id: &'static str,
+}
+
+const DEVICES: [Spec; 2] = [
+    Spec {
+        name: "Emulated Device 0",
+        compute_capability: (7, 5),
+        multiprocessors: 40,
+        memory: 8 << 30,
+        pci_bus_id: "0000:01:00.0",
+    },
+    Spec {
+        name: "Emulated Device 1",
+        compute_capability: (8, 6),
+        multiprocessors: 28,
+        memory: 16 << 30,
+        pci_bus_id: "0000:02:00.0",
+    },
+];
+
+/// Work enqueued on a stream.
+#[derive(Clone)]
+enum Op {
+    Memset { dst: usize, value: u32, width: usize, count: usize },
+    Copy { dst: usize, src: usize, bytes: usize },
+    Kernel { function: usize, run: kernels::Kernel, args: Vec<Vec<u8>> },
+    /// Complete `generation` of `event`. Captured records are given a generation when launched.
+    Record { event: usize, generation: u64 },
+    /// Wait until `generation` of `event` is completed.
+    Wait { event: usize, generation: u64 },
+}
+
+impl Op {
+    /// Whether a graph node running `self` can be updated to run `other`.
+    fn same_node(&self, other: &Op) -> bool {
+        match (self, other) {
+            (Op::Memset { width: a, .. }, Op::Memset { width: b, .. }) => a == b,
+            (Op::Copy { .. }, Op::Copy { .. }) => true,
+            (Op::Kernel { function: a, .. }, Op::Kernel { function: b, .. }) => a == b,
+            (Op::Record { event: a, .. }, Op::Record { event: b, .. }) => a == b,
+            (Op::Wait { event: a, .. }, Op::Wait { event: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+struct Stream {
+    context: usize,
+    blocking: bool,
+    queue: VecDeque<Op>,
+    capture: Option<Capture>,
+}
+
+struct Capture {
+    ops: Vec<Op>,
+    mode: CUstreamCaptureMode,
+    thread: ThreadId,
+    invalidated: bool,
+}
+
+struct Event {
+    context: usize,
+    timing: bool,
+    /// The generation of the last record, and of the last one that ran.
+    recorded: u64,
+    completed: u64,
+    /// The stream of the last record, 0 for the null stream.
+    stream: usize,
+    time: Option<Instant>,
+}
+
+struct Module {
+    context: usize,
+    /// The size of every parameter of every entry.
+    entries: HashMap<String, Vec<usize>>,
+}
+
+struct Function {
+    module: usize,
+    name: String,
+    params: Vec<usize>,
+}
+
+struct Allocation {
+    size: usize,
+    device: usize,
+}
+
+#[derive(Default)]
+struct Driver {
+    next: usize,
+    /// The context and retain count of the primary context of every device.
+    primary: [(usize, usize); DEVICES.len()],
+    /// The device of every context.
+    contexts: HashMap<usize, usize>,
+    allocations: BTreeMap<usize, Allocation>,
+    host: HashMap<usize, usize>,
+    streams: HashMap<usize, Stream>,
+    events: HashMap<usize, Event>,
+    modules: HashMap<usize, Module>,
+    functions: HashMap<usize, Function>,
+    graphs: HashMap<usize, Vec<Op>>,
+    execs: HashMap<usize, Vec<Op>>,
+}
+
+static DRIVER: Lazy<Mutex<Driver>> = Lazy::new(|| Mutex::new(Driver { next: 0x1000, ..Default::default() }));
+
+/// Held while work runs, so the work of a stream runs in order even when several threads wait on it.
+static EXEC: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static LOG: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn lock() -> MutexGuard<'static, Driver> {
+    DRIVER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn status(f: impl FnOnce() -> Result<(), CUresult>) -> CUresult {
+    match f() {
+        Ok(()) => SUCCESS,
+        Err(code) => code,
+    }
+}
+
+fn log(call: String) {
+    LOG.with(|log| log.borrow_mut().push(call));
+}
+
+/// Take the context and module calls made on this thread so far, such as
+/// `cuDevicePrimaryCtxRetain(0)` or `cuModuleUnload`.
+pub fn take_log() -> Vec<String> {
+    LOG.with(|log| std::mem::take(&mut *log.borrow_mut()))
+}
+
+/// The number of operations enqueued on `stream` that did not run yet.
+pub fn pending(stream: CUstream) -> usize {
+    lock().streams.get(&(stream as usize)).map_or(0, |stream| stream.queue.len())
+}
+
+/// The retain count of the primary context of `device`.
+pub fn retained(device: CUdevice) -> usize {
+    lock().primary.get(device as usize).map_or(0, |(_, count)| *count)
+}
+
+impl Driver {
+    fn handle(&mut self) -> usize {
+        self.next += 0x10;
+        self.next
+    }
+
+    /// The context current on this thread.
+    fn current(&self) -> Result<usize, CUresult> {
+        let context = CURRENT.with(Cell::get);
+
+        match self.contexts.contains_key(&context) {
+            true => Ok(context),
+            false => Err(INVALID_CONTEXT),
+        }
+    }
+
+    fn device(&self) -> Result<usize, CUresult> {
+        Ok(self.contexts[&self.current()?])
+    }
+
+    /// Whether `[ptr, ptr + bytes)` lies in one device allocation.
+    fn contains(&self, ptr: usize, bytes: usize) -> bool {
+        self.allocations.range(..=ptr)
+            .next_back()
+            .is_some_and(|(base, allocation)| ptr + bytes <= base + allocation.size)
+    }
+
+    /// Fail a call the driver does not allow while a stream is captured by this
+    /// thread or in global mode, invalidating those captures.
+    fn prohibited(&mut self) -> Result<(), CUresult> {
+        let thread = std::thread::current().id();
+        let mut conflict = false;
+
+        for capture in self.streams.values_mut().filter_map(|stream| stream.capture.as_mut()) {
+            let applies = match capture.mode {
+                CUstreamCaptureMode_enum_CU_STREAM_CAPTURE_MODE_GLOBAL => true,
+                CUstreamCaptureMode_enum_CU_STREAM_CAPTURE_MODE_THREAD_LOCAL => capture.thread == thread,
+                _ => false,
+            };
+
+            if applies {
+                capture.invalidated = true;
+                conflict = true;
+            }
+        }
+
+        match conflict {
+            true => Err(CAPTURE_UNSUPPORTED),
+            false => Ok(()),
+        }
+    }
+
+    /// Fail work on the legacy stream of `context` if a blocking stream of it is captured,
+    /// as the work would have to wait on it. The captures are invalidated.
+    fn implicit(&mut self, context: usize) -> Result<(), CUresult> {
+        let mut conflict = false;
+
+        for stream in self.streams.values_mut().filter(|stream| stream.context == context && stream.blocking) {
+            if let Some(capture) = stream.capture.as_mut() {
+                capture.invalidated = true;
+                conflict = true;
+            }
+        }
+
+        match conflict {
+            true => Err(CAPTURE_IMPLICIT),
+            false => Ok(()),
+        }
+    }
+
+    /// Give records and waits the generation of their event, as they are enqueued on `stream`.
+    fn resolve(&mut self, op: Op, stream: usize) -> Result<Op, CUresult> {
+        Ok(match op {
+            Op::Record { event, .. } => {
+                let event = (event, self.events.get_mut(&event).ok_or(INVALID_HANDLE)?);
+                event.1.recorded += 1;
+                event.1.stream = stream;
+                Op::Record { event: event.0, generation: event.1.recorded }
+            }
+            Op::Wait { event, .. } => {
+                let generation = self.events.get(&event).ok_or(INVALID_HANDLE)?.recorded;
+                Op::Wait { event, generation }
+            }
+            op => op,
+        })
+    }
+
+    /// The streams of `context`, or only its blocking ones.
+    fn streams_of(&self, context: usize, blocking_only: bool) -> Vec<usize> {
+        self.streams.iter()
+            .filter(|(_, stream)| stream.context == context && (stream.blocking || !blocking_only))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+}
+
+/// Enqueue `op` on `stream`, or add it to the capture of `stream`. Work
+/// on the null stream runs straight away, after every blocking stream.
+fn submit(stream: CUstream, op: Op) -> Result<(), CUresult> {
+    let mut driver = lock();
+    let context = driver.current()?;
+
+    if stream.is_null() {
+        driver.implicit(context)?;
+
+        let op = driver.resolve(op, 0)?;
+        let blocking = driver.streams_of(context, true);
+        drop(driver);
+
+        with_exec(|| {
+            for stream in blocking {
+                flush(stream, None);
+            }
+
+            execute(op);
+        });
+
+        return Ok(())
+    }
+
+    let key = stream as usize;
+    let target = driver.streams.get_mut(&key).ok_or(INVALID_HANDLE)?;
+
+    if target.context != context {
+        return Err(INVALID_CONTEXT)
+    }
+
+    if let Some(capture) = target.capture.as_mut() {
+        if capture.invalidated {
+            return Err(CAPTURE_INVALIDATED)
+        }
+
+        capture.ops.push(op);
+        return Ok(())
+    }
+
+    let op = driver.resolve(op, key)?;
+    driver.streams.get_mut(&key).unwrap().queue.push_back(op);
+
+    Ok(())
+}
+
+/// Run `f` holding `EXEC`, unless this thread already holds it.
+fn with_exec<R>(f: impl FnOnce() -> R) -> R {
+    struct Depth;
+
+    impl Drop for Depth {
+        fn drop(&mut self) {
+            DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+
+    let _guard = match DEPTH.with(Cell::get) {
+        0 => Some(EXEC.lock().unwrap_or_else(|e| e.into_inner())),
+        _ => None,
+    };
+
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let _depth = Depth;
+
+    f()
+}
+
+/// Run the work of `stream`, until `generation` of an event is completed if `until` is set.
+fn flush(stream: usize, until: Option<(usize, u64)>) {
+    with_exec(|| loop {
+        let op = {
+            let mut driver = lock();
+
+            if let Some((event, generation)) = until {
+                if driver.events.get(&event).is_none_or(|event| event.completed >= generation) {
+                    return
+                }
+            }
+
+            match driver.streams.get_mut(&stream).and_then(|stream| stream.queue.pop_front()) {
+                Some(op) => op,
+                None => return,
+            }
+        };
+
+        execute(op);
+    })
+}
+
+/// Run the work of every stream of `context`, or of every context.
+fn flush_all(context: Option<usize>) {
+    let streams: Vec<usize> = {
+        let driver = lock();
+
+        driver.streams.iter()
+            .filter(|(_, stream)| context.is_none_or(|context| stream.context == context))
+            .map(|(key, _)| *key)
+            .collect()
+    };
+
+    with_exec(|| {
+        for stream in streams {
+            flush(stream, None);
+        }
+    });
+}
+
+fn execute(op: Op) {
+    match op {
+        Op::Memset { dst, value, width, count } => unsafe {
+            let bytes = value.to_le_bytes();
+
+            for i in 0..count {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), (dst + i * width) as *mut u8, width);
+            }
+        },
+        Op::Copy { dst, src, bytes } => unsafe {
+            std::ptr::copy(src as *const u8, dst as *mut u8, bytes);
+        },
+        Op::Kernel { run, args, .. } => run(&kernels::Args(&args)),
+        Op::Record { event, generation } => {
+            if let Some(event) = lock().events.get_mut(&event) {
+                event.completed = event.completed.max(generation);
+                event.time = Some(Instant::now());
+            }
+        }
+        Op::Wait { event, generation } => {
+            let stream = lock().events.get(&event).map(|event| event.stream);
+
+            if let Some(stream) = stream {
+                flush(stream, Some((event, generation)));
+            }
+        }
+    }
+}
+
+/// Write `value` as a NUL terminated string of at most `len` bytes to `dst`.
+unsafe fn write_str(dst: *mut c_char, len: c_int, value: &str) {
+    let bytes = value.as_bytes();
+    let n = bytes.len().min((len.max(1) - 1) as usize);
+
+    std::ptr::copy_nonoverlapping(bytes.as_ptr().cast(), dst, n);
+    *dst.add(n) = 0;
+}
+
+fn spec(device: CUdevice) -> Result<&'static Spec, CUresult> {
+    DEVICES.get(usize::try_from(device).map_err(|_| INVALID_DEVICE)?).ok_or(INVALID_DEVICE)
+}
+
+pub unsafe fn cuInit(_flags: c_uint) -> CUresult {
+    SUCCESS
+}
+
+pub unsafe fn cuDriverGetVersion(version: *mut c_int) -> CUresult {
+    *version = 11080;
+    SUCCESS
+}
+
+pub unsafe fn cuGetErrorName(error: CUresult, out: *mut *const c_char) -> CUresult {
+    match ERRORS.iter().find(|(code, _, _)| *code == error) {
+        Some((_, name, _)) => {
+            *out = name.as_ptr().cast();
+            SUCCESS
+        }
+        None => {
+            *out = std::ptr::null();
+            INVALID_VALUE
+        }
+    }
+}
+
+pub unsafe fn cuGetErrorString(error: CUresult, out: *mut *const c_char) -> CUresult {
+    match ERRORS.iter().find(|(code, _, _)| *code == error) {
+        Some((_, _, desc)) => {
+            *out = desc.as_ptr().cast();
+            SUCCESS
+        }
+        None => {
+            *out = std::ptr::null();
+            INVALID_VALUE
+        }
+    }
+}
+
+pub unsafe fn cuDeviceGet(device: *mut CUdevice, ordinal: c_int) -> CUresult {
+    status(|| {
+        spec(ordinal)?;
+        *device = ordinal;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDeviceGetCount(count: *mut c_int) -> CUresult {
+    *count = DEVICES.len() as c_int;
+    SUCCESS
+}
+
+pub unsafe fn cuDeviceGetName(name: *mut c_char, len: c_int, device: CUdevice) -> CUresult {
+    status(|| {
+        write_str(name, len, spec(device)?.name);
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDeviceGetUuid_v2(uuid: *mut CUuuid, device: CUdevice) -> CUresult {
+    status(|| {
+        spec(device)?;
+
+        let mut bytes = [0; 16];
+        bytes[0] = 0x6e;
+        bytes[15] = device as c_char;
+        (*uuid).bytes = bytes;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDeviceGetPCIBusId(pci_bus_id: *mut c_char, len: c_int, device: CUdevice) -> CUresult {
+    status(|| {
+        write_str(pci_bus_id, len, spec(device)?.pci_bus_id);
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDeviceTotalMem_v2(bytes: *mut usize, device: CUdevice) -> CUresult {
+    status(|| {
+        *bytes = spec(device)?.memory;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDeviceGetAttribute(value: *mut c_int, attribute: CUdevice_attribute, device: CUdevice) -> CUresult {
+    status(|| {
+        let spec = spec(device)?;
+
+        *value = match attribute {
+            1..=3 => 1024,
+            4 => 64,
+            5 => c_int::MAX,
+            6 | 7 => 65535,
+            8 => 48 << 10,
+            10 => 32,
+            16 => spec.multiprocessors,
+            31 | 41 | 78 => 1,
+            39 => 1024,
+            75 => spec.compute_capability.0,
+            76 => spec.compute_capability.1,
+            _ => 0,
+        };
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDeviceGetDefaultMemPool(pool: *mut CUmemoryPool, device: CUdevice) -> CUresult {
+    status(|| {
+        spec(device)?;
+        *pool = (device as usize + 1) as CUmemoryPool;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDeviceGetMemPool(pool: *mut CUmemoryPool, device: CUdevice) -> CUresult {
+    cuDeviceGetDefaultMemPool(pool, device)
+}
+
+pub unsafe fn cuDeviceSetMemPool(device: CUdevice, _pool: CUmemoryPool) -> CUresult {
+    status(|| spec(device).map(|_| ()))
+}
+
+pub unsafe fn cuDevicePrimaryCtxRetain(context: *mut CUcontext, device: CUdevice) -> CUresult {
+    log(format!("cuDevicePrimaryCtxRetain({})", device));
+
+    status(|| {
+        spec(device)?;
+
+        let mut driver = lock();
+
+        if driver.primary[device as usize].1 == 0 {
+            let handle = driver.handle();
+            driver.contexts.insert(handle, device as usize);
+            driver.primary[device as usize].0 = handle;
+        }
+
+        driver.primary[device as usize].1 += 1;
+        *context = driver.primary[device as usize].0 as CUcontext;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuDevicePrimaryCtxRelease_v2(device: CUdevice) -> CUresult {
+    log(format!("cuDevicePrimaryCtxRelease({})", device));
+
+    status(|| {
+        spec(device)?;
+
+        let context = {
+            let mut driver = lock();
+            let (context, count) = &mut driver.primary[device as usize];
+
+            if *count == 0 {
+                return Err(INVALID_CONTEXT)
+            }
+
+            *count -= 1;
+
+            match *count {
+                0 => *context,
+                _ => return Ok(()),
+            }
+        };
+
+        destroy_context(context);
+        Ok(())
+    })
+}
+
+/// Run the work of `context`, and destroy it with everything created in it.
+fn destroy_context(context: usize) {
+    flush_all(Some(context));
+
+    let mut driver = lock();
+    driver.contexts.remove(&context);
+    driver.streams.retain(|_, stream| stream.context != context);
+    driver.events.retain(|_, event| event.context != context);
+
+    let modules: Vec<usize> = driver.modules.iter()
+        .filter(|(_, module)| module.context == context)
+        .map(|(key, _)| *key)
+        .collect();
+
+    for module in modules {
+        driver.modules.remove(&module);
+        driver.functions.retain(|_, function| function.module != module);
+    }
+}
+
+pub unsafe fn cuCtxCreate_v2(context: *mut CUcontext, _flags: c_uint, device: CUdevice) -> CUresult {
+    status(|| {
+        spec(device)?;
+
+        let mut driver = lock();
+        let handle = driver.handle();
+        driver.contexts.insert(handle, device as usize);
+        CURRENT.with(|current| current.set(handle));
+        *context = handle as CUcontext;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuCtxDestroy_v2(context: CUcontext) -> CUresult {
+    status(|| {
+        let key = context as usize;
+
+        {
+            let driver = lock();
+
+            if !driver.contexts.contains_key(&key) || driver.primary.iter().any(|(primary, _)| *primary == key) {
+                return Err(INVALID_CONTEXT)
+            }
+        }
+
+        destroy_context(key);
+        CURRENT.with(|current| if current.get() == key { current.set(0) });
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuCtxGetApiVersion(context: CUcontext, version: *mut c_uint) -> CUresult {
+    status(|| {
+        if !lock().contexts.contains_key(&(context as usize)) {
+            return Err(INVALID_CONTEXT)
+        }
+
+        *version = 3020;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuCtxGetCurrent(context: *mut CUcontext) -> CUresult {
+    *context = CURRENT.with(Cell::get) as CUcontext;
+    SUCCESS
+}
+
+pub unsafe fn cuCtxSetCurrent(context: CUcontext) -> CUresult {
+    log("cuCtxSetCurrent".to_owned());
+
+    status(|| {
+        if !context.is_null() && !lock().contexts.contains_key(&(context as usize)) {
+            return Err(INVALID_CONTEXT)
+        }
+
+        CURRENT.with(|current| current.set(context as usize));
+        Ok(())
+    })
+}
+
+pub unsafe fn cuCtxGetDevice(device: *mut CUdevice) -> CUresult {
+    status(|| {
+        *device = lock().device()? as CUdevice;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuCtxGetLimit(value: *mut usize, limit: CUlimit) -> CUresult {
+    status(|| {
+        lock().current()?;
+
+        *value = match limit {
+            // CU_LIMIT_STACK_SIZE
+            0 => 1024,
+            // CU_LIMIT_PRINTF_FIFO_SIZE
+            1 => 1 << 20,
+            // CU_LIMIT_MALLOC_HEAP_SIZE
+            2 => 8 << 20,
+            _ => 0,
+        };
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuCtxGetSharedMemConfig(config: *mut CUsharedconfig) -> CUresult {
+    status(|| {
+        lock().current()?;
+        *config = 1;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuCtxSynchronize() -> CUresult {
+    status(|| {
+        let context = {
+            let mut driver = lock();
+            driver.prohibited()?;
+            driver.current()?
+        };
+
+        flush_all(Some(context));
+        Ok(())
+    })
+}
+
+pub unsafe fn cuMemAlloc_v2(ptr: *mut CUdeviceptr, bytes: usize) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        driver.prohibited()?;
+
+        let device = driver.device()?;
+        let used: usize = driver.allocations.values()
+            .filter(|allocation| allocation.device == device)
+            .map(|allocation| allocation.size)
+            .sum();
+
+        if bytes == 0 {
+            return Err(INVALID_VALUE)
+        }
+
+        if used + bytes > DEVICES[device].memory {
+            return Err(OUT_OF_MEMORY)
+        }
+
+        let layout = Layout::from_size_align(bytes, 256).map_err(|_| OUT_OF_MEMORY)?;
+        let data = std::alloc::alloc(layout);
+
+        if data.is_null() {
+            return Err(OUT_OF_MEMORY)
+        }
+
+        data.write_bytes(0xff, bytes);
+        driver.allocations.insert(data as usize, Allocation { size: bytes, device });
+        *ptr = data as CUdeviceptr;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuMemFree_v2(ptr: CUdeviceptr) -> CUresult {
+    status(|| {
+        lock().prohibited()?;
+
+        // Freeing memory waits for the work that could still use it.
+        flush_all(None);
+
+        let allocation = lock().allocations.remove(&(ptr as usize)).ok_or(INVALID_VALUE)?;
+        std::alloc::dealloc(ptr as *mut u8, Layout::from_size_align(allocation.size, 256).unwrap());
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuMemGetInfo_v2(free: *mut usize, total: *mut usize) -> CUresult {
+    status(|| {
+        let driver = lock();
+        let device = driver.device()?;
+        let used: usize = driver.allocations.values()
+            .filter(|allocation| allocation.device == device)
+            .map(|allocation| allocation.size)
+            .sum();
+
+        *total = DEVICES[device].memory;
+        *free = DEVICES[device].memory - used;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuMemHostAlloc(ptr: *mut *mut c_void, bytes: usize, _flags: c_uint) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        driver.prohibited()?;
+        driver.current()?;
+
+        let layout = Layout::from_size_align(bytes.max(1), 64).map_err(|_| OUT_OF_MEMORY)?;
+        let data = std::alloc::alloc(layout);
+
+        if data.is_null() {
+            return Err(OUT_OF_MEMORY)
+        }
+
+        driver.host.insert(data as usize, bytes.max(1));
+        *ptr = data.cast();
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuMemAllocHost_v2(ptr: *mut *mut c_void, bytes: usize) -> CUresult {
+    cuMemHostAlloc(ptr, bytes, 0)
+}
+
+pub unsafe fn cuMemFreeHost(ptr: *mut c_void) -> CUresult {
+    status(|| {
+        lock().prohibited()?;
+        flush_all(None);
+
+        let size = lock().host.remove(&(ptr as usize)).ok_or(INVALID_VALUE)?;
+        std::alloc::dealloc(ptr.cast(), Layout::from_size_align(size, 64).unwrap());
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuMemHostRegister_v2(_ptr: *mut c_void, _bytes: usize, _flags: c_uint) -> CUresult {
+    status(|| lock().current().map(|_| ()))
+}
+
+pub unsafe fn cuMemHostUnregister(_ptr: *mut c_void) -> CUresult {
+    status(|| lock().current().map(|_| ()))
+}
+
+/// Enqueue a copy, checking that its device side lies in an allocation.
+fn copy(dst: usize, src: usize, bytes: usize, device: (bool, bool), stream: CUstream, sync: bool) -> Result<(), CUresult> {
+    {
+        let mut driver = lock();
+
+        if sync {
+            driver.prohibited()?;
+        }
+
+        if (device.0 && !driver.contains(dst, bytes)) || (device.1 && !driver.contains(src, bytes)) {
+            return Err(INVALID_VALUE)
+        }
+    }
+
+    if bytes == 0 {
+        return Ok(())
+    }
+
+    submit(stream, Op::Copy { dst, src, bytes })
+}
+
+/// Enqueue a memset of `count` values of `width` bytes.
+fn memset(dst: CUdeviceptr, value: u32, width: usize, count: usize, stream: CUstream, sync: bool) -> Result<(), CUresult> {
+    {
+        let mut driver = lock();
+
+        if sync {
+            driver.prohibited()?;
+        }
+
+        if !driver.contains(dst as usize, width * count) {
+            return Err(INVALID_VALUE)
+        }
+    }
+
+    submit(stream, Op::Memset { dst: dst as usize, value, width, count })
+}
+
+/// Whether `ptr` is in a device allocation, as unified addressing tells them apart from host memory.
+fn is_device(ptr: CUdeviceptr) -> bool {
+    lock().contains(ptr as usize, 0)
+}
+
+pub unsafe fn cuMemcpy(dst: CUdeviceptr, src: CUdeviceptr, bytes: usize) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (is_device(dst), is_device(src)), std::ptr::null_mut(), true))
+}
+
+pub unsafe fn cuMemcpyAsync(dst: CUdeviceptr, src: CUdeviceptr, bytes: usize, stream: CUstream) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (is_device(dst), is_device(src)), stream, false))
+}
+
+pub unsafe fn cuMemcpyDtoH_v2(dst: *mut c_void, src: CUdeviceptr, bytes: usize) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (false, true), std::ptr::null_mut(), true))
+}
+
+pub unsafe fn cuMemcpyDtoHAsync_v2(dst: *mut c_void, src: CUdeviceptr, bytes: usize, stream: CUstream) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (false, true), stream, false))
+}
+
+pub unsafe fn cuMemcpyHtoD_v2(dst: CUdeviceptr, src: *const c_void, bytes: usize) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (true, false), std::ptr::null_mut(), true))
+}
+
+pub unsafe fn cuMemcpyHtoDAsync_v2(dst: CUdeviceptr, src: *const c_void, bytes: usize, stream: CUstream) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (true, false), stream, false))
+}
+
+pub unsafe fn cuMemcpyDtoD_v2(dst: CUdeviceptr, src: CUdeviceptr, bytes: usize) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (true, true), std::ptr::null_mut(), true))
+}
+
+pub unsafe fn cuMemcpyDtoDAsync_v2(dst: CUdeviceptr, src: CUdeviceptr, bytes: usize, stream: CUstream) -> CUresult {
+    status(|| copy(dst as usize, src as usize, bytes, (true, true), stream, false))
+}
+
+pub unsafe fn cuMemsetD8_v2(dst: CUdeviceptr, value: c_uchar, count: usize) -> CUresult {
+    status(|| memset(dst, value as u32, 1, count, std::ptr::null_mut(), true))
+}
+
+pub unsafe fn cuMemsetD8Async(dst: CUdeviceptr, value: c_uchar, count: usize, stream: CUstream) -> CUresult {
+    status(|| memset(dst, value as u32, 1, count, stream, false))
+}
+
+pub unsafe fn cuMemsetD16_v2(dst: CUdeviceptr, value: c_ushort, count: usize) -> CUresult {
+    status(|| memset(dst, value as u32, 2, count, std::ptr::null_mut(), true))
+}
+
+pub unsafe fn cuMemsetD16Async(dst: CUdeviceptr, value: c_ushort, count: usize, stream: CUstream) -> CUresult {
+    status(|| memset(dst, value as u32, 2, count, stream, false))
+}
+
+pub unsafe fn cuMemsetD32_v2(dst: CUdeviceptr, value: c_uint, count: usize) -> CUresult {
+    status(|| memset(dst, value, 4, count, std::ptr::null_mut(), true))
+}
+
+pub unsafe fn cuMemsetD32Async(dst: CUdeviceptr, value: c_uint, count: usize, stream: CUstream) -> CUresult {
+    status(|| memset(dst, value, 4, count, stream, false))
+}
+
+pub unsafe fn cuModuleLoadData(module: *mut CUmodule, image: *const c_void) -> CUresult {
+    log("cuModuleLoadData".to_owned());
+
+    status(|| {
+        let ptx = CStr::from_ptr(image.cast()).to_str().map_err(|_| INVALID_PTX)?;
+        let entries = ptx::parse_entries(ptx).map_err(|_| INVALID_PTX)?;
+
+        let mut driver = lock();
+        let context = driver.current()?;
+        let handle = driver.handle();
+
+        driver.modules.insert(handle, Module {
+            context,
+            entries: entries.into_iter()
+                .map(|(name, params)| (name, params.iter().map(|param| param.size).collect()))
+                .collect(),
+        });
+
+        *module = handle as CUmodule;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuModuleLoad(module: *mut CUmodule, path: *const c_char) -> CUresult {
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return INVALID_VALUE,
+    };
+
+    match std::fs::read_to_string(path) {
+        Ok(ptx) => match std::ffi::CString::new(ptx) {
+            Ok(ptx) => cuModuleLoadData(module, ptx.as_ptr().cast()),
+            Err(_) => INVALID_PTX,
+        },
+        Err(_) => FILE_NOT_FOUND,
+    }
+}
+
+pub unsafe fn cuModuleUnload(module: CUmodule) -> CUresult {
+    log("cuModuleUnload".to_owned());
+
+    status(|| {
+        let key = module as usize;
+        let context = lock().modules.get(&key).ok_or(INVALID_HANDLE)?.context;
+
+        flush_all(Some(context));
+
+        let mut driver = lock();
+        driver.modules.remove(&key);
+        driver.functions.retain(|_, function| function.module != key);
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuModuleGetFunction(function: *mut CUfunction, module: CUmodule, name: *const c_char) -> CUresult {
+    status(|| {
+        let name = CStr::from_ptr(name).to_str().map_err(|_| NOT_FOUND)?;
+        let key = module as usize;
+
+        let mut driver = lock();
+        let params = driver.modules.get(&key).ok_or(INVALID_HANDLE)?
+            .entries.get(name).ok_or(NOT_FOUND)?
+            .clone();
+
+        let existing = driver.functions.iter()
+            .find(|(_, function)| function.module == key && function.name == name)
+            .map(|(handle, _)| *handle);
+
+        let handle = match existing {
+            Some(handle) => handle,
+            None => {
+                let handle = driver.handle();
+                driver.functions.insert(handle, Function { module: key, name: name.to_owned(), params });
+                handle
+            }
+        };
+
+        *function = handle as CUfunction;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuModuleGetLoadingMode(mode: *mut CUmoduleLoadingMode) -> CUresult {
+    // CU_MODULE_EAGER_LOADING
+    *mode = 1;
+    SUCCESS
+}
+
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn cuLaunchKernel(
+    function: CUfunction,
+    grid_x: c_uint, grid_y: c_uint, grid_z: c_uint,
+    block_x: c_uint, block_y: c_uint, block_z: c_uint,
+    _shared_mem_bytes: c_uint,
+    stream: CUstream,
+    params: *mut *mut c_void,
+    _extra: *mut *mut c_void,
+) -> CUresult {
+    status(|| {
+        let (name, sizes) = {
+            let driver = lock();
+            driver.current()?;
+
+            let function = driver.functions.get(&(function as usize)).ok_or(INVALID_HANDLE)?;
+            (function.name.clone(), function.params.clone())
+        };
+
+        let threads = block_x as u64 * block_y as u64 * block_z as u64;
+
+        if threads == 0 || threads > 1024 || grid_x == 0 || grid_y == 0 || grid_z == 0 {
+            return Err(INVALID_VALUE)
+        }
+
+        if params.is_null() && !sizes.is_empty() {
+            return Err(INVALID_VALUE)
+        }
+
+        let run = kernels::find(&name).ok_or(NOT_SUPPORTED)?;
+        let args = sizes.iter()
+            .enumerate()
+            .map(|(i, size)| std::slice::from_raw_parts(*params.add(i) as *const u8, *size).to_vec())
+            .collect();
+
+        submit(stream, Op::Kernel { function: function as usize, run, args })
+    })
+}
+
+pub unsafe fn cuOccupancyMaxPotentialBlockSize(
+    min_grid_size: *mut c_int,
+    block_size: *mut c_int,
+    function: CUfunction,
+    _block_size_to_dynamic_smem_size: CUoccupancyB2DSize,
+    _dynamic_smem_size: usize,
+    block_size_limit: c_int,
+) -> CUresult {
+    status(|| {
+        let driver = lock();
+        let device = driver.device()?;
+
+        if !driver.functions.contains_key(&(function as usize)) {
+            return Err(INVALID_HANDLE)
+        }
+
+        let block = match block_size_limit {
+            0 => 256,
+            limit => limit.min(256),
+        };
+
+        *block_size = block;
+        *min_grid_size = DEVICES[device].multiprocessors * (1024 / block);
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuStreamCreate(stream: *mut CUstream, flags: c_uint) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        let context = driver.current()?;
+        let handle = driver.handle();
+
+        driver.streams.insert(handle, Stream {
+            context,
+            // CU_STREAM_NON_BLOCKING
+            blocking: flags & 1 == 0,
+            queue: VecDeque::new(),
+            capture: None,
+        });
+
+        *stream = handle as CUstream;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuStreamCreateWithPriority(stream: *mut CUstream, flags: c_uint, _priority: c_int) -> CUresult {
+    cuStreamCreate(stream, flags)
+}
+
+pub unsafe fn cuStreamDestroy_v2(stream: CUstream) -> CUresult {
+    status(|| {
+        let key = stream as usize;
+
+        if !lock().streams.contains_key(&key) {
+            return Err(INVALID_HANDLE)
+        }
+
+        // The work left on a destroyed stream still runs.
+        flush(key, None);
+        lock().streams.remove(&key);
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuStreamSynchronize(stream: CUstream) -> CUresult {
+    status(|| {
+        let key = stream as usize;
+
+        let blocking = {
+            let mut driver = lock();
+            let context = driver.current()?;
+
+            match stream.is_null() {
+                true => {
+                    driver.implicit(context)?;
+                    driver.streams_of(context, true)
+                }
+                false => {
+                    let target = driver.streams.get(&key).ok_or(INVALID_HANDLE)?;
+
+                    if target.capture.is_some() {
+                        driver.prohibited()?;
+                        return Err(CAPTURE_UNSUPPORTED)
+                    }
+
+                    vec![key]
+                }
+            }
+        };
+
+        with_exec(|| {
+            for stream in blocking {
+                flush(stream, None);
+            }
+        });
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuStreamQuery(stream: CUstream) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        let context = driver.current()?;
+
+        let pending = match stream.is_null() {
+            true => driver.streams.values()
+                .any(|stream| stream.context == context && stream.blocking && !stream.queue.is_empty()),
+            false => {
+                let target = driver.streams.get(&(stream as usize)).ok_or(INVALID_HANDLE)?;
+
+                if target.capture.is_some() {
+                    driver.prohibited()?;
+                    return Err(CAPTURE_UNSUPPORTED)
+                }
+
+                !target.queue.is_empty()
+            }
+        };
+
+        match pending {
+            true => Err(NOT_READY),
+            false => Ok(()),
+        }
+    })
+}
+
+pub unsafe fn cuStreamWaitEvent(stream: CUstream, event: CUevent, _flags: c_uint) -> CUresult {
+    status(|| submit(stream, Op::Wait { event: event as usize, generation: 0 }))
+}
+
+pub unsafe fn cuStreamBeginCapture_v2(stream: CUstream, mode: CUstreamCaptureMode) -> CUresult {
+    status(|| {
+        if stream.is_null() {
+            return Err(CAPTURE_UNSUPPORTED)
+        }
+
+        let mut driver = lock();
+        let context = driver.current()?;
+        let target = driver.streams.get_mut(&(stream as usize)).ok_or(INVALID_HANDLE)?;
+
+        if target.context != context {
+            return Err(INVALID_CONTEXT)
+        }
+
+        if target.capture.is_some() {
+            return Err(ILLEGAL_STATE)
+        }
+
+        target.capture = Some(Capture {
+            ops: Vec::new(),
+            mode,
+            thread: std::thread::current().id(),
+            invalidated: false,
+        });
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuStreamEndCapture(stream: CUstream, graph: *mut CUgraph) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        let target = driver.streams.get_mut(&(stream as usize)).ok_or(INVALID_HANDLE)?;
+        let capture = target.capture.as_ref().ok_or(ILLEGAL_STATE)?;
+
+        if capture.mode != CUstreamCaptureMode_enum_CU_STREAM_CAPTURE_MODE_RELAXED && capture.thread != std::thread::current().id() {
+            return Err(CAPTURE_WRONG_THREAD)
+        }
+
+        let capture = target.capture.take().unwrap();
+        *graph = std::ptr::null_mut();
+
+        if capture.invalidated {
+            return Err(CAPTURE_INVALIDATED)
+        }
+
+        let handle = driver.handle();
+        driver.graphs.insert(handle, capture.ops);
+        *graph = handle as CUgraph;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuStreamIsCapturing(stream: CUstream, capture_status: *mut CUstreamCaptureStatus) -> CUresult {
+    status(|| {
+        let driver = lock();
+
+        *capture_status = match stream.is_null() {
+            true => CUstreamCaptureStatus_enum_CU_STREAM_CAPTURE_STATUS_NONE,
+            false => match &driver.streams.get(&(stream as usize)).ok_or(INVALID_HANDLE)?.capture {
+                None => CUstreamCaptureStatus_enum_CU_STREAM_CAPTURE_STATUS_NONE,
+                Some(capture) if capture.invalidated => CUstreamCaptureStatus_enum_CU_STREAM_CAPTURE_STATUS_INVALIDATED,
+                Some(_) => CUstreamCaptureStatus_enum_CU_STREAM_CAPTURE_STATUS_ACTIVE,
+            },
+        };
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuEventCreate(event: *mut CUevent, flags: c_uint) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        let context = driver.current()?;
+        let handle = driver.handle();
+
+        driver.events.insert(handle, Event {
+            context,
+            // CU_EVENT_DISABLE_TIMING
+            timing: flags & 2 == 0,
+            recorded: 0,
+            completed: 0,
+            stream: 0,
+            time: None,
+        });
+
+        *event = handle as CUevent;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuEventDestroy_v2(event: CUevent) -> CUresult {
+    status(|| {
+        lock().events.remove(&(event as usize)).ok_or(INVALID_HANDLE)?;
+        Ok(())
+    })
+}
+
+pub unsafe fn cuEventRecord(event: CUevent, stream: CUstream) -> CUresult {
+    status(|| {
+        let context = lock().events.get(&(event as usize)).ok_or(INVALID_HANDLE)?.context;
+
+        if lock().current()? != context {
+            return Err(INVALID_HANDLE)
+        }
+
+        submit(stream, Op::Record { event: event as usize, generation: 0 })
+    })
+}
+
+pub unsafe fn cuEventQuery(event: CUevent) -> CUresult {
+    status(|| {
+        let driver = lock();
+        let event = driver.events.get(&(event as usize)).ok_or(INVALID_HANDLE)?;
+
+        match event.completed >= event.recorded {
+            true => Ok(()),
+            false => Err(NOT_READY),
+        }
+    })
+}
+
+pub unsafe fn cuEventSynchronize(event: CUevent) -> CUresult {
+    status(|| {
+        let key = event as usize;
+        let (stream, generation) = {
+            let driver = lock();
+            let event = driver.events.get(&key).ok_or(INVALID_HANDLE)?;
+            (event.stream, event.recorded)
+        };
+
+        flush(stream, Some((key, generation)));
+        Ok(())
+    })
+}
+
+pub unsafe fn cuEventElapsedTime(ms: *mut f32, start: CUevent, end: CUevent) -> CUresult {
+    status(|| {
+        let driver = lock();
+        let start = driver.events.get(&(start as usize)).ok_or(INVALID_HANDLE)?;
+        let end = driver.events.get(&(end as usize)).ok_or(INVALID_HANDLE)?;
+
+        if !start.timing || !end.timing {
+            return Err(INVALID_HANDLE)
+        }
+
+        match (start.time, end.time) {
+            (Some(a), Some(b)) if start.completed >= start.recorded && end.completed >= end.recorded => {
+                *ms = match b >= a {
+                    true => (b - a).as_secs_f32() * 1000.0,
+                    false => -(a - b).as_secs_f32() * 1000.0,
+                };
+
+                Ok(())
+            }
+            _ => Err(NOT_READY),
+        }
+    })
+}
+
+pub unsafe fn cuGraphCreate(graph: *mut CUgraph, _flags: c_uint) -> CUresult {
+    let mut driver = lock();
+    let handle = driver.handle();
+    driver.graphs.insert(handle, Vec::new());
+    *graph = handle as CUgraph;
+
+    SUCCESS
+}
+
+pub unsafe fn cuGraphDestroy(graph: CUgraph) -> CUresult {
+    match lock().graphs.remove(&(graph as usize)) {
+        Some(_) => SUCCESS,
+        None => INVALID_HANDLE,
+    }
+}
+
+pub unsafe fn cuGraphGetNodes(graph: CUgraph, nodes: *mut CUgraphNode, count: *mut usize) -> CUresult {
+    status(|| {
+        let driver = lock();
+        let ops = driver.graphs.get(&(graph as usize)).ok_or(INVALID_HANDLE)?;
+
+        if !nodes.is_null() {
+            for i in 0..ops.len().min(*count) {
+                *nodes.add(i) = (graph as usize + i + 1) as CUgraphNode;
+            }
+        }
+
+        *count = ops.len();
+        Ok(())
+    })
+}
+
+pub unsafe fn cuGraphInstantiateWithFlags(exec: *mut CUgraphExec, graph: CUgraph, _flags: c_ulonglong) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        let ops = driver.graphs.get(&(graph as usize)).ok_or(INVALID_HANDLE)?.clone();
+        let handle = driver.handle();
+
+        driver.execs.insert(handle, ops);
+        *exec = handle as CUgraphExec;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuGraphExecDestroy(exec: CUgraphExec) -> CUresult {
+    match lock().execs.remove(&(exec as usize)) {
+        Some(_) => SUCCESS,
+        None => INVALID_HANDLE,
+    }
+}
+
+pub unsafe fn cuGraphExecUpdate(
+    exec: CUgraphExec,
+    graph: CUgraph,
+    error_node: *mut CUgraphNode,
+    result: *mut CUgraphExecUpdateResult,
+) -> CUresult {
+    status(|| {
+        let mut driver = lock();
+        let ops = driver.graphs.get(&(graph as usize)).ok_or(INVALID_HANDLE)?.clone();
+        let current = driver.execs.get_mut(&(exec as usize)).ok_or(INVALID_HANDLE)?;
+
+        *error_node = std::ptr::null_mut();
+
+        if current.len() != ops.len() {
+            *result = CUgraphExecUpdateResult_enum_CU_GRAPH_EXEC_UPDATE_ERROR_TOPOLOGY_CHANGED;
+            return Err(UPDATE_FAILURE)
+        }
+
+        if let Some(i) = current.iter().zip(ops.iter()).position(|(a, b)| !a.same_node(b)) {
+            *error_node = (graph as usize + i + 1) as CUgraphNode;
+            *result = CUgraphExecUpdateResult_enum_CU_GRAPH_EXEC_UPDATE_ERROR_FUNCTION_CHANGED;
+            return Err(UPDATE_FAILURE)
+        }
+
+        *current = ops;
+        *result = CUgraphExecUpdateResult_enum_CU_GRAPH_EXEC_UPDATE_SUCCESS;
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuGraphLaunch(exec: CUgraphExec, stream: CUstream) -> CUresult {
+    status(|| {
+        let ops = lock().execs.get(&(exec as usize)).ok_or(INVALID_HANDLE)?.clone();
+
+        for op in ops {
+            submit(stream, op)?;
+        }
+
+        Ok(())
+    })
+}
+
+pub unsafe fn cuGraphUpload(exec: CUgraphExec, stream: CUstream) -> CUresult {
+    status(|| {
+        let driver = lock();
+        let context = driver.current()?;
+
+        if !driver.execs.contains_key(&(exec as usize)) {
+            return Err(INVALID_HANDLE)
+        }
+
+        match stream.is_null() || driver.streams.get(&(stream as usize)).is_some_and(|stream| stream.context == context) {
+            true => Ok(()),
+            false => Err(INVALID_HANDLE),
+        }
+    })
+}
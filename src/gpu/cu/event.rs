@@ -4,29 +4,56 @@
 use super::*;
 use types::*;
 
-#[cfg(feature = "show_unimplemented")]
-pub fn create() {
-
+/// Creates an event. Events created with `disable_timing` cannot be timed, but are cheaper to record and wait on.
+pub fn create(disable_timing: bool) -> Result<Event> {
+    let mut event: sys::CUevent = std::ptr::null_mut();
+    // CU_EVENT_DISABLE_TIMING
+    let flags = if disable_timing { 2 } else { 0 };
+
+    unsafe {
+        check(sys::cuEventCreate(&mut event, flags))?;
+    }
+
+    Ok(Event {
+        ptr: event
+    })
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn destroy() {
-
+/// Destroy an event. If it is recorded but not completed, its resources are released once it completes.
+pub fn destroy(event: Event) -> Result<()> {
+    unsafe {
+        check(sys::cuEventDestroy_v2(event.ptr))
+    }
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn elapsed_time() {
+/// Returns the time between two completed events in milliseconds, with a resolution of around 0.5 microseconds.
+pub fn elapsed_time(start: &Event, end: &Event) -> Result<f32> {
+    let mut ms = 0.0;
+
+    unsafe {
+        check(sys::cuEventElapsedTime(&mut ms, start.ptr, end.ptr))?;
+    }
 
+    Ok(ms)
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn query() {
+/// Whether all the work captured by the last record of an event is completed.
+pub fn query(event: &Event) -> Result<bool> {
+    let code = unsafe { sys::cuEventQuery(event.ptr) };
 
-}
+    if code == sys::cudaError_enum_CUDA_ERROR_NOT_READY {
+        return Ok(false)
+    }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn record() {
+    check(code)?;
+    Ok(true)
+}
 
+/// Capture the work enqueued on a stream so far in an event.
+pub fn record(event: &Event, stream: &Stream) -> Result<()> {
+    unsafe {
+        check(sys::cuEventRecord(event.ptr, stream.ptr))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -34,7 +61,9 @@ pub fn record_with_flags() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn synchronize() {
-
+/// Wait until all the work captured by the last record of an event is completed.
+pub fn synchronize(event: &Event) -> Result<()> {
+    unsafe {
+        check(sys::cuEventSynchronize(event.ptr))
+    }
 }
\ No newline at end of file
@@ -94,9 +94,17 @@ pub fn clone() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn create() {
+/// Create an empty graph.
+pub fn create() -> Result<Graph> {
+    let mut graph: sys::CUgraph = std::ptr::null_mut();
+
+    unsafe {
+        check(sys::cuGraphCreate(&mut graph, 0))?;
+    }
 
+    Ok(Graph {
+        ptr: graph
+    })
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -104,9 +112,11 @@ pub fn debug_dot_print() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn destroy() {
-
+/// Destroy a graph. Executable graphs instantiated from it are not affected.
+pub fn destroy(graph: Graph) -> Result<()> {
+    unsafe {
+        check(sys::cuGraphDestroy(graph.ptr))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -139,9 +149,11 @@ pub fn exec_batch_mem_op_node_set_params() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn exec_destroy() {
-
+/// Destroy an executable graph.
+pub fn exec_destroy(exec: GraphExec) -> Result<()> {
+    unsafe {
+        check(sys::cuGraphExecDestroy(exec.ptr))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -184,9 +196,24 @@ pub fn exec_memset_node_set_params() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn exec_update() {
+/// Update the parameters of the nodes of `exec` to those of `graph`, without re-instantiating it.
+///
+/// Returns false if the topology of `graph` differs from the graph `exec` was 
+/// instantiated from, in which case `graph` has to be instantiated instead.
+pub fn exec_update(exec: &GraphExec, graph: &Graph) -> Result<bool> {
+    let mut error_node: sys::CUgraphNode = std::ptr::null_mut();
+    let mut result: sys::CUgraphExecUpdateResult = 0;
+
+    let code = unsafe {
+        sys::cuGraphExecUpdate(exec.ptr, graph.ptr, &mut error_node, &mut result)
+    };
+
+    if code == sys::cudaError_enum_CUDA_ERROR_GRAPH_EXEC_UPDATE_FAILURE {
+        return Ok(false)
+    }
 
+    check(code)?;
+    Ok(true)
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -214,9 +241,21 @@ pub fn get_edges() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn get_nodes() {
+/// Returns every node of a graph.
+pub fn get_nodes(graph: &Graph) -> Result<Vec<GraphNode>> {
+    let mut len = 0;
+
+    unsafe {
+        check(sys::cuGraphGetNodes(graph.ptr, std::ptr::null_mut(), &mut len))?;
+    }
 
+    let mut nodes: Vec<sys::CUgraphNode> = vec![std::ptr::null_mut(); len];
+
+    unsafe {
+        check(sys::cuGraphGetNodes(graph.ptr, nodes.as_mut_ptr(), &mut len))?;
+    }
+
+    Ok(nodes.into_iter().take(len).map(|ptr| GraphNode { ptr }).collect())
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -234,14 +273,22 @@ pub fn host_node_set_params() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn instantiate() {
-
+/// Create an executable graph from a graph.
+pub fn instantiate(graph: &Graph) -> Result<GraphExec> {
+    instantiate_with_flags(graph, 0)
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn instantiate_with_flags() {
+/// Create an executable graph from a graph, with `CUgraphInstantiate_flags`.
+pub fn instantiate_with_flags(graph: &Graph, flags: u64) -> Result<GraphExec> {
+    let mut exec: sys::CUgraphExec = std::ptr::null_mut();
 
+    unsafe {
+        check(sys::cuGraphInstantiateWithFlags(&mut exec, graph.ptr, flags))?;
+    }
+
+    Ok(GraphExec {
+        ptr: exec
+    })
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -268,9 +315,11 @@ pub fn kernel_node_set_attribute() {
 pub fn kernel_node_set_params() {
 
 }
-#[cfg(feature = "show_unimplemented")]
-pub fn launch() {
-
+/// Launch an executable graph on a stream. Only one instance of it may run at a time.
+pub fn launch(exec: &GraphExec, stream: &Stream) -> Result<()> {
+    unsafe {
+        check(sys::cuGraphLaunch(exec.ptr, stream.ptr))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -353,7 +402,9 @@ pub fn retain_user_object() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn upload() {
-
+/// Upload an executable graph to the device on a stream, without running it.
+pub fn upload(exec: &GraphExec, stream: &Stream) -> Result<()> {
+    unsafe {
+        check(sys::cuGraphUpload(exec.ptr, stream.ptr))
+    }
 }
\ No newline at end of file
@@ -20,7 +20,7 @@ pub fn host_func() {
 
 }
 
-/// Launches a Cuda Kernel
+/// Launches a Cuda Kernel. `args` holds a pointer to each argument of the kernel.
 pub fn kernel(
     function: &Function, 
     grid_dim: (u32, u32, u32),
@@ -41,8 +41,8 @@ pub fn kernel(
             block_dim.2,
             shared_mem_bytes,
             stream.ptr,
-            &mut args.as_ptr().cast_mut().cast::<c_void>(),
-            &mut extras.as_ptr().cast_mut().cast::<c_void>(),
+            args.as_ptr().cast_mut(),
+            if extras.is_empty() { std::ptr::null_mut() } else { extras.as_ptr().cast_mut() },
         ))
     }
 }
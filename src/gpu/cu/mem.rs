@@ -47,9 +47,16 @@ pub fn alloc<T>(len: usize) -> Result<DevicePtr> {
     })
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn alloc_host() {
+/// Allocates page-locked host memory for `len` elements of `T`, which the
+/// device can access directly, and copy to or from asynchronously.
+pub fn alloc_host<T>(len: usize) -> Result<*mut T> {
+    let mut ptr: *mut std::ffi::c_void = std::ptr::null_mut();
+
+    unsafe {
+        check(sys::cuMemAllocHost_v2(&mut ptr, len * std::mem::size_of::<T>()))?;
+    }
 
+    Ok(ptr.cast())
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -69,9 +76,11 @@ pub fn free(ptr: DevicePtr) -> Result<()> {
     }
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn free_host() {
-
+/// Frees page-locked host memory from `alloc_host` or `host_alloc`.
+pub fn free_host<T>(ptr: *mut T) -> Result<()> {
+    unsafe {
+        check(sys::cuMemFreeHost(ptr.cast()))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -84,14 +93,28 @@ pub fn get_handle_for_address_range() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn get_info() {
+/// Returns the free and total memory of the current context's device, in bytes.
+pub fn get_info() -> Result<(usize, usize)> {
+    let mut free = 0;
+    let mut total = 0;
+
+    unsafe {
+        check(sys::cuMemGetInfo_v2(&mut free, &mut total))?;
+    }
 
+    Ok((free, total))
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn host_alloc() {
+/// Allocates page-locked host memory for `len` elements of `T`, with `CU_MEMHOSTALLOC` flags. 
+/// Memory allocated with `CU_MEMHOSTALLOC_PORTABLE` (1) is page-locked for every context.
+pub fn host_alloc<T>(len: usize, flags: u32) -> Result<*mut T> {
+    let mut ptr: *mut std::ffi::c_void = std::ptr::null_mut();
+
+    unsafe {
+        check(sys::cuMemHostAlloc(&mut ptr, len * std::mem::size_of::<T>(), flags))?;
+    }
 
+    Ok(ptr.cast())
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -104,14 +127,18 @@ pub fn host_get_flags() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn host_register() {
-
+/// Page-locks `len` elements of existing host memory, with `CU_MEMHOSTREGISTER` flags.
+pub fn host_register<T>(ptr: *mut T, len: usize, flags: u32) -> Result<()> {
+    unsafe {
+        check(sys::cuMemHostRegister_v2(ptr.cast(), len * std::mem::size_of::<T>(), flags))
+    }
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn host_unregister() {
-
+/// Unlocks host memory page-locked with `host_register`.
+pub fn host_unregister<T>(ptr: *mut T) -> Result<()> {
+    unsafe {
+        check(sys::cuMemHostUnregister(ptr.cast()))
+    }
 }
 
 /// Copies memory.
@@ -119,7 +146,7 @@ pub fn cpy<T>(dst: &DevicePtr, src: &DevicePtr, len: usize) -> Result<()> {
     let size = std::mem::size_of::<T>();
 
     unsafe {
-        check(sys::cuMemcpy(src.ptr, dst.ptr, len * size))
+        check(sys::cuMemcpy(dst.ptr, src.ptr, len * size))
     }
 }
 
@@ -267,14 +294,18 @@ pub fn cpy_peer_async() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn set_d16() {
-
+/// Sets `len` 16-bit values to `value`.
+pub fn set_d16(dst: &DevicePtr, value: u16, len: usize) -> Result<()> {
+    unsafe {
+        check(sys::cuMemsetD16_v2(dst.ptr, value, len))
+    }
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn set_d16_async() {
-
+/// Sets `len` 16-bit values to `value` asynchronously.
+pub fn set_d16_async(dst: &DevicePtr, value: u16, len: usize, stream: &Stream) -> Result<()> {
+    unsafe {
+        check(sys::cuMemsetD16Async(dst.ptr, value, len, stream.ptr))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -302,9 +333,32 @@ pub fn set_d2_d8() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn set_d8_async() {
+/// Sets `len` 32-bit values to `value`.
+pub fn set_d32(dst: &DevicePtr, value: u32, len: usize) -> Result<()> {
+    unsafe {
+        check(sys::cuMemsetD32_v2(dst.ptr, value, len))
+    }
+}
+
+/// Sets `len` 32-bit values to `value` asynchronously.
+pub fn set_d32_async(dst: &DevicePtr, value: u32, len: usize, stream: &Stream) -> Result<()> {
+    unsafe {
+        check(sys::cuMemsetD32Async(dst.ptr, value, len, stream.ptr))
+    }
+}
 
+/// Sets `len` bytes to `value`.
+pub fn set_d8(dst: &DevicePtr, value: u8, len: usize) -> Result<()> {
+    unsafe {
+        check(sys::cuMemsetD8_v2(dst.ptr, value, len))
+    }
+}
+
+/// Sets `len` bytes to `value` asynchronously.
+pub fn set_d8_async(dst: &DevicePtr, value: u8, len: usize, stream: &Stream) -> Result<()> {
+    unsafe {
+        check(sys::cuMemsetD8Async(dst.ptr, value, len, stream.ptr))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
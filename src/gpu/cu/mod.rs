@@ -7,6 +7,7 @@ pub mod types;
 pub mod device;
 pub mod sys;
 pub mod ctx;
+pub mod primary;
 pub mod module;
 pub mod link;
 pub mod array;
@@ -23,6 +24,8 @@ pub mod launch;
 pub mod graph;
 pub mod user;
 pub mod occupancy;
+#[cfg(feature = "emulated")]
+pub mod emulated;
 
 pub use types::*;
 
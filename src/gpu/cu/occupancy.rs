@@ -24,6 +24,27 @@ pub fn max_active_clusters() {
 
 }
 
+/// Returns the minimum grid size to reach the maximum occupancy of `function`, and 
+/// the block size that reaches it, with `dynamic_smem_bytes` of dynamic shared memory
+/// per block. A `block_size_limit` of 0 means no limit.
+pub fn max_potential_block_size(function: &Function, dynamic_smem_bytes: usize, block_size_limit: u32) -> Result<(u32, u32)> {
+    let mut min_grid_size = 0;
+    let mut block_size = 0;
+
+    unsafe {
+        check(sys::cuOccupancyMaxPotentialBlockSize(
+            &mut min_grid_size,
+            &mut block_size,
+            function.ptr,
+            None,
+            dynamic_smem_bytes,
+            block_size_limit as i32,
+        ))?;
+    }
+
+    Ok((min_grid_size as u32, block_size as u32))
+}
+
 #[cfg(feature = "show_unimplemented")]
 pub fn max_potential_block_size_with_flags() {
 
@@ -0,0 +1,43 @@
+//! # 6.7 Primary Context Management
+
+use super::*;
+use types::*;
+
+/// Retain the primary context of a device, creating it if it is not active.
+/// 
+/// Every device has one primary context, shared by every user of the 
+/// driver in the process, including the runtime API. It is destroyed once 
+/// every retain is matched by a release.
+pub fn retain(device: &Device) -> Result<Context> {
+    let mut context: sys::CUcontext = std::ptr::null_mut();
+
+    unsafe {
+        check(sys::cuDevicePrimaryCtxRetain(&mut context, device.ptr))?;
+    }
+
+    Ok(Context {
+        ptr: context,
+    })
+}
+
+/// Release the primary context of a device, retained with `retain`.
+pub fn release(device: &Device) -> Result<()> {
+    unsafe {
+        check(sys::cuDevicePrimaryCtxRelease_v2(device.ptr))
+    }
+}
+
+#[cfg(feature = "show_unimplemented")]
+pub fn get_state() {
+
+}
+
+#[cfg(feature = "show_unimplemented")]
+pub fn reset() {
+
+}
+
+#[cfg(feature = "show_unimplemented")]
+pub fn set_flags() {
+
+}
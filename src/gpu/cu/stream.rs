@@ -14,9 +14,11 @@ pub fn attach_mem_async() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn begin_capture() {
-
+/// Begin capturing the work enqueued on a stream into a graph, instead of running it.
+pub fn begin_capture(stream: &Stream, mode: StreamCaptureMode) -> Result<()> {
+    unsafe {
+        check(sys::cuStreamBeginCapture_v2(stream.ptr, mode as u32))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -27,7 +29,7 @@ pub fn copy_attributes() {
 /// Creates a Stream
 pub fn create(is_non_blocking: bool) -> Result<Stream> {
     let mut stream: sys::CUstream = std::ptr::null_mut();
-    let flags = if is_non_blocking { 0 } else { 1 };
+    let flags = if is_non_blocking { 1 } else { 0 };
 
     unsafe {
         check(sys::cuStreamCreate(&mut stream, flags))?;
@@ -41,7 +43,7 @@ pub fn create(is_non_blocking: bool) -> Result<Stream> {
 /// Create a stream with a set priority. Lower numbers have higher priority.
 pub fn create_with_priority(priority: usize, is_non_blocking: bool) -> Result<Stream> {
     let mut stream: sys::CUstream = std::ptr::null_mut();
-    let flags = if is_non_blocking { 0 } else { 1 };
+    let flags = if is_non_blocking { 1 } else { 0 };
 
     unsafe {
         check(sys::cuStreamCreateWithPriority(&mut stream, flags, priority as i32))?;
@@ -59,9 +61,17 @@ pub fn destroy(stream: Stream) -> Result<()> {
     }
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn end_capture() {
+/// End capturing a stream, returning the captured graph.
+pub fn end_capture(stream: &Stream) -> Result<Graph> {
+    let mut graph: sys::CUgraph = std::ptr::null_mut();
 
+    unsafe {
+        check(sys::cuStreamEndCapture(stream.ptr, &mut graph))?;
+    }
+
+    Ok(Graph {
+        ptr: graph
+    })
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -84,14 +94,27 @@ pub fn get_flags() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn is_capturing() {
+/// Whether a stream is being captured. Also true if the capture was invalidated by an error.
+pub fn is_capturing(stream: &Stream) -> Result<bool> {
+    let mut status: sys::CUstreamCaptureStatus = 0;
 
+    unsafe {
+        check(sys::cuStreamIsCapturing(stream.ptr, &mut status))?;
+    }
+
+    Ok(status != 0)
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn query() {
+/// Whether all of a streams' tasks are completed.
+pub fn query(stream: &Stream) -> Result<bool> {
+    let code = unsafe { sys::cuStreamQuery(stream.ptr) };
+
+    if code == sys::cudaError_enum_CUDA_ERROR_NOT_READY {
+        return Ok(false)
+    }
 
+    check(code)?;
+    Ok(true)
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -111,9 +134,11 @@ pub fn update_capture_dependencies() {
 
 }
 
-#[cfg(feature = "show_unimplemented")]
-pub fn wait_event() {
-
+/// Make all future work enqueued on a stream wait until the last record of an event is completed.
+pub fn wait_event(stream: &Stream, event: &Event) -> Result<()> {
+    unsafe {
+        check(sys::cuStreamWaitEvent(stream.ptr, event.ptr, 0))
+    }
 }
 
 #[cfg(feature = "show_unimplemented")]
@@ -1,2 +1,5 @@
+#[cfg(not(feature = "emulated"))]
+include!(concat!(env!("OUT_DIR"), "/driver_bindings.rs"));
 
-include!(concat!(env!("OUT_DIR"), "/driver_bindings.rs"));
\ No newline at end of file
+#[cfg(feature = "emulated")]
+pub use super::emulated::*;
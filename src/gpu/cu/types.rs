@@ -21,16 +21,29 @@ pub struct Device {
     pub(crate) ptr: sys::CUdevice,
 }
 
+#[repr(transparent)]
 #[derive(Copy, Clone)]
 pub struct DevicePtr {
     pub(crate) ptr: sys::CUdeviceptr,
 }
 
+impl DevicePtr {
+    /// A pointer `len` elements of `T` past this one.
+    pub fn add<T>(&self, len: usize) -> Self {
+        Self {
+            ptr: self.ptr + (len * std::mem::size_of::<T>()) as sys::CUdeviceptr
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Event {
     pub(crate) ptr: sys::CUevent,
 }
 
+unsafe impl Send for Event {}
+unsafe impl Sync for Event {}
+
 #[derive(Copy, Clone)]
 pub struct ExternalMemory {
     pub(crate) ptr: sys::CUexternalMemory,
@@ -41,6 +54,9 @@ pub struct Function {
     pub(crate) ptr: sys::CUfunction,
 }
 
+unsafe impl Send for Function {}
+unsafe impl Sync for Function {}
+
 #[derive(Copy, Clone)]
 pub struct Graph {
     pub(crate) ptr: sys::CUgraph,
@@ -89,6 +105,9 @@ pub struct Stream {
     pub(crate) ptr: sys::CUstream,
 }
 
+unsafe impl Send for Stream {}
+unsafe impl Sync for Stream {}
+
 impl Stream {
     pub fn null() -> Self {
         Self {
@@ -383,4 +402,14 @@ pub enum DeviceAttribute {
     /// Device supports buffer sharing with dma_buf mechanism
     DMA_BUF_SUPPORTED = 124,
     MAX = 125,
-}
\ No newline at end of file
+}
+/// How a stream capture restricts potentially unsafe API calls, like allocations, while it is active.
+#[derive(Copy, Clone)]
+pub enum StreamCaptureMode {
+    /// Prohibit unsafe calls on every thread while any global capture is active.
+    GLOBAL = 0,
+    /// Prohibit unsafe calls on the thread that began the capture.
+    THREAD_LOCAL = 1,
+    /// Allow unsafe calls.
+    RELAXED = 2,
+}
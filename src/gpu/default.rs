@@ -10,7 +10,7 @@ static DEFAULT_DEVICE: Lazy<Arc<Device>> = Lazy::new(|| {
     cu::init()
         .expect("Failed to initialize Cuda!");
 
-    let dev = Device::pick_strongest()
+    let dev = Device::new()
         .expect("Failed to create global device!");
 
     dev
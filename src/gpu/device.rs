@@ -6,138 +6,206 @@ use std::sync::RwLock;
 use std::sync::Arc;
 
 use super::cu;
-use crate::error;
 use super::stream::Stream;
 use super::kernel::Kernel;
+use super::allocator::{Allocator, MemoryStats};
+use super::ptx::{self, Param};
+use super::modules;
+use super::info::{DeviceInfo, DevicePolicy};
+use super::context::{Context, Module};
+
+/// A loaded module, with the parameters of every entry parsed from its PTX.
+struct LoadedModule {
+    /// The name the module was loaded as, or the name of an embedded module.
+    name: String,
+    module: Arc<Module>,
+    entries: HashMap<String, Vec<Param>>,
+}
 
+/// A device, running in its primary context. Share it with an `Arc`; 
+/// its context is bound to any thread that uses it.
 pub struct Device {
-    context: cu::Context, 
-    modules: RwLock<HashMap<String, cu::Module>>,
+    context: Arc<Context>, 
+    ordinal: usize,
+    /// The compute capability, as `major * 10 + minor`.
+    sm: u32,
+    modules: RwLock<HashMap<String, LoadedModule>>,
+    allocator: Allocator,
 }
 
 impl Device {
-    /// Create a new device. By default, this function will pick the strongest device.
+    /// Create a new device. By default, this function will pick the strongest device,
+    /// unless `GT_DEVICE` sets another policy.
     pub fn new() -> Result<Arc<Self>> {
-        Self::pick_strongest()
+        Self::pick(DevicePolicy::from_env()?.unwrap_or(DevicePolicy::HighestCapability))
+    }
+
+    /// Pick a device with `policy`.
+    pub fn pick(policy: DevicePolicy) -> Result<Arc<Self>> {
+        Self::pick_ordinal(policy.find()?)
     }
 
     /// Pick the device at the provided ordinal index.
     pub fn pick_ordinal(ordinal: usize) -> Result<Arc<Self>> {
         let n = cu::device::get_count()?;
 
-        if ordinal > n {
-            return Err(anyhow!(error::gte_006(ordinal, n)));
+        if ordinal >= n {
+            return Err(anyhow!("No device at ordinal {}, there are {} devices!", ordinal, n));
         }
 
-        let device = cu::device::get(ordinal)?;
-        let context = cu::ctx::create(&device)?;
-        cu::ctx::set_current(&context)?;
-
-        Ok(Arc::new(Self {
-            context,
-            modules: RwLock::new(HashMap::new()),
-        }))
+        Self::from_ordinal(ordinal)
     }
 
     /// Pick the strongest device with the highest compute capability.
     pub fn pick_strongest() -> Result<Arc<Self>> {
-        let n = cu::device::get_count()?;
+        Self::pick(DevicePolicy::HighestCapability)
+    }
 
-        let mut device = cu::device::get(0)?;
-        let mut version = 
-            cu::device::get_attribute(cu::DeviceAttribute::COMPUTE_CAPABILITY_MAJOR, &device)? * 100 +
+    /// Retain the primary context of the device at `ordinal` and bind it to the current thread.
+    fn from_ordinal(ordinal: usize) -> Result<Arc<Self>> {
+        let device = cu::device::get(ordinal)?;
+        let sm = 
+            cu::device::get_attribute(cu::DeviceAttribute::COMPUTE_CAPABILITY_MAJOR, &device)? * 10 +
             cu::device::get_attribute(cu::DeviceAttribute::COMPUTE_CAPABILITY_MINOR, &device)?;
 
-        for i in 1..n {
-            let dev = cu::device::get(i)?;
-            let ver = 
-                cu::device::get_attribute(cu::DeviceAttribute::COMPUTE_CAPABILITY_MAJOR, &device)? * 100 +
-                cu::device::get_attribute(cu::DeviceAttribute::COMPUTE_CAPABILITY_MINOR, &device)?;
-
-            if ver > version {
-                device = dev;
-                version = ver;
-            }
-        }
-
-        let context = cu::ctx::create(&device)?;
-        cu::ctx::set_current(&context)?;
+        let context = Context::retain(device)?;
+        context.bind()?;
 
         Ok(Arc::new(Self {
             context,
+            ordinal,
+            sm: sm as u32,
             modules: RwLock::new(HashMap::new()),
+            allocator: Allocator::new(),
         }))
     }
 
-    /// Bind this devices' context to the current thread.
-    pub fn bind_to_thread(self: &Arc<Self>) -> Result<()> {
-        cu::ctx::set_current(&self.context)
+    /// The compute capability, as `major * 10 + minor`.
+    pub fn sm(&self) -> u32 {
+        self.sm
+    }
+
+    /// The ordinal of this device.
+    pub fn ordinal(&self) -> usize {
+        self.ordinal
+    }
+
+    /// The current properties of this device.
+    pub fn info(&self) -> Result<DeviceInfo> {
+        DeviceInfo::query(self.ordinal)
     }
 
-    /// Create a new stream
+    /// Bind this devices' context to the current thread, if it is not already.
+    /// Tensors, streams and kernels of this device do so before every driver call.
+    pub fn bind_to_thread(&self) -> Result<()> {
+        self.context.bind()
+    }
+
+    /// Create a new stream, which does not wait on the null stream, nor the null stream on it.
+    /// It is destroyed once every clone of it is dropped.
     pub fn fork(self: &Arc<Self>) -> Result<Stream> {
-        Ok(Stream {
-            stream: cu::stream::create(false)?
-        })
+        self.bind_to_thread()?;
+        Ok(Stream::from_raw(cu::stream::create(true)?, self))
     }
 
     /// Create a new stream with a priority
     pub fn fork_priority(self: &Arc<Self>, priority: usize) -> Result<Stream> {
-        Ok(Stream {
-            stream: cu::stream::create_with_priority(priority, false)?
-        })
+        self.bind_to_thread()?;
+        Ok(Stream::from_raw(cu::stream::create_with_priority(priority, true)?, self))
     }
 
     /// Load a Compiled PTX Module into device memory. Does nothing if a module with `name` already exists.
+    /// 
+    /// The parameters of every `.entry` are parsed, so launches can be checked against them.
     pub fn load_module(self: &Arc<Self>, name: &str, ptx: &str) -> Result<()> {
         let mut lock = self.modules.write().unwrap();
 
         if !lock.contains_key(name) {
-            let module = cu::module::load_data(ptx)?;
-            lock.insert(name.to_owned(), module);
+            let entries = ptx::parse_entries(ptx)?;
+            let module = Module::load(&self.context, ptx)?;
+            lock.insert(name.to_owned(), LoadedModule { name: name.to_owned(), module, entries });
         }
 
         Ok(())
     }
 
-    /// Get a Kernel from a loaded module. Returns an error if the module or function is not found.
+    /// Get a Kernel from a loaded module. If no loaded module has the kernel, the embedded 
+    /// module with the highest SM level this device supports is loaded first. Returns an 
+    /// error if the module or function is not found.
     pub fn get_kernel(self: &Arc<Self>, module: &str, kernel: &str) -> Result<Kernel> {
+        if let Some(kernel) = self.find_kernel(module, kernel)? {
+            return Ok(kernel)
+        }
+
+        self.load_embedded(module, kernel)?;
+
+        self.find_kernel(module, kernel)?
+            .ok_or(anyhow!("No entry with name: {} in module: {}.", kernel, module))
+    }
+
+    fn find_kernel(&self, module: &str, kernel: &str) -> Result<Option<Kernel>> {
+        self.bind_to_thread()?;
+
         let lock = self.modules.read().unwrap();
 
-        if let Some(module) = lock.get(module) {
-            let function = cu::module::get_function(module, kernel)?;
-            Ok(Kernel::from(kernel.to_owned(), function))
-        } else {
-            Err(anyhow!("No module with name: {} was loaded.", module))
+        for loaded in lock.values().filter(|loaded| loaded.name == module) {
+            if let Some(params) = loaded.entries.get(kernel) {
+                let function = loaded.module.function(kernel)?;
+                let kernel = Kernel::from(kernel.to_owned(), function, params.clone())
+                    .with_module(&loaded.module);
+
+                return Ok(Some(kernel))
+            }
         }
+
+        Ok(None)
+    }
+
+    /// Load the embedded PTX of `module` declaring `kernel`, with the highest SM level up to this device's.
+    fn load_embedded(self: &Arc<Self>, module: &str, kernel: &str) -> Result<()> {
+        let (ptx, entries) = modules::with_kernel(module, kernel, self.sm)
+            .ok_or(anyhow!("No module with name: {} was embedded with kernel: {} for sm_{}.", module, kernel, self.sm))?;
+
+        let mut lock = self.modules.write().unwrap();
+        let key = format!("{}_{}_sm_{}", ptx.module, ptx.dtype, ptx.sm);
+
+        if !lock.contains_key(&key) {
+            let module = Module::load(&self.context, ptx.source)?;
+            lock.insert(key, LoadedModule { name: ptx.module.to_owned(), module, entries: entries.clone() });
+        }
+
+        Ok(())
+    }
+
+    /// The caching allocator of this device.
+    pub fn allocator(&self) -> &Allocator {
+        &self.allocator
+    }
+
+    /// Bytes allocated, reserved and at peak by the allocator of this device.
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.allocator.stats()
+    }
+
+    /// Give the memory cached by the allocator back to the driver.
+    pub fn empty_cache(&self) -> Result<()> {
+        self.allocator.empty_cache()
     }
 
     /// check if a module is loaded
     pub fn is_module_loaded(self: &Arc<Self>, module: &str) -> bool {
         let lock = self.modules.read().unwrap();
-        lock.contains_key(module)
+        lock.values().any(|loaded| loaded.name == module)
     }
 }
 
 impl Drop for Device {
+    /// Frees the cached memory and drops the modules no Kernel holds anymore, 
+    /// before the primary context is released.
     fn drop(&mut self) {
-        let lock = self.modules.write().unwrap();
+        let _ = self.bind_to_thread()
+            .and_then(|_| self.allocator.empty_cache());
 
-        for (_, module) in lock.iter() {
-            cu::module::unload(*module).unwrap();
-        }
-
-        cu::ctx::destroy(self.context).unwrap();
+        self.modules.get_mut().unwrap().clear();
     }
 }
-
-impl Clone for Device {
-    fn clone(&self) -> Self {
-        let lock = self.modules.read().unwrap();
-
-        Self {
-            context: self.context,
-            modules: RwLock::new(lock.clone())
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,64 @@
+//! # Events
+//!
+//! An event marks a point in the work enqueued on a stream. Other streams can
+//! wait on it to order work across streams, the host can wait on it without
+//! waiting for the whole stream, and two events measure the time between them.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::cu;
+use super::device::Device;
+use super::stream::Stream;
+
+/// An event created on a device, destroyed when dropped.
+pub struct Event {
+    pub(crate) event: cu::Event,
+    /// Keeps the context alive until the event is destroyed.
+    device: Arc<Device>,
+}
+
+impl Event {
+    /// Create an event on `device`. Events without `timing` cannot be measured
+    /// with `elapsed_time`, but are cheaper to record and wait on.
+    pub fn new(device: &Arc<Device>, timing: bool) -> Result<Self> {
+        device.bind_to_thread()?;
+
+        Ok(Self {
+            event: cu::event::create(!timing)?,
+            device: device.clone(),
+        })
+    }
+
+    /// Mark the work enqueued on `stream` so far. Recording again moves the mark.
+    pub fn record(&self, stream: &Stream) -> Result<()> {
+        self.device.bind_to_thread()?;
+        cu::event::record(&self.event, &stream.stream)
+    }
+
+    /// Whether the work marked by the last record is completed.
+    pub fn query(&self) -> Result<bool> {
+        self.device.bind_to_thread()?;
+        cu::event::query(&self.event)
+    }
+
+    /// Wait until the work marked by the last record is completed.
+    pub fn synchronize(&self) -> Result<()> {
+        self.device.bind_to_thread()?;
+        cu::event::synchronize(&self.event)
+    }
+
+    /// The time from `start` to this event in milliseconds. Both must be completed and created with timing.
+    pub fn elapsed_time(&self, start: &Event) -> Result<f32> {
+        self.device.bind_to_thread()?;
+        cu::event::elapsed_time(&start.event, &self.event)
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        let _ = self.device.bind_to_thread()
+            .and_then(|_| cu::event::destroy(self.event));
+    }
+}
@@ -0,0 +1,147 @@
+//! # Graphs
+//!
+//! Work enqueued on a stream can be captured into a graph instead of running,
+//! and the graph launched again as a whole, paying the launch overhead once
+//! instead of once per kernel. A graph replays the pointers and parameters it
+//! was captured with. When they change, the work is captured again and the
+//! executable graph is updated in place, which is much cheaper than instantiating it.
+//!
+//! Memory freed while capturing, such as the scratch tensors of the captured work,
+//! is kept by the graph until it is dropped or updated, as replays still use it.
+
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+
+use super::cu;
+use super::Stream;
+use super::device::Device;
+use super::allocator::Block;
+
+/// A captured graph, with its executable instance.
+pub struct Graph {
+    graph: cu::Graph,
+    exec: cu::GraphExec,
+    /// The device of the stream it was captured on, whose context it lives in.
+    device: Arc<Device>,
+    /// The stream it was captured on.
+    stream: Stream,
+    /// The memory freed while capturing.
+    blocks: Vec<Block>,
+}
+
+impl Graph {
+    /// Capture the work `f` enqueues on `stream` and instantiate it. Nothing is run.
+    pub fn capture(stream: &Stream, f: impl FnOnce() -> Result<()>) -> Result<Self> {
+        let (device, graph, blocks) = record(stream, f)?;
+
+        match cu::graph::instantiate(&graph) {
+            Ok(exec) => Ok(Self { graph, exec, device, stream: stream.clone(), blocks }),
+            Err(e) => {
+                let _ = cu::graph::destroy(graph);
+                release(&device, stream, blocks);
+                Err(e)
+            }
+        }
+    }
+
+    /// Capture the work `f` enqueues on `stream` again, and update this graph to it.
+    /// The graph is only instantiated again if the captured work has a different topology.
+    pub fn update(&mut self, stream: &Stream, f: impl FnOnce() -> Result<()>) -> Result<()> {
+        let (device, graph, blocks) = record(stream, f)?;
+
+        let updated = cu::graph::exec_update(&self.exec, &graph)
+            .and_then(|updated| match updated {
+                true => Ok(None),
+                false => cu::graph::instantiate(&graph).map(Some),
+            });
+
+        match updated {
+            Ok(exec) => {
+                if let Some(exec) = exec {
+                    let _ = cu::graph::exec_destroy(std::mem::replace(&mut self.exec, exec));
+                }
+
+                let _ = cu::graph::destroy(std::mem::replace(&mut self.graph, graph));
+                release(&device, &self.stream, std::mem::replace(&mut self.blocks, blocks));
+                Ok(())
+            }
+            Err(e) => {
+                let _ = cu::graph::destroy(graph);
+                release(&device, stream, blocks);
+                Err(e)
+            }
+        }
+    }
+
+    /// Run the graph on `stream`.
+    pub fn launch(&self, stream: &Stream) -> Result<()> {
+        self.device.bind_to_thread()?;
+        cu::graph::launch(&self.exec, &stream.stream)
+    }
+
+    /// Upload the graph to the device ahead of its first launch, to take that cost out of it.
+    pub fn upload(&self, stream: &Stream) -> Result<()> {
+        self.device.bind_to_thread()?;
+        cu::graph::upload(&self.exec, &stream.stream)
+    }
+
+    /// The number of nodes in the graph.
+    pub fn nodes(&self) -> Result<usize> {
+        self.device.bind_to_thread()?;
+        Ok(cu::graph::get_nodes(&self.graph)?.len())
+    }
+}
+
+impl Drop for Graph {
+    fn drop(&mut self) {
+        let _ = self.device.bind_to_thread();
+        let _ = cu::graph::exec_destroy(self.exec);
+        let _ = cu::graph::destroy(self.graph);
+        release(&self.device, &self.stream, std::mem::take(&mut self.blocks));
+    }
+}
+
+/// Free `blocks`, once the work enqueued on `stream` until now has completed.
+fn release(device: &Device, stream: &Stream, blocks: Vec<Block>) {
+    let _ = device.bind_to_thread();
+
+    for block in blocks {
+        device.allocator().record_stream(&block, stream);
+        device.allocator().free(block);
+    }
+}
+
+/// Capture the work `f` enqueues on `stream` into a graph, on the device of `stream`,
+/// with the blocks freed on the device meanwhile. The capture is always ended, so a
+/// failing `f` leaves the stream usable.
+fn record(stream: &Stream, f: impl FnOnce() -> Result<()>) -> Result<(Arc<Device>, cu::Graph, Vec<Block>)> {
+    let device = match stream.device() {
+        Some(device) => device.clone(),
+        None => return Err(anyhow!("The null stream cannot be captured! Create a stream with Device::fork.")),
+    };
+
+    device.bind_to_thread()?;
+    cu::stream::begin_capture(&stream.stream, cu::StreamCaptureMode::THREAD_LOCAL)?;
+    device.allocator().pin();
+
+    let out = f();
+    let blocks = device.allocator().unpin();
+
+    // `f` may have bound another context, which must not end the capture.
+    let graph = device.bind_to_thread()
+        .and_then(|_| cu::stream::end_capture(&stream.stream));
+
+    match (out, graph) {
+        (Ok(()), Ok(graph)) => Ok((device, graph, blocks)),
+        (out, graph) => {
+            if let Ok(graph) = &graph {
+                let _ = cu::graph::destroy(*graph);
+            }
+
+            // The work captured until then was never run, so its memory can be reused.
+            release(&device, stream, blocks);
+            Err(out.and(graph.map(|_| ())).unwrap_err())
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! # Device Discovery
+//!
+//! The properties of every device in the system, and the policies that pick
+//! one of them. The `GT_DEVICE` environment variable overrides the policy
+//! of `Device::new`, as an ordinal, `memory` or `capability`.
+
+use anyhow::{Result, anyhow};
+
+use super::cu;
+
+/// The environment variable that overrides the policy of `Device::new`.
+pub const DEVICE_ENV: &str = "GT_DEVICE";
+
+/// The properties of a device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub ordinal: usize,
+    pub name: String,
+    /// Formatted like `nvidia-smi`, as `GPU-xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+    pub uuid: String,
+    pub pci_bus_id: String,
+    pub total_memory: usize,
+    pub free_memory: usize,
+    /// The compute capability, as `(major, minor)`.
+    pub compute_capability: (u32, u32),
+    pub multiprocessors: u32,
+    pub max_threads_per_block: u32,
+    /// Shared memory available per block, in bytes.
+    pub shared_memory_per_block: usize,
+}
+
+impl DeviceInfo {
+    /// Query the properties of the device at `ordinal`. The free memory is read from
+    /// its primary context, which is retained for the query if nothing holds it yet.
+    pub fn query(ordinal: usize) -> Result<Self> {
+        Self::query_with(ordinal, true)
+    }
+
+    /// Query the properties of the device at `ordinal`, leaving the free memory at 0 
+    /// unless `free` is set, so its primary context is not touched.
+    fn query_with(ordinal: usize, free: bool) -> Result<Self> {
+        let device = cu::device::get(ordinal)?;
+        let attribute = |attr| cu::device::get_attribute(attr, &device).map(|v| v as u32);
+
+        Ok(Self {
+            ordinal,
+            name: cu::device::get_name(&device)?,
+            uuid: format_uuid(&cu::device::get_uuid(&device)?),
+            pci_bus_id: cu::device::get_pci_bus_id(&device)?,
+            total_memory: cu::device::total_mem(&device)?,
+            free_memory: if free { free_memory(&device)? } else { 0 },
+            compute_capability: (
+                attribute(cu::DeviceAttribute::COMPUTE_CAPABILITY_MAJOR)?,
+                attribute(cu::DeviceAttribute::COMPUTE_CAPABILITY_MINOR)?,
+            ),
+            multiprocessors: attribute(cu::DeviceAttribute::MULTIPROCESSOR_COUNT)?,
+            max_threads_per_block: attribute(cu::DeviceAttribute::MAX_THREADS_PER_BLOCK)?,
+            shared_memory_per_block: attribute(cu::DeviceAttribute::MAX_SHARED_MEMORY_PER_BLOCK)? as usize,
+        })
+    }
+
+    /// The compute capability, as `major * 10 + minor`.
+    pub fn sm(&self) -> u32 {
+        self.compute_capability.0 * 10 + self.compute_capability.1
+    }
+}
+
+/// The properties of every device, by ordinal.
+pub fn devices() -> Result<Vec<DeviceInfo>> {
+    query_all(true)
+}
+
+fn query_all(free: bool) -> Result<Vec<DeviceInfo>> {
+    cu::init()?;
+
+    (0..cu::device::get_count()?)
+        .map(|ordinal| DeviceInfo::query_with(ordinal, free))
+        .collect()
+}
+
+/// How to pick a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DevicePolicy {
+    /// The device at the ordinal.
+    Ordinal(usize),
+    /// The device with the most free memory.
+    MostFreeMemory,
+    /// The device with the highest compute capability, then the most multiprocessors.
+    HighestCapability,
+}
+
+impl DevicePolicy {
+    /// Parse a policy, as an ordinal, `memory` or `capability`.
+    pub fn parse(policy: &str) -> Result<Self> {
+        match policy.trim() {
+            "memory" => Ok(Self::MostFreeMemory),
+            "capability" => Ok(Self::HighestCapability),
+            policy => policy.parse()
+                .map(Self::Ordinal)
+                .map_err(|_| anyhow!("Unknown device policy \"{}\"! Expected an ordinal, memory or capability.", policy)),
+        }
+    }
+
+    /// The policy set by `GT_DEVICE`, if it is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(DEVICE_ENV) {
+            Ok(policy) => Self::parse(&policy).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The ordinal of the device this policy picks out of every device. The free memory 
+    /// of each device is only queried for `MostFreeMemory`, as it retains their contexts.
+    pub fn find(&self) -> Result<usize> {
+        match self {
+            Self::Ordinal(ordinal) => Ok(*ordinal),
+            Self::MostFreeMemory => self.select(&query_all(true)?),
+            Self::HighestCapability => self.select(&query_all(false)?),
+        }
+    }
+
+    /// The ordinal of the device this policy picks out of `devices`. Ties go to the lowest ordinal.
+    pub fn select(&self, devices: &[DeviceInfo]) -> Result<usize> {
+        let best = match self {
+            Self::Ordinal(ordinal) => devices.iter().find(|info| info.ordinal == *ordinal),
+            Self::MostFreeMemory => devices.iter()
+                .rev()
+                .max_by_key(|info| info.free_memory),
+            Self::HighestCapability => devices.iter()
+                .rev()
+                .max_by_key(|info| (info.compute_capability, info.multiprocessors)),
+        };
+
+        best.map(|info| info.ordinal).ok_or(match self {
+            Self::Ordinal(ordinal) => anyhow!("No device at ordinal {}, there are {} devices!", ordinal, devices.len()),
+            _ => anyhow!("No devices found!"),
+        })
+    }
+}
+
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+
+    format!("GPU-{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// The free memory of `device`, from its primary context. The context
+/// bound to the current thread before the query is bound again after it.
+fn free_memory(device: &cu::Device) -> Result<usize> {
+    let previous = cu::ctx::try_get_current()?;
+    let context = cu::primary::retain(device)?;
+
+    let free = cu::ctx::set_current(&context).and_then(|_| cu::mem::get_info());
+
+    let restored = match previous {
+        Some(previous) => cu::ctx::set_current(&previous),
+        None => cu::ctx::set_current(&cu::Context { ptr: std::ptr::null_mut() }),
+    };
+
+    // The retain is released even if the previous context could not be bound again.
+    let released = cu::primary::release(device);
+
+    restored?;
+    released?;
+    Ok(free?.0)
+}
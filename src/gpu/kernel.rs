@@ -1,32 +1,150 @@
 
 use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::sync::Arc;
 
 use as_slice::AsSlice;
+use half::{f16, bf16};
 
 use super::cu;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use super::stream::Stream;
+use super::context::Module;
+use super::ptx::{Param, ParamKind};
+use crate::storage::Element;
+
+/// The block size picked by the occupancy API, and the smallest grid that fills the device with it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LaunchConfig {
+    pub block: u32,
+    pub max_grid: u32,
+}
+
+impl LaunchConfig {
+    /// A grid with a thread for each of `len` elements, capped at `max_grid`
+    /// blocks. Kernels loop with a stride of the grid over any elements left.
+    pub fn grid(&self, len: usize) -> u32 {
+        let blocks = len.div_ceil(self.block.max(1) as usize);
+        blocks.clamp(1, self.max_grid.max(1) as usize) as u32
+    }
+}
 
 #[derive(Clone)]
 pub struct Kernel {
     name: String,
     kernel: cu::Function,
+    params: Vec<Param>,
+    /// The module of the kernel, kept loaded as long as the kernel lives.
+    module: Option<Arc<Module>>,
 }
 
 impl Kernel {
-    pub fn from(name: String, kernel: cu::Function) -> Self {
+    /// A kernel taking `params`, as parsed from the `.entry` of its PTX.
+    pub fn from(name: String, kernel: cu::Function, params: Vec<Param>) -> Self {
         Self {
-            name, kernel,
+            name, kernel, params, module: None,
+        }
+    }
+
+    /// Keep `module` loaded as long as the kernel lives, and bind its context before launches.
+    pub(crate) fn with_module(mut self, module: &Arc<Module>) -> Self {
+        self.module = Some(module.clone());
+        self
+    }
+
+    fn bind(&self) -> Result<()> {
+        match self.module.as_ref() {
+            Some(module) => module.context.bind(),
+            None => Ok(()),
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    /// Check that arguments passed as `args` match the parameters of the kernel.
+    pub fn check_args(&self, args: &[Param]) -> Result<()> {
+        if args.len() != self.params.len() {
+            return Err(anyhow!("Kernel {} takes {} arguments, but {} were passed!", self.name, self.params.len(), args.len()))
+        }
+
+        for (i, (param, arg)) in self.params.iter().zip(args).enumerate() {
+            if !param.accepts(arg) {
+                return Err(anyhow!("Argument {} of kernel {} is a {}, but a {} was passed!", i, self.name, param, arg))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The launch configuration with the highest occupancy, cached by name in the
+    /// module of the kernel after the first call, so it is dropped when the module is unloaded.
+    pub fn config(&self) -> Result<LaunchConfig> {
+        let module = match self.module.as_ref() {
+            Some(module) => module,
+            None => return self.occupancy(),
+        };
+
+        if let Some(config) = module.configs.read().unwrap().get(&self.name) {
+            return Ok(*config)
+        }
+
+        let config = self.occupancy()?;
+        module.configs.write().unwrap().insert(self.name.clone(), config);
+
+        Ok(config)
+    }
+
+    fn occupancy(&self) -> Result<LaunchConfig> {
+        let (max_grid, block) = cu::occupancy::max_potential_block_size(&self.kernel, 0, 0)?;
+        Ok(LaunchConfig { block, max_grid })
+    }
+
+    /// Launch over `len` elements with the configuration from `config()`. The length is 
+    /// passed as a trailing `size_t` argument, so the kernel can loop with a grid stride 
+    /// and check its bounds. Does nothing if `len` is 0.
+    pub fn launch_n(&self, len: usize, stream: &Stream, params: impl ToKernelParams) -> Result<()> {
+        if len == 0 {
+            return Ok(())
+        }
+
+        let mut signature = params.signature();
+        signature.push(u64::PARAM);
+        self.check_args(&signature)?;
+
+        self.bind()?;
+
+        let config = self.config()?;
+        let n = len as u64;
+        let mut args = params.to_kernel_params().as_slice().to_vec();
+        args.push(to_c_void(&n));
+
+        cu::launch::kernel(
+            &self.kernel, 
+            (config.grid(len), 1, 1), 
+            (config.block, 1, 1), 
+            0, 
+            &stream.stream, 
+            &args, 
+            &[]
+        )
+    }
+
     pub fn launch(
         &self, 
         grid: impl Into<GridDim>, 
         block: impl Into<BlockDim>, 
-        stream: Stream, 
+        stream: &Stream, 
         params: impl ToKernelParams
     ) -> Result<()> {
+        self.check_args(&params.signature())?;
+        self.bind()?;
+
         let grid = grid.into();
         let block = block.into();
 
@@ -111,120 +229,96 @@ impl From<[u32; 3]> for BlockDim {
 }
 
 
-pub trait ToKernelParams {
-    type Output: AsSlice<Element=*mut c_void>;
-
-    fn to_kernel_params(&self) -> Self::Output;
+/// A type that can be passed as an argument to a kernel.
+pub trait KernelArg {
+    /// The kind and size of the parameter this argument is passed as.
+    const PARAM: Param;
 }
 
-impl<T> ToKernelParams for (T,) {
-    type Output = [*mut c_void; 1];
-
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0)
-        ]
-    }
+/// A device pointer to elements of `T`, passed to kernels.
+#[repr(transparent)]
+#[derive(Copy, Clone)]
+pub struct Ptr<T: Element> {
+    ptr: cu::DevicePtr,
+    _type: PhantomData<T>,
 }
 
-impl<T1,T2> ToKernelParams for (T1,T2) {
-    type Output = [*mut c_void; 2];
-
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0),
-            to_c_void(&self.1)
-        ]
+impl<T: Element> Ptr<T> {
+    /// # Safety
+    /// 
+    /// `ptr` must point to device memory holding elements of `T`.
+    pub unsafe fn new(ptr: cu::DevicePtr) -> Self {
+        Self {
+            ptr,
+            _type: PhantomData,
+        }
     }
-}
 
-impl<T1,T2,T3> ToKernelParams for (T1,T2,T3) {
-    type Output = [*mut c_void; 3];
-
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0),
-            to_c_void(&self.1),
-            to_c_void(&self.2),
-        ]
+    pub fn as_ptr(&self) -> cu::DevicePtr {
+        self.ptr
     }
 }
 
-impl<T1,T2,T3,T4> ToKernelParams for (T1,T2,T3,T4) {
-    type Output = [*mut c_void; 4];
-
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0),
-            to_c_void(&self.1),
-            to_c_void(&self.2),
-            to_c_void(&self.3),
-        ]
-    }
+impl<T: Element> KernelArg for Ptr<T> {
+    const PARAM: Param = Param { kind: ParamKind::Pointer, size: 8, align: std::mem::size_of::<T>() };
 }
 
-impl<T1,T2,T3,T4,T5> ToKernelParams for (T1,T2,T3,T4,T5) {
-    type Output = [*mut c_void; 5];
-
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0),
-            to_c_void(&self.1),
-            to_c_void(&self.2),
-            to_c_void(&self.3),
-            to_c_void(&self.4),
-        ]
-    }
+macro_rules! scalar_arg {
+    ($kind:ident, $($t:ty),*) => {
+        $(
+            impl KernelArg for $t {
+                const PARAM: Param = Param { kind: ParamKind::$kind, size: std::mem::size_of::<$t>(), align: 0 };
+            }
+        )*
+    };
 }
 
-impl<T1,T2,T3,T4,T5,T6> ToKernelParams for (T1,T2,T3,T4,T5,T6) {
-    type Output = [*mut c_void; 6];
+scalar_arg!(Float, f64, f32, f16, bf16);
+scalar_arg!(Int, i64, u64, i32, u32, usize);
 
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0),
-            to_c_void(&self.1),
-            to_c_void(&self.2),
-            to_c_void(&self.3),
-            to_c_void(&self.4),
-            to_c_void(&self.5),
-        ]
-    }
-}
+pub trait ToKernelParams {
+    type Output: AsSlice<Element=*mut c_void>;
 
-impl<T1,T2,T3,T4,T5,T6,T7> ToKernelParams for (T1,T2,T3,T4,T5,T6,T7) {
-    type Output = [*mut c_void; 7];
+    fn to_kernel_params(&self) -> Self::Output;
 
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0),
-            to_c_void(&self.1),
-            to_c_void(&self.2),
-            to_c_void(&self.3),
-            to_c_void(&self.4),
-            to_c_void(&self.5),
-            to_c_void(&self.6),
-        ]
-    }
+    /// The parameter each argument is passed as.
+    fn signature(&self) -> Vec<Param>;
 }
 
-impl<T1,T2,T3,T4,T5,T6,T7,T8> ToKernelParams for (T1,T2,T3,T4,T5,T6,T7,T8) {
-    type Output = [*mut c_void; 8];
+/// ToKernelParams for the tuples of up to 12 arguments, each given as `len => type index, ..`.
+macro_rules! tuple_params {
+    ($($len:literal => $($t:ident $i:tt),+;)*) => {
+        $(
+            impl<$($t: KernelArg),+> ToKernelParams for ($($t,)+) {
+                type Output = [*mut c_void; $len];
+
+                fn to_kernel_params(&self) -> Self::Output {
+                    [$(to_c_void(&self.$i)),+]
+                }
+
+                fn signature(&self) -> Vec<Param> {
+                    vec![$($t::PARAM),+]
+                }
+            }
+        )*
+    };
+}
 
-    fn to_kernel_params(&self) -> Self::Output {
-        [
-            to_c_void(&self.0),
-            to_c_void(&self.1),
-            to_c_void(&self.2),
-            to_c_void(&self.3),
-            to_c_void(&self.4),
-            to_c_void(&self.5),
-            to_c_void(&self.6),
-            to_c_void(&self.7),
-        ]
-    }
+tuple_params! {
+    1 => T1 0;
+    2 => T1 0, T2 1;
+    3 => T1 0, T2 1, T3 2;
+    4 => T1 0, T2 1, T3 2, T4 3;
+    5 => T1 0, T2 1, T3 2, T4 3, T5 4;
+    6 => T1 0, T2 1, T3 2, T4 3, T5 4, T6 5;
+    7 => T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6;
+    8 => T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7;
+    9 => T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8;
+    10 => T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8, T10 9;
+    11 => T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8, T10 9, T11 10;
+    12 => T1 0, T2 1, T3 2, T4 3, T5 4, T6 5, T7 6, T8 7, T9 8, T10 9, T11 10, T12 11;
 }
 
 fn to_c_void<T>(item: &T) -> *mut c_void {
     item as *const T as *mut c_void
-}
\ No newline at end of file
+}
@@ -0,0 +1,104 @@
+// Softmax cross-entropy over the channel axis of the logits in x, in NCHW,
+// against the class of every (n, h, w) in target. Every loop runs over the
+// len pixels, and an out of range class makes the loss NaN.
+
+// The offset of (n, 0, h, w) for the pixel at index.
+__device__ size_t pixel_offset(size_t index, size_t classes, size_t hw) {
+    return index / hw * classes * hw + index % hw;
+}
+
+// The sum of v over the threads of a block of up to 256 threads, a power of two.
+__device__ double block_sum(double v) {
+    __shared__ double partial[256];
+
+    partial[threadIdx.x] = v;
+    __syncthreads();
+
+    for (unsigned int stride = blockDim.x / 2; stride > 0; stride >>= 1) {
+        if (threadIdx.x < stride) {
+            partial[threadIdx.x] += partial[threadIdx.x + stride];
+        }
+        __syncthreads();
+    }
+
+    return partial[0];
+}
+
+// Writes the max of the logits at offset into max, and returns the sum of exp(x - max).
+template <typename T>
+__device__ double exp_sum(const T* x, size_t offset, size_t classes, size_t hw, double* max) {
+    *max = -INFINITY;
+    for (size_t k = 0; k < classes; k++) {
+        *max = fmax(*max, (double)x[offset + k * hw]);
+    }
+
+    double sum = 0.0;
+    for (size_t k = 0; k < classes; k++) {
+        sum += exp((double)x[offset + k * hw] - *max);
+    }
+
+    return sum;
+}
+
+// y[0] = mean(log(sum(exp(x))) - x[target]), in a single block
+template <typename T, typename I>
+__device__ void cross_entropy(const T* x, const I* target, T* y, size_t classes, size_t hw, size_t len) {
+    double loss = 0.0;
+
+    for (size_t index = threadIdx.x; index < len; index += blockDim.x) {
+        size_t offset = pixel_offset(index, classes, hw);
+        size_t cls = (size_t)target[index];
+        double max;
+        double sum = exp_sum(x, offset, classes, hw, &max);
+
+        loss += cls < classes ? log(sum) + max - (double)x[offset + cls * hw] : nan("");
+    }
+
+    loss = block_sum(loss);
+
+    if (threadIdx.x == 0) {
+        y[0] = T(loss / len);
+    }
+}
+
+// ty[0] = mean((softmax(x) - onehot(target)) . tx), in a single block
+template <typename T, typename I>
+__device__ void cross_entropy_tangent(const T* x, const I* target, const T* tx, T* ty, size_t classes, size_t hw, size_t len) {
+    double tangent = 0.0;
+
+    for (size_t index = threadIdx.x; index < len; index += blockDim.x) {
+        size_t offset = pixel_offset(index, classes, hw);
+        size_t cls = (size_t)target[index];
+        double max;
+        double sum = exp_sum(x, offset, classes, hw, &max);
+
+        for (size_t k = 0; k < classes; k++) {
+            size_t i = offset + k * hw;
+            tangent += (exp((double)x[i] - max) / sum - (k == cls ? 1.0 : 0.0)) * (double)tx[i];
+        }
+    }
+
+    tangent = block_sum(tangent);
+
+    if (threadIdx.x == 0) {
+        ty[0] = T(tangent / len);
+    }
+}
+
+// g += (softmax(x) - onehot(target)) * gy[0] / len
+template <typename T, typename I>
+__device__ void cross_entropy_wrt(const T* x, const I* target, const T* gy, T* g, size_t classes, size_t hw, size_t len) {
+    double scale = (double)gy[0] / len;
+
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        size_t offset = pixel_offset(index, classes, hw);
+        size_t cls = (size_t)target[index];
+        double max;
+        double sum = exp_sum(x, offset, classes, hw, &max);
+
+        for (size_t k = 0; k < classes; k++) {
+            size_t i = offset + k * hw;
+            g[i] = T((double)g[i] + (exp((double)x[i] - max) / sum - (k == cls ? 1.0 : 0.0)) * scale);
+        }
+    }
+}
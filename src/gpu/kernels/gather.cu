@@ -0,0 +1,167 @@
+// Indexing along an axis of x, of shape [n, c, h, w], with positions read from an index.
+// Positions out of range are read as zero, and nothing is written to them.
+
+// Add value to *address atomically. There is no atomicAdd of a double before sm_60.
+template <typename T>
+__device__ T atomic_add(T* address, T value) {
+    return atomicAdd(address, value);
+}
+
+template <>
+__device__ double atomic_add<double>(double* address, double value) {
+    unsigned long long* bits = (unsigned long long*)address;
+    unsigned long long old = *bits;
+    unsigned long long assumed;
+
+    do {
+        assumed = old;
+        old = atomicCAS(bits, assumed, __double_as_longlong(__longlong_as_double(assumed) + value));
+    } while (assumed != old);
+
+    return __longlong_as_double(old);
+}
+
+// The offset into x of the element at i of an index of shape [.., ic, ih, iw], with
+// its coordinate along axis read from the index, or -1 if it is out of range.
+template <typename I>
+__device__ long long position(size_t i, const I* index, const size_t* dims, size_t ic, size_t ih, size_t iw, size_t axis) {
+    size_t coords[4] = {i / (ic * ih * iw), i / (ih * iw) % ic, i / iw % ih, i % iw};
+    size_t p = (size_t)index[i];
+
+    if (p >= dims[axis]) {
+        return -1;
+    }
+
+    coords[axis] = p;
+    return ((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3];
+}
+
+// The offset into x of the element at i of y, the slices of x along axis at the k positions 
+// of the index, or -1 if the position is out of range.
+template <typename I>
+__device__ long long selected(size_t i, const I* index, const size_t* dims, size_t k, size_t axis) {
+    size_t shape[4] = {dims[0], dims[1], dims[2], dims[3]};
+    size_t coords[4];
+
+    shape[axis] = k;
+
+    for (int a = 3; a >= 0; a--) {
+        coords[a] = i % shape[a];
+        i /= shape[a];
+    }
+
+    size_t p = (size_t)index[coords[axis]];
+
+    if (p >= dims[axis]) {
+        return -1;
+    }
+
+    coords[axis] = p;
+    return ((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3];
+}
+
+// y[i] = x[position(i)]
+template <typename T, typename I>
+__device__ void gather(const T* x, const I* index, T* y, size_t n, size_t c, size_t h, size_t w, size_t ic, size_t ih, size_t iw, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = position(i, index, dims, ic, ih, iw, axis);
+        y[i] = p < 0 ? T(0.0) : x[p];
+    }
+}
+
+// y[i] += x[position(i)]
+template <typename T, typename I>
+__device__ void gather_add(const T* x, const I* index, T* y, size_t n, size_t c, size_t h, size_t w, size_t ic, size_t ih, size_t iw, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = position(i, index, dims, ic, ih, iw, axis);
+
+        if (p >= 0) {
+            y[i] = y[i] + x[p];
+        }
+    }
+}
+
+// y[position(i)] = x[i]
+template <typename T, typename I>
+__device__ void scatter(const T* x, const I* index, T* y, size_t n, size_t c, size_t h, size_t w, size_t ic, size_t ih, size_t iw, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = position(i, index, dims, ic, ih, iw, axis);
+
+        if (p >= 0) {
+            y[p] = x[i];
+        }
+    }
+}
+
+// y[position(i)] += x[i], summing repeated positions
+template <typename T, typename I>
+__device__ void scatter_add(const T* x, const I* index, T* y, size_t n, size_t c, size_t h, size_t w, size_t ic, size_t ih, size_t iw, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = position(i, index, dims, ic, ih, iw, axis);
+
+        if (p >= 0) {
+            atomic_add(&y[p], x[i]);
+        }
+    }
+}
+
+// mask[position(i)] = true
+template <typename T, typename I>
+__device__ void scatter_mark(const I* index, bool* mask, size_t n, size_t c, size_t h, size_t w, size_t ic, size_t ih, size_t iw, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = position(i, index, dims, ic, ih, iw, axis);
+
+        if (p >= 0) {
+            mask[p] = true;
+        }
+    }
+}
+
+// y[i] = x[selected(i)]
+template <typename T, typename I>
+__device__ void index_select(const T* x, const I* index, T* y, size_t n, size_t c, size_t h, size_t w, size_t k, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = selected(i, index, dims, k, axis);
+        y[i] = p < 0 ? T(0.0) : x[p];
+    }
+}
+
+// y[i] += x[selected(i)]
+template <typename T, typename I>
+__device__ void index_select_add(const T* x, const I* index, T* y, size_t n, size_t c, size_t h, size_t w, size_t k, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = selected(i, index, dims, k, axis);
+
+        if (p >= 0) {
+            y[i] = y[i] + x[p];
+        }
+    }
+}
+
+// g[selected(i)] += gy[i], only touching the selected slices
+template <typename T, typename I>
+__device__ void index_select_wrt(const T* gy, const I* index, T* g, size_t n, size_t c, size_t h, size_t w, size_t k, size_t axis, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        long long p = selected(i, index, dims, k, axis);
+
+        if (p >= 0) {
+            atomic_add(&g[p], gy[i]);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// y = mask ? value : x
+template <typename T>
+__device__ void masked_fill(const T* x, const bool* mask, T* y, double value, size_t len) {
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        y[index] = mask[index] ? T(value) : x[index];
+    }
+}
+
+// g += mask ? 0 : gy
+template <typename T>
+__device__ void masked_fill_wrt(const T* gy, const bool* mask, T* g, size_t len) {
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        if (!mask[index]) {
+            g[index] = g[index] + gy[index];
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// y = x1 * x2
+template <typename T>
+__device__ void mul(const T* x1, const T* x2, T* y, size_t len) {
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        y[index] = x1[index] * x2[index];
+    }
+}
+
+// y += x1 * x2
+template <typename T>
+__device__ void mul_add(const T* x1, const T* x2, T* y, size_t len) {
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        y[index] = y[index] + x1[index] * x2[index];
+    }
+}
@@ -0,0 +1,76 @@
+// Padding of the H and W axes. Modes are 0 for constant, 1 for reflect and 2 for replicate.
+
+// The index into an input axis of size for i, the index into the padded 
+// axis minus the padding, or -1 in the border of a constant pad.
+__device__ long long pad_source(long long i, long long size, unsigned int mode) {
+    long long last = size - 1;
+
+    switch (mode) {
+        case 1: return i < 0 ? -i : (i > last ? 2 * last - i : i);
+        case 2: return i < 0 ? 0 : (i > last ? last : i);
+        default: return i >= 0 && i <= last ? i : -1;
+    }
+}
+
+// Writes the ranges [lo, hi] of the padded axis of size out whose source is i, and returns
+// how many there are. Reflect padding is smaller than the input, so there are at most three.
+__device__ int pad_targets(long long i, long long size, long long pad, long long out, unsigned int mode, long long* lo, long long* hi) {
+    long long last = size - 1;
+    int count = 1;
+
+    lo[0] = i + pad;
+    hi[0] = i + pad;
+
+    if (mode == 2) {
+        lo[0] = i == 0 ? 0 : lo[0];
+        hi[0] = i == last ? out - 1 : hi[0];
+    }
+
+    if (mode == 1 && i >= 1 && i <= pad) {
+        lo[count] = hi[count] = pad - i;
+        count++;
+    }
+
+    if (mode == 1 && i < last && pad + 2 * last - i < out) {
+        lo[count] = hi[count] = pad + 2 * last - i;
+        count++;
+    }
+
+    return count;
+}
+
+// y = x of shape [.., h, w] padded into [.., oh, ow], starting at (top, left)
+template <typename T>
+__device__ void pad(const T* x, T* y, size_t h, size_t w, size_t top, size_t left, size_t oh, size_t ow, unsigned int mode, double value, size_t len) {
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        size_t nc = index / (oh * ow);
+        long long i = pad_source((long long)(index / ow % oh) - (long long)top, h, mode);
+        long long j = pad_source((long long)(index % ow) - (long long)left, w, mode);
+
+        y[index] = i < 0 || j < 0 ? T(value) : x[(nc * h + i) * w + j];
+    }
+}
+
+// g += the sum of gy over every element of y copied from each element of x
+template <typename T>
+__device__ void pad_wrt(const T* gy, T* g, size_t h, size_t w, size_t top, size_t left, size_t oh, size_t ow, unsigned int mode, size_t len) {
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        size_t nc = index / (h * w);
+        long long ilo[3], ihi[3], jlo[3], jhi[3];
+        int rows = pad_targets(index / w % h, h, top, oh, mode, ilo, ihi);
+        int cols = pad_targets(index % w, w, left, ow, mode, jlo, jhi);
+        double sum = 0.0;
+
+        for (int r = 0; r < rows; r++) {
+            for (int c = 0; c < cols; c++) {
+                for (long long i = ilo[r]; i <= ihi[r]; i++) {
+                    for (long long j = jlo[c]; j <= jhi[c]; j++) {
+                        sum += (double)gy[(nc * oh + i) * ow + j];
+                    }
+                }
+            }
+        }
+
+        g[index] = T((double)g[index] + sum);
+    }
+}
@@ -0,0 +1,33 @@
+// The offset in x, of shape dims, of the element at index of x permuted by axes,
+// where axis k of the permuted tensor is axis (axes >> 2k) & 3 of x.
+__device__ size_t permuted_offset(size_t index, const size_t* dims, unsigned int axes) {
+    size_t coords[4];
+
+    for (int k = 3; k >= 0; k--) {
+        unsigned int axis = (axes >> (2 * k)) & 3;
+        coords[axis] = index % dims[axis];
+        index /= dims[axis];
+    }
+
+    return ((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3];
+}
+
+// y = x of shape [n, c, h, w] with its axes reordered
+template <typename T>
+__device__ void permute(const T* x, T* y, size_t n, size_t c, size_t h, size_t w, unsigned int axes, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        y[index] = x[permuted_offset(index, dims, axes)];
+    }
+}
+
+// y += x of shape [n, c, h, w] with its axes reordered
+template <typename T>
+__device__ void permute_add(const T* x, T* y, size_t n, size_t c, size_t h, size_t w, unsigned int axes, size_t len) {
+    size_t dims[4] = {n, c, h, w};
+
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        y[index] = y[index] + x[permuted_offset(index, dims, axes)];
+    }
+}
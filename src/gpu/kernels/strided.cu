@@ -0,0 +1,51 @@
+// Copies through a layout of shape [n, c, h, w], whose element at (n, c, h, w) lies
+// at offset + n * sn + c * sc + h * sh + w * sw in the strided tensor.
+
+// Add value to *address atomically. There is no atomicAdd of a double before sm_60.
+template <typename T>
+__device__ T atomic_add(T* address, T value) {
+    return atomicAdd(address, value);
+}
+
+template <>
+__device__ double atomic_add<double>(double* address, double value) {
+    unsigned long long* bits = (unsigned long long*)address;
+    unsigned long long old = *bits;
+    unsigned long long assumed;
+
+    do {
+        assumed = old;
+        old = atomicCAS(bits, assumed, __double_as_longlong(__longlong_as_double(assumed) + value));
+    } while (assumed != old);
+
+    return __longlong_as_double(old);
+}
+
+// The offset in the strided tensor of the element at i, in NCHW order.
+__device__ size_t strided_offset(size_t i, size_t c, size_t h, size_t w, size_t sn, size_t sc, size_t sh, size_t sw, size_t offset) {
+    return offset + i / (c * h * w) * sn + i / (h * w) % c * sc + i / w % h * sh + i % w * sw;
+}
+
+// y[i] = x[strided_offset(i)]
+template <typename T>
+__device__ void strided(const T* x, T* y, size_t n, size_t c, size_t h, size_t w, size_t sn, size_t sc, size_t sh, size_t sw, size_t offset, size_t len) {
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        y[i] = x[strided_offset(i, c, h, w, sn, sc, sh, sw, offset)];
+    }
+}
+
+// y[strided_offset(i)] = x[i]
+template <typename T>
+__device__ void strided_scatter(const T* x, T* y, size_t n, size_t c, size_t h, size_t w, size_t sn, size_t sc, size_t sh, size_t sw, size_t offset, size_t len) {
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        y[strided_offset(i, c, h, w, sn, sc, sh, sw, offset)] = x[i];
+    }
+}
+
+// y[strided_offset(i)] += x[i], atomically as elements can overlap
+template <typename T>
+__device__ void strided_add(const T* x, T* y, size_t n, size_t c, size_t h, size_t w, size_t sn, size_t sc, size_t sh, size_t sw, size_t offset, size_t len) {
+    for (size_t i = (size_t)blockIdx.x * blockDim.x + threadIdx.x; i < len; i += (size_t)blockDim.x * gridDim.x) {
+        atomic_add(&y[strided_offset(i, c, h, w, sn, sc, sh, sw, offset)], x[i]);
+    }
+}
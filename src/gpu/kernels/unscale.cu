@@ -0,0 +1,13 @@
+// x *= factor, setting found_inf[0] to 1 if any element of x was inf or NaN.
+template <typename T>
+__device__ void unscale(T* x, int* found_inf, double factor, size_t len) {
+    for (size_t index = (size_t)blockIdx.x * blockDim.x + threadIdx.x; index < len; index += (size_t)blockDim.x * gridDim.x) {
+        double v = (double)x[index];
+
+        if (!isfinite(v)) {
+            found_inf[0] = 1;
+        }
+
+        x[index] = T(v * factor);
+    }
+}
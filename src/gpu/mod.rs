@@ -1,12 +1,23 @@
 
 pub mod cu;
+pub mod allocator;
 pub mod device;
+mod context;
 pub mod kernel;
 pub mod stream;
+pub mod event;
+pub mod graph;
 pub mod modules;
+pub mod ptx;
+pub mod info;
+pub mod codegen;
 mod default;
 
 pub use default::get_default_device;
 pub use device::Device;
+pub use info::{DeviceInfo, DevicePolicy, devices};
 pub use stream::Stream;
-pub use kernel::Kernel;
\ No newline at end of file
+pub use event::Event;
+pub use graph::Graph;
+pub use kernel::{Kernel, KernelArg, Ptr};
+pub use allocator::{Allocator, MemoryStats};
\ No newline at end of file
@@ -1,11 +1,47 @@
+//! # Embedded Modules
+//!
+//! Every PTX compiled by the build script is embedded in the binary, keyed by
+//! module name, dtype and the SM level it was compiled for. Devices load them
+//! on first use, picking the highest SM level they support. The entries of every
+//! PTX are parsed once, the first time a kernel is looked up.
 
-use super::cu;
+use std::sync::OnceLock;
 
-const PTX_DIR: &str = env!("OUT_DIR");
+use hashbrown::HashMap;
 
-pub fn from_ptx(name: &str) -> cu::Module {
+use super::ptx::{self, Param};
 
-    let path = format!("{}{}.ptx", PTX_DIR, name);
+/// A compiled PTX embedded in the binary.
+pub struct Ptx {
+    pub module: &'static str,
+    pub dtype: &'static str,
+    pub sm: u32,
+    pub source: &'static str,
+}
 
-    todo!()
-}
\ No newline at end of file
+include!(concat!(env!("OUT_DIR"), "/ptx_registry.rs"));
+
+/// Every embedded PTX.
+pub fn all() -> &'static [Ptx] {
+    PTX
+}
+
+/// The entries of every embedded PTX, in the order of `all()`. A PTX that 
+/// fails to parse has no entries, so no kernel is ever looked up in it.
+fn entries() -> &'static [HashMap<String, Vec<Param>>] {
+    static ENTRIES: OnceLock<Vec<HashMap<String, Vec<Param>>>> = OnceLock::new();
+
+    ENTRIES.get_or_init(|| {
+        PTX.iter()
+            .map(|ptx| ptx::parse_entries(ptx.source).unwrap_or_default())
+            .collect()
+    })
+}
+
+/// The PTX of `module` declaring `kernel` with the highest SM level up to `sm`, and its entries.
+pub fn with_kernel(module: &str, kernel: &str, sm: u32) -> Option<(&'static Ptx, &'static HashMap<String, Vec<Param>>)> {
+    PTX.iter()
+        .zip(entries())
+        .filter(|(ptx, entries)| ptx.module == module && ptx.sm <= sm && entries.contains_key(kernel))
+        .max_by_key(|(ptx, _)| ptx.sm)
+}
@@ -0,0 +1,171 @@
+//! # PTX Entry Signatures
+//!
+//! The parameters of every `.entry` in a PTX module, parsed when the module is
+//! loaded so kernel launches can be checked against them. A parameter is declared
+//! as `.param .<type> [.ptr [.<space>] [.align <n>]] <name>[<count>]`, or with
+//! `.align <n>` before the type for aggregates passed by value. A pointer declared
+//! as a plain `.u64` is told apart from an integer by the `cvta.to.global` its
+//! value is converted with in the body of the entry.
+
+use anyhow::{Result, anyhow};
+use hashbrown::HashMap;
+
+/// What a kernel parameter holds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParamKind {
+    /// A `.ptr` parameter, or a `.u64` that the entry uses as a global address.
+    Pointer,
+    /// A `.u` or `.s` integer.
+    Int,
+    /// A `.f` float.
+    Float,
+    /// Untyped `.b` bits, or an aggregate.
+    Bits,
+}
+
+/// A parameter of a kernel entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Param {
+    pub kind: ParamKind,
+    pub size: usize,
+    /// For a pointer, the alignment of the elements it points to, or 0 if it is not declared.
+    pub align: usize,
+}
+
+impl Param {
+    /// Whether an argument of this kind and size can be passed to this parameter.
+    /// A pointer is only accepted if it points to elements aligned to a multiple of the declared alignment.
+    pub fn accepts(&self, arg: &Param) -> bool {
+        if self.size != arg.size {
+            return false
+        }
+
+        match (self.kind, arg.kind) {
+            (ParamKind::Bits, _) => true,
+            (ParamKind::Pointer, ParamKind::Pointer) => self.align == 0 || arg.align.is_multiple_of(self.align),
+            (param, arg) => param == arg,
+        }
+    }
+}
+
+impl std::fmt::Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            ParamKind::Pointer => "pointer",
+            ParamKind::Int => "integer",
+            ParamKind::Float => "float",
+            ParamKind::Bits => "value",
+        };
+
+        match (self.kind, self.align) {
+            (ParamKind::Pointer, align) if align > 0 => write!(f, "pointer to elements of {} bytes", align),
+            _ => write!(f, "{} of {} bytes", kind, self.size),
+        }
+    }
+}
+
+/// The parameters of every entry in `ptx`, keyed by entry name.
+pub fn parse_entries(ptx: &str) -> Result<HashMap<String, Vec<Param>>> {
+    let mut entries = HashMap::new();
+    let mut rest = ptx;
+
+    while let Some(start) = rest.find(".entry") {
+        rest = &rest[start + ".entry".len()..];
+
+        let open = rest.find('(')
+            .ok_or(anyhow!("Missing parameter list of a .entry in PTX!"))?;
+        let close = rest.find(')')
+            .ok_or(anyhow!("Unterminated parameter list of a .entry in PTX!"))?;
+
+        let name = rest[..open].trim().to_owned();
+        let declared = &rest[open + 1..close];
+        rest = &rest[close..];
+
+        let body = &rest[..rest.find(".entry").unwrap_or(rest.len())];
+        let params = declared
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty())
+            .map(|param| parse_param(param, body).map_err(|e| anyhow!("{} in entry {}!", e, name)))
+            .collect::<Result<Vec<_>>>()?;
+
+        entries.insert(name, params);
+    }
+
+    Ok(entries)
+}
+
+/// Whether the `.u64` parameter `name` is loaded and converted to a global address in `body`.
+fn is_address(name: &str, body: &str) -> bool {
+    let instructions: Vec<Vec<&str>> = body.split(';')
+        .map(|instruction| instruction.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|t| !t.is_empty() && !matches!(*t, ")" | "{" | "}") && !t.ends_with(':'))
+            .collect())
+        .collect();
+
+    let param = format!("[{}]", name);
+
+    instructions.iter()
+        .filter_map(|i| match i.as_slice() {
+            ["ld.param.u64", register, source] if *source == param => Some(*register),
+            _ => None,
+        })
+        .any(|register| instructions.iter().any(|i| matches!(i.as_slice(), ["cvta.to.global.u64", _, source] if *source == register)))
+}
+
+fn parse_param(param: &str, body: &str) -> Result<Param> {
+    let mut kind = None;
+    let mut size = 0;
+    let mut pointer = false;
+    let mut align = 0;
+    let mut count = 1;
+    let mut name = "";
+    let mut tokens = param.split_whitespace();
+
+    if tokens.next() != Some(".param") {
+        return Err(anyhow!("Expected .param in \"{}\"", param))
+    }
+
+    while let Some(token) = tokens.next() {
+        match token {
+            ".ptr" => pointer = true,
+            ".align" => {
+                let n = tokens.next().and_then(|n| n.parse().ok())
+                    .ok_or(anyhow!("Invalid alignment in \"{}\"", param))?;
+
+                if pointer {
+                    align = n;
+                }
+            },
+            ".global" | ".const" | ".shared" | ".local" => {},
+            _ if token.starts_with('.') => {
+                let (k, bits) = token.split_at(2);
+                kind = Some(match k {
+                    ".u" | ".s" => ParamKind::Int,
+                    ".f" => ParamKind::Float,
+                    ".b" => ParamKind::Bits,
+                    _ => return Err(anyhow!("Unknown parameter type {}", token)),
+                });
+                size = bits.parse::<usize>().map_err(|_| anyhow!("Unknown parameter type {}", token))? / 8;
+            }
+            _ => {
+                name = token;
+
+                if let Some(open) = token.find('[') {
+                    count = token[open + 1..].trim_end_matches(']').parse()
+                        .map_err(|_| anyhow!("Invalid array length in \"{}\"", param))?;
+                    kind = Some(ParamKind::Bits);
+                }
+            }
+        }
+    }
+
+    let kind = kind.ok_or(anyhow!("Missing type in \"{}\"", param))?;
+    let pointer = pointer || (kind == ParamKind::Int && size == 8 && is_address(name, body));
+
+    Ok(Param {
+        kind: if pointer { ParamKind::Pointer } else { kind },
+        size: size * count,
+        align,
+    })
+}
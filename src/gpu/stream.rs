@@ -1,15 +1,90 @@
+//! # Streams
+//!
+//! Work enqueued on a stream runs in order, while work on different streams
+//! may run concurrently. Streams are created with `Device::fork`, and are
+//! destroyed once the last clone of them is dropped, after their work is
+//! completed. The null stream is never destroyed.
+
+use std::sync::Arc;
+
+use anyhow::Result;
 
 use super::cu;
+use super::device::Device;
+use super::event::Event;
+
+/// A stream created on a device, destroyed when dropped.
+struct Owned {
+    stream: cu::Stream,
+    /// Keeps the context alive until the stream is destroyed.
+    device: Arc<Device>,
+}
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        let _ = self.device.bind_to_thread()
+            .and_then(|_| cu::stream::synchronize(&self.stream))
+            .and_then(|_| cu::stream::destroy(self.stream))
+            .and_then(|_| self.device.allocator().release(&self.stream));
+    }
+}
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Stream {
     pub(crate) stream: cu::Stream,
+    owned: Option<Arc<Owned>>,
 }
 
 impl Stream {
     pub fn null() -> Self {
         Self {
-            stream: cu::Stream::null()
+            stream: cu::Stream::null(),
+            owned: None,
         }
     }
-}
\ No newline at end of file
+
+    /// Take ownership of a stream created on `device`.
+    pub(crate) fn from_raw(stream: cu::Stream, device: &Arc<Device>) -> Self {
+        Self {
+            stream,
+            owned: Some(Arc::new(Owned { stream, device: device.clone() })),
+        }
+    }
+
+    /// Whether this is the null stream.
+    pub fn is_null(&self) -> bool {
+        self.owned.is_none()
+    }
+
+    /// The device this stream was created on, or None for the null stream.
+    pub(crate) fn device(&self) -> Option<&Arc<Device>> {
+        self.owned.as_ref().map(|owned| &owned.device)
+    }
+
+    /// Bind the context of the device of this stream to the calling thread. The 
+    /// null stream is that of the context already bound, so nothing is done for it.
+    pub(crate) fn bind(&self) -> Result<()> {
+        match self.device() {
+            Some(device) => device.bind_to_thread(),
+            None => Ok(()),
+        }
+    }
+
+    /// Make all work enqueued on this stream from now on wait until `event` is completed.
+    pub fn wait_event(&self, event: &Event) -> Result<()> {
+        self.bind()?;
+        cu::stream::wait_event(&self.stream, &event.event)
+    }
+
+    /// Wait until all work enqueued on this stream is completed.
+    pub fn synchronize(&self) -> Result<()> {
+        self.bind()?;
+        cu::stream::synchronize(&self.stream)
+    }
+
+    /// Whether all work enqueued on this stream is completed.
+    pub fn query(&self) -> Result<bool> {
+        self.bind()?;
+        cu::stream::query(&self.stream)
+    }
+}
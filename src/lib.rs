@@ -21,4 +21,1545 @@ fn test_gpu() {
     for i in array.iter() {
         assert_eq!(*i, 1.0);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_cast() {
+    use half::f16;
+    use crate::storage::Cpu;
+    use crate::storage::CastMode;
+    use crate::storage::Storage;
+
+    let mut cpu: Cpu<f32> = Cpu::new([4].into());
+    cpu.clone_from(&[1.0, 2049.0, 70000.0, -70000.0]);
+
+    // 2049 is halfway between 2048 and 2050, so it rounds to the even 2048.
+    let nearest: Cpu<f16> = cpu.cast();
+    assert_eq!(nearest.as_slice()[0], f16::from_f32(1.0));
+    assert_eq!(nearest.as_slice()[1], f16::from_f32(2048.0));
+    assert_eq!(nearest.as_slice()[2], f16::INFINITY);
+
+    let saturated: Cpu<f16> = cpu.cast_with(CastMode::Saturate);
+    assert_eq!(saturated.as_slice()[2], f16::MAX);
+    assert_eq!(saturated.as_slice()[3], f16::MIN);
+}
+
+#[test]
+fn test_integer_storage() {
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let mut cpu: Cpu<i64> = Cpu::new([3].into());
+    cpu.clone_from(&[-1, 0, 1 << 40]);
+    assert_eq!(cpu.as_slice(), &[-1, 0, 1 << 40]);
+
+    let mut gpu: Gpu<i32> = Gpu::new([3].into());
+    gpu.fill(-7);
+    assert!(gpu.as_ndarray().iter().all(|v| *v == -7));
+    gpu.clone_from(&[1, 2, 3]);
+    assert_eq!(gpu.as_ndarray().into_raw_vec(), vec![1, 2, 3]);
+
+    let mut mask: Gpu<bool> = Gpu::new([4].into());
+    assert!(mask.as_ndarray().iter().all(|v| !*v));
+    mask.fill(true);
+    assert!(mask.as_ndarray().iter().all(|v| *v));
+
+    let mut bytes: Gpu<u8> = Gpu::new([5].into());
+    bytes.fill(255);
+    assert!(bytes.as_ndarray().iter().all(|v| *v == 255));
+}
+
+#[test]
+fn test_cross_entropy() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, cross_entropy};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+
+    // Logits of shape [2, 3, 1, 1], so two pixels of three classes.
+    let logits = [1.0f32, 2.0, 0.5, -1.0, 0.0, 3.0];
+    let classes = [0i64, 2];
+
+    // The loss is the mean of log(sum(exp(x))) - x[class], and its gradient is (softmax - onehot) / 2.
+    let mut loss = 0.0;
+    let mut grad = vec![0.0; 6];
+
+    for n in 0..2 {
+        let x = &logits[n * 3..n * 3 + 3];
+        let sum: f32 = x.iter().map(|v| v.exp()).sum();
+        loss += (sum.ln() - x[classes[n] as usize]) / 2.0;
+
+        for k in 0..3 {
+            let onehot = if k == classes[n] as usize { 1.0 } else { 0.0 };
+            grad[n * 3 + k] = (x[k].exp() / sum - onehot) / 2.0;
+        }
+    }
+
+    let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5);
+
+    let targets: ScopeBuilder<Cpu<i64>> = ScopeBuilder::new(&device);
+    let builder: ScopeBuilder<Cpu<f32>> = ScopeBuilder::new(&device);
+    let x = builder.input([2, 3, 1, 1].into());
+    let y = cross_entropy(x.clone(), targets.input([2, 1, 1, 1].into()));
+    let (x, y) = (x.level(), y.level());
+    let (targets, mut scope) = (targets.build(), builder.build());
+
+    targets.value(0).clone_from(&classes);
+    scope.value(x).clone_from(&logits);
+    scope.forward().unwrap();
+    scope.gradient(y).fill(1.0);
+    scope.backward().unwrap();
+
+    assert!(close(scope.value(y).as_slice(), &[loss]));
+    assert!(close(scope.gradient(x).as_slice(), &grad));
+
+    targets.value(0).clone_from(&[0, 3]);
+    assert!(scope.forward().is_err());
+
+    let targets: ScopeBuilder<Gpu<i64>> = ScopeBuilder::new(&device);
+    let builder: ScopeBuilder<Gpu<f32>> = ScopeBuilder::new(&device);
+    let x = builder.input([2, 3, 1, 1].into());
+    let y = cross_entropy(x.clone(), targets.input([2, 1, 1, 1].into()));
+    let (x, y) = (x.level(), y.level());
+    let (targets, mut scope) = (targets.build(), builder.build());
+
+    targets.value(0).clone_from(&classes);
+    scope.value(x).clone_from(&logits);
+    scope.forward().unwrap();
+    scope.gradient(y).fill(1.0);
+    scope.backward().unwrap();
+
+    assert!(close(&scope.value(y).as_ndarray().into_raw_vec(), &[loss]));
+    assert!(close(&scope.gradient(x).as_ndarray().into_raw_vec(), &grad));
+
+    // A class out of range makes the loss NaN on a Gpu.
+    targets.value(0).clone_from(&[0, 3]);
+    scope.forward().unwrap();
+    assert!(scope.value(y).as_ndarray()[[0, 0, 0, 0]].is_nan());
+}
+
+#[test]
+fn test_loss_scaler() {
+    use crate::nn::LossScaler;
+
+    let mut scaler = LossScaler::new()
+        .with_scale(1024.0)
+        .with_interval(2);
+
+    assert!(!scaler.update(true));
+    assert_eq!(scaler.scale(), 512.0);
+
+    assert!(scaler.update(false));
+    assert!(scaler.update(false));
+    assert_eq!(scaler.scale(), 1024.0);
+}
+
+#[test]
+fn test_amp_step() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{AmpBuilder, mul};
+    use crate::storage::{Cpu, Gpu, Storage, Tensor};
+    use half::f16;
+
+    let device = get_default_device();
+
+    // The Gpu unscales the gradients with the kernel of the `unscale` module.
+    macro_rules! run {
+        ($storage:ident) => {{
+            let builder: AmpBuilder<$storage<f32>, $storage<f16>> = AmpBuilder::new(&device);
+            let w = builder.param([4].into());
+            let loss = mul(w.clone(), w);
+            builder.loss(&loss).unwrap();
+
+            let mut amp = builder.build().unwrap();
+
+            let sgd = |w: &mut Tensor<$storage<f32>>, g: &Tensor<$storage<f32>>| {
+                let updated: Vec<f32> = w.as_ndarray().iter().zip(g.as_ndarray().iter())
+                    .map(|(w, g)| w - 0.1 * g)
+                    .collect();
+                w.clone_from(&updated);
+            };
+
+            // The gradient of w * w is 2w, scaled by the default 2^15 in f16 and unscaled in f32.
+            amp.param(0).clone_from(&[0.1, 0.2, 0.3, 0.4]);
+            amp.forward().unwrap();
+            amp.backward().unwrap();
+            assert!(amp.step(sgd));
+            assert!(amp.param(0).as_ndarray().iter().zip([0.08, 0.16, 0.24, 0.32]).all(|(w, v)| (w - v).abs() < 1e-3));
+
+            // 2 * 200 * 2^15 overflows f16, so the step is skipped and the scale is halved.
+            amp.param(0).clone_from(&[200.0; 4]);
+            amp.forward().unwrap();
+            amp.backward().unwrap();
+            assert!(!amp.step(sgd));
+            assert_eq!(amp.scaler().scale(), 16384.0);
+            assert!(amp.param(0).as_ndarray().iter().all(|w| *w == 200.0));
+        }};
+    }
+
+    run!(Cpu);
+    run!(Gpu);
+}
+
+#[test]
+fn test_view() {
+    use crate::storage::Cpu;
+    use crate::storage::Storage;
+    use crate::storage::View;
+
+    let mut cpu: Cpu<f32> = Cpu::new([1, 2, 2, 3].into());
+    cpu.clone_from(&(0..12).map(|i| i as f32).collect::<Vec<_>>());
+
+    let view = View::new(&cpu)
+        .select(1, 1).unwrap()
+        .transpose(2, 3).unwrap();
+
+    assert!(!view.is_contiguous());
+    assert_eq!(view.contiguous().as_slice(), &[6.0, 9.0, 7.0, 10.0, 8.0, 11.0]);
+
+    let rows = View::new(&cpu).narrow(2, 1, 1).unwrap();
+    assert_eq!(rows.iter().collect::<Vec<_>>(), vec![3.0, 4.0, 5.0, 9.0, 10.0, 11.0]);
+    assert!(rows.reshape([6].into()).is_err());
+    let (start, end) = (2, 1);
+    assert!(View::new(&cpu).slice(3, start..end).is_err());
+}
+
+#[test]
+fn test_as_strided() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, as_strided};
+    use crate::storage::{Cpu, Gpu, Layout, Storage};
+
+    let device = get_default_device();
+
+    // The Gpu gathers and adds through the layout with the kernels of the `strided` module.
+    macro_rules! run {
+        ($storage:ident) => {{
+            let builder: ScopeBuilder<$storage<f32>> = ScopeBuilder::new(&device);
+            let x = builder.input([1, 1, 2, 3].into());
+
+            // Columns 1..3 of X, transposed.
+            let layout = Layout::new(x.shape()).slice(3, 1..3).unwrap().transpose(2, 3).unwrap();
+            assert!(as_strided(x.clone(), Layout::new(&[1, 1, 2, 4].into())).is_err());
+
+            let y = as_strided(x.clone(), layout).unwrap();
+            let g = builder.backward(&y, std::slice::from_ref(&x)).unwrap().remove(0);
+            let (x, y, g) = (x.level(), y.level(), g.level());
+            let mut scope = builder.build();
+
+            scope.value(x).clone_from(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+            scope.forward().unwrap();
+            assert_eq!(scope.value(y).as_ndarray().as_slice().unwrap(), &[1.0, 4.0, 2.0, 5.0]);
+            assert_eq!(scope.value(g).as_ndarray().as_slice().unwrap(), &[0.0, 1.0, 1.0, 0.0, 1.0, 1.0]);
+
+            scope.zero_grad();
+            scope.gradient(y).clone_from(&[1.0, 2.0, 3.0, 4.0]);
+            scope.backward().unwrap();
+            assert_eq!(scope.gradient(x).as_ndarray().as_slice().unwrap(), &[0.0, 1.0, 3.0, 0.0, 2.0, 4.0]);
+        }};
+    }
+
+    run!(Cpu);
+    run!(Gpu);
+}
+
+#[test]
+fn test_hessian_vector_product() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, PadMode, mul, pad, reshape, gather, index_select, embedding, cross_entropy};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+    let values = [0.5, -1.0, 2.0, 1.5, -0.5, 1.0];
+    let direction = [1.0, 0.5, -1.0, 2.0, 0.0, -0.5];
+    let eps = 1e-4;
+
+    // The product of the Hessian of f with the direction, for two functions through every indexing 
+    // operator, then the gradients of each at x + eps * v and x - eps * v, from the ordinary backward pass.
+    macro_rules! run {
+        ($storage:ident) => {{
+            let indices: ScopeBuilder<$storage<i64>> = ScopeBuilder::new(&device);
+            let builder: ScopeBuilder<$storage<f64>> = ScopeBuilder::new(&device);
+            let x = builder.input([1, 1, 2, 3].into());
+            let v = builder.input([1, 1, 2, 3].into());
+
+            let a = gather(x.clone(), 3, indices.input([1, 1, 2, 3].into()));
+            let p = pad(mul(a, x.clone()), [1, 0, 0, 1], PadMode::Constant(0.0));
+            let s = index_select(p, 2, indices.input([4].into()));
+            let e = embedding(reshape(x.clone(), [6, 1][..].into()), indices.input([4].into()));
+            let fs = [mul(s.clone(), s), mul(mul(e.clone(), e.clone()), e)];
+
+            let hvps = fs.clone().map(|f| {
+                let g = builder.backward(&f, std::slice::from_ref(&x)).unwrap().remove(0);
+                builder.backward(&mul(g, v.clone()), std::slice::from_ref(&x)).unwrap().remove(0)
+            });
+
+            let (x, v, fs, hvps) = (x.level(), v.level(), fs.map(|f| f.level()), hvps.map(|h| h.level()));
+            let (indices, mut scope) = (indices.build(), builder.build());
+
+            indices.value(0).clone_from(&[2, 0, 0, 1, 2, 1]);
+            indices.value(1).clone_from(&[2, 0, 2, 1]);
+            indices.value(2).clone_from(&[5, 0, 5, 2]);
+            scope.value(v).clone_from(&direction);
+            scope.value(x).clone_from(&values);
+            scope.forward().unwrap();
+
+            let mut out: Vec<Vec<f64>> = hvps.iter().map(|h| scope.value(*h).as_ndarray().into_raw_vec()).collect();
+
+            for f in fs {
+                for sign in [1.0, -1.0] {
+                    let shifted: Vec<f64> = values.iter().zip(direction).map(|(x, v)| x + sign * eps * v).collect();
+
+                    scope.value(x).clone_from(&shifted);
+                    scope.forward().unwrap();
+                    scope.zero_grad();
+                    scope.gradient(f).fill(1.0);
+                    scope.backward().unwrap();
+
+                    out.push(scope.gradient(x).as_ndarray().into_raw_vec());
+                }
+            }
+
+            out
+        }};
+    }
+
+    let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6);
+
+    for out in [run!(Cpu), run!(Gpu)] {
+        for (i, hvp) in out[..2].iter().enumerate() {
+            let (plus, minus) = (&out[2 + 2 * i], &out[3 + 2 * i]);
+            let fd: Vec<f64> = plus.iter().zip(minus).map(|(p, m)| (p - m) / (2.0 * eps)).collect();
+
+            assert!(hvp.iter().any(|h| h.abs() > 0.1));
+            assert!(close(hvp, &fd), "{:?} != {:?}", hvp, fd);
+        }
+    }
+
+    // Operators whose gradient has no operators to be recorded with return an error.
+    let targets: ScopeBuilder<Cpu<i64>> = ScopeBuilder::new(&device);
+    let builder: ScopeBuilder<Cpu<f64>> = ScopeBuilder::new(&device);
+    let x = builder.input([2, 3, 1, 1].into());
+
+    let loss = cross_entropy(x.clone(), targets.input([2, 1, 1, 1].into()));
+    assert!(builder.backward(&loss, std::slice::from_ref(&x)).is_err());
+
+    let reflect = pad(x.clone(), [0, 0, 0, 0], PadMode::Reflect);
+    assert!(builder.backward(&reflect, std::slice::from_ref(&x)).is_err());
+}
+
+#[test]
+fn test_jacobian() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, PadMode, PlanMode, mul, cast, pad, transpose, gather, jacobian, jacobian_forward, jacobian_reverse};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+    let values = [0.5, -1.0, 2.0, 1.5, -0.5, 1.0];
+
+    // The Jacobian in forward-mode, one jvp per column, and in reverse-mode, one backward per row,
+    // then again in reverse-mode once the memory is planned for training.
+    macro_rules! run {
+        ($storage:ident) => {{
+            let indices: ScopeBuilder<$storage<i64>> = ScopeBuilder::new(&device);
+            let builder: ScopeBuilder<$storage<f64>> = ScopeBuilder::new(&device);
+            let x = builder.input([1, 1, 2, 3].into());
+
+            let t = transpose(x.clone(), 2, 3).unwrap();
+            let a = gather(t.clone(), 2, indices.input([1, 1, 3, 2].into()));
+            let y = pad(mul(a, t), [0, 1, 1, 0], PadMode::Reflect);
+
+            let (x, y) = (x.level(), y.level());
+            let (indices, mut scope) = (indices.build(), builder.build());
+
+            indices.value(0).clone_from(&[2, 0, 1, 1, 0, 2]);
+            scope.value(x).clone_from(&values);
+
+            let jacobians = [jacobian_forward(&mut scope, y, x).unwrap(), jacobian_reverse(&mut scope, y, x).unwrap(), jacobian(&mut scope, y, x).unwrap()];
+            scope.plan_memory(PlanMode::Training).unwrap();
+
+            [jacobians[0].clone(), jacobians[1].clone(), jacobians[2].clone(), jacobian_reverse(&mut scope, y, x).unwrap()]
+        }};
+    }
+
+    let close = |a: &ndarray::Array2<f64>, b: &ndarray::Array2<f64>| a.shape() == b.shape() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9);
+    let (cpu, gpu) = (run!(Cpu), run!(Gpu));
+
+    assert_eq!(cpu[0].shape(), [12, 6]);
+    assert!(cpu[0].iter().any(|v| *v != 0.0));
+
+    for jacobian in cpu.iter().chain(gpu.iter()) {
+        assert!(close(jacobian, &cpu[0]));
+    }
+
+    // The tangent of the source of a Cast is only set by zero_tangents on its own scope.
+    let source: ScopeBuilder<Cpu<f64>> = ScopeBuilder::new(&device);
+    let builder: ScopeBuilder<Cpu<f32>> = ScopeBuilder::new(&device);
+    let y = cast(source.input([4].into()), &builder);
+    let y = mul(y.clone(), y).level();
+    let (mut source, mut scope) = (source.build(), builder.build());
+
+    assert!(scope.jvp().is_err());
+    scope.zero_tangents().unwrap();
+    assert!(scope.jvp().is_err());
+
+    source.zero_tangents().unwrap();
+    source.value(0).clone_from(&[1.0, 2.0, 3.0, 4.0]);
+    source.tangent(0).fill(1.0);
+    scope.jvp().unwrap();
+    assert_eq!(scope.tangent(y).as_slice(), &[2.0, 4.0, 6.0, 8.0]);
+}
+
+#[test]
+fn test_checkpoint() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, Phase, mul};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+    let values: Vec<f32> = (0..16).map(|i| 0.5 + i as f32 / 16.0).collect();
+
+    // x^6 through two segments, each freeing one intermediate, with or without checkpoints. 
+    // Returns the gradient of X, then the estimated and the measured memory.
+    macro_rules! run {
+        ($storage:ident, $checkpoint:expr) => {{
+            let builder: ScopeBuilder<$storage<f32>> = ScopeBuilder::new(&device);
+            let x = builder.input([1, 1, 4, 4].into());
+
+            let segment = |y| {
+                let a = mul(x.clone(), y);
+                mul(a, x.clone())
+            };
+
+            let s2 = match $checkpoint {
+                true => {
+                    let s1 = builder.checkpoint(|| segment(x.clone()));
+                    builder.checkpoint(|| segment(s1))
+                }
+                false => segment(segment(x.clone())),
+            };
+
+            let y = mul(s2, x.clone());
+            let (x, y) = (x.level(), y.level());
+            let mut scope = builder.build();
+
+            scope.value(x).clone_from(&values);
+            scope.forward().unwrap();
+            scope.gradient(y).fill(1.0);
+            scope.backward().unwrap();
+
+            (scope.gradient(x).as_ndarray().into_raw_vec(), scope.memory(), scope.measured())
+        }};
+    }
+
+    let (cpu, plain, plain_measured) = run!(Cpu, false);
+    let expected: Vec<f32> = values.iter().map(|x| 6.0 * x.powi(5)).collect();
+
+    assert!(cpu.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-4 * b));
+    assert_eq!(plain.saved(), 0);
+    assert_eq!(plain_measured.peak, plain.total);
+    assert_eq!(plain_measured.resident, plain.total);
+
+    for (gradient, memory, measured) in [run!(Cpu, true), run!(Gpu, true), run!(Gpu, false)] {
+        assert_eq!(gradient, cpu);
+        assert!(measured.peak <= plain_measured.peak);
+        assert!(memory.peak >= measured.peak - 4 * 4 * 2);
+    }
+
+    // Each segment frees one value and gradient of 16 floats, and only one is recomputed at a time.
+    let (_, memory, measured) = run!(Cpu, true);
+    let freed = 2 * 16 * 4;
+
+    assert_eq!(memory.resident, plain.total - 2 * freed);
+    assert_eq!(memory.peak, plain.total - freed);
+    assert_eq!(memory.saved(), freed);
+    assert!(measured.resident < plain.total - freed);
+    assert!(measured.peak < plain.total);
+
+    // The backward pass recomputes every node of a segment but the last, whose output was kept,
+    // and restoring a segment that is still allocated keeps its buffers.
+    let builder: ScopeBuilder<Cpu<f32>> = ScopeBuilder::new(&device).with_profiler();
+    let x = builder.input([4].into());
+    let s = builder.checkpoint(|| mul(mul(x.clone(), x.clone()), x.clone()));
+    let y = mul(s, x.clone()).level();
+    let mut scope = builder.build();
+
+    scope.value(0).fill(2.0);
+    scope.forward().unwrap();
+    scope.gradient(y).fill(1.0);
+    scope.backward().unwrap();
+
+    let records = scope.profiler().unwrap().records().unwrap();
+    let forwards: Vec<usize> = (0..3)
+        .map(|node| records.iter().filter(|r| r.node == node && r.phase == Phase::Forward).count())
+        .collect();
+
+    assert_eq!(forwards, [2, 1, 1]);
+
+    scope.zero_tangents().unwrap();
+    let buffer = scope.value(1).as_slice().as_ptr();
+    scope.zero_tangents().unwrap();
+    assert_eq!(scope.value(1).as_slice().as_ptr(), buffer);
+}
+
+#[test]
+fn test_memory_plan() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, PlanMode, mul};
+    use crate::storage::{Cpu, Gpu, Shape, Storage, Tensor};
+
+    let device = get_default_device();
+    let values: Vec<f32> = (0..16).map(|i| 0.5 + i as f32 / 16.0).collect();
+
+    // x^6 as a chain of five products, planned in `mode` or not at all. Returns
+    // the value of Y, the gradient of X, then the estimated and the measured memory.
+    macro_rules! run {
+        ($storage:ident, $mode:expr) => {{
+            let builder: ScopeBuilder<$storage<f32>> = ScopeBuilder::new(&device);
+            let x = builder.input([1, 1, 4, 4].into());
+            let y = (0..5).fold(x.clone(), |y, _| mul(y, x.clone()));
+            let (x, y) = (x.level(), y.level());
+            let mut scope = builder.build();
+
+            let mode: Option<PlanMode> = $mode;
+            if let Some(mode) = mode {
+                scope.plan_memory(mode).unwrap();
+            }
+
+            scope.value(x).clone_from(&values);
+            scope.forward().unwrap();
+            let value = scope.value(y).as_ndarray().into_raw_vec();
+
+            if mode != Some(PlanMode::Inference) {
+                scope.gradient(y).fill(1.0);
+                scope.backward().unwrap();
+            }
+
+            let gradient = scope.gradient(x).as_ndarray().into_raw_vec();
+            (value, gradient, scope.memory(), scope.measured())
+        }};
+    }
+
+    let (value, gradient, plain, _) = run!(Cpu, None);
+    let expected: Vec<f32> = values.iter().map(|x| 6.0 * x.powi(5)).collect();
+
+    assert!(gradient.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-4 * b));
+
+    // The four intermediates hold a placeholder value and gradient when they are not in the pool.
+    let placeholders = 4 * 2 * 4;
+
+    for (v, g, memory, measured) in [run!(Cpu, Some(PlanMode::Training)), run!(Gpu, Some(PlanMode::Training))] {
+        assert_eq!((v, g), (value.clone(), gradient.clone()));
+
+        // Every value lives until its producer is differentiated, but two gradient buffers are shared.
+        assert_eq!(memory.peak, plain.total - 2 * 16 * 4);
+        assert!(measured.peak <= memory.peak + placeholders);
+        assert!(measured.peak < plain.total);
+    }
+
+    // On a Cpu, every product overwrites the previous one in-place, in a single buffer. 
+    // The Gpu has no in-place Mul, so the products alternate between two buffers.
+    for ((v, _, memory, measured), buffers) in [(run!(Cpu, Some(PlanMode::Inference)), 1), (run!(Gpu, Some(PlanMode::Inference)), 2)] {
+        assert_eq!(v, value);
+        assert_eq!(memory.peak, 2 * 2 * 16 * 4 + buffers * 16 * 4);
+        assert!(measured.peak <= memory.peak + placeholders);
+    }
+
+    // Inputs cannot be resized after planning.
+    let builder: ScopeBuilder<Cpu<f32>> = ScopeBuilder::new(&device);
+    let x = builder.input([1, 1, 4, 4].into());
+    mul(mul(x.clone(), x.clone()), x.clone());
+    let x = x.level();
+    let mut scope = builder.build();
+
+    scope.plan_memory(PlanMode::Training).unwrap();
+    *scope.value(x) = Tensor::new(Shape::from([1, 1, 2, 2]));
+    assert!(scope.forward().is_err());
+}
+
+#[test]
+fn test_shape_backward() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, PadMode, pad, transpose, squeeze, unsqueeze};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+    let values: Vec<f32> = (0..12).map(|i| i as f32).collect();
+    let weights: Vec<f32> = (0..30).map(|i| (i % 7) as f32 - 3.0).collect();
+
+    // The values of every output, then the gradient of X with the gradients of the outputs set to the weights.
+    let builder: ScopeBuilder<Cpu<f32>> = ScopeBuilder::new(&device);
+    let x = builder.input([1, 2, 2, 3].into());
+
+    assert!(transpose(x.clone(), 0, 4).is_err());
+    assert!(squeeze(x.clone(), 1).is_err());
+    assert!(unsqueeze(x.clone(), 0).is_err());
+
+    let ys = [
+        transpose(x.clone(), 2, 3).unwrap(),
+        pad(x.clone(), [1, 0, 0, 2], PadMode::Reflect),
+        unsqueeze(squeeze(x.clone(), 0).unwrap(), 0).unwrap(),
+    ];
+
+    let (x, ys) = (x.level(), ys.map(|y| y.level()));
+    let mut scope = builder.build();
+
+    scope.value(x).clone_from(&values);
+    scope.forward().unwrap();
+
+    for y in ys {
+        let len = scope.gradient(y).len();
+        scope.gradient(y).clone_from(&weights[..len]);
+    }
+
+    scope.backward().unwrap();
+
+    let cpu: Vec<Vec<f32>> = ys.iter().map(|y| scope.value(*y).as_slice().to_vec())
+        .chain([scope.gradient(x).as_slice().to_vec()])
+        .collect();
+
+    assert_eq!(&cpu[0][..6], &[0.0, 3.0, 1.0, 4.0, 2.0, 5.0]);
+    assert_eq!(&cpu[1][..5], &[3.0, 4.0, 5.0, 4.0, 3.0]);
+    assert_eq!(cpu[2], values);
+
+    let builder: ScopeBuilder<Gpu<f32>> = ScopeBuilder::new(&device);
+    let x = builder.input([1, 2, 2, 3].into());
+
+    let ys = [
+        transpose(x.clone(), 2, 3).unwrap(),
+        pad(x.clone(), [1, 0, 0, 2], PadMode::Reflect),
+        unsqueeze(squeeze(x.clone(), 0).unwrap(), 0).unwrap(),
+    ];
+
+    let (x, ys) = (x.level(), ys.map(|y| y.level()));
+    let mut scope = builder.build();
+
+    scope.value(x).clone_from(&values);
+    scope.forward().unwrap();
+
+    for y in ys {
+        let len = scope.gradient(y).len();
+        scope.gradient(y).clone_from(&weights[..len]);
+    }
+
+    scope.backward().unwrap();
+
+    let gpu: Vec<Vec<f32>> = ys.iter().map(|y| scope.value(*y).as_ndarray().into_raw_vec())
+        .chain([scope.gradient(x).as_ndarray().into_raw_vec()])
+        .collect();
+
+    assert_eq!(cpu, gpu);
+}
+
+#[test]
+fn test_indexing() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, gather, scatter, scatter_add, index_select, masked_fill, embedding};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+    let values: Vec<f32> = (0..24).map(|i| i as f32).collect();
+    let table: Vec<f32> = (0..15).map(|i| i as f32 / 2.0).collect();
+    let weights: Vec<f32> = (0..32).map(|i| (i % 5) as f32 - 2.0).collect();
+
+    // Positions along W, with repeats for gather and scatter_add, but none for scatter.
+    let positions = [3i64, 0, 1, 1, 2, 0, 0, 3, 3, 3, 1, 2];
+    let unique = [3i64, 0, 1, 2, 2, 1];
+    let repeated = [1i64, 1, 0, 3, 2, 2];
+    let selected = [2i64, 0, 2, 1];
+    let ids = [1i64, 3, 1, 0];
+    let mask: Vec<bool> = (0..24).map(|i| i % 3 == 0).collect();
+
+    // The values of every output, then the gradients of X, the source and the table,
+    // with the gradient of every output set to the weights.
+    macro_rules! run {
+        ($storage:ident) => {{
+            let indices: ScopeBuilder<$storage<i64>> = ScopeBuilder::new(&device);
+            let masks: ScopeBuilder<$storage<bool>> = ScopeBuilder::new(&device);
+            let builder: ScopeBuilder<$storage<f32>> = ScopeBuilder::new(&device);
+            let x = builder.input([2, 3, 1, 4].into());
+            let src = builder.input([1, 3, 1, 2].into());
+            let rows = builder.input([5, 3][..].into());
+
+            let ys = [
+                gather(x.clone(), 3, indices.input([2, 3, 1, 2].into())),
+                scatter(x.clone(), 3, indices.input([1, 3, 1, 2].into()), src.clone()),
+                scatter_add(x.clone(), 3, indices.input([1, 3, 1, 2].into()), src.clone()),
+                index_select(x.clone(), 1, indices.input([4].into())),
+                masked_fill(x.clone(), masks.input([2, 3, 1, 4].into()), -1.0),
+                embedding(rows.clone(), indices.input([2, 2].into())),
+            ];
+
+            let (x, src, rows, ys) = (x.level(), src.level(), rows.level(), ys.map(|y| y.level()));
+            let (indices, masks, mut scope) = (indices.build(), masks.build(), builder.build());
+
+            for (level, index) in [&positions[..], &unique, &repeated, &selected, &ids].into_iter().enumerate() {
+                indices.value(level).clone_from(index);
+            }
+
+            masks.value(0).clone_from(&mask);
+            scope.value(x).clone_from(&values);
+            scope.value(src).clone_from(&[10.0, 20.0, 30.0, 40.0, 50.0, 60.0]);
+            scope.value(rows).clone_from(&table);
+            scope.forward().unwrap();
+
+            for y in ys {
+                let len = scope.gradient(y).len();
+                scope.gradient(y).clone_from(&weights[..len]);
+            }
+
+            scope.backward().unwrap();
+
+            let out: Vec<Vec<f32>> = ys.iter().chain([&x, &src, &rows])
+                .map(|level| if [x, src, rows].contains(level) { scope.gradient(*level) } else { scope.value(*level) })
+                .map(|tensor| tensor.as_ndarray().into_raw_vec())
+                .collect();
+
+            // An index out of range, then positions repeated in a scatter, are errors on each storage.
+            indices.value(0).clone_from(&[4i64; 12]);
+            let out_of_range = scope.forward().is_err();
+            indices.value(0).clone_from(&positions);
+            indices.value(1).clone_from(&repeated);
+
+            (out, [out_of_range, scope.forward().is_err()])
+        }};
+    }
+
+    let (cpu, errors) = run!(Cpu);
+
+    assert_eq!(cpu[0][..4], [3.0, 0.0, 5.0, 5.0]);
+    assert_eq!(cpu[3][..4], [8.0, 9.0, 10.0, 11.0]);
+    assert_eq!(cpu[5], [1.5, 2.0, 2.5, 4.5, 5.0, 5.5, 1.5, 2.0, 2.5, 0.0, 0.5, 1.0]);
+    assert_eq!(errors, [true, true]);
+
+    // The embedding gradient sums the repeated row 1, and leaves rows 2 and 4 untouched.
+    let expected: Vec<f32> = (0..15).map(|i| match i / 3 {
+        0 => weights[9 + i % 3],
+        1 => weights[i % 3] + weights[6 + i % 3],
+        3 => weights[3 + i % 3],
+        _ => 0.0,
+    }).collect();
+
+    assert_eq!(cpu[8], expected);
+
+    let (gpu, errors) = run!(Gpu);
+
+    assert_eq!(cpu, gpu);
+    assert_eq!(errors, [true, true]);
+}
+
+#[test]
+fn test_many_inputs_and_outputs() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, concat, split};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+
+    // Three inputs concatenated along C, split into three outputs of other sizes. The first output
+    // is a result, the second is used twice by another concat, and the third is unused, so its gradient is zero.
+    macro_rules! run {
+        ($storage:ident) => {{
+            let builder: ScopeBuilder<$storage<f32>> = ScopeBuilder::new(&device);
+            let xs = [2, 1, 3].map(|c| builder.input([1, c, 1, 2].into()));
+            let ys = split(concat(&xs, 1), 1, &[1, 3, 2]);
+            let twice = concat(&[ys[1].clone(), ys[1].clone()], 0);
+
+            let (xs, ys, twice) = (xs.map(|x| x.level()), [0, 1, 2].map(|i| ys[i].level()), twice.level());
+            let mut scope = builder.build();
+
+            for (i, x) in xs.iter().enumerate() {
+                let values: Vec<f32> = (0..scope.value(*x).len()).map(|j| (j + [0, 4, 6][i]) as f32).collect();
+                scope.value(*x).clone_from(&values);
+            }
+
+            scope.forward().unwrap();
+            scope.gradient(ys[0]).fill(1.0);
+            let weights: Vec<f32> = (0..12).map(|i| i as f32).collect();
+            scope.gradient(twice).clone_from(&weights);
+            scope.backward().unwrap();
+
+            ys.iter().map(|y| scope.value(*y).as_ndarray().into_raw_vec())
+                .chain(xs.iter().map(|x| scope.gradient(*x).as_ndarray().into_raw_vec()))
+                .collect::<Vec<Vec<f32>>>()
+        }};
+    }
+
+    let cpu = run!(Cpu);
+
+    assert_eq!(cpu[0], [0.0, 1.0]);
+    assert_eq!(cpu[1], [2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+    assert_eq!(cpu[2], [8.0, 9.0, 10.0, 11.0]);
+    assert_eq!(cpu[3], [1.0, 1.0, 6.0, 8.0]);
+    assert_eq!(cpu[4], [10.0, 12.0]);
+    assert_eq!(cpu[5], [14.0, 16.0, 0.0, 0.0, 0.0, 0.0]);
+
+    assert_eq!(cpu, run!(Gpu));
+}
+
+#[test]
+fn test_two_consumers() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, mul};
+    use crate::storage::{Cpu, Gpu, Storage};
+
+    let device = get_default_device();
+
+    // X is read by two products and squared, so its gradient is the sum of every use: w + v + 2x.
+    macro_rules! run {
+        ($storage:ident) => {{
+            let builder: ScopeBuilder<$storage<f32>> = ScopeBuilder::new(&device);
+            let [x, w, v] = [0; 3].map(|_| builder.input([1, 1, 2, 2].into()));
+            let ys = [mul(x.clone(), w.clone()), mul(v.clone(), x.clone()), mul(x.clone(), x.clone())];
+
+            let ([x, w, v], ys) = ([x, w, v].map(|x| x.level()), ys.map(|y| y.level()));
+            let mut scope = builder.build();
+
+            scope.value(x).clone_from(&[1.0, 2.0, 3.0, 4.0]);
+            scope.value(w).clone_from(&[0.5, -1.0, 2.0, 0.0]);
+            scope.value(v).clone_from(&[3.0, 1.0, -2.0, 1.0]);
+            scope.forward().unwrap();
+
+            for y in ys {
+                scope.gradient(y).fill(1.0);
+            }
+
+            scope.backward().unwrap();
+
+            [x, w, v].map(|x| scope.gradient(x).as_ndarray().into_raw_vec())
+        }};
+    }
+
+    let cpu = run!(Cpu);
+
+    assert_eq!(cpu[0], [5.5, 4.0, 6.0, 9.0]);
+    assert_eq!(cpu[1], [1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(cpu[2], [1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(cpu, run!(Gpu));
+}
+
+#[test]
+fn test_accumulate() {
+    use crate::storage::Cpu;
+    use crate::storage::Storage;
+    use crate::storage::Layout;
+    use crate::storage::Accumulate;
+
+    let mut grad: Cpu<f32> = Cpu::new([2, 2].into());
+    grad.fill(1.0);
+
+    let mut src: Cpu<f32> = Cpu::new([2].into());
+    src.clone_from(&[2.0, 3.0]);
+
+    let layout = Layout::new(grad.shape()).select(1, 1).unwrap();
+    grad.scatter_add(&layout, &src);
+    grad.scatter_add(&layout, &src);
+
+    assert_eq!(grad.as_slice(), &[1.0, 5.0, 1.0, 7.0]);
+}
+
+#[test]
+fn test_allocator_bins() {
+    use crate::gpu::cu;
+    use crate::gpu::allocator::{Bins, size_class};
+
+    assert_eq!(size_class(1), 512);
+    assert_eq!(size_class(1000), 1024);
+    assert_eq!(size_class((1 << 20) + 1), 2 << 20);
+
+    let mut bins = Bins::default();
+    let a = bins.insert(cu::DevicePtr { ptr: 0x1000 }, 1024, 0);
+    let b = bins.insert(cu::DevicePtr { ptr: 0x2000 }, 1024, 0);
+    bins.put(a, vec![]);
+
+    // Blocks are only reused by the same stream and size class.
+    assert!(bins.take(1024, 1).is_none());
+    assert!(bins.take(512, 0).is_none());
+    assert_eq!(bins.take(1024, 0).unwrap().ptr.ptr, 0x1000);
+
+    bins.put(a, vec![]);
+    bins.put(b, vec![]);
+
+    let stats = bins.stats();
+    assert_eq!((stats.allocated, stats.reserved, stats.peak), (0, 2048, 2048));
+
+    assert_eq!(bins.drain().len(), 2);
+    assert_eq!(bins.stats().reserved, 0);
+}
+
+#[test]
+fn test_allocator() {
+    use crate::gpu::{Device, Stream};
+    use crate::storage::{Gpu, Storage};
+
+    // A device of its own, so the other tests do not allocate from it.
+    let device = Device::pick_ordinal(0).unwrap();
+    let stream = device.fork().unwrap();
+
+    let mut x: Gpu<f32> = Gpu::new_on([1000].into(), &device, &stream);
+    let ptr = x.as_ptr().ptr;
+    x.fill(3.0);
+
+    let stats = device.memory_stats();
+    assert_eq!((stats.allocated, stats.reserved), (4096, 4096));
+    drop(x);
+
+    // The block is cached for the stream, and zeroed again when it is reused.
+    let y: Gpu<f32> = Gpu::new_on([1000].into(), &device, &stream);
+    assert_eq!(y.as_ptr().ptr, ptr);
+    assert!(y.as_ndarray().iter().all(|v| *v == 0.0));
+
+    let z: Gpu<f32> = Gpu::new_on([1000].into(), &device, &Stream::null());
+    assert_ne!(z.as_ptr().ptr, ptr);
+    drop((y, z));
+
+    assert_eq!(device.memory_stats().allocated, 0);
+    assert_eq!(device.memory_stats().reserved, 8192);
+
+    // Destroying the stream gives its blocks back to the driver.
+    drop(stream);
+    assert_eq!(device.memory_stats().reserved, 4096);
+}
+
+#[test]
+#[cfg(feature = "emulated")]
+fn test_stream_order() {
+    use crate::gpu::{Stream, get_default_device};
+    use crate::gpu::cu::emulated::pending;
+    use crate::storage::{Gpu, Storage};
+
+    // Forked streams are non-blocking, so their work is not ordered with the null stream.
+    let device = get_default_device();
+    let stream = device.fork().unwrap();
+    let values: Vec<f32> = (0..16).map(|i| i as f32).collect();
+
+    // The memset that zeroes new memory is left on the stream, even by work on the null stream.
+    let mut x: Gpu<f32> = Gpu::new_on([16].into(), &device, &stream);
+    assert_eq!(pending(stream.stream.ptr), 1);
+    let _: Gpu<f32> = Gpu::new_on([16].into(), &device, &Stream::null());
+    assert_eq!(pending(stream.stream.ptr), 1);
+
+    // Copies are ordered after it, and wait for it.
+    x.clone_from(&values);
+    assert_eq!(pending(stream.stream.ptr), 0);
+    assert_eq!(x.as_ndarray().into_raw_vec(), values);
+
+    x.fill(2.0);
+    assert_eq!(pending(stream.stream.ptr), 1);
+    assert!(x.as_ndarray().iter().all(|v| *v == 2.0));
+    assert_eq!(pending(stream.stream.ptr), 0);
+
+    // Values that cannot be set with a memset are copied from the host.
+    let mut y: Gpu<f64> = Gpu::new_on([16].into(), &device, &stream);
+    y.fill(0.1);
+    assert!(y.as_ndarray().iter().all(|v| *v == 0.1));
+
+    let z: Gpu<f32> = y.cast();
+    assert!(z.as_ndarray().iter().all(|v| *v == 0.1));
+    assert_eq!(pending(stream.stream.ptr), 0);
+}
+
+#[test]
+#[cfg(feature = "emulated")]
+fn test_allocator_streams() {
+    use crate::gpu::Device;
+    use crate::gpu::cu::emulated::pending;
+    use crate::storage::{Gpu, Layout, Storage, Strided};
+
+    let device = Device::pick_ordinal(0).unwrap();
+    let (a, b) = (device.fork().unwrap(), device.fork().unwrap());
+
+    let mut x: Gpu<f32> = Gpu::new_on([4].into(), &device, &a);
+    x.clone_from(&[1.0, 2.0, 3.0, 4.0]);
+    let ptr = x.as_ptr().ptr;
+
+    // The copy into Y is left on stream B, and an event after it when X is freed.
+    let mut y: Gpu<f32> = Gpu::new_on([4].into(), &device, &b);
+    x.gather(&Layout::new(x.shape()), &mut y);
+    drop(x);
+    assert_eq!(pending(b.stream.ptr), 3);
+
+    // So the block of X is not reused on stream A before it is done.
+    let mut z: Gpu<f32> = Gpu::new_on([4].into(), &device, &a);
+    z.clone_from(&[9.0; 4]);
+    assert_ne!(z.as_ptr().ptr, ptr);
+    assert_eq!(y.as_ndarray().into_raw_vec(), vec![1.0, 2.0, 3.0, 4.0]);
+
+    // Once it is, it is.
+    let w: Gpu<f32> = Gpu::new_on([4].into(), &device, &a);
+    assert_eq!(w.as_ptr().ptr, ptr);
+}
+
+#[test]
+fn test_kernels() {
+    use half::f16;
+    use crate::storage::{Cpu, Kernels};
+
+    let a: Vec<f32> = (0..37).map(|i| i as f32 - 18.0).collect();
+    let b: Vec<f32> = (0..37).map(|i| (i % 5) as f32 * 0.5).collect();
+    let mut y = vec![1.0f32; 37];
+
+    Kernels::mul_add(&a, &b, &mut y);
+    assert!(y.iter().enumerate().all(|(i, y)| *y == 1.0 + a[i] * b[i]));
+
+    assert_eq!(f32::sum(&a), 0.0);
+    assert_eq!(<f32 as Kernels>::max(&a), 18.0);
+
+    let x = [f64::NAN, -1.0, 2.0, -3.0, 4.0, 5.0, -6.0, 7.0, 8.0];
+    let mut r = [0.0f64; 9];
+    f64::relu(&x, &mut r);
+    assert_eq!(r, [0.0, 0.0, 2.0, 0.0, 4.0, 5.0, 0.0, 7.0, 8.0]);
+
+    let h: Vec<f16> = a.iter().map(|v| f16::from_f32(*v)).collect();
+    let mut hy = vec![f16::ZERO; 37];
+    Kernels::add(&h, &h, &mut hy);
+    assert!(hy.iter().zip(&a).all(|(y, a)| y.to_f32() == 2.0 * a));
+
+    let cpu: Cpu<f32> = Cpu::new([3, 5].into());
+    assert_eq!(cpu.as_slice().as_ptr() as usize % 64, 0);
+    assert!(cpu.as_slice().iter().all(|v| *v == 0.0));
+}
+
+#[test]
+fn test_parallel() {
+    use std::panic::AssertUnwindSafe;
+    use crate::storage::{parallel, with_num_threads, Kernels};
+
+    let x: Vec<f32> = (0..100_000).map(|i| ((i * 7919) % 1000) as f32 * 0.001).collect();
+
+    let sum = || parallel::map_reduce(x.len(), |range| Kernels::sum(&x[range]), |a, b| a + b).unwrap();
+    let one = with_num_threads(Some(1), sum);
+    let four = with_num_threads(Some(4), sum);
+    assert_eq!(one.to_bits(), four.to_bits());
+
+    let mut y = vec![0.0f32; x.len()];
+    with_num_threads(Some(4), || parallel::binary(&x, &x, &mut y, Kernels::mul));
+    assert!(y.iter().zip(&x).all(|(y, x)| *y == x * x));
+
+    // 0 means every available core, for a Scope as well as globally.
+    let cores = std::thread::available_parallelism().unwrap().get();
+    assert_eq!(with_num_threads(Some(0), parallel::num_threads), cores);
+
+    // The panic of a worker reaches the caller, and the setting is restored.
+    with_num_threads(Some(2), || {
+        let panicked = std::panic::catch_unwind(AssertUnwindSafe(|| with_num_threads(Some(4), || {
+            parallel::for_each_chunk(&mut y, |start, _| assert!(start < parallel::CHUNK))
+        })));
+
+        assert!(panicked.is_err());
+        assert_eq!(parallel::num_threads(), 2);
+    });
+}
+
+#[test]
+fn test_launch_config() {
+    use crate::gpu::kernel::LaunchConfig;
+
+    let config = LaunchConfig { block: 256, max_grid: 80 };
+
+    assert_eq!(config.grid(1), 1);
+    assert_eq!(config.grid(1000), 4);
+    assert_eq!(config.grid(1 << 30), 80);
+
+    // Configurations are cached in the module, and computed again once it is reloaded.
+    for _ in 0..2 {
+        let device = crate::gpu::Device::pick_ordinal(0).unwrap();
+        let kernel = device.get_kernel("mul", "mul_f32").unwrap();
+        let config = kernel.config().unwrap();
+
+        assert!(config.block > 0 && config.max_grid > 0);
+        assert_eq!(device.get_kernel("mul", "mul_f32").unwrap().config().unwrap(), config);
+    }
+}
+
+#[test]
+fn test_ptx_signature() {
+    use crate::gpu::cu;
+    use crate::gpu::Kernel;
+    use crate::gpu::ptx::{self, Param, ParamKind};
+    use crate::gpu::kernel::{KernelArg, Ptr};
+    use half::f16;
+
+    let ptx = "
+        .visible .entry scale_f32(
+            .param .u64 .ptr .global .align 4 scale_f32_param_0,
+            .param .f32 scale_f32_param_1,
+            .param .u64 scale_f32_param_2
+        )
+        { ret; }
+    ";
+
+    let entries = ptx::parse_entries(ptx).unwrap();
+    let params = entries["scale_f32"].clone();
+
+    assert_eq!(params, vec![
+        Param { kind: ParamKind::Pointer, size: 8, align: 4 },
+        Param { kind: ParamKind::Float, size: 4, align: 0 },
+        Param { kind: ParamKind::Int, size: 8, align: 0 },
+    ]);
+
+    let kernel = Kernel::from("scale_f32".to_owned(), cu::Function { ptr: std::ptr::null_mut() }, params);
+    let ptr = Ptr::<f32>::PARAM;
+
+    assert!(kernel.check_args(&[ptr, f32::PARAM, u64::PARAM]).is_ok());
+    assert!(kernel.check_args(&[ptr, f64::PARAM, u64::PARAM]).is_err());
+    assert!(kernel.check_args(&[u64::PARAM, f32::PARAM, u64::PARAM]).is_err());
+    assert!(kernel.check_args(&[ptr, f32::PARAM]).is_err());
+
+    // A pointer to elements aligned to less, or a pointer passed as an integer, is rejected.
+    assert!(kernel.check_args(&[Ptr::<f64>::PARAM, f32::PARAM, u64::PARAM]).is_ok());
+    assert!(kernel.check_args(&[Ptr::<f16>::PARAM, f32::PARAM, u64::PARAM]).is_err());
+    assert!(kernel.check_args(&[ptr, f32::PARAM, ptr]).is_err());
+
+    // Without `.ptr`, pointers are the `.u64` parameters converted to global addresses.
+    let ptx = "
+        .visible .entry copy_f32(
+            .param .u64 copy_f32_param_0,
+            .param .u64 copy_f32_param_1,
+            .param .u64 copy_f32_param_2
+        )
+        {
+            ld.param.u64 %rd1, [copy_f32_param_0];
+            ld.param.u64 %rd2, [copy_f32_param_1];
+            ld.param.u64 %rd3, [copy_f32_param_2];
+            cvta.to.global.u64 %rd4, %rd2;
+            cvta.to.global.u64 %rd5, %rd1;
+            ret;
+        }
+    ";
+
+    let params = ptx::parse_entries(ptx).unwrap()["copy_f32"].clone();
+    let kinds: Vec<ParamKind> = params.iter().map(|param| param.kind).collect();
+    assert_eq!(kinds, vec![ParamKind::Pointer, ParamKind::Pointer, ParamKind::Int]);
+
+    let kernel = Kernel::from("copy_f32".to_owned(), cu::Function { ptr: std::ptr::null_mut() }, params);
+    assert!(kernel.check_args(&[ptr, ptr, u64::PARAM]).is_ok());
+    assert!(kernel.check_args(&[ptr, ptr, ptr]).is_err());
+    assert!(kernel.check_args(&[u64::PARAM, ptr, u64::PARAM]).is_err());
+}
+
+#[test]
+fn test_embedded_ptx() {
+    use crate::gpu::modules;
+
+    // Kernels are looked up in the entries parsed from every PTX, with the highest SM level up to the device's.
+    let (ptx, _) = modules::with_kernel("mul", "mul_f32", 86).unwrap();
+    assert_eq!(ptx.sm, 52);
+    assert!(ptx.source.contains(".entry"));
+    assert!(modules::with_kernel("mul", "mul_f32", 35).is_none());
+
+    let (ptx, entries) = modules::with_kernel("gather", "scatter_add_f64_i32", 86).unwrap();
+    assert_eq!((ptx.module, ptx.dtype), ("gather", "f64"));
+    assert_eq!(entries["scatter_add_f64_i32"].len(), 12);
+    assert!(modules::with_kernel("gather", "scatter_add_f64_i8", 86).is_none());
+    assert!(modules::with_kernel("mul", "scatter_add_f64_i32", 86).is_none());
+}
+
+#[test]
+fn test_codegen() {
+    use crate::gpu::codegen;
+
+    let template = include_str!("gpu/kernels/mul.cu");
+
+    let f32 = codegen::instantiate(template, "mul", "f32").unwrap();
+    assert!(f32.contains("extern \"C\" __global__ void mul_f32(const float* x1, const float* x2, float* y, size_t len) {\n    mul<float>(x1, x2, y, len);\n}"));
+    assert!(!f32.contains("#include"));
+
+    let bf16 = codegen::instantiate(template, "mul", "bf16").unwrap();
+    assert!(bf16.starts_with("#include <cuda_bf16.h>"));
+    assert!(bf16.contains("void mul_bf16(const __nv_bfloat16* x1, const __nv_bfloat16* x2, __nv_bfloat16* y, size_t len)"));
+
+    assert!(codegen::instantiate(template, "add", "f32").is_err());
+    assert!(codegen::instantiate(template, "mul", "i8").is_err());
+
+    let ptx = codegen::declare(&bf16, "mul", "bf16", "sm_80").unwrap();
+    assert!(ptx.contains(".target sm_80"));
+    assert!(ptx.contains(".visible .entry mul_bf16(\n\t.param .u64 .ptr .global .align 2 mul_bf16_param_0,\n\t.param .u64 .ptr .global .align 2 mul_bf16_param_1,\n\t.param .u64 .ptr .global .align 2 mul_bf16_param_2,\n\t.param .u64 mul_bf16_param_3\n)"));
+    assert!(codegen::declare(&bf16, "mul", "f32", "sm_80").is_err());
+
+    // Templates over an index are instantiated for every index type, and helpers are left alone.
+    let template = include_str!("gpu/kernels/cross_entropy.cu");
+    let f32 = codegen::instantiate(template, "cross_entropy", "f32").unwrap();
+    assert!(f32.contains("void cross_entropy_wrt_f32_u8(const float* x, const unsigned char* target, const float* gy, float* g, size_t classes, size_t hw, size_t len)"));
+    assert!(f32.contains("cross_entropy<float, long long>(x, target, y, classes, hw, len);"));
+    assert!(!f32.contains("exp_sum_f32"));
+
+    let ptx = codegen::declare(&f32, "cross_entropy", "f32", "sm_52").unwrap();
+    assert_eq!(ptx.matches(".entry").count(), 9);
+}
+
+#[test]
+fn test_graph_capture() {
+    use crate::gpu::{Graph, Stream, get_default_device};
+    use crate::storage::Gpu;
+    use crate::storage::Storage;
+
+    assert!(Graph::capture(&Stream::null(), || Ok(())).is_err());
+
+    let device = get_default_device();
+    let stream = device.fork().unwrap();
+    let mut x: Gpu<f32> = Gpu::new_on([4].into(), &device, &stream);
+
+    // Nothing runs while capturing.
+    let mut graph = Graph::capture(&stream, || { x.fill(2.0); Ok(()) }).unwrap();
+    stream.synchronize().unwrap();
+    assert!(x.as_ndarray().iter().all(|v| *v == 0.0));
+
+    graph.launch(&stream).unwrap();
+    stream.synchronize().unwrap();
+    assert!(x.as_ndarray().iter().all(|v| *v == 2.0));
+
+    graph.update(&stream, || { x.fill(3.0); Ok(()) }).unwrap();
+    graph.launch(&stream).unwrap();
+    stream.synchronize().unwrap();
+    assert!(x.as_ndarray().iter().all(|v| *v == 3.0));
+}
+
+#[test]
+fn test_scope_capture() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, add, mul, cast};
+    use crate::storage::{Gpu, Storage};
+
+    let device = get_default_device();
+    let stream = device.fork().unwrap();
+
+    let builder: ScopeBuilder<Gpu<f32>> = ScopeBuilder::new(&device).with_stream(&stream);
+    let x = builder.input([1, 1, 2, 2].into());
+    let w = builder.input([1, 1, 2, 2].into());
+    let y = mul(add(x.clone(), w.clone()), x.clone());
+    let (x, w, y) = (x.level(), w.level(), y.level());
+    let mut scope = builder.build();
+
+    // Every tensor of the Scope, and those the nodes allocate while running, are on its stream.
+    assert!((0..scope.levels()).all(|level| scope.value(level).stream().stream.ptr == stream.stream.ptr));
+
+    scope.value(x).clone_from(&[1.0, 2.0, 3.0, 4.0]);
+    scope.value(w).clone_from(&[0.5, 0.5, -1.0, 2.0]);
+
+    let step = |scope: &mut crate::nn::Scope<Gpu<f32>>| {
+        scope.forward()?;
+        scope.zero_grad();
+        scope.gradient(y).fill(1.0);
+        scope.backward()
+    };
+
+    // Nothing runs while capturing, and running on another stream would invalidate the capture.
+    let mut captured = scope.capture(step).unwrap();
+    stream.synchronize().unwrap();
+    assert!(scope.value(y).as_ndarray().iter().all(|v| *v == 0.0));
+
+    scope.replay(&mut captured, step).unwrap();
+    stream.synchronize().unwrap();
+    assert_eq!(scope.value(y).as_ndarray().as_slice().unwrap(), &[1.5, 5.0, 6.0, 24.0]);
+    assert_eq!(scope.gradient(x).as_ndarray().as_slice().unwrap(), &[2.5, 4.5, 5.0, 10.0]);
+    assert_eq!(scope.gradient(w).as_ndarray().as_slice().unwrap(), &[1.0, 2.0, 3.0, 4.0]);
+
+    // Cast converts on the host, so the Scope is rejected before anything is captured.
+    let source: ScopeBuilder<Gpu<f64>> = ScopeBuilder::new(&device);
+    let builder: ScopeBuilder<Gpu<f32>> = ScopeBuilder::new(&device).with_stream(&stream);
+    let z = cast(source.input([4].into()), &builder);
+    let _ = z.level();
+    let mut scope = builder.build();
+    assert!(scope.capture(|scope| scope.forward()).is_err());
+}
+
+#[test]
+fn test_capture_scratch() {
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, as_strided, concat, mul};
+    use crate::storage::{Gpu, Layout, Storage};
+
+    let device = get_default_device();
+    let stream = device.fork().unwrap();
+
+    let builder: ScopeBuilder<Gpu<f32>> = ScopeBuilder::new(&device).with_stream(&stream);
+    let x = builder.input([1, 1, 2, 2].into());
+    let w = builder.input([1, 1, 2, 2].into());
+    let t = as_strided(w.clone(), Layout::new(w.shape()).transpose(2, 3).unwrap()).unwrap();
+    let y = concat(&[x.clone(), t], 1);
+    let z = mul(y.clone(), y);
+    let (x, w, z) = (x.level(), w.level(), z.level());
+    let mut scope = builder.build();
+
+    scope.value(x).clone_from(&[1.0, 2.0, 3.0, 4.0]);
+    scope.value(w).clone_from(&[5.0, 6.0, 7.0, 8.0]);
+
+    let step = |scope: &mut crate::nn::Scope<Gpu<f32>>| {
+        scope.forward()?;
+        scope.zero_grad();
+        scope.gradient(z).fill(1.0);
+        scope.backward()
+    };
+
+    // The scratch tensors of as_strided and concat are allocated while capturing, so the
+    // step runs once first to cache their memory.
+    step(&mut scope).unwrap();
+
+    // They are freed while capturing, and not handed out again while the graph can be replayed.
+    let mut captured = scope.capture(step).unwrap();
+    let mut after: Vec<Gpu<f32>> = (0..8).map(|_| Gpu::new_on([4].into(), &device, &stream)).collect();
+    after.iter_mut().for_each(|t| t.fill(-1.0));
+
+    scope.replay(&mut captured, step).unwrap();
+    scope.replay(&mut captured, step).unwrap();
+    assert_eq!(scope.gradient(x).as_ndarray().as_slice().unwrap(), &[2.0, 4.0, 6.0, 8.0]);
+    assert_eq!(scope.gradient(w).as_ndarray().as_slice().unwrap(), &[10.0, 12.0, 14.0, 16.0]);
+    assert!(after.iter().all(|t| t.as_ndarray().iter().all(|v| *v == -1.0)));
+
+    // They are freed with the graph.
+    let allocated = device.memory_stats().allocated;
+    drop(captured);
+    assert!(device.memory_stats().allocated < allocated);
+}
+
+#[test]
+fn test_stream_event() {
+    use crate::gpu::{Event, Stream, get_default_device};
+    use crate::storage::Gpu;
+    use crate::storage::Storage;
+
+    assert!(Stream::null().is_null());
+
+    let device = get_default_device();
+    let producer = device.fork().unwrap();
+    let consumer = device.fork().unwrap();
+    assert!(!producer.is_null());
+
+    let start = Event::new(&device, true).unwrap();
+    let end = Event::new(&device, true).unwrap();
+
+    let mut x: Gpu<f32> = Gpu::new_on([1 << 20].into(), &device, &producer);
+
+    start.record(&producer).unwrap();
+    x.fill(1.0);
+    end.record(&producer).unwrap();
+
+    consumer.wait_event(&end).unwrap();
+    consumer.synchronize().unwrap();
+
+    assert!(end.query().unwrap());
+    assert!(producer.query().unwrap());
+    assert!(end.elapsed_time(&start).unwrap() >= 0.0);
+    assert!(x.as_ndarray().iter().all(|v| *v == 1.0));
+}
+
+#[test]
+fn test_profiler() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use crate::gpu::get_default_device;
+    use crate::nn::{ScopeBuilder, Phase, add, mul, cast};
+    use crate::storage::{Cpu, Storage};
+
+    let device = get_default_device();
+    let source: ScopeBuilder<Cpu<f64>> = ScopeBuilder::new(&device);
+    let builder: ScopeBuilder<Cpu<f32>> = ScopeBuilder::new(&device).with_profiler();
+    let x = builder.input([256].into());
+    let y = add(mul(x.clone(), x.clone()), cast(source.input([256].into()), &builder));
+    let (x, y) = (x.level(), y.level());
+    let mut scope = builder.build();
+
+    scope.value(x).clone_from(&[1.0; 256]);
+
+    // Another thread allocates and copies while the Scope runs, which its records do not count.
+    let stop = Arc::new(AtomicBool::new(false));
+    let (started, running) = std::sync::mpsc::channel();
+    let noise = {
+        let stop = stop.clone();
+        std::thread::spawn(move || while !stop.load(Ordering::Relaxed) {
+            let mut z: Cpu<f32> = Cpu::new([1 << 10].into());
+            z.clone_from(&[1.0; 1 << 10]);
+            let _ = started.send(());
+        })
+    };
+
+    running.recv().unwrap();
+
+    for _ in 0..100 {
+        scope.forward().unwrap();
+        scope.zero_grad();
+        scope.gradient(y).fill(1.0);
+        scope.backward().unwrap();
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    noise.join().unwrap();
+
+    let profiler = scope.profiler().unwrap();
+    let records = profiler.records().unwrap();
+    assert_eq!(records.len(), 800);
+    assert_eq!(records.iter().filter(|record| record.phase == Phase::Forward).count(), 300);
+    assert!(records.iter().all(|record| record.gpu.is_none() && record.copied == 0));
+
+    // Only the gradient Cast converts back to f64 is allocated.
+    for record in records.iter() {
+        let allocated = if record.operator == "Cast" && record.phase == Phase::Wrt(0) { 256 * 8 } else { 0 };
+        assert_eq!(record.allocated, allocated);
+    }
+
+    let trace = profiler.chrome_trace().unwrap();
+    assert!(trace.contains("\"name\":\"Mul.wrt_x2\""));
+    assert_eq!(trace.matches("\"ph\":\"X\"").count(), 800);
+
+    let summary = profiler.summary().unwrap();
+    let lines: Vec<_> = summary.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("operator"));
+    assert!(lines.iter().any(|line| line.starts_with("Mul") && line.contains(" 300 ")));
+}
+
+#[test]
+fn test_device_policy() {
+    use crate::gpu::{DeviceInfo, DevicePolicy};
+
+    let device = |ordinal, free_memory, compute_capability, multiprocessors| DeviceInfo {
+        ordinal,
+        name: "device".to_owned(),
+        uuid: String::new(),
+        pci_bus_id: String::new(),
+        total_memory: 1 << 34,
+        free_memory,
+        compute_capability,
+        multiprocessors,
+        max_threads_per_block: 1024,
+        shared_memory_per_block: 48 << 10,
+    };
+
+    let devices = [
+        device(0, 1 << 30, (7, 5), 40),
+        device(1, 1 << 33, (8, 6), 28),
+        device(2, 1 << 33, (8, 6), 84),
+        device(3, 1 << 32, (6, 1), 20),
+    ];
+
+    assert_eq!(DevicePolicy::Ordinal(3).select(&devices).unwrap(), 3);
+    assert!(DevicePolicy::Ordinal(4).select(&devices).is_err());
+    assert_eq!(DevicePolicy::MostFreeMemory.select(&devices).unwrap(), 1);
+    assert_eq!(DevicePolicy::HighestCapability.select(&devices).unwrap(), 2);
+    assert!(DevicePolicy::HighestCapability.select(&[]).is_err());
+    assert_eq!(devices[2].sm(), 86);
+
+    assert_eq!(DevicePolicy::parse("2").unwrap(), DevicePolicy::Ordinal(2));
+    assert_eq!(DevicePolicy::parse(" memory").unwrap(), DevicePolicy::MostFreeMemory);
+    assert_eq!(DevicePolicy::parse("capability").unwrap(), DevicePolicy::HighestCapability);
+    assert!(DevicePolicy::parse("fastest").is_err());
+}
+
+#[test]
+#[cfg(feature = "emulated")]
+fn test_device_pick() {
+    use crate::gpu::{Device, DevicePolicy, cu};
+    use crate::gpu::cu::emulated::take_log;
+
+    let retains = || take_log().iter().filter(|call| call.starts_with("cuDevicePrimaryCtxRetain")).count();
+    let count = cu::device::get_count().unwrap();
+
+    // Only picking by free memory retains the context of every device, to query it.
+    take_log();
+    let device = Device::pick(DevicePolicy::HighestCapability).unwrap();
+    assert_eq!(retains(), 1);
+    drop(device);
+
+    let device = Device::pick(DevicePolicy::MostFreeMemory).unwrap();
+    assert_eq!(retains(), count + 1);
+    drop(device);
+
+    assert!(Device::pick(DevicePolicy::Ordinal(count)).is_err());
+}
+
+#[test]
+fn test_context_binding() {
+    use crate::gpu::{Event, get_default_device};
+    use crate::storage::Gpu;
+    use crate::storage::Storage;
+
+    // The default device is created on another thread, and its tensors used on this one.
+    let x = std::thread::spawn(|| {
+        let mut x: Gpu<f32> = Gpu::new([4].into());
+        x.fill(2.0);
+        x
+    }).join().unwrap();
+
+    assert!(x.as_ndarray().iter().all(|v| *v == 2.0));
+
+    let y = std::thread::spawn(move || x.as_ndarray()).join().unwrap();
+    assert!(y.iter().all(|v| *v == 2.0));
+
+    // Streams and events bind their context on threads that never used the device.
+    let device = get_default_device();
+    let stream = device.fork().unwrap();
+    let event = Event::new(&device, false).unwrap();
+    event.record(&stream).unwrap();
+
+    std::thread::spawn(move || {
+        stream.wait_event(&event).unwrap();
+        stream.synchronize().unwrap();
+        assert!(stream.query().unwrap() && event.query().unwrap());
+    }).join().unwrap();
+
+    // A Device retains its context once. A Kernel keeps its module loaded after the 
+    // Device drops, and the module keeps the context, until the Kernel drops too.
+    #[cfg(feature = "emulated")]
+    {
+        use crate::gpu::Device;
+        use crate::gpu::cu::emulated::{retained, take_log};
+
+        let count = |log: &[String], call: &str| log.iter().filter(|c| c.as_str() == call).count();
+
+        take_log();
+        let device = Device::pick_ordinal(0).unwrap();
+        let kernel = device.get_kernel("strided", "strided_f32").unwrap();
+        drop(device);
+
+        let log = take_log();
+        assert_eq!(count(&log, "cuDevicePrimaryCtxRetain(0)"), 1);
+        assert_eq!(count(&log, "cuDevicePrimaryCtxRelease(0)"), 0);
+        assert_eq!(count(&log, "cuModuleLoadData"), 1);
+        assert_eq!(count(&log, "cuModuleUnload"), 0);
+        assert!(retained(0) > 0);
+
+        drop(kernel);
+        let log = take_log();
+        assert_eq!(count(&log, "cuModuleUnload"), 1);
+        assert_eq!(count(&log, "cuDevicePrimaryCtxRelease(0)"), 1);
+    }
+}
+
+#[test]
+#[cfg(feature = "emulated")]
+fn test_prefetch() {
+    use crate::gpu::get_default_device;
+    use crate::gpu::cu::emulated::pending;
+    use crate::storage::{Pinned, Prefetcher, Storage};
+
+    let device = get_default_device();
+    let compute = device.fork().unwrap();
+
+    let mut pinned: Pinned<f32> = Pinned::new_on([4].into(), &device);
+    pinned.clone_from(&[1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(pinned.as_slice(), &[1.0, 2.0, 3.0, 4.0]);
+    assert!(std::panic::catch_unwind(move || pinned.clone_from(&[1.0; 5])).is_err());
+
+    let batches: Vec<Vec<f32>> = (0..3).map(|i| vec![i as f32; 4]).collect();
+    let mut prefetcher: Prefetcher<f32> = Prefetcher::new([4].into(), &device, &compute).unwrap();
+    let mut pinned: Pinned<f32> = Pinned::new_on([4].into(), &device);
+
+    assert!(prefetcher.next().is_err());
+    prefetcher.prefetch(&batches[0]).unwrap();
+    assert!(prefetcher.prefetch(&batches[1]).is_err());
+
+    for i in 0..batches.len() {
+        let x = prefetcher.next().unwrap();
+        pinned.copy_from(x, &compute).unwrap();
+
+        // The copy of the next batch is enqueued while the work on this one is still pending.
+        if i + 1 < batches.len() {
+            prefetcher.prefetch(&batches[i + 1]).unwrap();
+            assert!(pending(compute.stream.ptr) > 0);
+        }
+
+        compute.synchronize().unwrap();
+        assert_eq!(pinned.as_slice(), batches[i].as_slice());
+    }
+}
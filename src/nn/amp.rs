@@ -0,0 +1,283 @@
+//! # Automatic Mixed Precision
+//!
+//! Parameters are stored in a master scope of a wide Float (usually `f32`),
+//! and cast into a half scope (`f16` or `bf16`) where they are used. Operators
+//! are run in whichever scope they are built in, so the half scope holds the
+//! operators that are chosen to run in half precision.
+//!
+//! Small gradients underflow in half precision, so the loss is multiplied by
+//! a scale before `backward()`. The gradients of the master parameters are
+//! divided by the same scale before the optimizer step. If any gradient is inf
+//! or NaN the step is skipped and the scale is reduced.
+
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use num_traits::AsPrimitive;
+
+use crate::storage::Storage;
+use crate::storage::{Cpu, Gpu};
+use crate::storage::Shape;
+use crate::storage::Tensor;
+use crate::storage::Float;
+use crate::gpu::Device;
+use super::scope::{Scope, ScopeBuilder};
+use super::operators::{Operator, Cast, cast};
+use super::var::Var;
+
+/// Dynamically adjusts the loss scale.
+///
+/// The scale is multiplied by `backoff` whenever an overflow is found,
+/// and by `growth` after `interval` steps in a row without one. It starts
+/// at 2^15, the largest power of two below the max of `f16`.
+pub struct LossScaler {
+    scale: f64,
+    growth: f64,
+    backoff: f64,
+    interval: usize,
+    good_steps: usize,
+}
+
+impl LossScaler {
+    pub fn new() -> Self {
+        Self {
+            scale: 32768.0,
+            growth: 2.0,
+            backoff: 0.5,
+            interval: 2000,
+            good_steps: 0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_growth(mut self, growth: f64) -> Self {
+        self.growth = growth;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: f64) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: usize) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The current loss scale.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Update the scale after a backward pass. Returns true if the optimizer step should be taken.
+    pub fn update(&mut self, found_inf: bool) -> bool {
+        if found_inf {
+            self.scale *= self.backoff;
+            self.good_steps = 0;
+            return false
+        }
+
+        self.good_steps += 1;
+
+        if self.good_steps >= self.interval {
+            self.scale *= self.growth;
+            self.good_steps = 0;
+        }
+
+        true
+    }
+}
+
+impl Default for LossScaler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct AmpBuilder<M: Storage, H: Storage> {
+    master: ScopeBuilder<M>,
+    half: ScopeBuilder<H>,
+    params: RefCell<Vec<usize>>,
+    loss: Cell<Option<usize>>,
+}
+
+impl<M: Storage, H: Storage> AmpBuilder<M, H>
+where
+    M: From<Shape> + 'static,
+    H: From<Shape> + 'static,
+    Cast<M>: Operator<H>,
+{
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            master: ScopeBuilder::new(device),
+            half: ScopeBuilder::new(device),
+            params: RefCell::new(Vec::new()),
+            loss: Cell::new(None),
+        }
+    }
+
+    /// The scope that operators are built in to run in full precision.
+    pub fn master(&self) -> &ScopeBuilder<M> {
+        &self.master
+    }
+
+    /// The scope that operators are built in to run in half precision.
+    pub fn half(&self) -> &ScopeBuilder<H> {
+        &self.half
+    }
+
+    /// Create a parameter with a master copy, returning its half precision cast.
+    pub fn param(&self, shape: Shape) -> Var<'_, H> {
+        let master = self.master.input(shape);
+        self.params.borrow_mut().push(master.level());
+        cast(master, &self.half)
+    }
+
+    /// Set the Var that `Amp::backward` differentiates, which must be built in the half scope.
+    pub fn loss(&self, loss: &Var<'_, H>) -> Result<()> {
+        if !std::ptr::eq(loss.scope(), &self.half) {
+            return Err(anyhow!("The loss must be built in the half scope!"))
+        }
+
+        self.loss.set(Some(loss.level()));
+        Ok(())
+    }
+
+    /// Finish building. The loss must be set with `loss()` first.
+    pub fn build(self) -> Result<Amp<M, H>> {
+        let loss = self.loss.get()
+            .ok_or_else(|| anyhow!("The loss was not set with AmpBuilder::loss!"))?;
+
+        Ok(Amp {
+            master: self.master.build(),
+            half: self.half.build(),
+            params: self.params.into_inner(),
+            loss,
+            scaler: LossScaler::new(),
+        })
+    }
+}
+
+pub struct Amp<M: Storage, H: Storage> {
+    master: Scope<M>,
+    half: Scope<H>,
+    params: Vec<usize>,
+    loss: usize,
+    scaler: LossScaler,
+}
+
+impl<M: Storage, H: Storage> Amp<M, H> 
+where
+    M: From<Shape> + Unscale,
+    H: From<Shape>,
+    M::F: Float,
+    H::F: Float,
+{
+    pub fn with_scaler(mut self, scaler: LossScaler) -> Self {
+        self.scaler = scaler;
+        self
+    }
+
+    pub fn scaler(&self) -> &LossScaler {
+        &self.scaler
+    }
+
+    /// Number of parameters created with `AmpBuilder::param`.
+    pub fn num_params(&self) -> usize {
+        self.params.len()
+    }
+
+    /// The master copy of the parameter at `index`.
+    pub fn param(&self, index: usize) -> &mut Tensor<M> {
+        self.master.value(self.params[index])
+    }
+
+    /// The loss computed by the last forward pass.
+    pub fn loss(&self) -> &Tensor<H> {
+        self.half.value(self.loss)
+    }
+
+    pub fn forward(&mut self) -> Result<()> {
+        self.master.forward()?;
+        self.half.forward()
+    }
+
+    /// Run the backward pass with the loss multiplied by the current scale.
+    pub fn backward(&mut self) -> Result<()> {
+        self.half.zero_grad();
+        self.master.zero_grad();
+
+        let scale = H::F::from_f64_nearest(self.scale());
+        self.half.gradient(self.loss).fill(scale);
+        self.half.backward()?;
+        self.master.backward()
+    }
+
+    /// Unscale the gradients of the master parameters and pass each
+    /// `(param, gradient)` pair to `step`, unless a gradient overflowed.
+    ///
+    /// Returns true if the step was taken.
+    pub fn step(&mut self, mut step: impl FnMut(&mut Tensor<M>, &Tensor<M>)) -> bool {
+        let factor = 1.0 / self.scale();
+        let mut found_inf = false;
+
+        for level in self.params.iter() {
+            found_inf |= !self.master.gradient(*level).unscale(factor);
+        }
+
+        if !self.scaler.update(found_inf) {
+            return false
+        }
+
+        for level in self.params.iter() {
+            step(self.master.value(*level), self.master.gradient(*level));
+        }
+
+        true
+    }
+
+    /// The scale of the loss, clamped so that it fits in the half precision Float.
+    fn scale(&self) -> f64 {
+        self.scaler.scale().min(H::F::MAX.as_())
+    }
+}
+
+/// Storages whose gradients can be unscaled where they are stored.
+pub trait Unscale: Storage {
+    /// Multiply every element by `factor`. Returns false if any element was inf or NaN.
+    fn unscale(&mut self, factor: f64) -> bool;
+}
+
+impl<T: Float> Unscale for Cpu<T> {
+    fn unscale(&mut self, factor: f64) -> bool {
+        let mut finite = true;
+
+        for g in self.as_slice_mut() {
+            let v: f64 = g.as_();
+            finite &= v.is_finite();
+            *g = T::from_f64_nearest(v * factor);
+        }
+
+        finite
+    }
+}
+
+/// Unscaled on the device, on the stream of the gradient, 
+/// so only whether an element overflowed is copied to the host.
+impl<T: Float> Unscale for Gpu<T> {
+    fn unscale(&mut self, factor: f64) -> bool {
+        let found_inf = Gpu::<i32>::new_on([1].into(), self.device(), self.stream());
+
+        self.device().get_kernel("unscale", &format!("unscale_{}", T::NAME))
+            .and_then(|kernel| kernel.launch_n(self.len(), self.stream(), (self.as_arg(), found_inf.as_arg(), factor)))
+            .expect("Failed to unscale on the device!");
+
+        found_inf.as_ndarray().iter().all(|v| *v == 0)
+    }
+}
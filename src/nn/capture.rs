@@ -0,0 +1,82 @@
+//! # Captured Steps
+//!
+//! A training step of a `Scope<Gpu<T>>`, usually `forward()`, `backward()` and
+//! the optimizer, launches the same kernels on the same memory every iteration.
+//! It is captured once into a `Graph` on the stream of the Scope and replayed,
+//! so the launch overhead is paid once per step instead of once per kernel.
+//!
+//! The graph holds the pointers of every value and gradient. Assigning a new
+//! tensor to a Var moves it, so `replay` captures the step again and updates
+//! the graph in place with the new pointers before launching it.
+
+use anyhow::{Result, anyhow};
+
+use crate::gpu::Graph;
+use crate::storage::{Float, Gpu};
+use super::scope::Scope;
+
+/// A step of a Scope captured into a graph.
+pub struct CapturedStep {
+    graph: Graph,
+    pointers: Vec<u64>,
+}
+
+impl CapturedStep {
+    /// The captured graph.
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+}
+
+impl<T: Float> Scope<Gpu<T>> {
+    /// Capture `step` into a graph on the stream of this Scope, set with `ScopeBuilder::with_stream`.
+    /// Nothing is run until the step is replayed.
+    ///
+    /// Only work enqueued on the stream of the Scope is captured, so `step` must not
+    /// fill, copy or allocate tensors on another stream. Memory cannot be requested from
+    /// the driver while capturing, so if the operators allocate scratch tensors, `step`
+    /// must be run once before to cache it. That memory is kept by the CapturedStep
+    /// until it is dropped. Scopes with checkpoints or
+    /// planned memory move their tensors while running, and cannot be captured, nor
+    /// can Scopes with operators that run on the host.
+    pub fn capture(&mut self, mut step: impl FnMut(&mut Self) -> Result<()>) -> Result<CapturedStep> {
+        if !self.is_static() {
+            return Err(anyhow!("A Scope with checkpoints or planned memory cannot be captured!"))
+        }
+
+        if self.profiler().is_some() {
+            return Err(anyhow!("A profiled Scope cannot be captured, as the profiler synchronizes with the device!"))
+        }
+
+        if let Some(name) = self.uncapturable() {
+            return Err(anyhow!("{} runs on the host, and cannot be captured!", name))
+        }
+
+        let stream = self.stream().clone();
+        let pointers = pointers(self);
+        let graph = Graph::capture(&stream, || step(self))?;
+
+        Ok(CapturedStep { graph, pointers })
+    }
+
+    /// Launch a captured step on the stream of this Scope. If any value or gradient moved
+    /// since the capture, `step` is captured again and the graph updated with the new pointers.
+    pub fn replay(&mut self, captured: &mut CapturedStep, mut step: impl FnMut(&mut Self) -> Result<()>) -> Result<()> {
+        let stream = self.stream().clone();
+        let current = pointers(self);
+
+        if current != captured.pointers {
+            captured.graph.update(&stream, || step(self))?;
+            captured.pointers = current;
+        }
+
+        captured.graph.launch(&stream)
+    }
+}
+
+/// The pointer of every value and gradient of `scope`.
+fn pointers<T: Float>(scope: &Scope<Gpu<T>>) -> Vec<u64> {
+    (0..scope.levels())
+        .flat_map(|level| [scope.value(level).as_ptr().ptr, scope.gradient(level).as_ptr().ptr])
+        .collect()
+}
@@ -0,0 +1,81 @@
+//! # Jacobians
+//!
+//! Full Jacobians of small functions in a Scope, from the Var at level `x` to 
+//! the Var at level `y`. Forward-mode finds one column per pass and reverse-mode
+//! one row per pass, so `jacobian` picks whichever needs fewer passes.
+
+use anyhow::Result;
+use ndarray::Array2;
+use num_traits::AsPrimitive;
+
+use crate::storage::Storage;
+use crate::storage::Shape;
+use crate::storage::Float;
+use super::scope::Scope;
+
+/// The Jacobian of `y` with respect to `x`, with shape `[len(y), len(x)]`.
+pub fn jacobian<S>(scope: &mut Scope<S>, y: usize, x: usize) -> Result<Array2<f64>> 
+where
+    S: Storage + From<Shape>,
+    S::F: Float,
+{
+    if scope.shape(x).len() <= scope.shape(y).len() {
+        jacobian_forward(scope, y, x)
+    } else {
+        jacobian_reverse(scope, y, x)
+    }
+}
+
+/// The Jacobian of `y` with respect to `x`, one `jvp()` per element of `x`.
+pub fn jacobian_forward<S>(scope: &mut Scope<S>, y: usize, x: usize) -> Result<Array2<f64>> 
+where
+    S: Storage + From<Shape>,
+    S::F: Float,
+{
+    let (m, n) = (scope.shape(y).len(), scope.shape(x).len());
+    let mut out = Array2::zeros((m, n));
+
+    for j in 0..n {
+        scope.zero_tangents()?;
+        scope.tangent(x).clone_from(&basis(n, j));
+        scope.jvp()?;
+
+        for (i, v) in scope.tangent(y).as_ndarray().iter().enumerate() {
+            out[[i, j]] = v.as_();
+        }
+    }
+
+    Ok(out)
+}
+
+/// The Jacobian of `y` with respect to `x`, one `forward()` and `backward()` per element of `y`.
+/// The forward pass is run again for every row, as planned memory reuses the values once
+/// they are differentiated.
+pub fn jacobian_reverse<S>(scope: &mut Scope<S>, y: usize, x: usize) -> Result<Array2<f64>> 
+where
+    S: Storage + From<Shape>,
+    S::F: Float,
+{
+    let (m, n) = (scope.shape(y).len(), scope.shape(x).len());
+    let mut out = Array2::zeros((m, n));
+
+    for i in 0..m {
+        scope.forward()?;
+        scope.zero_grad();
+        scope.gradient(y).clone_from(&basis(m, i));
+        scope.backward()?;
+
+        for (j, v) in scope.gradient(x).as_ndarray().iter().enumerate() {
+            out[[i, j]] = v.as_();
+        }
+    }
+
+    Ok(out)
+}
+
+/// A vector of `len` zeros with a one at `index`.
+fn basis<T: Float>(len: usize, index: usize) -> Vec<T> {
+    let mut out = vec![T::ZERO; len];
+    out[index] = T::from_f64_nearest(1.0);
+    out
+}
@@ -0,0 +1,221 @@
+
+use std::fmt;
+
+use crate::storage::Shape;
+
+/// Bytes held by the values and gradients of a Scope.
+///
+/// For a memory plan, `peak` is the planned peak: the bytes of the 
+/// tensors that are never planned plus the bytes of every pooled buffer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Every value and gradient, if nothing was ever freed.
+    pub total: usize,
+    /// Kept alive between forward and backward, with checkpointed segments freed.
+    pub resident: usize,
+    /// The most held at once, while the largest segment is recomputed during backward.
+    pub peak: usize,
+}
+
+impl MemoryReport {
+    /// Bytes saved by checkpointing or planning, or zero if the peak is above the total.
+    pub fn saved(&self) -> usize {
+        self.total.saturating_sub(self.peak)
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "total: {} B, resident: {} B, peak: {} B, saved: {} B", self.total, self.resident, self.peak, self.saved())
+    }
+}
+
+/// What a memory plan has to keep tensors alive for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlanMode {
+    /// Values are kept until the backward pass of their producer, and gradients are planned too.
+    Training,
+    /// Only the forward pass is run, so values are freed after their last consumer, 
+    /// and elementwise operators can overwrite an input that is no longer needed.
+    Inference,
+}
+
+/// A value or gradient of a Var, by level.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Slot {
+    Value(usize),
+    Gradient(usize),
+}
+
+/// The levels a node reads and writes, and the input it can overwrite.
+pub(crate) struct PlanNode {
+    pub inputs: Vec<usize>,
+    pub outputs: Vec<usize>,
+    pub inplace: Option<usize>,
+}
+
+/// The steps during which a slot is alive, where step `i` is the forward pass 
+/// of node `i` and step `2n - 1 - i` is the backward pass of node `i`.
+struct Lifetime {
+    slot: Slot,
+    len: usize,
+    first: usize,
+    last: usize,
+    /// The level of the input whose buffer this value takes over in-place.
+    from: Option<usize>,
+    buffer: usize,
+}
+
+/// Which buffer of a shared pool every planned value and gradient lives in, and when.
+///
+/// Only the values and gradients of outputs that are consumed by other nodes in the 
+/// scope are planned. Inputs, results and anything read by another scope keep their 
+/// own tensors. Buffers are only shared between tensors of the same length.
+pub(crate) struct MemoryPlan {
+    pub mode: PlanMode,
+    pub shapes: Vec<Shape>,
+    /// Levels whose tensors live in the pool.
+    pub planned: Vec<usize>,
+    /// Slots to move out of the pool before each step.
+    pub acquire: Vec<Vec<(Slot, usize)>>,
+    /// Slots to move back into the pool after each step.
+    pub release: Vec<Vec<(Slot, usize)>>,
+    /// For every node, the input its output overwrites.
+    pub inplace: Vec<Option<usize>>,
+    pub buffers: usize,
+    pub report: MemoryReport,
+}
+
+/// Plan `nodes`, in topological order, over Vars of the provided shapes. Levels
+/// that are `kept` are never planned. `size` is the size of an element in bytes.
+pub(crate) fn plan(mode: PlanMode, nodes: &[PlanNode], shapes: Vec<Shape>, kept: &[bool], size: usize) -> MemoryPlan {
+    let lens: Vec<usize> = shapes.iter().map(|shape| shape.len()).collect();
+    let n = nodes.len();
+    let steps = match mode {
+        PlanMode::Training => 2 * n,
+        PlanMode::Inference => n,
+    };
+
+    let mut producer = vec![None; lens.len()];
+    let mut consumers = vec![Vec::new(); lens.len()];
+
+    for (i, node) in nodes.iter().enumerate() {
+        node.outputs.iter().for_each(|level| producer[*level] = Some(i));
+        node.inputs.iter().for_each(|level| consumers[*level].push(i));
+    }
+
+    let is_planned: Vec<bool> = (0..lens.len())
+        .map(|level| producer[level].is_some() && !consumers[level].is_empty() && !kept[level])
+        .collect();
+
+    let planned: Vec<usize> = (0..lens.len()).filter(|level| is_planned[*level]).collect();
+
+    let mut lifetimes = Vec::new();
+    let mut value_last = vec![0; lens.len()];
+
+    for level in planned.iter().copied() {
+        let p = producer[level].unwrap();
+        let c = *consumers[level].iter().max().unwrap();
+
+        // The backward pass of the producer can read its output, after every consumer.
+        let last = match mode {
+            PlanMode::Training => 2 * n - 1 - p,
+            PlanMode::Inference => c,
+        };
+
+        value_last[level] = last;
+        lifetimes.push(Lifetime { slot: Slot::Value(level), len: lens[level], first: p, last, from: None, buffer: 0 });
+
+        if mode == PlanMode::Training {
+            lifetimes.push(Lifetime { slot: Slot::Gradient(level), len: lens[level], first: 2 * n - 1 - c, last: 2 * n - 1 - p, from: None, buffer: 0 });
+        }
+    }
+
+    // An output can take over the buffer of an input that dies at the same node.
+    let mut inplace = vec![None; n];
+
+    if mode == PlanMode::Inference {
+        for (i, node) in nodes.iter().enumerate() {
+            let index = match node.inplace {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let x = node.inputs[index];
+            let y = node.outputs[0];
+            if is_planned[x] && is_planned[y] && value_last[x] == i && lens[x] == lens[y] 
+                && node.inputs.iter().filter(|level| **level == x).count() == 1 
+            {
+                inplace[i] = Some(index);
+                lifetimes.iter_mut()
+                    .find(|l| l.slot == Slot::Value(y))
+                    .unwrap()
+                    .from = Some(x);
+            }
+        }
+    }
+
+    lifetimes.sort_by_key(|l| l.first);
+
+    let mut acquire = vec![Vec::new(); steps];
+    let mut release = vec![Vec::new(); steps];
+    let mut buffers: Vec<(usize, usize)> = Vec::new();
+    let mut assigned = vec![0; lens.len()];
+    let mut handed_off = Vec::new();
+
+    for l in lifetimes.iter_mut() {
+        l.buffer = match l.from {
+            Some(x) => {
+                handed_off.push(x);
+                assigned[x]
+            }
+            None => {
+                let free = buffers.iter().position(|(len, busy)| *len == l.len && *busy < l.first);
+
+                free.unwrap_or_else(|| {
+                    buffers.push((l.len, 0));
+                    buffers.len() - 1
+                })
+            }
+        };
+
+        buffers[l.buffer].1 = l.last;
+
+        if let Slot::Value(level) = l.slot {
+            assigned[level] = l.buffer;
+        }
+
+        if l.from.is_none() {
+            acquire[l.first].push((l.slot, l.buffer));
+        }
+    }
+
+    // Inputs that were taken over in-place are never moved back into the pool.
+    for l in lifetimes.iter() {
+        if !matches!(l.slot, Slot::Value(level) if handed_off.contains(&level)) {
+            release[l.last].push((l.slot, l.buffer));
+        }
+    }
+
+    let total = lens.iter().map(|len| 2 * len * size).sum::<usize>();
+    let planned_bytes = planned.iter().map(|level| 2 * lens[*level] * size).sum::<usize>();
+    let kept = total - planned_bytes;
+
+    let resident = kept + lifetimes.iter()
+        .filter(|l| l.first < n && l.last >= n)
+        .map(|l| l.len * size)
+        .sum::<usize>();
+
+    let pooled = buffers.iter().map(|(len, _)| len * size).sum::<usize>();
+
+    MemoryPlan {
+        mode,
+        shapes,
+        planned,
+        acquire,
+        release,
+        inplace,
+        buffers: buffers.len(),
+        report: MemoryReport { total, resident, peak: kept + pooled },
+    }
+}
@@ -3,4 +3,20 @@ mod scope;
 mod operators;
 mod node;
 mod scheduler;
-mod var;
\ No newline at end of file
+mod var;
+mod amp;
+mod memory;
+mod jacobian;
+mod capture;
+mod profiler;
+
+pub use amp::{Amp, AmpBuilder, LossScaler, Unscale};
+pub use scope::{Scope, ScopeBuilder};
+pub use operators::{add, mul, cast, fill, cross_entropy, as_strided};
+pub use operators::{PadMode, pad, permute, transpose, squeeze, unsqueeze};
+pub use operators::{concat, split, reshape};
+pub use operators::{gather, scatter, scatter_add, index_select, masked_fill, embedding};
+pub use memory::{MemoryReport, PlanMode};
+pub use capture::CapturedStep;
+pub use profiler::{Profiler, Phase, Record};
+pub use jacobian::{jacobian, jacobian_forward, jacobian_reverse};
\ No newline at end of file
@@ -1,8 +1,10 @@
 
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::marker::PhantomData;
 
 use upto::UpTo;
-use ndarray::Array4;
+use anyhow::Result;
 
 use crate::util::Unsafe;
 use crate::storage::Storage;
@@ -12,12 +14,22 @@ use crate::storage::Shape;
 use crate::gpu::Kernel;
 use crate::gpu::Stream;
 use crate::gpu::Device;
-use crate::storage::Float;
+use crate::storage::Element;
+use super::var::Var;
 
+/// The most kernels a node can hold, stored inline since nodes are launched from the hot path.
+pub const MAX_KERNELS: usize = 6;
+
+/// An operator with its inputs and outputs.
+///
+/// Inputs and outputs are indexed from 0. `x1..x5` and `g1..g5`
+/// are shorthands for the first five inputs and their gradients.
+/// There is no limit on inputs and outputs, but at most `MAX_KERNELS` kernels.
 pub struct Node<S: Storage> {
-    operator: Box<dyn Operator<S>>,
-    dependencies: UpTo<6, Arc<Dependency<S>>>,
-    kernels: UpTo<6, Kernel>,
+    operator: Unsafe<Box<dyn Operator<S>>>,
+    inputs: Vec<Arc<Dependency<S>>>,
+    outputs: Vec<Arc<Dependency<S>>>,
+    kernels: UpTo<MAX_KERNELS, Kernel>,
     stream: Stream,
 }
 
@@ -25,14 +37,29 @@ impl<S: Storage> Node<S>
 where
     S: From<Shape> 
 {
+    /// Set the shape of the first output.
     pub fn reshape(&self, shape: Shape) {
-        *self.dependencies[0].input.get_mut() = Tensor::new(shape);
+        self.reshape_output(0, shape)
+    }
+
+    /// Compute the first output by overwriting the input at `index`, which is moved into Y.
+    pub(crate) fn forward_inplace(&self, index: usize) -> Result<()> {
+        let x = std::mem::replace(self.inputs[index].input.get_mut(), Tensor::new(Shape::from([1])));
+        *self.outputs[0].input.get_mut() = x;
+
+        self.operator.get_mut().forward_inplace(self, index)
+    }
+
+    /// Set the shape of the output at `index`, resetting its value and gradient.
+    pub fn reshape_output(&self, index: usize, shape: Shape) {
+        *self.outputs[index].gradient.get_mut() = Tensor::new(shape.clone());
+        *self.outputs[index].input.get_mut() = Tensor::new(shape);
     }
 }
 
 impl<S: Storage> Node<S> {
-    pub fn stream(&self) -> Stream {
-        self.stream
+    pub fn stream(&self) -> &Stream {
+        &self.stream
     }
 
     pub fn kernel(&self, index: usize) -> &Kernel {
@@ -40,82 +67,233 @@ impl<S: Storage> Node<S> {
     }
 
     pub fn build() -> NodeBuilder<S> {
-        todo!()
+        NodeBuilder {
+            _storage: PhantomData,
+            deps: Vec::new(),
+            kern: Vec::new(),
+            outputs: 1,
+        }
+    }
+
+    /// Compute the outputs of this node.
+    pub fn forward(&self) -> Result<()> {
+        self.operator.get_mut().forward(self)
+    }
+
+    /// Recompute the output shapes of this node.
+    pub fn resize(&self) -> Result<()> {
+        self.operator.get_mut().reshape(self)
+    }
+
+    /// Compute the gradients of all inputs of this node. 
+    pub fn backward(&self) -> Result<()> {
+        self.operator.get().backward(self)
+    }
+
+    /// Compute the tangents of all outputs of this node.
+    pub fn tangent(&self) -> Result<()> {
+        self.operator.get().tangent(self)
+    }
+
+    pub(crate) fn operator(&self) -> &dyn Operator<S> {
+        self.operator.get().as_ref()
+    }
+
+    /// The levels of the inputs of this node in its scope.
+    pub(crate) fn input_levels(&self) -> Vec<usize> {
+        self.inputs.iter().map(|dep| dep.index).collect()
+    }
+
+    /// The levels of the outputs of this node in its scope.
+    pub(crate) fn output_levels(&self) -> Vec<usize> {
+        self.outputs.iter().map(|dep| dep.index).collect()
+    }
+
+    /// The number of inputs of this node.
+    pub fn inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// The number of outputs of this node.
+    pub fn outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// The input at `index`.
+    pub fn x(&self, index: usize) -> &Tensor<S> {
+        self.inputs[index].input.get()
+    }
+
+    /// The gradient of the input at `index`.
+    pub fn g(&self, index: usize) -> &mut Tensor<S> {
+        self.inputs[index].gradient.get_mut()
+    }
+
+    /// The output at `index`.
+    pub fn y(&self, index: usize) -> &mut Tensor<S> {
+        self.outputs[index].input.get_mut()
     }
 
-    pub fn y(&self) -> &mut Tensor<S> {
-        self.dependencies[0].input.get_mut()
+    /// The gradient of the output at `index`.
+    pub fn gy(&self, index: usize) -> &Tensor<S> {
+        self.outputs[index].gradient.get()
     }
 
-    pub fn gy(&self) -> &Tensor<S> {
-        self.dependencies[0].gradient.get()
+    /// The tangent of the input at `index`, in forward-mode.
+    pub fn tx(&self, index: usize) -> &Tensor<S> {
+        self.inputs[index].tangent.get()
+    }
+
+    /// The tangent of the output at `index`, in forward-mode.
+    pub fn ty(&self, index: usize) -> &mut Tensor<S> {
+        self.outputs[index].tangent.get_mut()
     }
 
     pub fn x1(&self) -> &Tensor<S> {
-        self.dependencies[1].input.get()
+        self.x(0)
     }
 
     pub fn x2(&self) -> &Tensor<S> {
-        self.dependencies[2].input.get()
+        self.x(1)
     }
 
     pub fn x3(&self) -> &Tensor<S> {
-        self.dependencies[3].input.get()
+        self.x(2)
     }
 
     pub fn x4(&self) -> &Tensor<S> {
-        self.dependencies[4].input.get()
+        self.x(3)
     }
 
     pub fn x5(&self) -> &Tensor<S> {
-        self.dependencies[5].input.get()
+        self.x(4)
     }
 
     pub fn g1(&self) -> &mut Tensor<S> {
-        self.dependencies[1].gradient.get_mut()
+        self.g(0)
     }
 
     pub fn g2(&self) -> &mut Tensor<S> {
-        self.dependencies[2].gradient.get_mut()
+        self.g(1)
     }
 
     pub fn g3(&self) -> &mut Tensor<S> {
-        self.dependencies[3].gradient.get_mut()
+        self.g(2)
     }
 
     pub fn g4(&self) -> &mut Tensor<S> {
-        self.dependencies[4].gradient.get_mut()
+        self.g(3)
     }
 
     pub fn g5(&self) -> &mut Tensor<S> {
-        self.dependencies[5].gradient.get_mut()
+        self.g(4)
     }
 }
 
-struct Dependency<S: Storage> {
+pub(crate) struct Dependency<S: Storage> {
     pub input: Unsafe<Tensor<S>>,
     pub gradient: Unsafe<Tensor<S>>,
+    /// Only sized to the input by `Scope::zero_tangents`, so it costs nothing until forward-mode is used.
+    pub tangent: Unsafe<Tensor<S>>,
     pub index: usize,
+    /// Read by an operator of another scope, through an Input or a Cast, 
+    /// so it must stay alive whatever the nodes of its own scope do.
+    pub shared: AtomicBool,
+}
+
+impl<S: Storage> Dependency<S> 
+where
+    S: From<Shape>
+{
+    pub fn new(shape: Shape, index: usize) -> Self {
+        Self {
+            input: Unsafe::new(Tensor::new(shape.clone())),
+            gradient: Unsafe::new(Tensor::new(shape)),
+            tangent: Unsafe::new(Tensor::new(Shape::from([1]))),
+            index,
+            shared: AtomicBool::new(false),
+        }
+    }
+}
+
+/// A dependency from a scope of another storage that an operator reads, 
+/// but never differentiates. Used for integer targets, indices and masks.
+pub struct Input<U: Storage>(Arc<Dependency<U>>);
+
+impl<U: Storage> Clone for Input<U> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<U: Storage> Input<U> {
+    pub fn new(x: &Var<U>) -> Self {
+        Self(x.dependency())
+    }
+
+    pub fn get(&self) -> &Tensor<U> {
+        self.0.input.get()
+    }
 }
 
 pub struct NodeBuilder<S: Storage> {
-    data: Array4<S::F>,
+    _storage: PhantomData<S>,
     deps: Vec<usize>,
     kern: Vec<Kernel>,
+    outputs: usize,
 }
 
-impl<S: Storage> NodeBuilder<S> {
-    pub fn with_kernel(mut self, dev: &Arc<Device>, module: &str, kern: &str) -> Self {
+impl<S: Storage + 'static> NodeBuilder<S> {
+    /// Add the Var as the next input of the node.
+    pub fn with_input(mut self, x: &Var<S>) -> Self {
+        self.deps.push(x.level());
+        self
+    }
+
+    /// Give the node `count` outputs instead of one.
+    pub fn with_outputs(mut self, count: usize) -> Self {
+        self.outputs = count;
+        self
+    }
+
+    /// The number of outputs the node will have.
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    /// Create the Node with `outputs` on `stream`, using the levels of the inputs to find their dependencies.
+    pub(crate) fn finish(self, operator: impl Operator<S> + 'static, outputs: Vec<Arc<Dependency<S>>>, deps: &[Arc<Dependency<S>>], stream: Stream) -> Node<S> {
+        Node {
+            operator: Unsafe::new(Box::new(operator)),
+            inputs: self.deps.iter().map(|level| deps[*level].clone()).collect(),
+            outputs,
+            kernels: UpTo::from_vec(self.kern),
+            stream,
+        }
+    }
+
+    /// Add the kernel `<kern>_<dtype>` of `module`, loading the module on `dev` if it isn't yet.
+    /// Panics if the kernel fails to load, or if the node already holds `MAX_KERNELS` kernels.
+    pub fn with_kernel(self, dev: &Arc<Device>, module: &str, kern: &str) -> Self {
         let full_kernel_name = kern.to_owned() + "_" + S::F::NAME;
+        self.push_kernel(dev, module, &full_kernel_name)
+    }
+
+    /// Add the kernel `<kern>_<dtype>_<index>` of `module`, for an index stored in `U`.
+    pub fn with_indexed_kernel<U: Storage>(self, dev: &Arc<Device>, module: &str, kern: &str) -> Self {
+        let full_kernel_name = format!("{}_{}_{}", kern, S::F::NAME, U::F::NAME);
+        self.push_kernel(dev, module, &full_kernel_name)
+    }
+
+    fn push_kernel(mut self, dev: &Arc<Device>, module: &str, kernel: &str) -> Self {
+        if self.kern.len() == MAX_KERNELS {
+            panic!("Failed to add kernel {} from module {}: a node holds at most {} kernels!", kernel, module, MAX_KERNELS);
+        }
 
-        let kern = 
-        if dev.is_module_loaded(module) {
-            dev.get_kernel(module, &full_kernel_name)   
-                .expect(&format!("Failed to load kernel {} from module {}!", module, full_kernel_name));
-        } else {
-            
-        };
+        let kernel = dev.get_kernel(module, kernel)
+            .unwrap_or_else(|e| panic!("Failed to load kernel {} from module {}: {}", kernel, module, e));
 
+        self.kern.push(kernel);
         self
     }
 }
\ No newline at end of file
@@ -0,0 +1,66 @@
+
+use super::*;
+
+pub struct Add;
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for Add {
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        let layout = Layout::new(node.x1().shape());
+
+        node.x1().gather(&layout, node.y(0));
+        node.y(0).scatter_add(&layout, node.x2());
+
+        Ok(())
+    }
+
+    fn inplace(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn forward_inplace(&mut self, node: &Node<S>, index: usize) -> Result<()> {
+        node.y(0).scatter_add(&Layout::new(node.y(0).shape()), node.x(1 - index));
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        let layout = Layout::new(node.x1().shape());
+
+        node.tx(0).gather(&layout, node.ty(0));
+        node.ty(0).scatter_add(&layout, node.tx(1));
+
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        let x1 = node.x1().shape();
+        let x2 = node.x2().shape();
+
+        if x1 != x2 {
+            return Err(anyhow!("X1 and X2 shape must match!"))
+        }
+
+        node.reshape(x1.clone());
+
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, index: usize) -> Result<()> {
+        node.g(index).scatter_add(&Layout::new(node.gy(0).shape()), node.gy(0));
+        Ok(())
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, S>], gy: &[Var<'s, S>], _index: usize) -> Result<Var<'s, S>> {
+        Ok(gy[0].clone())
+    }
+}
+
+pub fn add<'s, S>(x1: Var<'s, S>, x2: Var<'s, S>) -> Var<'s, S> 
+where
+    S: Accumulate + From<Shape> + 'static,
+{
+    let node = Node::<S>::build()
+        .with_input(&x1)
+        .with_input(&x2);
+
+    x1.scope().push(node, Add)
+}
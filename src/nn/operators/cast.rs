@@ -0,0 +1,133 @@
+
+use std::sync::Arc;
+
+use super::*;
+
+/// Converts a Var from a scope of another Float type into this scope.
+/// 
+/// A Node only holds dependencies of its own storage, so the input 
+/// lives on the operator instead. Its gradient is written by `backward`,
+/// since the Node has no inputs for `wrt` to be called with.
+/// 
+/// On the Gpu every conversion is a host round-trip, see `Gpu::cast_with`.
+pub struct Cast<U: Storage> {
+    source: Arc<Dependency<U>>,
+    mode: CastMode,
+}
+
+impl<T: Float, U: Float> Operator<Cpu<T>> for Cast<Cpu<U>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.source.input.cast_into(node.y(0), self.mode);
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        node.reshape(self.source.input.shape().clone());
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.source_tangent()?.cast_into(node.ty(0), self.mode);
+        Ok(())
+    }
+
+    fn backward(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        profiler::record(Phase::Wrt(0), || {
+            let shape = node.gy(0).shape();
+            let mut gradient = Cpu::<U>::from(shape.clone());
+
+            node.gy(0).cast_into(&mut gradient, self.mode);
+            self.source.gradient.get_mut().scatter_add(&Layout::new(shape), &gradient);
+
+            Ok(())
+        })
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Cpu<T>>], _gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Err(no_grad())
+    }
+}
+
+impl<T: Float, U: Float> Operator<Gpu<T>> for Cast<Gpu<U>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.source.input.cast_into(node.y(0), self.mode);
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        node.reshape(self.source.input.shape().clone());
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.source_tangent()?.cast_into(node.ty(0), self.mode);
+        Ok(())
+    }
+
+    /// The conversion is done on the host.
+    fn capturable(&self) -> bool {
+        false
+    }
+
+    fn backward(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        profiler::record(Phase::Wrt(0), || {
+            // The sum runs on the stream of the source, so the cast gradient is placed there too.
+            let shape = node.gy(0).shape();
+            let source = self.source.gradient.get_mut();
+            let mut gradient = Gpu::<U>::new_on(shape.clone(), source.device(), source.stream());
+
+            node.gy(0).cast_into(&mut gradient, self.mode);
+            source.scatter_add(&Layout::new(shape), &gradient);
+
+            Ok(())
+        })
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Gpu<T>>], _gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Err(no_grad())
+    }
+}
+
+impl<U: Storage> Cast<U> {
+    /// The tangent of the source, which is only sized by `zero_tangents` on the scope of the source.
+    fn source_tangent(&self) -> Result<&Tensor<U>> {
+        let tangent = self.source.tangent.get();
+
+        if tangent.shape() != self.source.input.shape() {
+            return Err(anyhow!("The tangent of the source of a Cast is not set, call zero_tangents on its scope first!"))
+        }
+
+        Ok(tangent)
+    }
+}
+
+/// The source of a Cast is in a scope of another storage, so a recorded backward pass
+/// cannot reach it. Cast the gradient back with `cast` instead.
+fn no_grad() -> anyhow::Error {
+    anyhow!("A Cast cannot be differentiated twice, since its source is in another scope!")
+}
+
+/// Cast `x` into `scope`, rounding to the nearest even value.
+pub fn cast<'s, S, U>(x: Var<'_, U>, scope: &'s ScopeBuilder<S>) -> Var<'s, S> 
+where
+    S: Storage + From<Shape> + 'static,
+    U: Storage + 'static,
+    Cast<U>: Operator<S>,
+{
+    cast_with(x, scope, CastMode::Nearest)
+}
+
+/// Cast `x` into `scope` with the provided CastMode.
+pub fn cast_with<'s, S, U>(x: Var<'_, U>, scope: &'s ScopeBuilder<S>, mode: CastMode) -> Var<'s, S> 
+where
+    S: Storage + From<Shape> + 'static,
+    U: Storage + 'static,
+    Cast<U>: Operator<S>,
+{
+    let cast = Cast {
+        source: x.dependency(),
+        mode,
+    };
+
+    scope.push(Node::<S>::build(), cast)
+}
@@ -0,0 +1,163 @@
+
+use super::*;
+
+/// Joins the inputs along an existing `axis`.
+pub struct Concat {
+    axis: usize,
+}
+
+impl Concat {
+    /// The layout of the input at `index` in Y.
+    fn layout<S: Storage>(&self, node: &Node<S>, index: usize) -> Result<Layout> {
+        let start = (0..index).map(|i| node.x(i).shape()[self.axis]).sum();
+        let len = node.x(index).shape()[self.axis];
+
+        Layout::new(node.y(0).shape()).narrow(self.axis, start, len)
+    }
+}
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for Concat 
+where
+    S::F: Float,
+{
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        for i in 0..node.inputs() {
+            let layout = self.layout(node, i)?;
+            node.y(0).scatter(&layout, node.x(i));
+        }
+
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        for i in 0..node.inputs() {
+            let layout = self.layout(node, i)?;
+            node.ty(0).scatter(&layout, node.tx(i));
+        }
+
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        let mut shape = node.x1().shape().as_array4();
+
+        for i in 1..node.inputs() {
+            let x = node.x(i).shape().as_array4();
+
+            if (0..4).any(|j| j != self.axis && x[j] != shape[j]) {
+                return Err(anyhow!("All inputs of Concat must match outside of axis {}!", self.axis))
+            }
+
+            shape[self.axis] += x[self.axis];
+        }
+
+        node.reshape(shape.into());
+
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, index: usize) -> Result<()> {
+        add_region(node, &self.layout(node, index)?, index)
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>], index: usize) -> Result<Var<'s, S>> {
+        let start = xs[..index].iter().map(|x| x.shape()[self.axis]).sum();
+        Ok(narrow(gy[0].clone(), self.axis, start, xs[index].shape()[self.axis]))
+    }
+}
+
+/// Joins the inputs along a new axis inserted before `axis`.
+pub struct Stack {
+    axis: usize,
+}
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for Stack 
+where
+    S::F: Float,
+{
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        for i in 0..node.inputs() {
+            let layout = Layout::new(node.y(0).shape()).select(self.axis, i)?;
+            node.y(0).scatter(&layout, node.x(i));
+        }
+
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        for i in 0..node.inputs() {
+            let layout = Layout::new(node.y(0).shape()).select(self.axis, i)?;
+            node.ty(0).scatter(&layout, node.tx(i));
+        }
+
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        let x1 = node.x1().shape();
+        let mut dims = x1.to_vec();
+
+        if dims.len() == 4 || self.axis > dims.len() {
+            return Err(anyhow!("Cannot stack inputs of shape {:?} on axis {}!", dims, self.axis))
+        }
+
+        if (1..node.inputs()).any(|i| node.x(i).shape() != x1) {
+            return Err(anyhow!("All inputs of Stack must have the same shape!"))
+        }
+
+        dims.insert(self.axis, node.inputs());
+        node.reshape(dims.as_slice().into());
+
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, index: usize) -> Result<()> {
+        let layout = Layout::new(node.gy(0).shape()).select(self.axis, index)?;
+        add_region(node, &layout, index)
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>], index: usize) -> Result<Var<'s, S>> {
+        let g = narrow(gy[0].clone(), self.axis, index, 1);
+        Ok(reshape(g, xs[index].shape().clone()))
+    }
+}
+
+/// Add the region of `gy` selected by `layout` into the gradient of the input at `index`.
+fn add_region<S: Accumulate + From<Shape>>(node: &Node<S>, layout: &Layout, index: usize) -> Result<()> {
+    let shape = node.x(index).shape();
+    let mut g = S::from(shape.clone());
+
+    node.gy(0).gather(layout, &mut g);
+    node.g(index).scatter_add(&Layout::new(shape), &g);
+
+    Ok(())
+}
+
+fn with_inputs<'s, S>(xs: &[Var<'s, S>]) -> NodeBuilder<S>
+where
+    S: Storage + 'static,
+{
+    if xs.is_empty() {
+        panic!("Expected at least 1 input!")
+    }
+
+    xs.iter().fold(Node::<S>::build(), |node, x| node.with_input(x))
+}
+
+/// Join `xs` along an existing `axis`.
+pub fn concat<'s, S>(xs: &[Var<'s, S>], axis: usize) -> Var<'s, S>
+where
+    S: Accumulate + From<Shape> + 'static,
+    S::F: Float,
+{
+    xs[0].scope().push(with_inputs(xs), Concat { axis })
+}
+
+/// Join `xs` along a new axis inserted before `axis`.
+pub fn stack<'s, S>(xs: &[Var<'s, S>], axis: usize) -> Var<'s, S>
+where
+    S: Accumulate + From<Shape> + 'static,
+    S::F: Float,
+{
+    xs[0].scope().push(with_inputs(xs), Stack { axis })
+}
@@ -0,0 +1,235 @@
+
+use num_traits::AsPrimitive;
+
+use super::*;
+
+/// Softmax cross-entropy over the channel axis, averaged over every (n, h, w).
+///
+/// X1 holds the logits in NCHW. The target holds the class index of
+/// every (n, h, w), with shape [N, 1, H, W].
+pub struct CrossEntropy<U: Storage> {
+    target: Input<U>,
+}
+
+impl<I: Integer> CrossEntropy<Cpu<I>> {
+    /// Calls `f` with the offset of (n, 0, h, w), the
+    /// channel stride, and the target class at (n, h, w).
+    fn for_each_pixel<T: Float>(&self, node: &Node<Cpu<T>>, mut f: impl FnMut(usize, usize, usize) -> Result<()>) -> Result<()> {
+        let shape = node.x1().shape();
+        let (n, c, hw) = (shape['N'], shape['C'], shape['H'] * shape['W']);
+        let target = self.target.get().as_slice();
+
+        for i in 0..n {
+            for j in 0..hw {
+                let class = target[i * hw + j].as_index();
+
+                if class >= c {
+                    return Err(anyhow!("Target class {} is out of range for {} classes!", class, c))
+                }
+
+                f(i * c * hw + j, hw, class)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Float, I: Integer> Operator<Cpu<T>> for CrossEntropy<Cpu<I>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        let x1 = node.x1().as_slice();
+        let shape = node.x1().shape();
+        let (c, count) = (shape['C'], shape.len() / shape['C']);
+
+        let mut loss = 0.0f64;
+
+        self.for_each_pixel(node, |offset, stride, class| {
+            let logits = (0..c).map(|k| AsPrimitive::<f64>::as_(x1[offset + k * stride]));
+            let max = logits.clone().fold(f64::NEG_INFINITY, f64::max);
+            let sum: f64 = logits.map(|v| (v - max).exp()).sum();
+            let target: f64 = x1[offset + class * stride].as_();
+
+            loss += sum.ln() + max - target;
+            Ok(())
+        })?;
+
+        node.y(0).fill(T::from_f64_nearest(loss / count as f64));
+
+        Ok(())
+    }
+
+    /// The tangent of the loss is the mean of `(softmax - onehot) . tx` over every pixel.
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        let x1 = node.x1().as_slice();
+        let tx = node.tx(0).as_slice();
+        let shape = node.x1().shape();
+        let (c, count) = (shape['C'], shape.len() / shape['C']);
+
+        let mut ty = 0.0f64;
+
+        self.for_each_pixel(node, |offset, stride, class| {
+            let logits = (0..c).map(|k| AsPrimitive::<f64>::as_(x1[offset + k * stride]));
+            let max = logits.clone().fold(f64::NEG_INFINITY, f64::max);
+            let sum: f64 = logits.map(|v| (v - max).exp()).sum();
+
+            for k in 0..c {
+                let v: f64 = x1[offset + k * stride].as_();
+                let t: f64 = tx[offset + k * stride].as_();
+                let onehot = if k == class { 1.0 } else { 0.0 };
+                ty += ((v - max).exp() / sum - onehot) * t;
+            }
+
+            Ok(())
+        })?;
+
+        node.ty(0).fill(T::from_f64_nearest(ty / count as f64));
+
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        check_target(node.x1().shape(), self.target.get().shape())?;
+        node.reshape([1].into());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let x1 = node.x1().as_slice();
+        let g1 = node.g1().as_slice_mut();
+        let shape = node.x1().shape();
+        let (c, count) = (shape['C'], shape.len() / shape['C']);
+        let gy: f64 = node.gy(0).as_slice()[0].as_();
+        let scale = gy / count as f64;
+
+        self.for_each_pixel(node, |offset, stride, class| {
+            let logits = (0..c).map(|k| AsPrimitive::<f64>::as_(x1[offset + k * stride]));
+            let max = logits.clone().fold(f64::NEG_INFINITY, f64::max);
+            let sum: f64 = logits.map(|v| (v - max).exp()).sum();
+
+            for k in 0..c {
+                let v: f64 = x1[offset + k * stride].as_();
+                let onehot = if k == class { 1.0 } else { 0.0 };
+                g1[offset + k * stride] = g1[offset + k * stride] + T::from_f64_nearest(((v - max).exp() / sum - onehot) * scale);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Cpu<T>>], _gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Err(no_grad())
+    }
+}
+
+/// The threads of the single block that reduces the loss over every pixel, as sized in `cross_entropy.cu`.
+const BLOCK: u32 = 256;
+
+/// On a Gpu, a target class out of range makes the loss NaN instead of an error.
+impl<T: Float, I: Integer> Operator<Gpu<T>> for CrossEntropy<Gpu<I>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        let shape = node.x1().shape();
+        let (c, hw) = (shape['C'], shape['H'] * shape['W']);
+        self.target.get().record_stream(node.stream());
+
+        node.kernel(0).launch(
+            [1],
+            [BLOCK],
+            node.stream(),
+            (
+                node.x1().as_arg(),
+                self.target.get().as_arg(),
+                node.y(0).as_arg(),
+                c as u64,
+                hw as u64,
+                (shape.len() / c) as u64,
+            )
+        )
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        let shape = node.x1().shape();
+        let (c, hw) = (shape['C'], shape['H'] * shape['W']);
+        self.target.get().record_stream(node.stream());
+
+        node.kernel(1).launch(
+            [1],
+            [BLOCK],
+            node.stream(),
+            (
+                node.x1().as_arg(),
+                self.target.get().as_arg(),
+                node.tx(0).as_arg(),
+                node.ty(0).as_arg(),
+                c as u64,
+                hw as u64,
+                (shape.len() / c) as u64,
+            )
+        )
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        check_target(node.x1().shape(), self.target.get().shape())?;
+        node.reshape([1].into());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        let shape = node.x1().shape();
+        let (c, hw) = (shape['C'], shape['H'] * shape['W']);
+        self.target.get().record_stream(node.stream());
+
+        node.kernel(2).launch_n(
+            shape.len() / c,
+            node.stream(),
+            (
+                node.x1().as_arg(),
+                self.target.get().as_arg(),
+                node.gy(0).as_arg(),
+                node.g1().as_arg(),
+                c as u64,
+                hw as u64,
+            )
+        )
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Gpu<T>>], _gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Err(no_grad())
+    }
+}
+
+/// The gradient of the loss needs the softmax of X1, which no operator computes, so it cannot be recorded.
+fn no_grad() -> anyhow::Error {
+    anyhow!("CrossEntropy cannot be differentiated twice, since its gradient needs a softmax operator!")
+}
+
+fn check_target(x1: &Shape, target: &Shape) -> Result<()> {
+    if x1['N'] != target['N'] || x1['H'] != target['H'] || x1['W'] != target['W'] || target['C'] != 1 {
+        return Err(anyhow!("Target shape must be [N, 1, H, W] of X1!"))
+    }
+
+    Ok(())
+}
+
+/// Mean softmax cross-entropy of the logits `x` against the integer class indices in `target`.
+pub fn cross_entropy<'s, S, U>(x: Var<'s, S>, target: Var<'_, U>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    CrossEntropy<U>: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&x);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_indexed_kernel::<U>(x.device(), "cross_entropy", "cross_entropy")
+            .with_indexed_kernel::<U>(x.device(), "cross_entropy", "cross_entropy_tangent")
+            .with_indexed_kernel::<U>(x.device(), "cross_entropy", "cross_entropy_wrt");
+    }
+
+    let op = CrossEntropy {
+        target: Input::new(&target),
+    };
+
+    x.scope().push(node, op)
+}
@@ -0,0 +1,151 @@
+use super::*;
+use super::index_select;
+
+/// Looks up rows of the table X1, with shape [V, D], for every id in the index.
+///
+/// Y has the shape of the index with D appended, so the index can have at most 3 dimensions.
+/// The backward pass adds into the rows of the table gradient that were looked up, 
+/// and leaves every other row untouched.
+pub struct Embedding<U: Storage> {
+    index: Input<U>,
+}
+
+impl<I: Integer> Embedding<Cpu<I>> {
+    /// Look up the rows of `table` into `y`.
+    fn apply<T: Float>(&self, table: &Tensor<Cpu<T>>, y: &mut Tensor<Cpu<T>>) -> Result<()> {
+        let shape = table.shape();
+        let (vocab, dim) = (shape[0], shape.len() / shape[0]);
+        let rows = table.as_slice();
+
+        let out: Vec<T> = self.ids(vocab)?.into_iter()
+            .flat_map(|id| rows[id * dim..(id + 1) * dim].iter().copied())
+            .collect();
+
+        y.clone_from(&out);
+
+        Ok(())
+    }
+
+    fn ids(&self, vocab: usize) -> Result<Vec<usize>> {
+        self.index.get().as_slice().iter()
+            .map(|id| {
+                let id = id.as_index();
+
+                if id >= vocab {
+                    return Err(anyhow!("Embedding id {} is out of range for {} rows!", id, vocab))
+                }
+
+                Ok(id)
+            })
+            .collect()
+    }
+}
+
+impl<U: Storage> Embedding<U> {
+    fn output(&self, x1: &Shape) -> Result<Shape> {
+        let mut dims = self.index.get().shape().to_vec();
+
+        if x1.dims() != 2 || dims.len() > 3 {
+            return Err(anyhow!("Embedding needs a table of [V, D] and an index of at most 3 dimensions!"))
+        }
+
+        dims.push(x1[1]);
+
+        Ok(dims.as_slice().into())
+    }
+}
+
+impl<T: Float, I: Integer> Operator<Cpu<T>> for Embedding<Cpu<I>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.x1(), node.y(0))
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.tx(0), node.ty(0))
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        node.reshape(self.output(node.x1().shape())?);
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let shape = node.x1().shape();
+        let (vocab, dim) = (shape[0], shape.len() / shape[0]);
+        let ids = self.ids(vocab)?;
+        let gy = node.gy(0).as_slice();
+        let g1 = node.g1().as_slice_mut();
+
+        for (i, id) in ids.into_iter().enumerate() {
+            for (g, gy) in g1[id * dim..(id + 1) * dim].iter_mut().zip(&gy[i * dim..(i + 1) * dim]) {
+                *g = *g + *gy;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The gradient of Y added into zeros of the table at its ids.
+    fn grad<'s>(&self, xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Ok(index_select::push_index_add(gy[0].clone(), 0, self.index.clone(), xs[0].shape().clone(), true))
+    }
+}
+
+/// On a Gpu, ids out of range look up zeros instead of an error.
+impl<T: Float, I: Integer> Operator<Gpu<T>> for Embedding<Gpu<I>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        index_select::launch(node, 0, &rows(node.x1().shape()), node.x1(), self.index.get(), node.y(0), 0)
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        index_select::launch(node, 0, &rows(node.x1().shape()), node.tx(0), self.index.get(), node.ty(0), 0)
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        node.reshape(self.output(node.x1().shape())?);
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        index_select::launch(node, 1, &rows(node.x1().shape()), node.gy(0), self.index.get(), node.g1(), 0)
+    }
+
+    /// The gradient of Y added into zeros of the table at its ids.
+    fn grad<'s>(&self, xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Ok(index_select::push_index_add(gy[0].clone(), 0, self.index.clone(), xs[0].shape().clone(), true))
+    }
+}
+
+/// The table of [V, D] as V rows along the first axis.
+fn rows(table: &Shape) -> Shape {
+    [table[0], table[1], 1, 1].into()
+}
+
+/// Push an Embedding of `table`, with its kernels on a Gpu.
+pub(super) fn push_embedding<'s, S, U>(table: Var<'s, S>, index: Input<U>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    Embedding<U>: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&table);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_indexed_kernel::<U>(table.device(), "gather", "index_select")
+            .with_indexed_kernel::<U>(table.device(), "gather", "index_select_wrt");
+    }
+
+    table.scope().push(node, Embedding { index })
+}
+
+/// Look up the rows of `table` for every id in `index`.
+pub fn embedding<'s, S, U>(table: Var<'s, S>, index: Var<'_, U>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    Embedding<U>: Operator<S>,
+{
+    push_embedding(table, Input::new(&index))
+}
@@ -0,0 +1,38 @@
+
+use super::*;
+
+/// A constant with every element set to `value`. It has no inputs.
+pub struct Fill {
+    shape: Shape,
+    value: f64,
+}
+
+impl<S> Operator<S> for Fill 
+where
+    S: Storage + From<Shape>,
+    S::F: Float,
+{
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        node.y(0).fill(S::F::from_f64_nearest(self.value));
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        node.reshape(self.shape.clone());
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        node.ty(0).fill(S::F::ZERO);
+        Ok(())
+    }
+}
+
+/// A constant of `shape` in `scope`, with every element set to `value`.
+pub fn fill<S>(scope: &ScopeBuilder<S>, shape: Shape, value: f64) -> Var<'_, S>
+where
+    S: Storage + From<Shape> + 'static,
+    S::F: Float,
+{
+    scope.push(Node::<S>::build(), Fill { shape, value })
+}
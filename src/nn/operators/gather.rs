@@ -0,0 +1,447 @@
+use num_traits::AsPrimitive;
+
+use crate::gpu::{cu, Ptr};
+use super::*;
+
+/// Reads X1 along `axis` at the positions in the index, so that
+/// `y[n, c, h, w] = x1[.., index[n, c, h, w], ..]` with the other coordinates unchanged.
+pub struct Gather<U: Storage> {
+    axis: usize,
+    index: Input<U>,
+}
+
+/// Writes X2 into a copy of X1 along `axis` at the positions in the index,
+/// so that `y[.., index[n, c, h, w], ..] = x2[n, c, h, w]`. With `add`, 
+/// the values are summed instead, and repeated positions accumulate. Without
+/// it, repeated positions are an error, as the Gpu writes them in no set order.
+pub struct Scatter<U: Storage> {
+    axis: usize,
+    index: Input<U>,
+    add: bool,
+}
+
+/// For every element of `index`, the offset of the element of `shape` it points to.
+pub(super) fn positions<I: Integer>(index: &[I], index_shape: &Shape, shape: &Shape, axis: usize) -> Result<Vec<usize>> {
+    let dims = shape.as_array4();
+    let [_, c, h, w] = index_shape.as_array4();
+
+    check_index(index_shape, shape, axis)?;
+
+    index.iter().enumerate()
+        .map(|(i, v)| {
+            let mut coords = [i / (c * h * w), (i / (h * w)) % c, (i / w) % h, i % w];
+            coords[axis] = v.as_index();
+
+            if coords[axis] >= dims[axis] {
+                return Err(anyhow!("Index {} is out of range for axis {} of size {}!", coords[axis], axis, dims[axis]))
+            }
+
+            Ok(((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3])
+        })
+        .collect()
+}
+
+/// No two elements of the index may point to the same position.
+fn check_unique(positions: &[usize]) -> Result<()> {
+    let mut sorted = positions.to_vec();
+    sorted.sort_unstable();
+
+    match sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        Some(pair) => Err(anyhow!("Position {} is written twice by a scatter, use scatter_add to sum repeated positions!", pair[0])),
+        None => Ok(()),
+    }
+}
+
+/// Check every position of an index on a Gpu, by copying it to the host, 
+/// so that the kernels are never launched with positions the Cpu rejects.
+fn check_positions<I: Integer>(index: &Tensor<Gpu<I>>, shape: &Shape, axis: usize, unique: bool) -> Result<()> {
+    let host = index.as_ndarray();
+    let positions = positions(host.as_slice().unwrap(), index.shape(), shape, axis)?;
+
+    if unique {
+        check_unique(&positions)?;
+    }
+
+    Ok(())
+}
+
+/// The index may not be larger than `shape` on any axis but `axis`.
+fn check_index(index_shape: &Shape, shape: &Shape, axis: usize) -> Result<()> {
+    check_axis(axis)?;
+
+    (0..4).filter(|i| *i != axis)
+        .find(|i| index_shape[*i] > shape[*i])
+        .map_or(Ok(()), |i| Err(anyhow!("Index is larger than the input on axis {}!", i)))
+}
+
+impl<T: Float, I: Integer> Operator<Cpu<T>> for Gather<Cpu<I>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.x1(), node.y(0))
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.tx(0), node.ty(0))
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        check_axis(self.axis)?;
+        node.reshape(self.index.get().shape().clone());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let index = self.index.get();
+        let positions = positions(index.as_slice(), index.shape(), node.x1().shape(), self.axis)?;
+        let mut g1 = vec![0.0f64; node.g1().len()];
+
+        for (i, gy) in positions.into_iter().zip(node.gy(0).as_slice()) {
+            g1[i] += AsPrimitive::<f64>::as_(*gy);
+        }
+
+        accumulate(node.g1(), &from_f64(g1));
+
+        Ok(())
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Ok(self.grad_var(xs, gy))
+    }
+}
+
+/// On a Gpu, the index is checked on the host before the forward and tangent passes.
+impl<T: Float, I: Integer> Operator<Gpu<T>> for Gather<Gpu<I>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        check_positions(self.index.get(), node.x1().shape(), self.axis, false)?;
+        launch(node, 0, node.x1().as_arg(), self.index.get(), node.y(0).as_arg(), self.axis)
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        check_positions(self.index.get(), node.x1().shape(), self.axis, false)?;
+        launch(node, 0, node.tx(0).as_arg(), self.index.get(), node.ty(0).as_arg(), self.axis)
+    }
+
+    /// The index is copied to the host to be checked.
+    fn capturable(&self) -> bool {
+        false
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        let index = self.index.get().shape();
+        check_index(index, node.x1().shape(), self.axis)?;
+        node.reshape(index.clone());
+        Ok(())
+    }
+
+    /// GY is added into G1 at the positions, summing repeated positions.
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        launch(node, 1, node.gy(0).as_arg(), self.index.get(), node.g1().as_arg(), self.axis)
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Ok(self.grad_var(xs, gy))
+    }
+}
+
+impl<T: Float, I: Integer> Operator<Cpu<T>> for Scatter<Cpu<I>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.x1(), node.x2(), node.y(0))
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.tx(0), node.tx(1), node.ty(0))
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.check(node.x1().shape(), node.x2().shape())?;
+        node.reshape(node.x1().shape().clone());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, index: usize) -> Result<()> {
+        match index {
+            0 => self.wrt_base(node),
+            _ => self.wrt_src(node),
+        }
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Ok(self.grad_var(xs, gy, index))
+    }
+}
+
+/// On a Gpu, the index is checked on the host before the forward and tangent passes.
+impl<T: Float, I: Integer> Operator<Gpu<T>> for Scatter<Gpu<I>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.apply_gpu(node, node.x1(), node.x2(), node.y(0))
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.apply_gpu(node, node.tx(0), node.tx(1), node.ty(0))
+    }
+
+    /// The index is copied to the host to be checked.
+    fn capturable(&self) -> bool {
+        false
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.check(node.x1().shape(), node.x2().shape())?;
+        node.reshape(node.x1().shape().clone());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Gpu<T>>, index: usize) -> Result<()> {
+        let index_tensor = self.index.get();
+
+        if index == 1 {
+            return launch(node, 2, node.gy(0).as_arg(), index_tensor, node.g2().as_arg(), self.axis)
+        }
+
+        // Positions that were overwritten by X2 receive no gradient
+        let g1 = node.g1();
+        let mask = Gpu::<bool>::new_on(g1.shape().clone(), g1.device(), node.stream());
+
+        if !self.add {
+            let [n, c, h, w] = node.x1().shape().as_array4();
+            let [_, ic, ih, iw] = index_tensor.shape().as_array4();
+
+            node.kernel(1).launch_n(
+                index_tensor.len(),
+                node.stream(),
+                (
+                    index_tensor.as_arg(),
+                    mask.as_arg(),
+                    n as u64, c as u64, h as u64, w as u64,
+                    ic as u64, ih as u64, iw as u64,
+                    self.axis as u64,
+                )
+            )?;
+        }
+
+        node.kernel(3).launch_n(
+            g1.len(),
+            node.stream(),
+            (
+                node.gy(0).as_arg(),
+                mask.as_arg(),
+                g1.as_arg(),
+            )
+        )
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Ok(self.grad_var(xs, gy, index))
+    }
+}
+
+/// Launch the gather kernel `k` of `node` over every element of the index,
+/// with the dims of X1 and of the index, as `(x, index, y, n, c, h, w, ic, ih, iw, axis)`.
+fn launch<T: Float, I: Integer>(node: &Node<Gpu<T>>, k: usize, x: Ptr<T>, index: &Tensor<Gpu<I>>, y: Ptr<T>, axis: usize) -> Result<()> {
+    let [n, c, h, w] = node.x1().shape().as_array4();
+    let [_, ic, ih, iw] = index.shape().as_array4();
+    index.record_stream(node.stream());
+
+    node.kernel(k).launch_n(
+        index.len(),
+        node.stream(),
+        (
+            x,
+            index.as_arg(),
+            y,
+            n as u64, c as u64, h as u64, w as u64,
+            ic as u64, ih as u64, iw as u64,
+            axis as u64,
+        )
+    )
+}
+
+impl<U: Storage + 'static> Gather<U> {
+    /// Y scattered back into zeros of the shape of X1.
+    fn grad_var<'s, S>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>]) -> Var<'s, S>
+    where
+        S: StorageInfo + From<Shape> + 'static,
+        Scatter<U>: Operator<S>,
+        S::F: Float,
+    {
+        let zeros = fill(xs[0].scope(), xs[0].shape().clone(), 0.0);
+        push_scatter(zeros, self.axis, self.index.clone(), gy[0].clone(), true)
+    }
+}
+
+impl<U: Storage + 'static> Scatter<U> {
+    fn check(&self, x1: &Shape, x2: &Shape) -> Result<()> {
+        check_index(self.index.get().shape(), x1, self.axis)?;
+
+        if self.index.get().shape() != x2 {
+            return Err(anyhow!("Index and X2 shape must match!"))
+        }
+
+        Ok(())
+    }
+
+    fn grad_var<'s, S>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>], index: usize) -> Var<'s, S>
+    where
+        S: StorageInfo + From<Shape> + 'static,
+        Scatter<U>: Operator<S>,
+        Gather<U>: Operator<S>,
+        S::F: Float,
+    {
+        match index {
+            0 if self.add => gy[0].clone(),
+            0 => {
+                let zeros = fill(xs[0].scope(), xs[1].shape().clone(), 0.0);
+                push_scatter(gy[0].clone(), self.axis, self.index.clone(), zeros, false)
+            }
+            _ => push_gather(gy[0].clone(), self.axis, self.index.clone()),
+        }
+    }
+}
+
+impl<I: Integer> Scatter<Cpu<I>> {
+    fn apply<T: Float>(&self, x1: &Tensor<Cpu<T>>, x2: &Tensor<Cpu<T>>, y: &mut Tensor<Cpu<T>>) -> Result<()> {
+        let index = self.index.get();
+        let positions = positions(index.as_slice(), index.shape(), x1.shape(), self.axis)?;
+        let mut out = x1.as_slice().to_vec();
+
+        if !self.add {
+            check_unique(&positions)?;
+        }
+
+        for (i, x2) in positions.into_iter().zip(x2.as_slice()) {
+            out[i] = if self.add { out[i] + *x2 } else { *x2 };
+        }
+
+        y.clone_from(&out);
+
+        Ok(())
+    }
+
+    /// Positions that were overwritten by X2 receive no gradient.
+    fn wrt_base<T: Float>(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        let mut g1 = node.gy(0).as_slice().to_vec();
+
+        if !self.add {
+            let index = self.index.get();
+
+            for i in positions(index.as_slice(), index.shape(), node.x1().shape(), self.axis)? {
+                g1[i] = T::ZERO;
+            }
+        }
+
+        accumulate(node.g1(), &g1);
+
+        Ok(())
+    }
+
+    fn wrt_src<T: Float>(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        let index = self.index.get();
+        let positions = positions(index.as_slice(), index.shape(), node.x1().shape(), self.axis)?;
+        let gy = node.gy(0).as_slice();
+
+        let g2: Vec<T> = positions.into_iter().map(|i| gy[i]).collect();
+        accumulate(node.g2(), &g2);
+
+        Ok(())
+    }
+}
+
+impl<I: Integer> Scatter<Gpu<I>> {
+    /// Copy X1 into Y on the stream of the node, then write X2 into it.
+    fn apply_gpu<T: Float>(&self, node: &Node<Gpu<T>>, x1: &Tensor<Gpu<T>>, x2: &Tensor<Gpu<T>>, y: &mut Tensor<Gpu<T>>) -> Result<()> {
+        check_positions(self.index.get(), x1.shape(), self.axis, !self.add)?;
+        cu::mem::cpy_async::<T>(&y.as_ptr(), &x1.as_ptr(), x1.len(), &node.stream().stream)?;
+        launch(node, 0, x2.as_arg(), self.index.get(), y.as_arg(), self.axis)
+    }
+}
+
+impl<I: Integer> Gather<Cpu<I>> {
+    fn apply<T: Float>(&self, x1: &Tensor<Cpu<T>>, y: &mut Tensor<Cpu<T>>) -> Result<()> {
+        let index = self.index.get();
+        let positions = positions(index.as_slice(), index.shape(), x1.shape(), self.axis)?;
+        let x1 = x1.as_slice();
+
+        let out: Vec<T> = positions.into_iter().map(|i| x1[i]).collect();
+        y.clone_from(&out);
+
+        Ok(())
+    }
+}
+
+fn check_axis(axis: usize) -> Result<()> {
+    if axis >= 4 {
+        return Err(anyhow!("Axis {} is out of range for a 4D tensor!", axis))
+    }
+
+    Ok(())
+}
+
+/// Push a Gather of `x`, with its kernels on a Gpu.
+fn push_gather<'s, S, U>(x: Var<'s, S>, axis: usize, index: Input<U>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    Gather<U>: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&x);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_indexed_kernel::<U>(x.device(), "gather", "gather")
+            .with_indexed_kernel::<U>(x.device(), "gather", "scatter_add");
+    }
+
+    x.scope().push(node, Gather { axis, index })
+}
+
+/// Push a Scatter of `src` into `x`, with its kernels on a Gpu.
+fn push_scatter<'s, S, U>(x: Var<'s, S>, axis: usize, index: Input<U>, src: Var<'s, S>, add: bool) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    Scatter<U>: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&x)
+        .with_input(&src);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_indexed_kernel::<U>(x.device(), "gather", if add { "scatter_add" } else { "scatter" })
+            .with_indexed_kernel::<U>(x.device(), "gather", "scatter_mark")
+            .with_indexed_kernel::<U>(x.device(), "gather", "gather_add")
+            .with_kernel(x.device(), "masked_fill", "masked_fill_wrt");
+    }
+
+    x.scope().push(node, Scatter { axis, index, add })
+}
+
+/// Read `x` along `axis` at the positions in `index`.
+pub fn gather<'s, S, U>(x: Var<'s, S>, axis: usize, index: Var<'_, U>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    Gather<U>: Operator<S>,
+{
+    push_gather(x, axis, Input::new(&index))
+}
+
+/// Write `src` into a copy of `x` along `axis` at the positions in `index`.
+pub fn scatter<'s, S, U>(x: Var<'s, S>, axis: usize, index: Var<'_, U>, src: Var<'s, S>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    Scatter<U>: Operator<S>,
+{
+    push_scatter(x, axis, Input::new(&index), src, false)
+}
+
+/// Add `src` into a copy of `x` along `axis` at the positions in `index`.
+pub fn scatter_add<'s, S, U>(x: Var<'s, S>, axis: usize, index: Var<'_, U>, src: Var<'s, S>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    Scatter<U>: Operator<S>,
+{
+    push_scatter(x, axis, Input::new(&index), src, true)
+}
@@ -0,0 +1,314 @@
+use num_traits::AsPrimitive;
+
+use crate::gpu::cu;
+use super::*;
+use super::embedding::{self, Embedding};
+
+/// Selects whole slices of X1 along `axis`, in the order of the index.
+/// The index holds one position per slice, and its length becomes the size of `axis`.
+pub struct IndexSelect<U: Storage> {
+    axis: usize,
+    index: Input<U>,
+}
+
+impl<I: Integer> IndexSelect<Cpu<I>> {
+    fn apply<T: Float>(&self, x1: &Tensor<Cpu<T>>, y: &mut Tensor<Cpu<T>>) -> Result<()> {
+        let src = x1.as_slice();
+
+        let out: Vec<T> = self.sources(x1.shape())?.into_iter()
+            .map(|i| src[i])
+            .collect();
+
+        y.clone_from(&out);
+
+        Ok(())
+    }
+
+    fn sources(&self, x1: &Shape) -> Result<Vec<usize>> {
+        sources(self.index.get().as_slice(), x1, self.axis)
+    }
+}
+
+/// For every element of the selection of `index` along `axis` of `shape`, the offset of the element it is read from.
+fn sources<I: Integer>(index: &[I], shape: &Shape, axis: usize) -> Result<Vec<usize>> {
+    let dims = shape.as_array4();
+    let mut shape = dims;
+    shape[axis] = index.len();
+
+    let [n, c, h, w] = shape;
+    let mut out = Vec::with_capacity(n * c * h * w);
+
+    for (n, c, h, w) in itertools::iproduct!(0..n, 0..c, 0..h, 0..w) {
+        let mut coords = [n, c, h, w];
+        coords[axis] = index[coords[axis]].as_index();
+
+        if coords[axis] >= dims[axis] {
+            return Err(anyhow!("Index {} is out of range for axis {} of size {}!", coords[axis], axis, dims[axis]))
+        }
+
+        out.push(((coords[0] * dims[1] + coords[1]) * dims[2] + coords[2]) * dims[3] + coords[3]);
+    }
+
+    Ok(out)
+}
+
+/// Adds the slices of X1 into zeros of `shape` along `axis`, at the positions in the index. It is 
+/// the adjoint of IndexSelect, and of Embedding with `rows`, recorded when they are differentiated.
+pub struct IndexAdd<U: Storage> {
+    axis: usize,
+    index: Input<U>,
+    shape: Shape,
+    rows: bool,
+}
+
+impl<U: Storage> IndexSelect<U> {
+    fn output(&self, x1: &Shape) -> Result<Shape> {
+        if self.axis >= 4 {
+            return Err(anyhow!("Axis {} is out of range for a 4D tensor!", self.axis))
+        }
+
+        let mut shape = x1.as_array4();
+        shape[self.axis] = self.index.get().len();
+
+        Ok(shape.into())
+    }
+}
+
+impl<T: Float, I: Integer> Operator<Cpu<T>> for IndexSelect<Cpu<I>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.x1(), node.y(0))
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.tx(0), node.ty(0))
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        node.reshape(self.output(node.x1().shape())?);
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let mut g1 = vec![0.0f64; node.g1().len()];
+
+        for (i, gy) in self.sources(node.x1().shape())?.into_iter().zip(node.gy(0).as_slice()) {
+            g1[i] += AsPrimitive::<f64>::as_(*gy);
+        }
+
+        accumulate(node.g1(), &from_f64(g1));
+
+        Ok(())
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Ok(push_index_add(gy[0].clone(), self.axis, self.index.clone(), xs[0].shape().clone(), false))
+    }
+}
+
+/// On a Gpu, positions out of range are read as zero instead of an error.
+impl<T: Float, I: Integer> Operator<Gpu<T>> for IndexSelect<Gpu<I>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        launch(node, 0, node.x1().shape(), node.x1(), self.index.get(), node.y(0), self.axis)
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        launch(node, 0, node.x1().shape(), node.tx(0), self.index.get(), node.ty(0), self.axis)
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        node.reshape(self.output(node.x1().shape())?);
+        Ok(())
+    }
+
+    /// GY is added into the selected slices of G1 only.
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        launch(node, 1, node.x1().shape(), node.gy(0), self.index.get(), node.g1(), self.axis)
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Ok(push_index_add(gy[0].clone(), self.axis, self.index.clone(), xs[0].shape().clone(), false))
+    }
+}
+
+impl<U: Storage> IndexAdd<U> {
+    fn check(&self, x1: &Shape) -> Result<()> {
+        let mut dims = self.shape.as_array4();
+        dims[self.axis] = self.index.get().len();
+
+        if x1.len() != dims.iter().product() {
+            return Err(anyhow!("X1 must have the shape of the selection of {} positions along axis {}!", dims[self.axis], self.axis))
+        }
+
+        Ok(())
+    }
+
+    /// The selection of the gradient of Y is the gradient of X1.
+    fn grad_var<'s, S>(&self, gy: &[Var<'s, S>]) -> Var<'s, S>
+    where
+        S: StorageInfo + From<Shape> + 'static,
+        U: 'static,
+        IndexSelect<U>: Operator<S>,
+        Embedding<U>: Operator<S>,
+    {
+        match self.rows {
+            true => embedding::push_embedding(gy[0].clone(), self.index.clone()),
+            false => push_index_select(gy[0].clone(), self.axis, self.index.clone()),
+        }
+    }
+}
+
+impl<T: Float, I: Integer> Operator<Cpu<T>> for IndexAdd<Cpu<I>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.x1(), node.y(0))
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.tx(0), node.ty(0))
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.check(node.x1().shape())?;
+        node.reshape(self.shape.clone());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let gy = node.gy(0).as_slice();
+        let g1 = node.g1().as_slice_mut();
+
+        for (g, i) in g1.iter_mut().zip(sources(self.index.get().as_slice(), &self.shape, self.axis)?) {
+            *g = *g + gy[i];
+        }
+
+        Ok(())
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Ok(self.grad_var(gy))
+    }
+}
+
+impl<I: Integer> IndexAdd<Cpu<I>> {
+    fn apply<T: Float>(&self, x1: &Tensor<Cpu<T>>, y: &mut Tensor<Cpu<T>>) -> Result<()> {
+        let mut out = vec![0.0f64; y.len()];
+
+        for (i, x) in sources(self.index.get().as_slice(), &self.shape, self.axis)?.into_iter().zip(x1.as_slice()) {
+            out[i] += AsPrimitive::<f64>::as_(*x);
+        }
+
+        y.clone_from(&from_f64(out));
+
+        Ok(())
+    }
+}
+
+/// On a Gpu, positions out of range are skipped instead of an error.
+impl<T: Float, I: Integer> Operator<Gpu<T>> for IndexAdd<Gpu<I>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.apply(node, node.x1(), node.y(0))
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.apply(node, node.tx(0), node.ty(0))
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.check(node.x1().shape())?;
+        node.reshape(self.shape.clone());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        launch(node, 1, &self.shape, node.gy(0), self.index.get(), node.g1(), self.axis)
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Ok(self.grad_var(gy))
+    }
+}
+
+impl<I: Integer> IndexAdd<Gpu<I>> {
+    /// Zero Y on the stream of the node, then add X1 into it.
+    fn apply<T: Float>(&self, node: &Node<Gpu<T>>, x1: &Tensor<Gpu<T>>, y: &Tensor<Gpu<T>>) -> Result<()> {
+        cu::mem::set_d8_async(&y.as_ptr(), 0, y.len() * std::mem::size_of::<T>(), &node.stream().stream)?;
+        launch(node, 0, &self.shape, x1, self.index.get(), y, self.axis)
+    }
+}
+
+/// Launch the index_select kernel `k` of `node` over every element of the selection `y` from `x` 
+/// of shape `dims`, as `(x, index, y, n, c, h, w, k, axis)`. The wrt kernel takes GY as `x` and G as `y`.
+pub(super) fn launch<T: Float, I: Integer>(
+    node: &Node<Gpu<T>>,
+    k: usize,
+    dims: &Shape,
+    x: &Tensor<Gpu<T>>,
+    index: &Tensor<Gpu<I>>,
+    y: &Tensor<Gpu<T>>,
+    axis: usize,
+) -> Result<()> {
+    let [n, c, h, w] = dims.as_array4();
+    let mut len = [n, c, h, w];
+    len[axis] = index.len();
+    index.record_stream(node.stream());
+
+    node.kernel(k).launch_n(
+        len.iter().product(),
+        node.stream(),
+        (
+            x.as_arg(),
+            index.as_arg(),
+            y.as_arg(),
+            n as u64, c as u64, h as u64, w as u64,
+            index.len() as u64,
+            axis as u64,
+        )
+    )
+}
+
+/// Push an IndexSelect of `x`, with its kernels on a Gpu.
+fn push_index_select<'s, S, U>(x: Var<'s, S>, axis: usize, index: Input<U>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    IndexSelect<U>: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&x);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_indexed_kernel::<U>(x.device(), "gather", "index_select")
+            .with_indexed_kernel::<U>(x.device(), "gather", "index_select_wrt");
+    }
+
+    x.scope().push(node, IndexSelect { axis, index })
+}
+
+/// Push an IndexAdd of `x` into zeros of `shape`, with its kernels on a Gpu.
+pub(super) fn push_index_add<'s, S, U>(x: Var<'s, S>, axis: usize, index: Input<U>, shape: Shape, rows: bool) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    IndexAdd<U>: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&x);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_indexed_kernel::<U>(x.device(), "gather", "index_select_wrt")
+            .with_indexed_kernel::<U>(x.device(), "gather", "index_select_add");
+    }
+
+    x.scope().push(node, IndexAdd { axis, index, shape, rows })
+}
+
+/// Select the slices of `x` along `axis` at the positions in `index`.
+pub fn index_select<'s, S, U>(x: Var<'s, S>, axis: usize, index: Var<'_, U>) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    IndexSelect<U>: Operator<S>,
+{
+    push_index_select(x, axis, Input::new(&index))
+}
@@ -0,0 +1,163 @@
+use super::*;
+
+/// Replaces the elements of X1 where the mask is true with `value`.
+pub struct MaskedFill<U: Storage> {
+    mask: Input<U>,
+    value: f64,
+}
+
+impl<T: Float> Operator<Cpu<T>> for MaskedFill<Cpu<bool>> {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        let x1 = node.x1().as_slice().to_vec();
+        self.apply(x1, node.y(0), self.value);
+        Ok(())
+    }
+
+    fn inplace(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn forward_inplace(&mut self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let y = node.y(0).as_slice().to_vec();
+        self.apply(y, node.y(0), self.value);
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        let tx = node.tx(0).as_slice().to_vec();
+        self.apply(tx, node.ty(0), 0.0);
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.check(node.x1().shape())?;
+        node.reshape(node.x1().shape().clone());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let g1: Vec<T> = node.gy(0).as_slice().iter()
+            .zip(self.mask.get().as_slice())
+            .map(|(gy, mask)| if *mask { T::ZERO } else { *gy })
+            .collect();
+
+        accumulate(node.g1(), &g1);
+
+        Ok(())
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Ok(push_masked_fill(gy[0].clone(), self.mask.clone(), 0.0))
+    }
+}
+
+impl<T: Float> Operator<Gpu<T>> for MaskedFill<Gpu<bool>> {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.launch(node, node.x1(), node.y(0), self.value)
+    }
+
+    fn inplace(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn forward_inplace(&mut self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        self.launch(node, node.y(0), node.y(0), self.value)
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.launch(node, node.tx(0), node.ty(0), 0.0)
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.check(node.x1().shape())?;
+        node.reshape(node.x1().shape().clone());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        self.mask.get().record_stream(node.stream());
+        node.kernel(1).launch_n(
+            node.g1().len(),
+            node.stream(),
+            (
+                node.gy(0).as_arg(),
+                self.mask.get().as_arg(),
+                node.g1().as_arg(),
+            )
+        )
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Ok(push_masked_fill(gy[0].clone(), self.mask.clone(), 0.0))
+    }
+}
+
+impl<U: Storage<F = bool>> MaskedFill<U> {
+    fn check(&self, x1: &Shape) -> Result<()> {
+        if self.mask.get().shape() != x1 {
+            return Err(anyhow!("Mask and X1 shape must match!"))
+        }
+
+        Ok(())
+    }
+}
+
+impl MaskedFill<Cpu<bool>> {
+    /// Write `x` into `y`, with `value` where the mask is true.
+    fn apply<T: Float>(&self, x: Vec<T>, y: &mut Tensor<Cpu<T>>, value: f64) {
+        let value = T::from_f64_nearest(value);
+
+        let out: Vec<T> = x.into_iter()
+            .zip(self.mask.get().as_slice())
+            .map(|(x, mask)| if *mask { value } else { x })
+            .collect();
+
+        y.clone_from(&out);
+    }
+}
+
+impl MaskedFill<Gpu<bool>> {
+    /// Write `x` into `y`, with `value` where the mask is true. `x` and `y` may be the same tensor.
+    fn launch<T: Float>(&self, node: &Node<Gpu<T>>, x: &Tensor<Gpu<T>>, y: &Tensor<Gpu<T>>, value: f64) -> Result<()> {
+        self.mask.get().record_stream(node.stream());
+        node.kernel(0).launch_n(
+            y.len(),
+            node.stream(),
+            (
+                x.as_arg(),
+                self.mask.get().as_arg(),
+                y.as_arg(),
+                value,
+            )
+        )
+    }
+}
+
+/// Push a MaskedFill of `x`, with its kernels on a Gpu.
+fn push_masked_fill<'s, S, U>(x: Var<'s, S>, mask: Input<U>, value: f64) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    MaskedFill<U>: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&x);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_kernel(x.device(), "masked_fill", "masked_fill")
+            .with_kernel(x.device(), "masked_fill", "masked_fill_wrt");
+    }
+
+    x.scope().push(node, MaskedFill { mask, value })
+}
+
+/// Replace the elements of `x` where `mask` is true with `value`.
+pub fn masked_fill<'s, S, U>(x: Var<'s, S>, mask: Var<'_, U>, value: f64) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    U: Storage + 'static,
+    MaskedFill<U>: Operator<S>,
+{
+    push_masked_fill(x, Input::new(&mask), value)
+}
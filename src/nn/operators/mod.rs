@@ -1,26 +1,131 @@
 
 use anyhow::Result;
 use anyhow::anyhow;
-use itertools::multizip;
 
 use crate::storage::Storage;
 use super::node::Node;
 use crate::storage::Gpu;
 use crate::storage::Cpu;
 use crate::storage::Float;
+use crate::storage::Kernels;
+use crate::storage::parallel;
 use super::var::Var;
 use crate::storage::StorageInfo;
 use super::node::NodeBuilder;
+use super::node::Dependency;
+use super::scope::ScopeBuilder;
+use crate::storage::Shape;
+use crate::storage::CastMode;
+use crate::storage::Integer;
+use crate::storage::Element;
+use super::node::Input;
+use crate::storage::Layout;
+use crate::storage::Strided;
+use crate::storage::Accumulate;
+use crate::storage::Tensor;
+use super::profiler::{self, Phase};
 
 mod mul;
+mod add;
+mod fill;
+mod cast;
+mod cross_entropy;
+mod reshape;
+mod permute;
+mod narrow;
+mod strided;
+mod concat;
+mod pad;
+mod gather;
+mod index_select;
+mod masked_fill;
+mod embedding;
+
+pub use mul::mul;
+pub use add::{Add, add};
+pub use fill::{Fill, fill};
+pub use cast::{Cast, cast, cast_with};
+pub use cross_entropy::{CrossEntropy, cross_entropy};
+pub use reshape::{Reshape, reshape, squeeze, unsqueeze};
+pub use permute::{Permute, permute, transpose};
+pub use narrow::{Narrow, Split, narrow, split};
+pub use strided::{AsStrided, AsStridedScatter, as_strided};
+pub use concat::{Concat, Stack, concat, stack};
+pub use pad::{Pad, PadMode, pad};
+pub use gather::{Gather, Scatter, gather, scatter, scatter_add};
+pub use index_select::{IndexSelect, IndexAdd, index_select};
+pub use masked_fill::{MaskedFill, masked_fill};
+pub use embedding::{Embedding, embedding};
 
 #[allow(unused_variables)]
 pub trait Operator<S: Storage> {
     fn forward(&mut self, node: &Node<S>) -> Result<()>;
     fn reshape(&mut self, node: &Node<S>) -> Result<()>;
-    fn wrt_x1(&self, node: &Node<S>) -> Result<()> { Ok(()) }
-    fn wrt_x2(&self, node: &Node<S>) -> Result<()> { Ok(()) }
-    fn wrt_x3(&self, node: &Node<S>) -> Result<()> { Ok(()) }
-    fn wrt_x4(&self, node: &Node<S>) -> Result<()> { Ok(()) }
-    fn wrt_x5(&self, node: &Node<S>) -> Result<()> { Ok(()) }
-}
\ No newline at end of file
+
+    /// Add the gradient of the input at `index` into `g(index)`. Gradients are 
+    /// never overwritten, so a Var consumed by several nodes receives their sum.
+    fn wrt(&self, node: &Node<S>, index: usize) -> Result<()> { Ok(()) }
+
+    /// Compute the gradients of every input. Operators that hold 
+    /// inputs of their own, like Cast, override this to reach them.
+    fn backward(&self, node: &Node<S>) -> Result<()> {
+        (0..node.inputs()).try_for_each(|i| profiler::record(Phase::Wrt(i), || self.wrt(node, i)))
+    }
+
+    /// The name of the operator, as shown by the profiler.
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        let name = &name[..name.find('<').unwrap_or(name.len())];
+        &name[name.rfind("::").map_or(0, |i| i + 2)..]
+    }
+
+    /// Whether every call only enqueues work on the stream of the node, so it can be captured 
+    /// into a graph. Operators that go through the host, like Cast on a Gpu, return false.
+    fn capturable(&self) -> bool { true }
+
+    /// The input that Y can overwrite once nothing else needs it, for elementwise operators.
+    /// The memory planner then calls `forward_inplace` with Y already holding that input.
+    fn inplace(&self) -> Option<usize> { None }
+
+    /// Compute Y in-place, where Y holds the values of the input at `index`,
+    /// and that input only holds a placeholder.
+    fn forward_inplace(&mut self, node: &Node<S>, index: usize) -> Result<()> {
+        Err(anyhow!("{} cannot run in-place!", std::any::type_name::<Self>()))
+    }
+
+    /// Write the directional derivative of every output into `ty(i)`, given 
+    /// the tangents of the inputs in `tx(i)`. This is what `Scope::jvp` calls.
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        Err(anyhow!("{} does not support forward-mode differentiation!", std::any::type_name::<Self>()))
+    }
+
+    /// Build the gradient of the input at `index` out of other operators, given 
+    /// the Vars of the inputs and of the gradients of the outputs. This is what 
+    /// `ScopeBuilder::backward` records, so the gradient can be differentiated again.
+    fn grad<'s>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>], index: usize) -> Result<Var<'s, S>> {
+        Err(anyhow!("{} does not support higher-order gradients!", std::any::type_name::<Self>()))
+    }
+}
+
+/// Copy a tensor to the host, in NCHW order.
+fn host<S: Storage>(tensor: &Tensor<S>) -> Vec<S::F> {
+    tensor.as_ndarray().into_raw_vec()
+}
+
+/// Add `values`, in NCHW order, into `dst`.
+fn accumulate<S: Storage>(dst: &mut Tensor<S>, values: &[S::F]) 
+where
+    S::F: Float,
+{
+    let sum: Vec<S::F> = host(dst).into_iter()
+        .zip(values)
+        .map(|(x, v)| x + *v)
+        .collect();
+
+    dst.clone_from(&sum);
+}
+
+/// Convert values accumulated in f64 back into a Float.
+fn from_f64<T: Float>(values: Vec<f64>) -> Vec<T> {
+    values.into_iter().map(T::from_f64_nearest).collect()
+}
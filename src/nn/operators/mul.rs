@@ -1,18 +1,20 @@
 
 use super::*;
 
-struct Mul;
+pub struct Mul;
 
 impl<T: Float> Operator<Cpu<T>> for Mul {
     fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
-        let y = node.y().as_slice_mut();
-        let x1 = node.x1().as_slice();
-        let x2 = node.x2().as_slice();
+        parallel::binary(node.x1().as_slice(), node.x2().as_slice(), node.y(0).as_slice_mut(), Kernels::mul);
+        Ok(())
+    }
 
-        for (y, x1, x2) in multizip((y, x1, x2)) {
-            *y = *x1 * *x2
-        }
+    fn inplace(&self) -> Option<usize> {
+        Some(0)
+    }
 
+    fn forward_inplace(&mut self, node: &Node<Cpu<T>>, index: usize) -> Result<()> {
+        parallel::assign(node.y(0).as_slice_mut(), node.x(1 - index).as_slice(), Kernels::mul_assign);
         Ok(())
     }
 
@@ -29,47 +31,39 @@ impl<T: Float> Operator<Cpu<T>> for Mul {
         Ok(())
     }
 
-    fn wrt_x1(&self, node: &Node<Cpu<T>>) -> Result<()> {
-        let gy = node.gy().as_slice();
-        let x2 = node.x2().as_slice();
-        let g1 = node.g1().as_slice_mut();
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        let ty = node.ty(0).as_slice_mut();
 
-        for (gy, x2, g1) in multizip((gy, x2, g1)) {
-            *g1 = *gy * *x2
-        }
+        parallel::binary(node.tx(0).as_slice(), node.x2().as_slice(), ty, Kernels::mul);
+        parallel::binary(node.x1().as_slice(), node.tx(1).as_slice(), ty, Kernels::mul_add);
 
         Ok(())
     }
 
-    fn wrt_x2(&self, node: &Node<Cpu<T>>) -> Result<()> {
-        let gy = node.gy().as_slice();
-        let x1 = node.x1().as_slice();
-        let g2 = node.g2().as_slice_mut();
-
-        for (gy, x1, g2) in multizip((gy, x1, g2)) {
-            *g2 = *gy * *x1
-        }
-
+    /// The gradient of each input is `gy` times the other input.
+    fn wrt(&self, node: &Node<Cpu<T>>, index: usize) -> Result<()> {
+        parallel::binary(node.gy(0).as_slice(), node.x(1 - index).as_slice(), node.g(index).as_slice_mut(), Kernels::mul_add);
         Ok(())
     }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], index: usize) -> Result<Var<'s, Cpu<T>>> {
+        Ok(mul(gy[0].clone(), xs[1 - index].clone()))
+    }
 }
 
 impl<T: Float> Operator<Gpu<T>> for Mul {
     fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
-        let y = node.y();
+        let y = node.y(0);
         let x1 = node.x1();
         let x2 = node.x2();
 
-        let len = y.len() as u32;
-
-        node.kernel(0).launch(
-            [len / 1024], 
-            [1024], 
+        node.kernel(0).launch_n(
+            y.len(), 
             node.stream(),
             (
-                y.as_ptr(),
-                x1.as_ptr(),
-                x2.as_ptr(),
+                x1.as_arg(),
+                x2.as_arg(),
+                y.as_arg(),
             )
         )?;
 
@@ -77,27 +71,59 @@ impl<T: Float> Operator<Gpu<T>> for Mul {
     }
 
     fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        let x1 = node.x1().shape();
+        let x2 = node.x2().shape();
+
+        if x1 != x2 {
+            return Err(anyhow!("X1 and X2 shape must match!"))
+        }
+
+        node.reshape(x1.clone());
+
         Ok(())
     }
 
-    fn wrt_x1(&self, node: &Node<Gpu<T>>) -> Result<()> {
-        Ok(())
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        let ty = node.ty(0);
+
+        node.kernel(0).launch_n(ty.len(), node.stream(), (node.tx(0).as_arg(), node.x2().as_arg(), ty.as_arg()))?;
+        node.kernel(1).launch_n(ty.len(), node.stream(), (node.x1().as_arg(), node.tx(1).as_arg(), ty.as_arg()))
     }
 
-    fn wrt_x2(&self, node: &Node<Gpu<T>>) -> Result<()> {
-        Ok(())
+    /// The gradient of each input is `gy` times the other input, added by the `mul_add` kernel.
+    fn wrt(&self, node: &Node<Gpu<T>>, index: usize) -> Result<()> {
+        let g = node.g(index);
+
+        node.kernel(1).launch_n(
+            g.len(),
+            node.stream(),
+            (
+                node.gy(0).as_arg(),
+                node.x(1 - index).as_arg(),
+                g.as_arg(),
+            )
+        )
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], index: usize) -> Result<Var<'s, Gpu<T>>> {
+        Ok(mul(gy[0].clone(), xs[1 - index].clone()))
     }
 }
 
 pub fn mul<'s, S>(x1: Var<'s, S>, x2: Var<'s, S>) -> Var<'s, S> 
 where
-    S: StorageInfo,
+    S: StorageInfo + From<Shape> + 'static,
+    Mul: Operator<S>,
 {
-    let node: NodeBuilder<S> = Node::build();
+    let mut node = Node::<S>::build()
+        .with_input(&x1)
+        .with_input(&x2);
 
     if S::TYPE == "gpu" {
-        
+        node = node
+            .with_kernel(x1.device(), "mul", "mul")
+            .with_kernel(x1.device(), "mul", "mul_add");
     }
 
-    todo!()
+    x1.scope().push(node, Mul)
 }
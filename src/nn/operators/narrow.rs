@@ -0,0 +1,154 @@
+
+use super::*;
+
+/// Keeps `len` elements of `axis` of X1, starting at `start`.
+pub struct Narrow {
+    axis: usize,
+    start: usize,
+    len: usize,
+}
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for Narrow 
+where
+    S::F: Float,
+{
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        let x1 = node.x1();
+        x1.gather(&Layout::new(x1.shape()).narrow(self.axis, self.start, self.len)?, node.y(0));
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        let tx = node.tx(0);
+        tx.gather(&Layout::new(tx.shape()).narrow(self.axis, self.start, self.len)?, node.ty(0));
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        let layout = Layout::new(node.x1().shape()).narrow(self.axis, self.start, self.len)?;
+        node.reshape(layout.shape());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, _index: usize) -> Result<()> {
+        let g1 = node.g1();
+        let layout = Layout::new(g1.shape()).narrow(self.axis, self.start, self.len)?;
+        g1.scatter_add(&layout, node.gy(0));
+        Ok(())
+    }
+
+    /// Y padded back to the shape of X1 with zeros on either side.
+    fn grad<'s>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>], _index: usize) -> Result<Var<'s, S>> {
+        let mut shape = xs[0].shape().as_array4();
+        let scope = xs[0].scope();
+        let mut parts = Vec::new();
+
+        if self.start > 0 {
+            shape[self.axis] = self.start;
+            parts.push(fill(scope, shape.into(), 0.0));
+        }
+
+        parts.push(gy[0].clone());
+
+        let rest = xs[0].shape()[self.axis] - self.start - self.len;
+
+        if rest > 0 {
+            shape[self.axis] = rest;
+            parts.push(fill(scope, shape.into(), 0.0));
+        }
+
+        Ok(concat(&parts, self.axis))
+    }
+}
+
+pub fn narrow<'s, S>(x: Var<'s, S>, axis: usize, start: usize, len: usize) -> Var<'s, S>
+where
+    S: Accumulate + From<Shape> + 'static,
+    S::F: Float,
+{
+    let node = Node::<S>::build()
+        .with_input(&x);
+
+    x.scope().push(node, Narrow { axis, start, len })
+}
+
+/// Splits `axis` of X1 into consecutive pieces, one output per size.
+pub struct Split {
+    axis: usize,
+    sizes: Vec<usize>,
+}
+
+impl Split {
+    /// The layout of the output at `index` in X1.
+    fn layout(&self, shape: &Shape, index: usize) -> Result<Layout> {
+        let start = self.sizes[..index].iter().sum();
+        Layout::new(shape).narrow(self.axis, start, self.sizes[index])
+    }
+}
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for Split 
+where
+    S::F: Float,
+{
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        let x1 = node.x1();
+
+        for i in 0..node.outputs() {
+            x1.gather(&self.layout(x1.shape(), i)?, node.y(i));
+        }
+
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        let tx = node.tx(0);
+
+        for i in 0..node.outputs() {
+            tx.gather(&self.layout(tx.shape(), i)?, node.ty(i));
+        }
+
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        let shape = node.x1().shape();
+
+        if self.sizes.iter().sum::<usize>() != shape[self.axis] {
+            return Err(anyhow!("Sizes {:?} must add up to the size of axis {}!", self.sizes, self.axis))
+        }
+
+        for i in 0..node.outputs() {
+            node.reshape_output(i, self.layout(shape, i)?.shape());
+        }
+
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, _index: usize) -> Result<()> {
+        let g1 = node.g1();
+        let shape = g1.shape().clone();
+
+        for i in 0..node.outputs() {
+            g1.scatter_add(&self.layout(&shape, i)?, node.gy(i));
+        }
+
+        Ok(())
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, S>], gy: &[Var<'s, S>], _index: usize) -> Result<Var<'s, S>> {
+        Ok(concat(gy, self.axis))
+    }
+}
+
+/// Split `axis` into consecutive pieces with the provided `sizes`.
+pub fn split<'s, S>(x: Var<'s, S>, axis: usize, sizes: &[usize]) -> Vec<Var<'s, S>>
+where
+    S: Accumulate + From<Shape> + 'static,
+    S::F: Float,
+{
+    let node = Node::<S>::build()
+        .with_input(&x)
+        .with_outputs(sizes.len());
+
+    x.scope().push_many(node, Split { axis, sizes: sizes.to_vec() })
+}
@@ -0,0 +1,214 @@
+
+use num_traits::AsPrimitive;
+
+use super::*;
+
+#[derive(Copy, Clone, Debug)]
+pub enum PadMode {
+    /// Fill the border with a value.
+    Constant(f64),
+    /// Mirror the input, without repeating the edge.
+    Reflect,
+    /// Repeat the edge of the input.
+    Replicate,
+}
+
+/// Pads the H and W axes of X1 by `[top, bottom, left, right]`.
+pub struct Pad {
+    pads: [usize; 4],
+    mode: PadMode,
+}
+
+impl Pad {
+    /// Index into the input axis of `size` for `i`, the index into the padded axis minus the padding.
+    fn source(&self, i: isize, size: usize) -> Option<usize> {
+        let last = size as isize - 1;
+
+        match self.mode {
+            PadMode::Constant(_) => (0..=last).contains(&i).then_some(i as usize),
+            PadMode::Replicate => Some(i.clamp(0, last) as usize),
+            PadMode::Reflect => Some(if i < 0 { -i } else if i > last { 2 * last - i } else { i } as usize),
+        }
+    }
+
+    /// Pad `x` into `y`, filling the border of a constant pad with `value`.
+    fn apply<S: Storage>(&self, x: &Tensor<S>, y: &mut Tensor<S>, value: S::F) {
+        let src = host(x);
+
+        let out: Vec<S::F> = self.sources(x.shape()).into_iter()
+            .map(|i| i.map(|i| src[i]).unwrap_or(value))
+            .collect();
+
+        y.clone_from(&out);
+    }
+
+    /// The value of the border, which is zero unless the pad is constant.
+    fn value(&self) -> f64 {
+        match self.mode {
+            PadMode::Constant(v) => v,
+            _ => 0.0,
+        }
+    }
+
+    /// The gradient of a constant pad is GY cropped back to X1. A Reflect or Replicate pad adds 
+    /// its border into the edge of X1 instead, which no operator records.
+    fn grad_var<'s, S>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>]) -> Result<Var<'s, S>>
+    where
+        S: Accumulate + From<Shape> + 'static,
+        S::F: Float,
+    {
+        if !matches!(self.mode, PadMode::Constant(_)) {
+            return Err(anyhow!("Only the gradient of a constant pad can be differentiated again, not of {:?}!", self.mode))
+        }
+
+        let [_, _, h, w] = xs[0].shape().as_array4();
+        let [top, _, left, _] = self.pads;
+        let y = narrow(narrow(gy[0].clone(), 2, top, h), 3, left, w);
+
+        Ok(match y.shape() == xs[0].shape() {
+            true => y,
+            false => reshape(y, xs[0].shape().clone()),
+        })
+    }
+
+    /// The shape of Y for X1 of `shape`.
+    fn output(&self, shape: &Shape) -> Result<Shape> {
+        let [n, c, h, w] = shape.as_array4();
+        let [top, bottom, left, right] = self.pads;
+
+        if let PadMode::Reflect = self.mode {
+            if top.max(bottom) >= h || left.max(right) >= w {
+                return Err(anyhow!("Reflect padding must be smaller than the input!"))
+            }
+        }
+
+        Ok([n, c, h + top + bottom, w + left + right].into())
+    }
+
+    /// Launch `pad` from `x` into `y`, or `pad_wrt` from the gradient `y` into `x`, with `value` for the border.
+    fn launch<T: Float>(&self, node: &Node<Gpu<T>>, x: &Gpu<T>, y: &Gpu<T>, value: Option<f64>) -> Result<()> {
+        let [_, _, h, w] = x.shape().as_array4();
+        let [_, _, oh, ow] = y.shape().as_array4();
+        let [top, _, left, _] = self.pads;
+
+        let mode: u32 = match self.mode {
+            PadMode::Constant(_) => 0,
+            PadMode::Reflect => 1,
+            PadMode::Replicate => 2,
+        };
+
+        let dims = (h as u64, w as u64, top as u64, left as u64, oh as u64, ow as u64);
+
+        match value {
+            Some(value) => node.kernel(0).launch_n(
+                y.len(),
+                node.stream(),
+                (x.as_arg(), y.as_arg(), dims.0, dims.1, dims.2, dims.3, dims.4, dims.5, mode, value)
+            ),
+            None => node.kernel(1).launch_n(
+                x.len(),
+                node.stream(),
+                (y.as_arg(), x.as_arg(), dims.0, dims.1, dims.2, dims.3, dims.4, dims.5, mode)
+            ),
+        }
+    }
+
+    /// For every element of Y, the index of the element of X1 it is copied from.
+    fn sources(&self, x1: &Shape) -> Vec<Option<usize>> {
+        let [n, c, h, w] = x1.as_array4();
+        let [top, bottom, left, right] = self.pads;
+        let (oh, ow) = (h + top + bottom, w + left + right);
+        let mut out = Vec::with_capacity(n * c * oh * ow);
+
+        for nc in 0..n * c {
+            for i in 0..oh {
+                for j in 0..ow {
+                    let y = self.source(i as isize - top as isize, h);
+                    let x = self.source(j as isize - left as isize, w);
+                    out.push(y.zip(x).map(|(y, x)| (nc * h + y) * w + x));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+impl<T: Float> Operator<Cpu<T>> for Pad {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.x1(), node.y(0), T::from_f64_nearest(self.value()));
+        Ok(())
+    }
+
+    /// The border of a constant pad does not depend on X1, so its tangent is zero.
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        self.apply(node.tx(0), node.ty(0), T::ZERO);
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        node.reshape(self.output(node.x1().shape())?);
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let gy = node.gy(0).as_ndarray();
+        let mut g1 = vec![0.0f64; node.g1().len()];
+
+        for (i, gy) in self.sources(node.x1().shape()).into_iter().zip(gy.iter()) {
+            if let Some(i) = i {
+                g1[i] += AsPrimitive::<f64>::as_(*gy);
+            }
+        }
+
+        accumulate(node.g1(), &from_f64(g1));
+
+        Ok(())
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        self.grad_var(xs, gy)
+    }
+}
+
+impl<T: Float> Operator<Gpu<T>> for Pad {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.launch(node, node.x1(), node.y(0), Some(self.value()))
+    }
+
+    /// The border of a constant pad does not depend on X1, so its tangent is zero.
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        self.launch(node, node.tx(0), node.ty(0), Some(0.0))
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        node.reshape(self.output(node.x1().shape())?);
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        self.launch(node, node.g1(), node.gy(0), None)
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        self.grad_var(xs, gy)
+    }
+}
+
+/// Pad the H and W axes of `x` by `[top, bottom, left, right]`.
+pub fn pad<'s, S>(x: Var<'s, S>, pads: [usize; 4], mode: PadMode) -> Var<'s, S>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    Pad: Operator<S>,
+{
+    let mut node = Node::<S>::build()
+        .with_input(&x);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_kernel(x.device(), "pad", "pad")
+            .with_kernel(x.device(), "pad", "pad_wrt");
+    }
+
+    x.scope().push(node, Pad { pads, mode })
+}
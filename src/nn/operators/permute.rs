@@ -0,0 +1,132 @@
+use super::*;
+
+/// Reorders the axes of X1, so axis `i` of Y is axis `axes[i]` of X1.
+pub struct Permute {
+    axes: [usize; 4],
+}
+
+impl Permute {
+    fn inverse(&self) -> [usize; 4] {
+        let mut out = [0; 4];
+
+        for (i, axis) in self.axes.iter().enumerate() {
+            out[*axis] = i;
+        }
+
+        out
+    }
+
+    /// Launch the kernel at `kernel` with `x` permuted by `axes`, packed two bits per axis.
+    fn launch<T: Float>(node: &Node<Gpu<T>>, kernel: usize, x: &Gpu<T>, y: &Gpu<T>, axes: [usize; 4]) -> Result<()> {
+        let [n, c, h, w] = x.shape().as_array4();
+        let axes: u32 = axes.iter().enumerate().map(|(k, axis)| (*axis as u32) << (2 * k)).sum();
+
+        node.kernel(kernel).launch_n(
+            y.len(),
+            node.stream(),
+            (
+                x.as_arg(),
+                y.as_arg(),
+                n as u64,
+                c as u64,
+                h as u64,
+                w as u64,
+                axes,
+            )
+        )
+    }
+}
+
+impl<T: Float> Operator<Cpu<T>> for Permute {
+    fn forward(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        let x1 = node.x1();
+        x1.gather(&Layout::new(x1.shape()).permute(self.axes)?, node.y(0));
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<Cpu<T>>) -> Result<()> {
+        let tx = node.tx(0);
+        tx.gather(&Layout::new(tx.shape()).permute(self.axes)?, node.ty(0));
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<Cpu<T>>) -> Result<()> {
+        let layout = Layout::new(node.x1().shape()).permute(self.axes)?;
+        node.reshape(layout.shape());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Cpu<T>>, _index: usize) -> Result<()> {
+        let gy = node.gy(0);
+        let shape = node.x1().shape();
+        let mut g1 = Cpu::<T>::from(shape.clone());
+
+        gy.gather(&Layout::new(gy.shape()).permute(self.inverse())?, &mut g1);
+        node.g1().scatter_add(&Layout::new(shape), &g1);
+
+        Ok(())
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Cpu<T>>], gy: &[Var<'s, Cpu<T>>], _index: usize) -> Result<Var<'s, Cpu<T>>> {
+        permute(gy[0].clone(), self.inverse())
+    }
+}
+
+impl<T: Float> Operator<Gpu<T>> for Permute {
+    fn forward(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        Self::launch(node, 0, node.x1(), node.y(0), self.axes)
+    }
+
+    fn tangent(&self, node: &Node<Gpu<T>>) -> Result<()> {
+        Self::launch(node, 0, node.tx(0), node.ty(0), self.axes)
+    }
+
+    fn reshape(&mut self, node: &Node<Gpu<T>>) -> Result<()> {
+        let layout = Layout::new(node.x1().shape()).permute(self.axes)?;
+        node.reshape(layout.shape());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<Gpu<T>>, _index: usize) -> Result<()> {
+        Self::launch(node, 1, node.gy(0), node.g1(), self.inverse())
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, Gpu<T>>], gy: &[Var<'s, Gpu<T>>], _index: usize) -> Result<Var<'s, Gpu<T>>> {
+        permute(gy[0].clone(), self.inverse())
+    }
+}
+
+/// Reorder the axes of `x`, which must use every axis exactly once.
+pub fn permute<'s, S>(x: Var<'s, S>, axes: [usize; 4]) -> Result<Var<'s, S>>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    Permute: Operator<S>,
+{
+    Layout::new(x.shape()).permute(axes)?;
+
+    let mut node = Node::<S>::build()
+        .with_input(&x);
+
+    if S::TYPE == "gpu" {
+        node = node
+            .with_kernel(x.device(), "permute", "permute")
+            .with_kernel(x.device(), "permute", "permute_add");
+    }
+
+    Ok(x.scope().push(node, Permute { axes }))
+}
+
+/// Swap axes `a` and `b`.
+pub fn transpose<'s, S>(x: Var<'s, S>, a: usize, b: usize) -> Result<Var<'s, S>>
+where
+    S: StorageInfo + From<Shape> + 'static,
+    Permute: Operator<S>,
+{
+    if a >= 4 || b >= 4 {
+        return Err(anyhow!("Cannot transpose axes {} and {} of a 4D tensor!", a, b))
+    }
+
+    let mut axes = [0, 1, 2, 3];
+    axes.swap(a, b);
+    permute(x, axes)
+}
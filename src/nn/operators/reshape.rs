@@ -0,0 +1,88 @@
+
+use super::*;
+
+/// Gives the elements of X1 a new shape with the same length.
+pub struct Reshape {
+    shape: Shape,
+}
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for Reshape {
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        let x1 = node.x1();
+        x1.gather(&Layout::new(x1.shape()), node.y(0));
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        let tx = node.tx(0);
+        tx.gather(&Layout::new(tx.shape()), node.ty(0));
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        let x1 = node.x1().shape();
+
+        if x1.len() != self.shape.len() {
+            return Err(anyhow!("Cannot reshape {} elements into {}!", x1.len(), self.shape.len()))
+        }
+
+        node.reshape(self.shape.clone());
+
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, _index: usize) -> Result<()> {
+        node.g1().scatter_add(&Layout::new(node.x1().shape()), node.gy(0));
+        Ok(())
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>], _index: usize) -> Result<Var<'s, S>> {
+        Ok(reshape(gy[0].clone(), xs[0].shape().clone()))
+    }
+}
+
+pub fn reshape<'s, S>(x: Var<'s, S>, shape: Shape) -> Var<'s, S>
+where
+    S: Accumulate + From<Shape> + 'static,
+{
+    let node = Node::<S>::build()
+        .with_input(&x);
+
+    x.scope().push(node, Reshape { shape })
+}
+
+/// Remove `axis`, which must have size 1.
+pub fn squeeze<'s, S>(x: Var<'s, S>, axis: usize) -> Result<Var<'s, S>>
+where
+    S: Accumulate + From<Shape> + 'static,
+{
+    let mut dims = x.shape().to_vec();
+
+    if axis >= dims.len() || dims[axis] != 1 {
+        return Err(anyhow!("Cannot squeeze axis {} of shape {:?}!", axis, dims))
+    }
+
+    dims.remove(axis);
+
+    if dims.is_empty() {
+        dims.push(1);
+    }
+
+    Ok(reshape(x, dims.as_slice().into()))
+}
+
+/// Insert an axis of size 1 before `axis`.
+pub fn unsqueeze<'s, S>(x: Var<'s, S>, axis: usize) -> Result<Var<'s, S>>
+where
+    S: Accumulate + From<Shape> + 'static,
+{
+    let mut dims = x.shape().to_vec();
+
+    if axis > dims.len() || dims.len() == 4 {
+        return Err(anyhow!("Cannot unsqueeze axis {} of shape {:?}!", axis, dims))
+    }
+
+    dims.insert(axis, 1);
+
+    Ok(reshape(x, dims.as_slice().into()))
+}
@@ -0,0 +1,111 @@
+use super::*;
+
+/// Copies the window of X1 selected by a Layout into Y, in NCHW order. This is how a 
+/// View of a Var is used as an operator input, by passing its `layout()`.
+pub struct AsStrided {
+    layout: Layout,
+}
+
+/// Adds X1 into the window selected by a Layout of zeros of `shape`, the adjoint of AsStrided.
+pub struct AsStridedScatter {
+    layout: Layout,
+    shape: Shape,
+}
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for AsStrided 
+where
+    S::F: Float,
+{
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        node.x1().gather(&self.layout, node.y(0));
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        node.tx(0).gather(&self.layout, node.ty(0));
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        check_layout(&self.layout, node.x1().shape())?;
+        node.reshape(self.layout.shape());
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, _index: usize) -> Result<()> {
+        node.g1().scatter_add(&self.layout, node.gy(0));
+        Ok(())
+    }
+
+    fn grad<'s>(&self, xs: &[Var<'s, S>], gy: &[Var<'s, S>], _index: usize) -> Result<Var<'s, S>> {
+        let node = Node::<S>::build()
+            .with_input(&gy[0]);
+
+        Ok(gy[0].scope().push(node, AsStridedScatter { layout: self.layout, shape: xs[0].shape().clone() }))
+    }
+}
+
+impl<S: Accumulate + From<Shape> + 'static> Operator<S> for AsStridedScatter 
+where
+    S::F: Float,
+{
+    fn forward(&mut self, node: &Node<S>) -> Result<()> {
+        let y = node.y(0);
+        y.fill(S::F::ZERO);
+        y.scatter_add(&self.layout, node.x1());
+        Ok(())
+    }
+
+    fn tangent(&self, node: &Node<S>) -> Result<()> {
+        let ty = node.ty(0);
+        ty.fill(S::F::ZERO);
+        ty.scatter_add(&self.layout, node.tx(0));
+        Ok(())
+    }
+
+    fn reshape(&mut self, node: &Node<S>) -> Result<()> {
+        check_layout(&self.layout, &self.shape)?;
+
+        if node.x1().shape().as_array4() != self.layout.shape().as_array4() {
+            return Err(anyhow!("X1 shape must match the shape of the layout!"))
+        }
+
+        node.reshape(self.shape.clone());
+
+        Ok(())
+    }
+
+    fn wrt(&self, node: &Node<S>, _index: usize) -> Result<()> {
+        let mut window = Tensor::<S>::new(self.layout.shape());
+        node.gy(0).gather(&self.layout, &mut window);
+        node.g1().scatter_add(&Layout::new(&self.layout.shape()), &window);
+        Ok(())
+    }
+
+    fn grad<'s>(&self, _xs: &[Var<'s, S>], gy: &[Var<'s, S>], _index: usize) -> Result<Var<'s, S>> {
+        as_strided(gy[0].clone(), self.layout)
+    }
+}
+
+fn check_layout(layout: &Layout, shape: &Shape) -> Result<()> {
+    if layout.end() > shape.len() {
+        return Err(anyhow!("Layout reaches element {}, past the {} elements of X1!", layout.end() - 1, shape.len()))
+    }
+
+    Ok(())
+}
+
+/// The window of `x` selected by `layout`, such as the layout of a View:
+/// `as_strided(x, Layout::new(x.shape()).slice(2, 0..4)?.transpose(2, 3)?)`.
+pub fn as_strided<'s, S>(x: Var<'s, S>, layout: Layout) -> Result<Var<'s, S>>
+where
+    S: Accumulate + From<Shape> + 'static,
+    S::F: Float,
+{
+    check_layout(&layout, x.shape())?;
+
+    let node = Node::<S>::build()
+        .with_input(&x);
+
+    Ok(x.scope().push(node, AsStrided { layout }))
+}
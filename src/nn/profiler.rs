@@ -0,0 +1,277 @@
+//! # Profiler
+//!
+//! An opt-in record of every `forward` and `wrt` call of the nodes of a Scope,
+//! enabled with `ScopeBuilder::with_profiler`. Every call records its wall time
+//! on the host, and the bytes allocated and copied by storage on its thread while it ran.
+//! On a Gpu, the host only enqueues the kernels, so events recorded on the stream
+//! of the Scope around every call also measure the time the device spent on it.
+//!
+//! The records export to a Chrome trace, which opens in `chrome://tracing` or
+//! Perfetto, and to a table summarizing them by operator.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::gpu::{Device, Event, Stream};
+use crate::storage::counters;
+
+/// The call of a node a record measures.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Forward,
+    /// The gradient of the input at the index.
+    Wrt(usize),
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Forward => write!(f, "forward"),
+            Phase::Wrt(index) => write!(f, "wrt_x{}", index + 1),
+        }
+    }
+}
+
+/// A measured call of a node.
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// The index of the node in its Scope.
+    pub node: usize,
+    pub operator: &'static str,
+    pub phase: Phase,
+    /// When the call started on the host, since the profiler was created.
+    pub start: Duration,
+    pub cpu: Duration,
+    /// When the device started the call, and how long it spent on it, on a Gpu.
+    pub gpu: Option<(Duration, Duration)>,
+    /// Bytes allocated and copied by storage on the thread of the call, while it ran.
+    pub allocated: usize,
+    pub copied: usize,
+}
+
+#[derive(Default)]
+struct State {
+    records: Vec<Record>,
+    /// The events around the calls whose device time is not resolved yet, by record.
+    pending: Vec<(usize, Event, Event)>,
+    /// An event recorded when the device was idle, with the host time it was recorded at.
+    base: Option<(Event, Duration)>,
+}
+
+pub struct Profiler {
+    epoch: Instant,
+    /// The device to create events on, for Scopes on a Gpu.
+    device: Option<Arc<Device>>,
+    state: Mutex<State>,
+}
+
+impl Profiler {
+    /// Create a profiler, which times the device too if there is one.
+    pub fn new(device: Option<&Arc<Device>>) -> Self {
+        Self {
+            epoch: Instant::now(),
+            device: device.cloned(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Run `f` as the `phase` call of the node at `node`, whose kernels are launched on `stream`.
+    pub fn record<R>(&self, node: usize, operator: &'static str, phase: Phase, stream: &Stream, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        let events = match self.device.as_ref() {
+            Some(device) => {
+                self.base(device, stream)?;
+
+                let events = (Event::new(device, true)?, Event::new(device, true)?);
+                events.0.record(stream)?;
+                Some(events)
+            }
+            None => None,
+        };
+
+        let allocated = counters::allocated();
+        let copied = counters::copied();
+        let start = self.epoch.elapsed();
+
+        let out = f()?;
+
+        let cpu = self.epoch.elapsed() - start;
+        let mut state = self.state.lock().unwrap();
+        let index = state.records.len();
+
+        if let Some((begin, end)) = events {
+            end.record(stream)?;
+            state.pending.push((index, begin, end));
+        }
+
+        state.records.push(Record {
+            node,
+            operator,
+            phase,
+            start,
+            cpu,
+            gpu: None,
+            allocated: counters::allocated() - allocated,
+            copied: counters::copied() - copied,
+        });
+
+        Ok(out)
+    }
+
+    /// Record the base event the first time the device is timed, once `stream` is idle,
+    /// so the device time of every call can be placed on the timeline of the host.
+    fn base(&self, device: &Arc<Device>, stream: &Stream) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.base.is_none() {
+            let event = Event::new(device, true)?;
+
+            stream.synchronize()?;
+            event.record(stream)?;
+            state.base = Some((event, self.epoch.elapsed()));
+        }
+
+        Ok(())
+    }
+
+    /// Every record so far, in the order the calls were made.
+    /// Waits for the device to finish the calls it has not timed yet.
+    pub fn records(&self) -> Result<Vec<Record>> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+
+        for (index, begin, end) in state.pending.drain(..) {
+            let (base, offset) = state.base.as_ref().unwrap();
+
+            end.synchronize()?;
+
+            let start = *offset + Duration::from_secs_f32(begin.elapsed_time(base)?.max(0.0) / 1000.0);
+            let time = Duration::from_secs_f32(end.elapsed_time(&begin)?.max(0.0) / 1000.0);
+
+            state.records[index].gpu = Some((start, time));
+        }
+
+        Ok(state.records.clone())
+    }
+
+    /// Forget every record.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.records.clear();
+        state.pending.clear();
+    }
+
+    /// The records as a Chrome trace, with the host calls on one track and the device calls on another.
+    pub fn chrome_trace(&self) -> Result<String> {
+        let mut events = Vec::new();
+
+        for record in self.records()? {
+            let name = format!("{}.{}", record.operator, record.phase);
+            let args = format!(
+                "{{\"node\":{},\"allocated\":{},\"copied\":{}}}",
+                record.node, record.allocated, record.copied
+            );
+
+            events.push(trace_event(&name, "cpu", 0, record.start, record.cpu, &args));
+
+            if let Some((start, time)) = record.gpu {
+                events.push(trace_event(&name, "gpu", 1, start, time, &args));
+            }
+        }
+
+        Ok(format!(
+            "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n",
+            events.join(",\n")
+        ))
+    }
+
+    /// Write the Chrome trace to `path`.
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.chrome_trace()?)?;
+        Ok(())
+    }
+
+    /// A table of the calls, time and memory of every operator, the most expensive first.
+    pub fn summary(&self) -> Result<String> {
+        let mut totals: HashMap<&'static str, (usize, Duration, Option<Duration>, usize, usize)> = HashMap::new();
+
+        for record in self.records()? {
+            let total = totals.entry(record.operator).or_default();
+
+            total.0 += 1;
+            total.1 += record.cpu;
+            total.2 = record.gpu.map(|(_, time)| total.2.unwrap_or_default() + time).or(total.2);
+            total.3 += record.allocated;
+            total.4 += record.copied;
+        }
+
+        let mut totals: Vec<_> = totals.into_iter().collect();
+        totals.sort_by_key(|(operator, (_, cpu, gpu, _, _))| (std::cmp::Reverse(gpu.unwrap_or(*cpu)), *operator));
+
+        let mut out = format!(
+            "{:<20} {:>8} {:>12} {:>12} {:>14} {:>14}\n",
+            "operator", "calls", "cpu ms", "gpu ms", "allocated", "copied"
+        );
+
+        for (operator, (calls, cpu, gpu, allocated, copied)) in totals {
+            let gpu = gpu.map_or("-".to_owned(), |gpu| format!("{:.3}", gpu.as_secs_f64() * 1000.0));
+
+            writeln!(
+                out, "{:<20} {:>8} {:>12.3} {:>12} {:>14} {:>14}",
+                operator, calls, cpu.as_secs_f64() * 1000.0, gpu, allocated, copied
+            )?;
+        }
+
+        Ok(out)
+    }
+}
+
+fn trace_event(name: &str, category: &str, track: usize, start: Duration, time: Duration, args: &str) -> String {
+    format!(
+        "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3},\"args\":{}}}",
+        name, category, track, start.as_secs_f64() * 1e6, time.as_secs_f64() * 1e6, args
+    )
+}
+
+/// The node a Scope is running on this thread, while it is profiled.
+struct Current {
+    profiler: Arc<Profiler>,
+    node: usize,
+    operator: &'static str,
+    stream: Stream,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Current>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with the node at `node` as the one `record` measures, if there is a profiler.
+pub(crate) fn with_node<R>(profiler: Option<&Arc<Profiler>>, node: usize, operator: &'static str, stream: &Stream, f: impl FnOnce() -> R) -> R {
+    let profiler = match profiler {
+        Some(profiler) => profiler.clone(),
+        None => return f(),
+    };
+
+    let current = Current { profiler, node, operator, stream: stream.clone() };
+    let previous = CURRENT.with(|c| c.replace(Some(current)));
+    let out = f();
+    CURRENT.with(|c| c.replace(previous));
+    out
+}
+
+/// Run `f` as the `phase` call of the current node, recording it if it is profiled.
+pub(crate) fn record<R>(phase: Phase, f: impl FnOnce() -> Result<R>) -> Result<R> {
+    let current = CURRENT.with(|c| {
+        c.borrow().as_ref().map(|c| (c.profiler.clone(), c.node, c.operator, c.stream.clone()))
+    });
+
+    match current {
+        Some((profiler, node, operator, stream)) => profiler.record(node, operator, phase, &stream, f),
+        None => f(),
+    }
+}
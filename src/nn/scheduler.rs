@@ -1,10 +1,85 @@
 
+use std::ops::Range;
+
 use upto::UpTo;
 
+use crate::storage::Shape;
+
+/// A step of a forward or backward pass over the nodes of a Scope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// Run the forward pass of the node at this index.
+    Forward(usize),
+    /// Run the backward pass of the node at this index.
+    Backward(usize),
+    /// Reallocate the freed outputs of the segment at this index, unless they are still allocated.
+    Restore(usize),
+    /// Free the intermediate outputs of the segment at this index.
+    Free(usize),
+}
+
+/// A range of nodes whose intermediate outputs are freed after the forward 
+/// pass, and recomputed when the backward pass reaches the segment.
+pub(crate) struct Segment {
+    pub nodes: Range<usize>,
+    /// Levels and shapes of the outputs that are only used inside the segment.
+    pub freed: Vec<(usize, Shape)>,
+}
+
+/// Orders the steps of the forward and backward passes, restoring checkpointed 
+/// segments before they run, and recomputing them before they are differentiated.
+/// The outputs of the last node of a segment are never freed, so it is not recomputed.
 pub struct Scheduler {
+    forward: Vec<Step>,
+    backward: Vec<Step>,
+}
+
+impl Scheduler {
+    /// Schedule `count` nodes, with the provided checkpointed segments.
+    pub(crate) fn new(count: usize, segments: &[Segment]) -> Self {
+        let ending = |i: usize| segments.iter().position(|s| s.nodes.end == i + 1);
+        let starting = |i: usize| segments.iter().position(|s| s.nodes.start == i);
+
+        let mut forward = Vec::with_capacity(count);
+        let mut backward = Vec::with_capacity(count);
 
+        for i in 0..count {
+            if let Some(s) = starting(i) {
+                forward.push(Step::Restore(s));
+            }
+
+            forward.push(Step::Forward(i));
+
+            if let Some(s) = ending(i) {
+                forward.push(Step::Free(s));
+            }
+        }
+
+        for i in (0..count).rev() {
+            if let Some(s) = ending(i) {
+                backward.push(Step::Restore(s));
+                backward.extend((segments[s].nodes.start..i).map(Step::Forward));
+            }
+
+            backward.push(Step::Backward(i));
+
+            if let Some(s) = starting(i) {
+                backward.push(Step::Free(s));
+            }
+        }
+
+        Self { forward, backward }
+    }
+
+    pub(crate) fn forward(&self) -> &[Step] {
+        &self.forward
+    }
+
+    pub(crate) fn backward(&self) -> &[Step] {
+        &self.backward
+    }
 }
 
 struct Group {
     indices: UpTo<10, usize>,
-}
\ No newline at end of file
+}
@@ -1,22 +1,695 @@
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use anyhow::{Result, anyhow};
+use hashbrown::{HashMap, HashSet};
 
 use crate::storage::Storage;
+use crate::storage::StorageInfo;
+use crate::storage::Shape;
+use crate::storage::Tensor;
+use crate::storage::Element;
+use crate::storage::Float;
+use crate::storage::Accumulate;
+use crate::storage::with_num_threads;
+use crate::storage::with_placement;
 use super::node::Node;
+use super::node::NodeBuilder;
+use super::node::Dependency;
+use super::operators::{Operator, Add, Fill, add, fill};
+use super::var::Var;
+use super::scheduler::{Scheduler, Segment, Step};
+use super::profiler::{self, Profiler, Phase};
+use super::memory::{self, MemoryReport, MemoryPlan, PlanMode, PlanNode, Slot};
 use crate::gpu::device::Device;
+use crate::gpu::Stream;
 
 pub struct Scope<S: Storage> {
-    nodes: Vec<Node<S>>,
+    nodes: Vec<Arc<Node<S>>>,
+    deps: Vec<Arc<Dependency<S>>>,
+    segments: Vec<Segment>,
+    scheduler: Scheduler,
+    memory: MemoryReport,
+    measured: Cell<MemoryReport>,
+    plan: Option<MemoryPlan>,
+    pool: RefCell<Vec<Option<Tensor<S>>>>,
+    threads: Option<usize>,
+    device: Arc<Device>,
+    stream: Stream,
+    profiler: Option<Arc<Profiler>>,
+}
+
+impl<S: Storage> Scope<S> {
+    /// Run the operators of this Scope on `threads` threads, or on every available 
+    /// core if `threads` is 0, instead of the global setting.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// The number of threads set for this Scope, if any.
+    pub fn threads(&self) -> Option<usize> {
+        self.threads
+    }
+
+    /// The stream the kernels of this Scope are launched on.
+    pub fn stream(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// Run `f` on the threads of this Scope, with its tensors allocated on its device and stream.
+    fn placed<R>(&self, f: impl FnOnce() -> R) -> R {
+        with_num_threads(self.threads, || with_placement(&self.device, &self.stream, f))
+    }
+
+    /// The profiler of this Scope, if it was built with one.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    /// The number of Vars in this Scope.
+    pub fn levels(&self) -> usize {
+        self.deps.len()
+    }
+
+    /// Whether every value and gradient stays in place while running, 
+    /// which is not the case with checkpoints or planned memory.
+    pub(crate) fn is_static(&self) -> bool {
+        self.segments.is_empty() && self.plan.is_none()
+    }
+
+    /// The shape of the Var at `level`, as recorded by a memory plan or a checkpoint, 
+    /// since the values they free only hold a placeholder.
+    pub(crate) fn shape(&self, level: usize) -> Shape {
+        if let Some(plan) = self.plan.as_ref() {
+            return plan.shapes[level].clone()
+        }
+
+        self.segments.iter()
+            .flat_map(|segment| segment.freed.iter())
+            .find(|(freed, _)| *freed == level)
+            .map_or_else(|| self.deps[level].input.shape().clone(), |(_, shape)| shape.clone())
+    }
+
+    /// The name of the first operator that cannot be captured into a graph, if any.
+    pub(crate) fn uncapturable(&self) -> Option<&'static str> {
+        self.nodes.iter()
+            .map(|node| node.operator())
+            .find(|operator| !operator.capturable())
+            .map(|operator| operator.name())
+    }
+
+    /// The value of the Var at `level`. Values inside checkpointed 
+    /// segments are freed by `forward()`, and only hold a placeholder.
+    pub fn value(&self, level: usize) -> &mut Tensor<S> {
+        self.deps[level].input.get_mut()
+    }
+
+    /// The total gradient of the Var at `level`, summed over every node that consumed it.
+    pub fn gradient(&self, level: usize) -> &mut Tensor<S> {
+        self.deps[level].gradient.get_mut()
+    }
+
+    /// Reset the gradient of every Var to zero. Call this before seeding the
+    /// gradient of the loss, as `backward()` adds into the existing gradients.
+    pub fn zero_grad(&mut self) {
+        for dep in self.deps.iter() {
+            dep.gradient.get_mut().fill(S::F::ZERO);
+        }
+    }
+
+    /// Bytes held by the values and gradients, and the savings of checkpointing or planning,
+    /// as estimated from the shapes when the Scope was built or planned.
+    pub fn memory(&self) -> MemoryReport {
+        self.memory
+    }
+
+    /// Bytes held by the values and gradients, and by the pool of a memory plan, as measured 
+    /// while running: `resident` after the last `forward()`, and `peak` over every pass so far.
+    pub fn measured(&self) -> MemoryReport {
+        MemoryReport { total: self.memory.total, ..self.measured.get() }
+    }
+
+    /// Bytes held by the values and gradients, and by the pool of a memory plan.
+    fn held(&self) -> usize {
+        let deps: usize = self.deps.iter()
+            .map(|dep| dep.input.len() + dep.gradient.len())
+            .sum();
+
+        let pool: usize = self.pool.borrow().iter()
+            .flatten()
+            .map(|tensor| tensor.len())
+            .sum();
+
+        (deps + pool) * std::mem::size_of::<S::F>()
+    }
+
+    /// Raise the measured peak to the bytes held now.
+    fn measure(&self) {
+        let mut measured = self.measured.get();
+        measured.peak = measured.peak.max(self.held());
+        self.measured.set(measured);
+    }
+}
+
+impl<S: Storage> Scope<S> 
+where
+    S: From<Shape>
+{
+    /// Run the forward pass of every node, in the order they were added,
+    /// freeing the intermediate outputs of every checkpointed segment.
+    pub fn forward(&mut self) -> Result<()> {
+        if let Some(plan) = self.plan.as_ref() {
+            let changed = self.deps.iter().enumerate()
+                .any(|(level, dep)| !plan.planned.contains(&level) && dep.input.shape() != &plan.shapes[level]);
+
+            if changed {
+                return Err(anyhow!("The shapes of this Scope changed since its memory was planned!"))
+            }
+        }
+
+        self.placed(|| {
+            self.scheduler.forward().iter().try_for_each(|step| self.run(step))
+        })?;
+
+        let mut measured = self.measured.get();
+        measured.resident = self.held();
+        self.measured.set(measured);
+
+        Ok(())
+    }
+
+    /// Run the backward pass of every node, in the reverse order they were added,
+    /// recomputing each checkpointed segment before it is reached.
+    ///
+    /// Each node adds its contribution into the gradients of its inputs, 
+    /// so a Var used by several nodes receives the sum of all of them.
+    pub fn backward(&mut self) -> Result<()> {
+        if let Some(PlanMode::Inference) = self.plan.as_ref().map(|plan| plan.mode) {
+            return Err(anyhow!("A Scope planned for inference cannot run backward!"))
+        }
+
+        self.placed(|| {
+            self.scheduler.backward().iter().try_for_each(|step| self.run(step))
+        })
+    }
+
+    fn run(&self, step: &Step) -> Result<()> {
+        match step {
+            Step::Forward(i) => {
+                self.acquire(*i);
+                self.measure();
+
+                self.profile(*i, || profiler::record(Phase::Forward, || {
+                    match self.plan.as_ref().and_then(|plan| plan.inplace[*i]) {
+                        Some(index) => self.nodes[*i].forward_inplace(index),
+                        None => self.nodes[*i].forward(),
+                    }
+                }))?;
+
+                self.release(*i);
+                Ok(())
+            }
+            Step::Backward(i) => {
+                let step = 2 * self.nodes.len() - 1 - i;
+
+                self.acquire(step);
+                self.measure();
+                self.profile(*i, || self.nodes[*i].backward())?;
+                self.release(step);
+                Ok(())
+            }
+            Step::Restore(s) => {
+                for (level, shape) in self.segments[*s].freed.iter() {
+                    let (value, gradient) = (self.deps[*level].input.get_mut(), self.deps[*level].gradient.get_mut());
+
+                    if value.shape() != shape {
+                        *value = Tensor::new(shape.clone());
+                    }
+
+                    match gradient.shape() == shape {
+                        true => gradient.fill(S::F::ZERO),
+                        false => *gradient = Tensor::new(shape.clone()),
+                    }
+                }
+
+                self.measure();
+                Ok(())
+            }
+            Step::Free(s) => {
+                for (level, _) in self.segments[*s].freed.iter() {
+                    *self.deps[*level].input.get_mut() = Tensor::new(Shape::from([1]));
+                    *self.deps[*level].gradient.get_mut() = Tensor::new(Shape::from([1]));
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Run `f` as the node at `node`, for the profiler.
+    fn profile<R>(&self, node: usize, f: impl FnOnce() -> R) -> R {
+        let operator = self.nodes[node].operator().name();
+        profiler::with_node(self.profiler.as_ref(), node, operator, &self.stream, f)
+    }
+
+    /// Move the tensors planned to be alive from `step` out of the pool.
+    fn acquire(&self, step: usize) {
+        let plan = match self.plan.as_ref() {
+            Some(plan) => plan,
+            None => return,
+        };
+
+        let mut pool = self.pool.borrow_mut();
+
+        for (slot, buffer) in plan.acquire[step].iter() {
+            let (level, gradient) = match slot {
+                Slot::Value(level) => (*level, false),
+                Slot::Gradient(level) => (*level, true),
+            };
+
+            let shape = plan.shapes[level].clone();
+
+            let mut tensor = match pool[*buffer].take() {
+                Some(mut tensor) => {
+                    tensor.set_shape(shape);
+                    tensor
+                }
+                None => Tensor::new(shape),
+            };
+
+            if gradient {
+                tensor.fill(S::F::ZERO);
+                *self.deps[level].gradient.get_mut() = tensor;
+            } else {
+                *self.deps[level].input.get_mut() = tensor;
+            }
+        }
+    }
+
+    /// Move the tensors planned to die after `step` back into the pool.
+    fn release(&self, step: usize) {
+        let plan = match self.plan.as_ref() {
+            Some(plan) => plan,
+            None => return,
+        };
+
+        let mut pool = self.pool.borrow_mut();
+
+        for (slot, buffer) in plan.release[step].iter() {
+            let tensor = match slot {
+                Slot::Value(level) => self.deps[*level].input.get_mut(),
+                Slot::Gradient(level) => self.deps[*level].gradient.get_mut(),
+            };
+
+            pool[*buffer] = Some(std::mem::replace(tensor, Tensor::new(Shape::from([1]))));
+        }
+    }
+
+    /// Plan where every value and gradient lives, from their lifetimes over the nodes, 
+    /// and share a pool of buffers between them from now on. Returns the planned 
+    /// memory, before anything is run. Values that are freed only hold a placeholder,
+    /// and the shapes of the inputs cannot change afterwards.
+    pub fn plan_memory(&mut self, mode: PlanMode) -> Result<MemoryReport> {
+        if !self.segments.is_empty() {
+            return Err(anyhow!("A Scope with checkpoints cannot be planned!"))
+        }
+
+        if self.plan.is_some() {
+            return Err(anyhow!("Memory of this Scope is already planned!"))
+        }
+
+        let kept: Vec<bool> = self.deps.iter()
+            .map(|dep| dep.shared.load(Ordering::Relaxed))
+            .collect();
+
+        let nodes: Vec<PlanNode> = self.nodes.iter()
+            .map(|node| PlanNode {
+                inputs: node.input_levels(),
+                outputs: node.output_levels(),
+                inplace: node.operator().inplace(),
+            })
+            .collect();
+
+        let shapes = self.deps.iter().map(|dep| dep.input.shape().clone()).collect();
+        let plan = memory::plan(mode, &nodes, shapes, &kept, std::mem::size_of::<S::F>());
+
+        self.placed(|| {
+            for level in plan.planned.iter() {
+                *self.deps[*level].input.get_mut() = Tensor::new(Shape::from([1]));
+                *self.deps[*level].gradient.get_mut() = Tensor::new(Shape::from([1]));
+            }
+        });
+
+        *self.pool.borrow_mut() = (0..plan.buffers).map(|_| None).collect();
+        self.memory = plan.report;
+        self.plan = Some(plan);
+
+        Ok(self.memory)
+    }
+
+    /// Reset the tangent of every Var to zeros of its shape. Call this before 
+    /// setting the tangents of the inputs with `tangent(level)` and running `jvp()`.
+    pub fn zero_tangents(&mut self) -> Result<()> {
+        self.placed(|| {
+            for s in 0..self.segments.len() {
+                self.run(&Step::Restore(s))?;
+            }
+
+            for dep in self.deps.iter() {
+                let shape = dep.input.shape();
+                let tangent = dep.tangent.get_mut();
+
+                if tangent.shape() != shape {
+                    *tangent = Tensor::new(shape.clone());
+                }
+
+                tangent.fill(S::F::ZERO);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// The tangent of the Var at `level`, in forward-mode.
+    pub fn tangent(&self, level: usize) -> &mut Tensor<S> {
+        self.deps[level].tangent.get_mut()
+    }
+
+    /// Run the forward pass of every node, propagating the tangents of the inputs
+    /// alongside the values. Afterwards, the tangent of every Var holds the
+    /// Jacobian-vector product of the Var with the tangents of the inputs.
+    pub fn jvp(&mut self) -> Result<()> {
+        if self.plan.is_some() {
+            return Err(anyhow!("Forward-mode cannot run on a Scope with planned memory!"))
+        }
+
+        self.placed(|| {
+            for s in 0..self.segments.len() {
+                self.run(&Step::Restore(s))?;
+            }
+
+            if self.deps.iter().any(|dep| dep.tangent.shape() != dep.input.shape()) {
+                return Err(anyhow!("The tangents are not set, call zero_tangents first!"))
+            }
+
+            for node in self.nodes.iter() {
+                node.forward()?;
+                node.tangent()?;
+            }
+
+            Ok(())
+        })
+    }
 }
 
 pub struct ScopeBuilder<S: Storage> {
     device: Arc<Device>,
     scope: RefCell<Scope<S>>,
+    checkpoints: RefCell<Vec<Range<usize>>>,
+    checkpointing: Cell<bool>,
 }
 
 impl<S: Storage> ScopeBuilder<S> {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            device: device.clone(),
+            scope: RefCell::new(Scope {
+                nodes: Vec::new(),
+                deps: Vec::new(),
+                segments: Vec::new(),
+                scheduler: Scheduler::new(0, &[]),
+                memory: MemoryReport::default(),
+                measured: Cell::new(MemoryReport::default()),
+                plan: None,
+                pool: RefCell::new(Vec::new()),
+                threads: None,
+                device: device.clone(),
+                stream: Stream::null(),
+                profiler: None,
+            }),
+            checkpoints: RefCell::new(Vec::new()),
+            checkpointing: Cell::new(false),
+        }
+    }
+
     pub fn device(&self) -> &Arc<Device> {
         &self.device
     }
-}
\ No newline at end of file
+
+    /// Launch the kernels of every node on `stream`, instead of the null stream, and allocate 
+    /// their tensors on it. Inputs added before are allocated again, and nodes must come after.
+    /// A Scope must run on its own stream to be captured into a graph.
+    pub fn with_stream(self, stream: &Stream) -> Self 
+    where
+        S: From<Shape>
+    {
+        let mut scope = self.scope.borrow_mut();
+
+        if !scope.nodes.is_empty() {
+            panic!("The stream of a Scope must be set before adding nodes!")
+        }
+
+        scope.stream = stream.clone();
+
+        scope.placed(|| {
+            for dep in scope.deps.iter() {
+                let shape = dep.input.shape().clone();
+                *dep.input.get_mut() = Tensor::new(shape.clone());
+                *dep.gradient.get_mut() = Tensor::new(shape);
+            }
+        });
+
+        drop(scope);
+        self
+    }
+
+    /// Profile every call of every node, see `Scope::profiler`. On a Gpu,
+    /// the device is timed too, with events on the stream of the Scope.
+    pub fn with_profiler(self) -> Self 
+    where
+        S: StorageInfo
+    {
+        let device = (S::TYPE == "gpu").then_some(&self.device);
+        self.scope.borrow_mut().profiler = Some(Arc::new(Profiler::new(device)));
+        self
+    }
+
+    /// Finish building and return the Scope.
+    pub fn build(self) -> Scope<S> 
+    where
+        S: From<Shape>
+    {
+        let mut scope = self.scope.into_inner();
+
+        scope.segments = self.checkpoints.into_inner().into_iter()
+            .map(|nodes| segment(&scope, nodes))
+            .collect();
+
+        scope.scheduler = Scheduler::new(scope.nodes.len(), &scope.segments);
+        scope.memory = memory(&scope);
+
+        // The segments are restored by the forward pass that needs them.
+        scope.placed(|| {
+            for segment in scope.segments.iter() {
+                for (level, _) in segment.freed.iter() {
+                    *scope.deps[*level].input.get_mut() = Tensor::new(Shape::from([1]));
+                    *scope.deps[*level].gradient.get_mut() = Tensor::new(Shape::from([1]));
+                }
+            }
+        });
+
+        scope
+    }
+
+    /// Build a segment of the graph whose intermediate outputs are freed after 
+    /// `forward()` and recomputed by `backward()`, trading compute for memory.
+    /// Only the outputs used outside of the segment are kept.
+    pub fn checkpoint<R>(&self, f: impl FnOnce() -> R) -> R {
+        if self.checkpointing.replace(true) {
+            panic!("Checkpoints cannot be nested!")
+        }
+
+        let start = self.scope.borrow().nodes.len();
+        let out = f();
+        let end = self.scope.borrow().nodes.len();
+
+        self.checkpointing.set(false);
+
+        if end > start {
+            self.checkpoints.borrow_mut().push(start..end);
+        }
+
+        out
+    }
+
+    /// Get the Dependency a Var refers to, for an operator of another scope, 
+    /// which is why it is marked as shared and never freed by this one.
+    pub(crate) fn dependency(&self, level: usize) -> Arc<Dependency<S>> {
+        let dep = self.scope.borrow().deps[level].clone();
+        dep.shared.store(true, Ordering::Relaxed);
+        dep
+    }
+}
+
+impl<S: Storage + 'static> ScopeBuilder<S> 
+where
+    S: From<Shape>
+{
+    /// Create a Var with no operator, whose value is set by the user.
+    pub fn input(&self, shape: Shape) -> Var<'_, S> {
+        let mut scope = self.scope.borrow_mut();
+        let level = scope.deps.len();
+
+        let dep = scope.placed(|| Dependency::new(shape.clone(), level));
+        scope.deps.push(Arc::new(dep));
+
+        Var::new(self, shape, level)
+    }
+
+    /// Add a node to the scope, returning a Var for its output.
+    pub(crate) fn push(&self, node: NodeBuilder<S>, operator: impl Operator<S> + 'static) -> Var<'_, S> {
+        self.push_many(node, operator).remove(0)
+    }
+
+    /// Add a node to the scope, returning a Var for each of its outputs.
+    pub(crate) fn push_many(&self, node: NodeBuilder<S>, operator: impl Operator<S> + 'static) -> Vec<Var<'_, S>> {
+        let mut scope = self.scope.borrow_mut();
+        let level = scope.deps.len();
+
+        let (ys, node) = scope.placed(|| {
+            let ys: Vec<_> = (0..node.outputs())
+                .map(|i| Arc::new(Dependency::new(Shape::from([1]), level + i)))
+                .collect();
+
+            let node = node.finish(operator, ys.clone(), &scope.deps, scope.stream.clone());
+
+            node.resize()
+                .expect("Failed to reshape node!");
+
+            (ys, node)
+        });
+
+        let vars = (0..node.outputs())
+            .map(|i| Var::new(self, node.y(i).shape().clone(), level + i))
+            .collect();
+
+        scope.deps.extend(ys);
+        scope.nodes.push(Arc::new(node));
+
+        vars
+    }
+}
+
+/// Find the outputs of `nodes` that no other node or scope depends on. The outputs 
+/// of the last node are always kept, since they are the result of the segment.
+fn segment<S: Storage>(scope: &Scope<S>, nodes: Range<usize>) -> Segment {
+    let mut outside = HashSet::new();
+
+    for (i, node) in scope.nodes.iter().enumerate() {
+        if !nodes.contains(&i) {
+            outside.extend(node.input_levels());
+        }
+    }
+
+    let freed = nodes.start..nodes.end - 1;
+
+    let freed = freed.flat_map(|i| scope.nodes[i].output_levels())
+        .filter(|level| !outside.contains(level) && !scope.deps[*level].shared.load(Ordering::Relaxed))
+        .map(|level| (level, scope.deps[level].input.shape().clone()))
+        .collect();
+
+    Segment { nodes, freed }
+}
+
+fn memory<S: Storage>(scope: &Scope<S>) -> MemoryReport {
+    let bytes = |shape: &Shape| 2 * shape.len() * std::mem::size_of::<S::F>();
+
+    let total = scope.deps.iter()
+        .map(|dep| bytes(dep.input.shape()))
+        .sum();
+
+    let freed: Vec<usize> = scope.segments.iter()
+        .map(|segment| segment.freed.iter().map(|(_, shape)| bytes(shape)).sum())
+        .collect();
+
+    let resident = total - freed.iter().sum::<usize>();
+
+    MemoryReport {
+        total,
+        resident,
+        peak: resident + freed.iter().max().unwrap_or(&0),
+    }
+}
+
+impl<S: Accumulate + From<Shape> + 'static> ScopeBuilder<S> 
+where
+    S::F: Float,
+    Add: Operator<S>,
+    Fill: Operator<S>,
+{
+    /// Record the backward pass from `y` as new nodes, returning the gradient of `y` 
+    /// with respect to each of `wrt`, with the gradient of `y` seeded with ones.
+    ///
+    /// The returned Vars are computed by `forward()` like any other, and can be 
+    /// differentiated again, for Hessian-vector products or gradient penalties.
+    /// Every operator between `wrt` and `y` must implement `Operator::grad`.
+    pub fn backward<'s>(&'s self, y: &Var<'s, S>, wrt: &[Var<'s, S>]) -> Result<Vec<Var<'s, S>>> {
+        let count = self.scope.borrow().nodes.len();
+
+        // Only the levels that depend on `wrt` need a gradient.
+        let mut needed: HashSet<usize> = wrt.iter().map(|x| x.level()).collect();
+
+        for i in 0..count {
+            let node = self.scope.borrow().nodes[i].clone();
+
+            if node.input_levels().iter().any(|level| needed.contains(level)) {
+                needed.extend(node.output_levels());
+            }
+        }
+
+        let mut grads = HashMap::new();
+        grads.insert(y.level(), fill(self, y.shape().clone(), 1.0));
+
+        for i in (0..count).rev() {
+            let node = self.scope.borrow().nodes[i].clone();
+            let outputs = node.output_levels();
+
+            if !outputs.iter().any(|level| grads.contains_key(level)) {
+                continue
+            }
+
+            let gys: Vec<_> = outputs.iter().enumerate()
+                .map(|(j, level)| match grads.get(level) {
+                    Some(g) => g.clone(),
+                    None => fill(self, node.y(j).shape().clone(), 0.0),
+                })
+                .collect();
+
+            let xs: Vec<_> = node.input_levels().into_iter().enumerate()
+                .map(|(j, level)| Var::new(self, node.x(j).shape().clone(), level))
+                .collect();
+
+            for (j, x) in xs.iter().enumerate().filter(|(_, x)| needed.contains(&x.level())) {
+                let g = node.operator().grad(&xs, &gys, j)?;
+
+                let total = match grads.remove(&x.level()) {
+                    Some(prev) => add(prev, g),
+                    None => g,
+                };
+
+                grads.insert(x.level(), total);
+            }
+        }
+
+        Ok(wrt.iter()
+            .map(|x| match grads.get(&x.level()) {
+                Some(g) => g.clone(),
+                None => fill(self, x.shape().clone(), 0.0),
+            })
+            .collect())
+    }
+}
@@ -3,9 +3,8 @@ use std::sync::Arc;
 
 use crate::storage::Storage;
 use super::scope::ScopeBuilder;
-use crate::storage::{Cpu, Gpu, Float};
+use super::node::Dependency;
 use crate::storage::Shape;
-use crate::gpu::device::Device;
 
 pub struct Var<'s, S: Storage> {
     scope: &'s ScopeBuilder<S>,
@@ -14,7 +13,39 @@ pub struct Var<'s, S: Storage> {
 }
 
 impl<'s, S: Storage> Var<'s, S> {
+    pub(crate) fn new(scope: &'s ScopeBuilder<S>, shape: Shape, level: usize) -> Self {
+        Self {
+            scope, shape, level,
+        }
+    }
+
+    /// The scope this Var was created in.
+    pub fn scope(&self) -> &'s ScopeBuilder<S> {
+        self.scope
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    /// The index of this Vars' dependency in the scope.
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub(crate) fn dependency(&self) -> Arc<Dependency<S>> {
+        self.scope.dependency(self.level)
+    }
+}
 
+impl<'s, S: Storage> Clone for Var<'s, S> {
+    fn clone(&self) -> Self {
+        Self {
+            scope: self.scope,
+            shape: self.shape.clone(),
+            level: self.level,
+        }
+    }
 }
 
 impl<'s, S: Storage> std::ops::Deref for Var<'s, S> {
@@ -23,4 +54,4 @@ impl<'s, S: Storage> std::ops::Deref for Var<'s, S> {
     fn deref(&self) -> &Self::Target {
         &self.scope
     }
-}
\ No newline at end of file
+}
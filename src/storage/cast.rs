@@ -0,0 +1,31 @@
+
+use super::float::Float;
+
+/// How `cast` handles values that do not fit in the target Float.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum CastMode {
+    /// Round to the nearest even value. Values that overflow become +/- inf.
+    #[default]
+    Nearest,
+    /// Round to the nearest even value, clamping anything outside
+    /// the range of the target (including inf) to +/- MAX. NaN stays NaN.
+    Saturate,
+}
+
+/// Convert every element of `src` into `dst`.
+pub fn cast_slice<T: Float, U: Float>(src: &[T], dst: &mut [U], mode: CastMode) {
+    if src.len() != dst.len() {
+        panic!("Length of src and dst must match to cast!")
+    }
+
+    let max: f64 = U::MAX.as_();
+
+    for (y, x) in dst.iter_mut().zip(src.iter()) {
+        let v: f64 = x.as_();
+
+        *y = match mode {
+            CastMode::Nearest => U::from_f64_nearest(v),
+            CastMode::Saturate => U::from_f64_nearest(v.clamp(-max, max)),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! # Memory Counters
+//!
+//! Totals of the bytes allocated for storage by the current thread, and of the
+//! bytes it copied into or out of it. They only ever grow, so the profiler measures
+//! a call by the difference before and after it, without counting the work of
+//! Scopes running on other threads.
+
+use std::cell::Cell;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static COPIED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The total bytes allocated for storage on this thread, whether fresh or from a cache.
+pub fn allocated() -> usize {
+    ALLOCATED.with(|allocated| allocated.get())
+}
+
+/// The total bytes copied between the host and storage on this thread.
+pub fn copied() -> usize {
+    COPIED.with(|copied| copied.get())
+}
+
+pub(crate) fn count_alloc(bytes: usize) {
+    ALLOCATED.with(|allocated| allocated.set(allocated.get() + bytes));
+}
+
+pub(crate) fn count_copy(bytes: usize) {
+    COPIED.with(|copied| copied.set(copied.get() + bytes));
+}
@@ -1,31 +1,33 @@
 
-use std::alloc::Layout;
-use std::alloc;
 use std::any::TypeId;
 
 use half::{f16, bf16};
 use ndarray::Array4;
 
 use super::float::Float;
+use super::element::Element;
+use super::cast::{self, CastMode};
 use super::shape::Shape;
 use super::traits::Storage;
 use super::traits::StorageInfo;
+use super::pool;
+use super::parallel;
+use super::counters;
 
-pub struct Cpu<T: Float> {
+pub struct Cpu<T: Element> {
     data: *mut T,
     shape: Shape,
 }
 
-impl<T: Float> Cpu<T> {
+impl<T: Element> Cpu<T> {
+    /// Zeroed, 64-byte aligned memory from the host pool.
     pub fn new(shape: Shape) -> Self {
-        let layout = Layout::array::<T>(shape.len()).unwrap();
-
-        let ptr = unsafe {
-            alloc::alloc_zeroed(layout).cast::<T>()
-        };
+        let bytes = shape.len() * std::mem::size_of::<T>();
+        let ptr = pool::alloc_zeroed(bytes);
+        counters::count_alloc(bytes);
 
         Self {
-            data: ptr,
+            data: ptr.cast::<T>(),
             shape,
         }
     }
@@ -45,7 +47,34 @@ impl<T: Float> Cpu<T> {
     }
 }
 
-impl<T: Float> Storage for Cpu<T> {
+impl<T: Float> Cpu<T> {
+    /// Convert to another Float type, rounding to the nearest even value.
+    pub fn cast<U: Float>(&self) -> Cpu<U> {
+        self.cast_with(CastMode::Nearest)
+    }
+
+    /// Convert to another Float type with the provided CastMode.
+    pub fn cast_with<U: Float>(&self, mode: CastMode) -> Cpu<U> {
+        let mut out = Cpu::new(self.shape.clone());
+        self.cast_into(&mut out, mode);
+        out
+    }
+
+    /// Convert into an existing storage of the same length.
+    pub fn cast_into<U: Float>(&self, dst: &mut Cpu<U>, mode: CastMode) {
+        let src = self.as_slice();
+
+        if src.len() != dst.len() {
+            panic!("Length of src and dst must match to cast!")
+        }
+
+        parallel::for_each_chunk(dst.as_slice_mut(), |start, dst| {
+            cast::cast_slice(&src[start..start + dst.len()], dst, mode)
+        });
+    }
+}
+
+impl<T: Element> Storage for Cpu<T> {
     type F = T;
 
     fn shape(&self) -> &Shape {
@@ -53,10 +82,7 @@ impl<T: Float> Storage for Cpu<T> {
     }
 
     fn fill(&mut self, v: T) {
-        unsafe {
-            std::slice::from_raw_parts_mut(self.data, self.shape.len())
-                .iter_mut().for_each(|x| *x = v)
-        };
+        parallel::for_each_chunk(self.as_slice_mut(), |_, x| x.fill(v));
     }
 
     fn clone_from(&mut self, data: &[T]) {
@@ -69,13 +95,13 @@ impl<T: Float> Storage for Cpu<T> {
         unsafe {
             std::ptr::copy_nonoverlapping(data.as_ptr(), self.data, len)
         }
+
+        counters::count_copy(len * std::mem::size_of::<T>());
     }
 
     fn as_ndarray(&self) -> Array4<T> {
-        let len = self.shape.len();
-        let vec = unsafe {
-            Vec::from_raw_parts(self.data, len, len)
-        };
+        let vec = self.as_slice().to_vec();
+        counters::count_copy(vec.len() * std::mem::size_of::<T>());
         Array4::from_shape_vec(self.shape.as_array4(), vec).unwrap()
     }
 
@@ -86,20 +112,28 @@ impl<T: Float> Storage for Cpu<T> {
     fn len(&self) -> usize {
         self.shape.len()
     }
+
+    fn set_shape(&mut self, shape: Shape) {
+        if shape.len() != self.shape.len() {
+            panic!("Cannot set a shape of another length!")
+        }
+
+        self.shape = shape;
+    }
 }
 
-impl<T: Float> StorageInfo for Cpu<T> {
+impl<T: Element> StorageInfo for Cpu<T> {
     const TYPE: &'static str = "cpu";
     const FLOAT: &'static str = T::NAME;
 }
 
-impl<T: Float> From<Shape> for Cpu<T> {
+impl<T: Element> From<Shape> for Cpu<T> {
     fn from(value: Shape) -> Self {
         Cpu::new(value)
     }
 }
 
-impl<T: Float> From<&Array4<T>> for Cpu<T> {
+impl<T: Element> From<&Array4<T>> for Cpu<T> {
     fn from(value: &Array4<T>) -> Self {
         let shape: Shape = value.shape().into();
 
@@ -110,11 +144,8 @@ impl<T: Float> From<&Array4<T>> for Cpu<T> {
     }
 }
 
-impl<T: Float> Drop for Cpu<T> {
+impl<T: Element> Drop for Cpu<T> {
     fn drop(&mut self) {
-        let len = self.shape.len();
-        unsafe {
-            std::alloc::dealloc(self.data as *mut u8, Layout::array::<T>(len).unwrap());
-        }
+        pool::free(self.data as *mut u8, self.shape.len() * std::mem::size_of::<T>());
     }
 }
\ No newline at end of file
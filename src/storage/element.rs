@@ -0,0 +1,82 @@
+
+use half::{bf16, f16};
+
+/// Any type that can be held by a Storage.
+pub trait Element
+    : Copy
+    + Clone
+    + PartialEq
+    + Send
+    + Sync
+    + 'static
+{
+    const NAME: &'static str;
+
+    /// The value of an element whose bytes are all zero.
+    const ZERO: Self;
+}
+
+/// Element types that can be used as indices, such as class labels or embedding ids.
+pub trait Integer: Element {
+    /// Convert to an index. Negative values wrap, and are
+    /// caught by the bounds checks of the operators using them.
+    fn as_index(self) -> usize;
+}
+
+impl Element for f64 {
+    const NAME: &'static str = "f64";
+    const ZERO: Self = 0.0;
+}
+
+impl Element for f32 {
+    const NAME: &'static str = "f32";
+    const ZERO: Self = 0.0;
+}
+
+impl Element for bf16 {
+    const NAME: &'static str = "bf16";
+    const ZERO: Self = bf16::ZERO;
+}
+
+impl Element for f16 {
+    const NAME: &'static str = "f16";
+    const ZERO: Self = f16::ZERO;
+}
+
+impl Element for i64 {
+    const NAME: &'static str = "i64";
+    const ZERO: Self = 0;
+}
+
+impl Element for i32 {
+    const NAME: &'static str = "i32";
+    const ZERO: Self = 0;
+}
+
+impl Element for u8 {
+    const NAME: &'static str = "u8";
+    const ZERO: Self = 0;
+}
+
+impl Element for bool {
+    const NAME: &'static str = "bool";
+    const ZERO: Self = false;
+}
+
+impl Integer for i64 {
+    fn as_index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Integer for i32 {
+    fn as_index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Integer for u8 {
+    fn as_index(self) -> usize {
+        self as usize
+    }
+}
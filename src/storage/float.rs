@@ -6,9 +6,11 @@ use num_traits::FromPrimitive;
 use num_traits::AsPrimitive;
 use half::{bf16, f16};
 
+use super::element::Element;
+use super::simd::Kernels;
+
 pub trait Float
-    : Copy 
-    + Clone 
+    : Element
     + Zero 
     + FromPrimitive 
     + AsPrimitive<f32>
@@ -17,22 +19,43 @@ pub trait Float
     + Mul<Output=Self>
     + Div<Output=Self>
     + Sub<Output=Self>
+    + Kernels
 {
-    const NAME: &'static str;
+    /// The largest finite value of this type.
+    const MAX: Self;
+
+    /// Convert from an f64, rounding to the nearest even value.
+    fn from_f64_nearest(v: f64) -> Self;
 }
         
 impl Float for f64 {
-    const NAME: &'static str = "f64";
+    const MAX: Self = f64::MAX;
+
+    fn from_f64_nearest(v: f64) -> Self {
+        v
+    }
 }
 
 impl Float for f32 {
-    const NAME: &'static str = "f32";
+    const MAX: Self = f32::MAX;
+
+    fn from_f64_nearest(v: f64) -> Self {
+        v as f32
+    }
 }
 
 impl Float for bf16 {
-    const NAME: &'static str = "bf16";
+    const MAX: Self = bf16::MAX;
+
+    fn from_f64_nearest(v: f64) -> Self {
+        bf16::from_f64(v)
+    }
 }
 
 impl Float for f16 {
-    const NAME: &'static str = "f16";
+    const MAX: Self = f16::MAX;
+
+    fn from_f64_nearest(v: f64) -> Self {
+        f16::from_f64(v)
+    }
 }
\ No newline at end of file
@@ -1,53 +1,202 @@
 
+use std::cell::RefCell;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use ndarray::Array4;
 
 use crate::gpu::cu;
+use crate::gpu::{Device, Stream, Ptr};
+use crate::gpu::allocator::Block;
 use super::shape::Shape;
 use super::traits::{Storage, StorageInfo};
 use super::float::Float;
+use super::element::Element;
+use super::cast::{self, CastMode};
+use super::counters;
 
-pub struct Gpu<T: Float> {
+thread_local! {
+    static PLACEMENT: RefCell<Option<(Arc<Device>, Stream)>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with the tensors created by `Gpu::new` allocated on `device` and `stream`
+/// on the current thread. The previous placement is restored even if `f` panics.
+pub fn with_placement<R>(device: &Arc<Device>, stream: &Stream, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<(Arc<Device>, Stream)>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            PLACEMENT.with(|placement| *placement.borrow_mut() = self.0.take());
+        }
+    }
+
+    let previous = PLACEMENT.with(|placement| placement.replace(Some((device.clone(), stream.clone()))));
+    let _restore = Restore(previous);
+    f()
+}
+
+pub struct Gpu<T: Element> {
     _type: PhantomData<T>,
+    device: Arc<Device>,
+    stream: Stream,
+    block: Block,
     data: cu::DevicePtr,
     shape: Shape,
 }
 
-impl<T: Float> Gpu<T> {
+impl<T: Element> Gpu<T> {
+    /// Allocate zeroed memory on the device and stream set by `with_placement` on 
+    /// this thread, or on the default device and the null stream.
     pub fn new(shape: Shape) -> Self {
-        let _ = crate::gpu::get_default_device();
+        match PLACEMENT.with(|placement| placement.borrow().clone()) {
+            Some((device, stream)) => Self::new_on(shape, &device, &stream),
+            None => Self::new_on(shape, &crate::gpu::get_default_device(), &Stream::null()),
+        }
+    }
+
+    /// Allocate zeroed memory from the allocator of `device`, for use on `stream`.
+    pub fn new_on(shape: Shape, device: &Arc<Device>, stream: &Stream) -> Self {
+        device.bind_to_thread()
+            .expect("Failed to bind the context of the device!");
+
         let len = shape.len();
-        let ptr = cu::mem::alloc::<T>(len).unwrap();
-        let zeroes = vec![T::zero(); len];
-        cu::mem::cpy_h_to_d(&ptr, zeroes.as_ptr(), len)
-            .expect("Failed to copy data to the gpu!");
+        let block = device.allocator().alloc(len * std::mem::size_of::<T>(), stream)
+            .expect("Failed to allocate memory on the gpu!");
+
+        counters::count_alloc(len * std::mem::size_of::<T>());
 
-        Self {
+        let out = Self {
             _type: PhantomData,
-            data: ptr,
+            device: device.clone(),
+            stream: stream.clone(),
+            block,
+            data: block.ptr,
             shape,
-        }
+        };
+
+        cu::mem::set_d8_async(&out.data, 0, len * std::mem::size_of::<T>(), &out.stream.stream)
+            .expect("Failed to zero memory on the gpu!");
+
+        out
+    }
+
+    /// Bind the context of the device to the calling thread, before any driver call.
+    fn bind(&self) {
+        self.device.bind_to_thread()
+            .expect("Failed to bind the context of the device!");
+    }
+
+    /// Copy `data` to the start of this memory. The copy is ordered after the work
+    /// on the stream of this memory, which is synchronized before `data` is released.
+    fn upload(&self, data: &[T]) {
+        self.bind();
+
+        let mut dst = self.data;
+        cu::mem::cpy_h_to_d_async(&mut dst, data.as_ptr(), data.len(), &self.stream.stream)
+            .and_then(|_| self.stream.synchronize())
+            .expect("Failed to copy data to the gpu!");
+
+        counters::count_copy(std::mem::size_of_val(data));
+    }
+
+    /// Copy the start of this memory into `data`, after the work on the stream of this memory.
+    fn download(&self, data: &mut [T]) {
+        self.bind();
+
+        cu::mem::cpy_d_to_h_async(data.as_mut_ptr(), &self.data, data.len(), &self.stream.stream)
+            .and_then(|_| self.stream.synchronize())
+            .expect("Failed to copy from device to host!");
+
+        counters::count_copy(std::mem::size_of_val(data));
     }
 
     pub fn as_ptr(&self) -> cu::DevicePtr {
         self.data
     }
+
+    /// A typed pointer to pass to kernels.
+    pub fn as_arg(&self) -> Ptr<T> {
+        unsafe { Ptr::new(self.data) }
+    }
+
+    /// The device this memory was allocated on.
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// The stream this memory is used on.
+    pub fn stream(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// Record that this memory is used by work on `stream`, so once it is dropped, 
+    /// it is not reused before that work completes.
+    pub fn record_stream(&self, stream: &Stream) {
+        self.device.allocator().record_stream(&self.block, stream);
+    }
+}
+
+/// There is no kernel converting between Float types, so a cast is a host round-trip:
+/// the storage is downloaded, converted with `cast_slice` and uploaded.
+impl<T: Float> Gpu<T> {
+    /// Convert to another Float type, rounding to the nearest even value.
+    pub fn cast<U: Float>(&self) -> Gpu<U> {
+        self.cast_with(CastMode::Nearest)
+    }
+
+    /// Convert to another Float type with the provided CastMode.
+    pub fn cast_with<U: Float>(&self, mode: CastMode) -> Gpu<U> {
+        let mut out = Gpu::new_on(self.shape.clone(), &self.device, &self.stream);
+        self.cast_into(&mut out, mode);
+        out
+    }
+
+    /// Convert into an existing storage of the same length.
+    /// 
+    /// The conversion is done on the host, so this costs a 
+    /// copy in each direction.
+    pub fn cast_into<U: Float>(&self, dst: &mut Gpu<U>, mode: CastMode) {
+        let len = self.shape.len();
+        let mut src = vec![T::ZERO; len];
+        let mut out = vec![U::ZERO; len];
+
+        self.download(&mut src);
+        cast::cast_slice(&src, &mut out, mode);
+        dst.clone_from(&out);
+    }
 }
 
-impl<T: Float> Storage for Gpu<T> {
+impl<T: Element> Storage for Gpu<T> {
     type F = T;
 
     fn shape(&self) -> &Shape {
         &self.shape
     }
 
+    /// Fills with a memset when the element is 1, 2 or 4 bytes or repeats 
+    /// a single byte, and with a host copy otherwise.
     fn fill(&mut self, v: T) {
+        self.bind();
+
         let len = self.shape.len();
-        let vec = vec![v; len];
+        let size = std::mem::size_of::<T>();
+        let stream = &self.stream.stream;
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&v as *const T as *const u8, size)
+        };
 
-        cu::mem::cpy_h_to_d(&self.data, vec.as_ptr(), len)
-            .expect("Failed to copy data to the gpu!")
+        let result = match bytes {
+            [b] => cu::mem::set_d8_async(&self.data, *b, len, stream),
+            [a, b] => cu::mem::set_d16_async(&self.data, u16::from_ne_bytes([*a, *b]), len, stream),
+            [a, b, c, d] => cu::mem::set_d32_async(&self.data, u32::from_ne_bytes([*a, *b, *c, *d]), len, stream),
+            [b, rest @ ..] if rest.iter().all(|r| r == b) => cu::mem::set_d8_async(&self.data, *b, len * size, stream),
+            _ => {
+                self.upload(&vec![v; len]);
+                Ok(())
+            }
+        };
+
+        result.expect("Failed to fill memory on the gpu!")
     }
 
     fn clone_from(&mut self, data: &[T]) {
@@ -57,16 +206,12 @@ impl<T: Float> Storage for Gpu<T> {
             panic!("Length of data is not the same as the inner data!")
         }
 
-        cu::mem::cpy_h_to_d(&self.data, data.as_ptr(), len)
-            .expect("Failed to copy data to the gpu!");
+        self.upload(&data[..len]);
     }
 
     fn as_ndarray(&self) -> Array4<T> {
-        let len = self.shape.len();
-        let mut vec = vec![T::zero(); len];
-
-        cu::mem::cpy_d_to_h(vec.as_mut_ptr(), &self.data, len)
-            .expect("Failed to copy from device to host!");
+        let mut vec = vec![T::ZERO; self.shape.len()];
+        self.download(&mut vec);
 
         Array4::from_shape_vec(self.shape.as_array4(), vec)
             .expect("Failed to create Array4 from Tensor!")
@@ -79,20 +224,28 @@ impl<T: Float> Storage for Gpu<T> {
     fn len(&self) -> usize {
         self.shape.len()
     }
+
+    fn set_shape(&mut self, shape: Shape) {
+        if shape.len() != self.shape.len() {
+            panic!("Cannot set a shape of another length!")
+        }
+
+        self.shape = shape;
+    }
 }
 
-impl<T: Float> StorageInfo for Gpu<T> {
+impl<T: Element> StorageInfo for Gpu<T> {
     const TYPE: &'static str = "gpu";
     const FLOAT: &'static str = T::NAME;
 }
 
-impl<T: Float> From<Shape> for Gpu<T> {
+impl<T: Element> From<Shape> for Gpu<T> {
     fn from(value: Shape) -> Self {
         Self::new(value)
     }
 }
 
-impl<T: Float> From<&Array4<T>> for Gpu<T> {
+impl<T: Element> From<&Array4<T>> for Gpu<T> {
     fn from(value: &Array4<T>) -> Self {
         let shape: Shape = value.shape().into();
     
@@ -103,9 +256,9 @@ impl<T: Float> From<&Array4<T>> for Gpu<T> {
     }
 }
 
-impl<T: Float> Drop for Gpu<T> {
+impl<T: Element> Drop for Gpu<T> {
     fn drop(&mut self) {
-        cu::mem::free(self.data)
-            .expect("Failed to free memory!");
+        let _ = self.device.bind_to_thread();
+        self.device.allocator().free(self.block);
     }
 }
\ No newline at end of file
@@ -5,11 +5,28 @@ mod traits;
 mod cpu;
 mod gpu;
 mod float;
+mod element;
+mod cast;
+mod view;
+mod simd;
+mod pool;
+mod pinned;
+mod prefetch;
+pub mod counters;
+pub mod parallel;
 
 pub use float::Float;
+pub use element::{Element, Integer};
+pub use cast::CastMode;
+pub use view::{View, Layout, Strided, Accumulate};
 pub use tensor::Tensor;
-pub use gpu::Gpu;
+pub use gpu::{Gpu, with_placement};
+pub use pinned::Pinned;
+pub use prefetch::Prefetcher;
 pub use traits::Storage;
 pub use shape::Shape;
 pub use cpu::Cpu;
+pub use simd::{Kernels, Isa, isa};
+pub use parallel::{set_num_threads, num_threads, with_num_threads};
+pub use pool::{empty_cache as empty_host_cache, cached as host_cached};
 pub use traits::StorageInfo;
\ No newline at end of file
@@ -0,0 +1,239 @@
+//! # Intra-op Parallelism
+//!
+//! Large operators on `Cpu` storage split their work into chunks of a fixed
+//! number of elements, which are spread over a number of threads. The chunks
+//! do not depend on the number of threads, and partial results of reductions
+//! are combined in chunk order, so results are bit-identical however many
+//! threads are used.
+//!
+//! The number of threads is set globally with `set_num_threads`, and can be
+//! overridden on the current thread with `with_num_threads`, as done by a Scope.
+//! Either way, 0 means every available core. The work runs on a pool of worker 
+//! threads that are spawned the first time they are needed, and kept alive.
+
+use std::cell::Cell;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+
+/// Elements per chunk. Operators smaller than this run on the calling thread.
+pub const CHUNK: usize = 1 << 15;
+
+/// The global number of threads, or 0 for every available core.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+static POOL: OnceLock<Pool> = OnceLock::new();
+
+thread_local! {
+    static LOCAL: Cell<Option<usize>> = const { Cell::new(None) };
+    /// Whether this thread is a worker of the pool, which runs nested work itself.
+    static WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set the number of threads used by operators, or 0 to use every available core.
+pub fn set_num_threads(threads: usize) {
+    THREADS.store(threads, Ordering::Relaxed);
+}
+
+/// The number of threads used by operators on the current thread.
+pub fn num_threads() -> usize {
+    let threads = LOCAL.with(|local| local.get())
+        .unwrap_or_else(|| THREADS.load(Ordering::Relaxed));
+
+    match threads {
+        0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        threads => threads,
+    }
+}
+
+/// Run `f` with the number of threads overridden on the current thread, if `threads` is set.
+/// The previous setting is restored even if `f` panics.
+pub fn with_num_threads<R>(threads: Option<usize>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<usize>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            LOCAL.with(|local| local.set(self.0));
+        }
+    }
+
+    let _restore = Restore(LOCAL.with(|local| local.replace(threads.or(local.get()))));
+    f()
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Worker threads that take jobs from a shared queue.
+struct Pool {
+    sender: Mutex<Sender<Job>>,
+    receiver: Arc<Mutex<Receiver<Job>>>,
+    workers: Mutex<usize>,
+}
+
+impl Pool {
+    fn get() -> &'static Pool {
+        POOL.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel();
+
+            Pool {
+                sender: Mutex::new(sender),
+                receiver: Arc::new(Mutex::new(receiver)),
+                workers: Mutex::new(0),
+            }
+        })
+    }
+
+    /// Spawn workers until there are at least `workers`.
+    fn grow(&self, workers: usize) {
+        let mut spawned = self.workers.lock().unwrap();
+
+        while *spawned < workers {
+            let receiver = self.receiver.clone();
+            *spawned += 1;
+
+            std::thread::Builder::new()
+                .name("gtensor-worker".to_owned())
+                .spawn(move || {
+                    WORKER.with(|worker| worker.set(true));
+
+                    loop {
+                        let job = match receiver.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => return,
+                        };
+
+                        job();
+                    }
+                })
+                .expect("Failed to spawn a worker thread!");
+        }
+    }
+
+    fn execute(&self, job: Job) {
+        self.sender.lock().unwrap()
+            .send(job)
+            .expect("The worker threads are gone!");
+    }
+}
+
+/// Counts the jobs that are still running, and whether any of them panicked.
+struct Latch {
+    state: Mutex<(usize, bool)>,
+    done: Condvar,
+}
+
+impl Latch {
+    fn finish(&self, panicked: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.0 -= 1;
+        state.1 |= panicked;
+        self.done.notify_all();
+    }
+
+    /// Wait until every job is finished, returning whether any of them panicked.
+    fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        while state.0 > 0 {
+            state = self.done.wait(state).unwrap();
+        }
+
+        state.1
+    }
+}
+
+/// Split `jobs` into contiguous groups, one per thread, and run `f` on every job.
+/// The calling thread runs the first group, and the workers of the pool the others.
+fn run<J: Send>(jobs: Vec<J>, f: impl Fn(J) + Sync) {
+    let threads = num_threads().min(jobs.len());
+
+    // Workers run nested work themselves, as waiting on the pool from it could deadlock.
+    if threads <= 1 || WORKER.with(|worker| worker.get()) {
+        return jobs.into_iter().for_each(f)
+    }
+
+    let per_thread = jobs.len().div_ceil(threads);
+    let mut groups = Vec::with_capacity(threads);
+    let mut jobs = jobs.into_iter().peekable();
+
+    while jobs.peek().is_some() {
+        groups.push(jobs.by_ref().take(per_thread).collect::<Vec<_>>());
+    }
+
+    let f = &f;
+    let mut groups = groups.into_iter();
+    let first = groups.next().unwrap();
+
+    let pool = Pool::get();
+    let latch = Latch { state: Mutex::new((groups.len(), false)), done: Condvar::new() };
+    let latch = &latch;
+
+    pool.grow(groups.len());
+
+    for group in groups {
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| group.into_iter().for_each(f)));
+            latch.finish(result.is_err());
+        });
+
+        // SAFETY: the job only borrows from this call, which waits for it below, even on a panic.
+        pool.execute(unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + '_>, Job>(job) });
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| first.into_iter().for_each(f)));
+    let panicked = latch.wait();
+
+    if let Err(payload) = result {
+        panic::resume_unwind(payload)
+    }
+
+    if panicked {
+        panic!("A parallel job panicked!")
+    }
+}
+
+/// Call `f` with the offset and contents of every chunk of `y`, in parallel.
+pub fn for_each_chunk<T: Send>(y: &mut [T], f: impl Fn(usize, &mut [T]) + Sync) {
+    let jobs: Vec<_> = y.chunks_mut(CHUNK)
+        .enumerate()
+        .map(|(i, chunk)| (i * CHUNK, chunk))
+        .collect();
+
+    run(jobs, |(start, chunk)| f(start, chunk));
+}
+
+/// Call `map` with the range of every chunk of `0..len` in parallel,
+/// and combine the results in order with `reduce`.
+pub fn map_reduce<R: Send>(len: usize, map: impl Fn(Range<usize>) -> R + Sync, reduce: impl Fn(R, R) -> R) -> Option<R> {
+    let mut partials: Vec<Option<R>> = (0..len.div_ceil(CHUNK)).map(|_| None).collect();
+
+    let jobs: Vec<_> = partials.iter_mut().enumerate().collect();
+
+    run(jobs, |(i, partial)| {
+        *partial = Some(map(i * CHUNK..((i + 1) * CHUNK).min(len)));
+    });
+
+    partials.into_iter().map(|p| p.unwrap()).reduce(reduce)
+}
+
+/// `y = f(a, b)` chunk by chunk, in parallel.
+pub fn binary<T: Send + Sync>(a: &[T], b: &[T], y: &mut [T], f: impl Fn(&[T], &[T], &mut [T]) + Sync) {
+    assert!(a.len() == y.len() && b.len() == y.len(), "Inputs must have the same length!");
+
+    for_each_chunk(y, |start, y| {
+        let range = start..start + y.len();
+        f(&a[range.clone()], &b[range], y)
+    });
+}
+
+/// `f(y, x)` chunk by chunk, in parallel.
+pub fn assign<T: Send + Sync>(y: &mut [T], x: &[T], f: impl Fn(&mut [T], &[T]) + Sync) {
+    assert!(x.len() == y.len(), "Inputs must have the same length!");
+
+    for_each_chunk(y, |start, y| {
+        let len = y.len();
+        f(y, &x[start..start + len])
+    });
+}
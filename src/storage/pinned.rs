@@ -0,0 +1,165 @@
+//! # Pinned Storage
+//!
+//! Host memory that is page-locked, so the device copies to and from it with
+//! DMA, asynchronously on a stream. Pageable memory has to be staged by the
+//! driver, which blocks the host until the copy is done.
+//!
+//! Pinned memory is taken out of the memory the system can page, so it is best
+//! kept to staging buffers that are reused, like those of a `Prefetcher`.
+
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use ndarray::Array4;
+
+use crate::gpu::cu;
+use crate::gpu::{Device, Stream};
+use super::shape::Shape;
+use super::traits::Storage;
+use super::element::Element;
+use super::gpu::Gpu;
+use super::counters;
+
+/// `CU_MEMHOSTALLOC_PORTABLE`, so the memory is page-locked for every context.
+const PORTABLE: u32 = 1;
+
+pub struct Pinned<T: Element> {
+    data: *mut T,
+    shape: Shape,
+    /// Keeps the context alive until the memory is freed.
+    device: Arc<Device>,
+}
+
+unsafe impl<T: Element> Send for Pinned<T> {}
+unsafe impl<T: Element> Sync for Pinned<T> {}
+
+impl<T: Element> Pinned<T> {
+    /// Allocate zeroed, page-locked memory on the default device.
+    pub fn new(shape: Shape) -> Self {
+        Self::new_on(shape, &crate::gpu::get_default_device())
+    }
+
+    /// Allocate zeroed, page-locked memory for use with `device`.
+    pub fn new_on(shape: Shape, device: &Arc<Device>) -> Self {
+        device.bind_to_thread()
+            .expect("Failed to bind the context of the device!");
+
+        let len = shape.len().max(1);
+        let data = cu::mem::host_alloc::<T>(len, PORTABLE)
+            .expect("Failed to allocate pinned memory!");
+
+        unsafe {
+            std::ptr::write_bytes(data, 0, len);
+        }
+
+        counters::count_alloc(shape.len() * std::mem::size_of::<T>());
+
+        Self {
+            data,
+            shape,
+            device: device.clone(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe {
+            std::slice::from_raw_parts(self.data, self.shape.len())
+        }
+    }
+
+    pub fn as_slice_mut(&mut self) -> &mut [T] {
+        unsafe {
+            std::slice::from_raw_parts_mut(self.data, self.shape.len())
+        }
+    }
+
+    /// Enqueue a copy into `dst` on `stream`. This memory must not be written until the copy is done.
+    pub fn copy_to(&self, dst: &mut Gpu<T>, stream: &Stream) -> Result<()> {
+        self.check_len(dst.len())?;
+        self.device.bind_to_thread()?;
+
+        let mut ptr = dst.as_ptr();
+        cu::mem::cpy_h_to_d_async(&mut ptr, self.data, self.shape.len(), &stream.stream)?;
+
+        counters::count_copy(self.shape.len() * std::mem::size_of::<T>());
+        Ok(())
+    }
+
+    /// Enqueue a copy from `src` on `stream`. This memory must not be read until the copy is done.
+    pub fn copy_from(&mut self, src: &Gpu<T>, stream: &Stream) -> Result<()> {
+        self.check_len(src.len())?;
+        self.device.bind_to_thread()?;
+
+        cu::mem::cpy_d_to_h_async(self.data, &src.as_ptr(), self.shape.len(), &stream.stream)?;
+
+        counters::count_copy(self.shape.len() * std::mem::size_of::<T>());
+        Ok(())
+    }
+
+    fn check_len(&self, len: usize) -> Result<()> {
+        if len != self.shape.len() {
+            return Err(anyhow!("Cannot copy {} elements between pinned and device memory of {}!", self.shape.len(), len))
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Element> Storage for Pinned<T> {
+    type F = T;
+
+    fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    fn fill(&mut self, v: T) {
+        self.as_slice_mut().fill(v);
+    }
+
+    fn clone_from(&mut self, data: &[T]) {
+        let len = self.shape.len();
+
+        if len != data.len() {
+            panic!("Length of data is not the same as the inner data!")
+        }
+
+        self.as_slice_mut().copy_from_slice(data);
+        counters::count_copy(len * std::mem::size_of::<T>());
+    }
+
+    fn as_ndarray(&self) -> Array4<T> {
+        let vec = self.as_slice().to_vec();
+        counters::count_copy(vec.len() * std::mem::size_of::<T>());
+        Array4::from_shape_vec(self.shape.as_array4(), vec).unwrap()
+    }
+
+    fn clone_into(&mut self, array: Array4<Self::F>) {
+        let data: Vec<T> = array.iter().copied().collect();
+        self.clone_from(&data);
+    }
+
+    fn len(&self) -> usize {
+        self.shape.len()
+    }
+
+    fn set_shape(&mut self, shape: Shape) {
+        if shape.len() != self.shape.len() {
+            panic!("Cannot set a shape of another length!")
+        }
+
+        self.shape = shape;
+    }
+}
+
+impl<T: Element> From<Shape> for Pinned<T> {
+    fn from(value: Shape) -> Self {
+        Pinned::new(value)
+    }
+}
+
+impl<T: Element> Drop for Pinned<T> {
+    fn drop(&mut self) {
+        let _ = self.device.bind_to_thread()
+            .and_then(|_| cu::mem::free_host(self.data));
+    }
+}
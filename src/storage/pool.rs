@@ -0,0 +1,100 @@
+//! # Host Memory Pool
+//!
+//! The memory of `Cpu` storage is aligned to 64 bytes, so every row of a
+//! cache line or vector register starts on a boundary. Freed allocations are
+//! kept in a global pool keyed by size and handed out again, zeroed, to the
+//! next allocation of the same size.
+
+use std::alloc::{self, Layout};
+use std::sync::Mutex;
+
+use hashbrown::HashMap;
+use once_cell::sync::Lazy;
+
+/// Alignment of every allocation, in bytes.
+pub const ALIGN: usize = 64;
+
+/// Freed memory beyond this many bytes is given back to the system.
+const MAX_CACHED: usize = 1 << 30;
+
+#[derive(Default)]
+struct Pool {
+    free: HashMap<usize, Vec<usize>>,
+    cached: usize,
+}
+
+static POOL: Lazy<Mutex<Pool>> = Lazy::new(Default::default);
+
+fn layout(bytes: usize) -> Layout {
+    Layout::from_size_align(bytes.max(1).next_multiple_of(ALIGN), ALIGN)
+        .expect("Failed to create a memory layout!")
+}
+
+/// Allocate `bytes` of zeroed memory, reusing a pooled allocation of the same size if there is one.
+pub(crate) fn alloc_zeroed(bytes: usize) -> *mut u8 {
+    let layout = layout(bytes);
+    let reused = {
+        let mut pool = POOL.lock().unwrap();
+        let ptr = pool.free.get_mut(&layout.size()).and_then(|ptrs| ptrs.pop());
+
+        if ptr.is_some() {
+            pool.cached -= layout.size();
+        }
+
+        ptr
+    };
+
+    match reused {
+        Some(ptr) => unsafe {
+            let ptr = ptr as *mut u8;
+            std::ptr::write_bytes(ptr, 0, layout.size());
+            ptr
+        },
+        None => {
+            let mut ptr = unsafe { alloc::alloc_zeroed(layout) };
+
+            if ptr.is_null() {
+                empty_cache();
+                ptr = unsafe { alloc::alloc_zeroed(layout) };
+            }
+
+            if ptr.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+
+            ptr
+        }
+    }
+}
+
+/// Return memory from `alloc_zeroed` of `bytes` to the pool.
+pub(crate) fn free(ptr: *mut u8, bytes: usize) {
+    let layout = layout(bytes);
+    let mut pool = POOL.lock().unwrap();
+
+    if pool.cached + layout.size() > MAX_CACHED {
+        unsafe { alloc::dealloc(ptr, layout) };
+        return
+    }
+
+    pool.cached += layout.size();
+    pool.free.entry(layout.size()).or_default().push(ptr as usize);
+}
+
+/// Bytes held by the pool that are not in use.
+pub fn cached() -> usize {
+    POOL.lock().unwrap().cached
+}
+
+/// Give every pooled allocation back to the system.
+pub fn empty_cache() {
+    let mut pool = POOL.lock().unwrap();
+
+    for (size, ptrs) in pool.free.drain() {
+        for ptr in ptrs {
+            unsafe { alloc::dealloc(ptr as *mut u8, Layout::from_size_align_unchecked(size, ALIGN)) };
+        }
+    }
+
+    pool.cached = 0;
+}
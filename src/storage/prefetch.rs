@@ -0,0 +1,126 @@
+//! # Prefetching
+//!
+//! Copies batches to the device on a dedicated copy stream, so the copy of the
+//! next batch overlaps the compute on the current one. Each batch is staged in
+//! pinned memory, and two buffers on the device are used in turn:
+//!
+//! ```ignore
+//! prefetcher.prefetch(&batches[0])?;
+//!
+//! for i in 0..batches.len() {
+//!     let x = prefetcher.next()?;
+//!     // Enqueue the step on `prefetcher.stream()`.
+//!
+//!     if i + 1 < batches.len() {
+//!         prefetcher.prefetch(&batches[i + 1])?;
+//!     }
+//! }
+//! ```
+//!
+//! The compute stream waits on the copy with an event, and the copy into a
+//! buffer waits on the compute that last read it, so the host never blocks
+//! on either stream besides to reuse a staging buffer.
+
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+
+use crate::gpu::{Device, Stream, Event};
+use super::shape::Shape;
+use super::traits::Storage;
+use super::element::Element;
+use super::gpu::Gpu;
+use super::pinned::Pinned;
+
+pub struct Prefetcher<T: Element> {
+    copy: Stream,
+    compute: Stream,
+    staging: [Pinned<T>; 2],
+    buffers: [Gpu<T>; 2],
+    /// Recorded on the copy stream once a buffer is filled.
+    ready: [Event; 2],
+    /// Recorded on the compute stream once a buffer is no longer read.
+    consumed: [Event; 2],
+    /// The slot the next batch is copied into.
+    head: usize,
+    pending: Option<usize>,
+    current: Option<usize>,
+}
+
+impl<T: Element> Prefetcher<T> {
+    /// Prefetch batches of `shape` to `device`, for use on the `compute` stream.
+    pub fn new(shape: Shape, device: &Arc<Device>, compute: &Stream) -> Result<Self> {
+        let copy = device.fork()?;
+
+        let buffers = [
+            Gpu::new_on(shape.clone(), device, compute),
+            Gpu::new_on(shape.clone(), device, compute),
+        ];
+
+        // The buffers are zeroed on the compute stream, before any copy into them.
+        compute.synchronize()?;
+
+        Ok(Self {
+            copy,
+            compute: compute.clone(),
+            staging: [
+                Pinned::new_on(shape.clone(), device),
+                Pinned::new_on(shape, device),
+            ],
+            buffers,
+            ready: [Event::new(device, false)?, Event::new(device, false)?],
+            consumed: [Event::new(device, false)?, Event::new(device, false)?],
+            head: 0,
+            pending: None,
+            current: None,
+        })
+    }
+
+    /// The stream the batches are used on.
+    pub fn stream(&self) -> &Stream {
+        &self.compute
+    }
+
+    /// Start copying `batch` to the device. The previous batch must have been taken with `next`.
+    pub fn prefetch(&mut self, batch: &[T]) -> Result<()> {
+        if self.pending.is_some() {
+            return Err(anyhow!("A batch is already prefetched!"))
+        }
+
+        let slot = self.head;
+        let staging = &mut self.staging[slot];
+
+        if batch.len() != staging.len() {
+            return Err(anyhow!("Cannot prefetch a batch of {} elements into buffers of {}!", batch.len(), staging.len()))
+        }
+
+        // The last copy out of the staging buffer must be done before it is written.
+        self.ready[slot].synchronize()?;
+        staging.clone_from(batch);
+
+        self.copy.wait_event(&self.consumed[slot])?;
+        staging.copy_to(&mut self.buffers[slot], &self.copy)?;
+        self.ready[slot].record(&self.copy)?;
+
+        self.pending = Some(slot);
+        self.head = 1 - slot;
+
+        Ok(())
+    }
+
+    /// Take the prefetched batch. Work enqueued on the compute stream from now on
+    /// waits for its copy, and the batch taken before is released for reuse.
+    pub fn next(&mut self) -> Result<&Gpu<T>> {
+        let slot = self.pending.take()
+            .ok_or(anyhow!("No batch was prefetched!"))?;
+
+        if let Some(current) = self.current {
+            self.consumed[current].record(&self.compute)?;
+        }
+
+        self.compute.wait_event(&self.ready[slot])?;
+        self.current = Some(slot);
+
+        Ok(&self.buffers[slot])
+    }
+}
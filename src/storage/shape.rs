@@ -11,6 +11,15 @@ impl Shape {
         self.0.iter().product()
     }
 
+    /// The number of dimensions the shape was created with.
+    pub fn dims(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn to_vec(&self) -> Vec<usize> {
+        self.0.as_vec()
+    }
+
     pub fn as_array4(&self) -> [usize; 4] {
         let mut out = [1usize; 4];
 
@@ -0,0 +1,425 @@
+//! # Vectorized Kernels
+//!
+//! Elementwise, reduction and activation kernels over slices, used by the
+//! operators of `Cpu` storage. `f32` and `f64` are vectorized with AVX-512 or
+//! AVX2 on x86_64 and NEON on aarch64, picked once at runtime. `f16` and
+//! `bf16` are converted to `f32` in small chunks and use the `f32` kernels.
+//!
+//! Elementwise kernels round exactly like their scalar loops. Reductions
+//! are summed in a different order, so they can differ in the last bits.
+
+use std::ops::Range;
+
+use once_cell::sync::Lazy;
+use half::{f16, bf16};
+use half::slice::HalfFloatSliceExt;
+
+/// The instruction set used by the kernels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Isa {
+    Scalar,
+    Avx2,
+    Avx512,
+    Neon,
+}
+
+static ISA: Lazy<Isa> = Lazy::new(detect);
+
+fn detect() -> Isa {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f") {
+            return Isa::Avx512
+        }
+
+        if is_x86_feature_detected!("avx2") {
+            return Isa::Avx2
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Isa::Neon
+        }
+    }
+
+    Isa::Scalar
+}
+
+/// The instruction set detected on this machine.
+pub fn isa() -> Isa {
+    *ISA
+}
+
+/// Slice kernels for a Float type.
+pub trait Kernels: Sized {
+    /// `y = a + b`
+    fn add(a: &[Self], b: &[Self], y: &mut [Self]);
+    /// `y = a * b`
+    fn mul(a: &[Self], b: &[Self], y: &mut [Self]);
+    /// `y += x`
+    fn add_assign(y: &mut [Self], x: &[Self]);
+    /// `y *= x`
+    fn mul_assign(y: &mut [Self], x: &[Self]);
+    /// `y += a * b`, rounding the product before the sum.
+    fn mul_add(a: &[Self], b: &[Self], y: &mut [Self]);
+    /// The sum of every element.
+    fn sum(x: &[Self]) -> Self;
+    /// The largest element, ignoring NaN. Negative infinity for an empty slice.
+    fn max(x: &[Self]) -> Self;
+    /// `y = max(x, 0)`, with NaN mapped to 0.
+    fn relu(x: &[Self], y: &mut [Self]);
+}
+
+mod scalar {
+    use std::ops::{Add, Mul};
+
+    pub fn add<T: Copy + Add<Output = T>>(a: &[T], b: &[T], y: &mut [T]) {
+        y.iter_mut().zip(a.iter().zip(b)).for_each(|(y, (a, b))| *y = *a + *b)
+    }
+
+    pub fn mul<T: Copy + Mul<Output = T>>(a: &[T], b: &[T], y: &mut [T]) {
+        y.iter_mut().zip(a.iter().zip(b)).for_each(|(y, (a, b))| *y = *a * *b)
+    }
+
+    pub fn add_assign<T: Copy + Add<Output = T>>(y: &mut [T], x: &[T]) {
+        y.iter_mut().zip(x).for_each(|(y, x)| *y = *y + *x)
+    }
+
+    pub fn mul_assign<T: Copy + Mul<Output = T>>(y: &mut [T], x: &[T]) {
+        y.iter_mut().zip(x).for_each(|(y, x)| *y = *y * *x)
+    }
+
+    pub fn mul_add<T: Copy + Add<Output = T> + Mul<Output = T>>(a: &[T], b: &[T], y: &mut [T]) {
+        y.iter_mut().zip(a.iter().zip(b)).for_each(|(y, (a, b))| *y = *y + *a * *b)
+    }
+
+    macro_rules! float {
+        ($t:ident) => {
+            pub mod $t {
+                pub fn sum(x: &[$t]) -> $t {
+                    x.iter().sum()
+                }
+
+                pub fn max(x: &[$t]) -> $t {
+                    x.iter().fold($t::NEG_INFINITY, |m, x| m.max(*x))
+                }
+
+                pub fn relu(x: &[$t], y: &mut [$t]) {
+                    y.iter_mut().zip(x).for_each(|(y, x)| *y = x.max(0.0))
+                }
+            }
+        };
+    }
+
+    float!(f32);
+    float!(f64);
+}
+
+/// Generates a module of kernels for one instruction set and type from its intrinsics.
+/// Every kernel runs over whole vectors and finishes the tail with scalar code.
+#[allow(unused_macros)]
+macro_rules! vectorized {
+    (
+        mod $name:ident: $t:ident, $feature:literal, $lanes:literal,
+        $vec:ty, $load:ident, $store:ident, $splat:ident, $add:ident, $mul:ident, $max:ident
+    ) => {
+        pub mod $name {
+            #[cfg(target_arch = "x86_64")]
+            use std::arch::x86_64::*;
+            #[cfg(target_arch = "aarch64")]
+            use std::arch::aarch64::*;
+
+            const LANES: usize = $lanes;
+
+            #[target_feature(enable = $feature)]
+            unsafe fn binary(a: &[$t], b: &[$t], y: &mut [$t], f: impl Fn($vec, $vec) -> $vec, g: impl Fn($t, $t) -> $t) {
+                assert!(a.len() == y.len() && b.len() == y.len(), "Kernel inputs must have the same length!");
+
+                let body = y.len() - y.len() % LANES;
+
+                for i in (0..body).step_by(LANES) {
+                    $store(y.as_mut_ptr().add(i), f($load(a.as_ptr().add(i)), $load(b.as_ptr().add(i))));
+                }
+
+                for i in body..y.len() {
+                    y[i] = g(a[i], b[i]);
+                }
+            }
+
+            #[target_feature(enable = $feature)]
+            unsafe fn assign(y: &mut [$t], x: &[$t], f: impl Fn($vec, $vec) -> $vec, g: impl Fn($t, $t) -> $t) {
+                assert!(x.len() == y.len(), "Kernel inputs must have the same length!");
+
+                let body = y.len() - y.len() % LANES;
+
+                for i in (0..body).step_by(LANES) {
+                    let p = y.as_mut_ptr().add(i);
+                    $store(p, f($load(p), $load(x.as_ptr().add(i))));
+                }
+
+                for i in body..y.len() {
+                    y[i] = g(y[i], x[i]);
+                }
+            }
+
+            #[target_feature(enable = $feature)]
+            unsafe fn reduce(x: &[$t], init: $t, f: impl Fn($vec, $vec) -> $vec, g: impl Fn($t, $t) -> $t) -> $t {
+                let body = x.len() - x.len() % LANES;
+                let mut acc = $splat(init);
+
+                for i in (0..body).step_by(LANES) {
+                    acc = f($load(x.as_ptr().add(i)), acc);
+                }
+
+                let mut lanes = [init; LANES];
+                $store(lanes.as_mut_ptr(), acc);
+
+                x[body..].iter().chain(lanes.iter()).fold(init, |acc, x| g(acc, *x))
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn add(a: &[$t], b: &[$t], y: &mut [$t]) {
+                binary(a, b, y, |a, b| $add(a, b), |a, b| a + b)
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn mul(a: &[$t], b: &[$t], y: &mut [$t]) {
+                binary(a, b, y, |a, b| $mul(a, b), |a, b| a * b)
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn add_assign(y: &mut [$t], x: &[$t]) {
+                assign(y, x, |y, x| $add(y, x), |y, x| y + x)
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn mul_assign(y: &mut [$t], x: &[$t]) {
+                assign(y, x, |y, x| $mul(y, x), |y, x| y * x)
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn mul_add(a: &[$t], b: &[$t], y: &mut [$t]) {
+                assert!(a.len() == y.len() && b.len() == y.len(), "Kernel inputs must have the same length!");
+
+                let body = y.len() - y.len() % LANES;
+
+                for i in (0..body).step_by(LANES) {
+                    let p = y.as_mut_ptr().add(i);
+                    $store(p, $add($load(p), $mul($load(a.as_ptr().add(i)), $load(b.as_ptr().add(i)))));
+                }
+
+                for i in body..y.len() {
+                    y[i] += a[i] * b[i];
+                }
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn sum(x: &[$t]) -> $t {
+                reduce(x, 0.0, |x, acc| $add(x, acc), |acc, x| acc + x)
+            }
+
+            /// The max intrinsics return their second operand when either is NaN,
+            /// so NaN elements are always passed first.
+            #[target_feature(enable = $feature)]
+            pub unsafe fn max(x: &[$t]) -> $t {
+                reduce(x, $t::NEG_INFINITY, |x, acc| $max(x, acc), $t::max)
+            }
+
+            #[target_feature(enable = $feature)]
+            pub unsafe fn relu(x: &[$t], y: &mut [$t]) {
+                assert!(x.len() == y.len(), "Kernel inputs must have the same length!");
+
+                let body = y.len() - y.len() % LANES;
+                let zero = $splat(0.0);
+
+                for i in (0..body).step_by(LANES) {
+                    $store(y.as_mut_ptr().add(i), $max($load(x.as_ptr().add(i)), zero));
+                }
+
+                for i in body..y.len() {
+                    y[i] = x[i].max(0.0);
+                }
+            }
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+vectorized!(mod avx2_f32: f32, "avx2", 8, __m256, _mm256_loadu_ps, _mm256_storeu_ps, _mm256_set1_ps, _mm256_add_ps, _mm256_mul_ps, _mm256_max_ps);
+#[cfg(target_arch = "x86_64")]
+vectorized!(mod avx2_f64: f64, "avx2", 4, __m256d, _mm256_loadu_pd, _mm256_storeu_pd, _mm256_set1_pd, _mm256_add_pd, _mm256_mul_pd, _mm256_max_pd);
+#[cfg(target_arch = "x86_64")]
+vectorized!(mod avx512_f32: f32, "avx512f", 16, __m512, _mm512_loadu_ps, _mm512_storeu_ps, _mm512_set1_ps, _mm512_add_ps, _mm512_mul_ps, _mm512_max_ps);
+#[cfg(target_arch = "x86_64")]
+vectorized!(mod avx512_f64: f64, "avx512f", 8, __m512d, _mm512_loadu_pd, _mm512_storeu_pd, _mm512_set1_pd, _mm512_add_pd, _mm512_mul_pd, _mm512_max_pd);
+// vmaxnm returns the number when one operand is NaN, like the scalar max.
+#[cfg(target_arch = "aarch64")]
+vectorized!(mod neon_f32: f32, "neon", 4, float32x4_t, vld1q_f32, vst1q_f32, vdupq_n_f32, vaddq_f32, vmulq_f32, vmaxnmq_f32);
+#[cfg(target_arch = "aarch64")]
+vectorized!(mod neon_f64: f64, "neon", 2, float64x2_t, vld1q_f64, vst1q_f64, vdupq_n_f64, vaddq_f64, vmulq_f64, vmaxnmq_f64);
+
+/// Calls the kernel `$f` of the detected instruction set, or `$scalar`.
+macro_rules! dispatch {
+    ($avx512:ident, $avx2:ident, $neon:ident, $f:ident, $scalar:expr, ($($arg:expr),*)) => {
+        match isa() {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx512 => unsafe { $avx512::$f($($arg),*) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { $avx2::$f($($arg),*) },
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { $neon::$f($($arg),*) },
+            _ => $scalar($($arg),*),
+        }
+    };
+}
+
+macro_rules! native {
+    ($t:ident, $avx512:ident, $avx2:ident, $neon:ident) => {
+        impl Kernels for $t {
+            fn add(a: &[Self], b: &[Self], y: &mut [Self]) {
+                dispatch!($avx512, $avx2, $neon, add, scalar::add, (a, b, y))
+            }
+
+            fn mul(a: &[Self], b: &[Self], y: &mut [Self]) {
+                dispatch!($avx512, $avx2, $neon, mul, scalar::mul, (a, b, y))
+            }
+
+            fn add_assign(y: &mut [Self], x: &[Self]) {
+                dispatch!($avx512, $avx2, $neon, add_assign, scalar::add_assign, (y, x))
+            }
+
+            fn mul_assign(y: &mut [Self], x: &[Self]) {
+                dispatch!($avx512, $avx2, $neon, mul_assign, scalar::mul_assign, (y, x))
+            }
+
+            fn mul_add(a: &[Self], b: &[Self], y: &mut [Self]) {
+                dispatch!($avx512, $avx2, $neon, mul_add, scalar::mul_add, (a, b, y))
+            }
+
+            fn sum(x: &[Self]) -> Self {
+                dispatch!($avx512, $avx2, $neon, sum, scalar::$t::sum, (x))
+            }
+
+            fn max(x: &[Self]) -> Self {
+                dispatch!($avx512, $avx2, $neon, max, scalar::$t::max, (x))
+            }
+
+            fn relu(x: &[Self], y: &mut [Self]) {
+                dispatch!($avx512, $avx2, $neon, relu, scalar::$t::relu, (x, y))
+            }
+        }
+    };
+}
+
+native!(f32, avx512_f32, avx2_f32, neon_f32);
+native!(f64, avx512_f64, avx2_f64, neon_f64);
+
+/// Elements converted to `f32` at a time by the half precision kernels.
+const CHUNK: usize = 256;
+
+/// Calls `f` with the range and `f32` conversion of every chunk of `x`.
+fn chunks<T>(x: &[T], mut f: impl FnMut(Range<usize>, &[f32]))
+where
+    [T]: HalfFloatSliceExt,
+{
+    let mut buf = [0.0f32; CHUNK];
+
+    for start in (0..x.len()).step_by(CHUNK) {
+        let range = start..(start + CHUNK).min(x.len());
+        let buf = &mut buf[..range.len()];
+        x[range.clone()].convert_to_f32_slice(buf);
+        f(range, buf);
+    }
+}
+
+/// Calls `f` with chunks of `a`, `b` and `y` converted to `f32`, and converts `y` back.
+fn binary<T>(a: &[T], b: &[T], y: &mut [T], f: impl Fn(&[f32], &[f32], &mut [f32]))
+where
+    [T]: HalfFloatSliceExt,
+{
+    assert!(a.len() == y.len() && b.len() == y.len(), "Kernel inputs must have the same length!");
+
+    let mut fb = [0.0f32; CHUNK];
+    let mut fy = [0.0f32; CHUNK];
+
+    chunks(a, |range, fa| {
+        let (fb, fy) = (&mut fb[..range.len()], &mut fy[..range.len()]);
+        b[range.clone()].convert_to_f32_slice(fb);
+        y[range.clone()].convert_to_f32_slice(fy);
+        f(fa, fb, fy);
+        y[range].convert_from_f32_slice(fy);
+    });
+}
+
+/// Calls `f` with chunks of `y` and `x` converted to `f32`, and converts `y` back.
+fn assign<T>(y: &mut [T], x: &[T], f: impl Fn(&mut [f32], &[f32]))
+where
+    [T]: HalfFloatSliceExt,
+{
+    assert!(x.len() == y.len(), "Kernel inputs must have the same length!");
+
+    let mut fy = [0.0f32; CHUNK];
+
+    chunks(x, |range, fx| {
+        let fy = &mut fy[..range.len()];
+        y[range.clone()].convert_to_f32_slice(fy);
+        f(fy, fx);
+        y[range].convert_from_f32_slice(fy);
+    });
+}
+
+macro_rules! half {
+    ($t:ident) => {
+        impl Kernels for $t {
+            fn add(a: &[Self], b: &[Self], y: &mut [Self]) {
+                binary(a, b, y, |a, b, y| <f32 as Kernels>::add(a, b, y))
+            }
+
+            fn mul(a: &[Self], b: &[Self], y: &mut [Self]) {
+                binary(a, b, y, |a, b, y| <f32 as Kernels>::mul(a, b, y))
+            }
+
+            fn add_assign(y: &mut [Self], x: &[Self]) {
+                assign(y, x, |y, x| <f32 as Kernels>::add_assign(y, x))
+            }
+
+            fn mul_assign(y: &mut [Self], x: &[Self]) {
+                assign(y, x, |y, x| <f32 as Kernels>::mul_assign(y, x))
+            }
+
+            /// The product is rounded to `f32`, not to this type, before the sum.
+            fn mul_add(a: &[Self], b: &[Self], y: &mut [Self]) {
+                binary(a, b, y, |a, b, y| <f32 as Kernels>::mul_add(a, b, y))
+            }
+
+            fn sum(x: &[Self]) -> Self {
+                let mut sum = 0.0;
+                chunks(x, |_, x| sum += <f32 as Kernels>::sum(x));
+                Self::from_f32(sum)
+            }
+
+            fn max(x: &[Self]) -> Self {
+                let mut max = f32::NEG_INFINITY;
+                chunks(x, |_, x| max = max.max(<f32 as Kernels>::max(x)));
+                Self::from_f32(max)
+            }
+
+            fn relu(x: &[Self], y: &mut [Self]) {
+                assert!(x.len() == y.len(), "Kernel inputs must have the same length!");
+
+                let mut fy = [0.0f32; CHUNK];
+
+                chunks(x, |range, fx| {
+                    let fy = &mut fy[..range.len()];
+                    <f32 as Kernels>::relu(fx, fy);
+                    y[range].convert_from_f32_slice(fy);
+                });
+            }
+        }
+    };
+}
+
+half!(f16);
+half!(bf16);
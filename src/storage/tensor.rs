@@ -6,6 +6,8 @@ use super::traits::Storage;
 use super::cpu::Cpu;
 use super::gpu::Gpu;
 use super::float::Float;
+use super::cast::CastMode;
+use super::view::View;
 
 pub struct Tensor<S: Storage>(S);
 
@@ -27,6 +29,37 @@ where
     }
 }
 
+impl<S: Storage> Tensor<S> {
+    /// A view of the whole tensor, to slice or permute without copying.
+    pub fn view(&self) -> View<'_, S> {
+        View::new(&self.0)
+    }
+}
+
+impl<T: Float> Tensor<Cpu<T>> {
+    /// Convert to a Tensor of another Float type.
+    pub fn cast<U: Float>(&self) -> Tensor<Cpu<U>> {
+        Tensor(self.0.cast())
+    }
+
+    /// Convert to a Tensor of another Float type with the provided CastMode.
+    pub fn cast_with<U: Float>(&self, mode: CastMode) -> Tensor<Cpu<U>> {
+        Tensor(self.0.cast_with(mode))
+    }
+}
+
+impl<T: Float> Tensor<Gpu<T>> {
+    /// Convert to a Tensor of another Float type.
+    pub fn cast<U: Float>(&self) -> Tensor<Gpu<U>> {
+        Tensor(self.0.cast())
+    }
+
+    /// Convert to a Tensor of another Float type with the provided CastMode.
+    pub fn cast_with<U: Float>(&self, mode: CastMode) -> Tensor<Gpu<U>> {
+        Tensor(self.0.cast_with(mode))
+    }
+}
+
 impl<S: Storage> std::ops::Deref for Tensor<S> {
     type Target = S;
 
@@ -2,10 +2,10 @@
 use ndarray::Array4;
 
 use super::shape::Shape;
-use super::float::Float;
+use super::element::Element;
 
 pub trait Storage {
-    type F: Float;
+    type F: Element;
 
     fn shape(&self) -> &Shape;
     fn fill(&mut self, v: Self::F);
@@ -13,6 +13,9 @@ pub trait Storage {
     fn as_ndarray(&self) -> Array4<Self::F>;
     fn clone_into(&mut self, array: Array4<Self::F>);
     fn len(&self) -> usize;
+
+    /// Give the elements another shape of the same length, without copying.
+    fn set_shape(&mut self, shape: Shape);
 }
 
 pub trait StorageInfo: Storage {
@@ -20,7 +23,7 @@ pub trait StorageInfo: Storage {
     /// Either `gpu` or `cpu`. 
     const TYPE: &'static str;
 
-    /// The Float type this storage contains.
-    /// Could be `f64`, `f16`, `f32`, or `bf16`. 
+    /// The Element type this storage contains.
+    /// Could be `f64`, `f16`, `f32`, `bf16`, `i64`, `i32`, `u8` or `bool`. 
     const FLOAT: &'static str;
 }
\ No newline at end of file
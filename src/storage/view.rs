@@ -0,0 +1,461 @@
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use itertools::iproduct;
+
+use crate::gpu::cu;
+use crate::gpu::{Device, Kernel, modules};
+use super::shape::Shape;
+use super::traits::Storage;
+use super::element::Element;
+use super::float::Float;
+use super::cpu::Cpu;
+use super::gpu::Gpu;
+use super::parallel;
+
+/// The placement of a strided window in a flat NCHW buffer.
+///
+/// Element (n, c, h, w) of the window is found at
+/// `offset + n * strides[0] + c * strides[1] + h * strides[2] + w * strides[3]`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Layout {
+    offset: usize,
+    shape: [usize; 4],
+    strides: [usize; 4],
+}
+
+impl Layout {
+    /// The layout of a whole contiguous buffer with `shape`.
+    pub fn new(shape: &Shape) -> Self {
+        let shape = shape.as_array4();
+
+        Self {
+            offset: 0,
+            shape,
+            strides: contiguous_strides(shape),
+        }
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape.into()
+    }
+
+    pub fn strides(&self) -> [usize; 4] {
+        self.strides
+    }
+
+    /// Offset of the first element of the window.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    /// Offset of the element at `index`.
+    pub fn index_of(&self, index: [usize; 4]) -> usize {
+        self.offset + (0..4).map(|i| index[i] * self.strides[i]).sum::<usize>()
+    }
+
+    /// One past the offset of the last element, or 0 without elements.
+    pub fn end(&self) -> usize {
+        match self.len() {
+            0 => 0,
+            _ => self.index_of(self.shape.map(|size| size - 1)) + 1,
+        }
+    }
+
+    /// Whether the elements are laid out in NCHW order without gaps.
+    pub fn is_contiguous(&self) -> bool {
+        let expected = contiguous_strides(self.shape);
+        (0..4).all(|i| self.shape[i] == 1 || self.strides[i] == expected[i])
+    }
+
+    /// Keep `len` elements of `axis` starting at `start`.
+    pub fn narrow(&self, axis: usize, start: usize, len: usize) -> Result<Self> {
+        check_axis(axis)?;
+
+        if start + len > self.shape[axis] {
+            return Err(anyhow!("Cannot narrow axis {} of size {} to {}..{}!", axis, self.shape[axis], start, start + len))
+        }
+
+        let mut out = *self;
+        out.offset += start * self.strides[axis];
+        out.shape[axis] = len;
+
+        Ok(out)
+    }
+
+    /// Keep the elements of `axis` within `range`.
+    pub fn slice(&self, axis: usize, range: Range<usize>) -> Result<Self> {
+        if range.end < range.start {
+            return Err(anyhow!("Cannot slice axis {} with {}..{}, which ends before it starts!", axis, range.start, range.end))
+        }
+
+        self.narrow(axis, range.start, range.end - range.start)
+    }
+
+    /// Keep only `index` of `axis`. The axis is kept with size 1.
+    pub fn select(&self, axis: usize, index: usize) -> Result<Self> {
+        self.narrow(axis, index, 1)
+    }
+
+    /// Swap two axes.
+    pub fn transpose(&self, a: usize, b: usize) -> Result<Self> {
+        check_axis(a)?;
+        check_axis(b)?;
+
+        let mut out = *self;
+        out.shape.swap(a, b);
+        out.strides.swap(a, b);
+
+        Ok(out)
+    }
+
+    /// Reorder the axes, so axis `i` of the output is axis `axes[i]` of this layout.
+    pub fn permute(&self, axes: [usize; 4]) -> Result<Self> {
+        let mut seen = [false; 4];
+
+        for axis in axes {
+            check_axis(axis)?;
+            seen[axis] = true;
+        }
+
+        if seen.contains(&false) {
+            return Err(anyhow!("Permutation {:?} must use every axis exactly once!", axes))
+        }
+
+        let mut out = *self;
+
+        for (i, axis) in axes.into_iter().enumerate() {
+            out.shape[i] = self.shape[axis];
+            out.strides[i] = self.strides[axis];
+        }
+
+        Ok(out)
+    }
+
+    /// The same elements with another shape. Only possible when the layout is contiguous.
+    pub fn reshape(&self, shape: &Shape) -> Result<Self> {
+        if shape.len() != self.len() {
+            return Err(anyhow!("Cannot reshape a view of {} elements into {}!", self.len(), shape.len()))
+        }
+
+        if !self.is_contiguous() {
+            return Err(anyhow!("Cannot reshape a view that is not contiguous, call contiguous() first!"))
+        }
+
+        Ok(Self {
+            offset: self.offset,
+            ..Self::new(shape)
+        })
+    }
+
+    /// Offsets of every element, in NCHW order.
+    pub fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        let [n, c, h, w] = self.shape;
+        iproduct!(0..n, 0..c, 0..h, 0..w)
+            .map(|(n, c, h, w)| self.index_of([n, c, h, w]))
+    }
+
+    /// Offsets of the first element of every row along W, in NCHW order.
+    fn rows(&self) -> impl Iterator<Item = usize> + '_ {
+        let [n, c, h, _] = self.shape;
+        iproduct!(0..n, 0..c, 0..h)
+            .map(|(n, c, h)| self.index_of([n, c, h, 0]))
+    }
+}
+
+/// Storages whose elements can be copied through a Layout.
+pub trait Strided: Storage + Sized {
+    /// Copy the elements of `self` selected by `layout` into `dst`, in NCHW order.
+    fn gather(&self, layout: &Layout, dst: &mut Self);
+
+    /// Copy the elements of `src`, in NCHW order, into the elements of `self` selected by `layout`.
+    fn scatter(&mut self, layout: &Layout, src: &Self);
+}
+
+impl<T: Element> Strided for Cpu<T> {
+    fn gather(&self, layout: &Layout, dst: &mut Self) {
+        let src = self.as_slice();
+
+        for (y, i) in dst.as_slice_mut().iter_mut().zip(layout.offsets()) {
+            *y = src[i];
+        }
+    }
+
+    fn scatter(&mut self, layout: &Layout, src: &Self) {
+        let dst = self.as_slice_mut();
+
+        for (x, i) in src.as_slice().iter().zip(layout.offsets()) {
+            dst[i] = *x;
+        }
+    }
+}
+
+/// Copies run on the device, on the stream of the destination: contiguous layouts 
+/// are copied as a whole, and others with the kernels of the `strided` module. Elements
+/// with no kernels are copied row by row if the rows are contiguous, and on the host otherwise.
+impl<T: Element> Strided for Gpu<T> {
+    fn gather(&self, layout: &Layout, dst: &mut Self) {
+        dst.device().bind_to_thread()
+            .expect("Failed to bind the context of the device!");
+
+        let stream = &dst.stream().stream;
+        self.record_stream(dst.stream());
+
+        if layout.is_contiguous() {
+            cu::mem::cpy_async::<T>(&dst.as_ptr(), &self.as_ptr().add::<T>(layout.offset), layout.len(), stream)
+                .expect("Failed to copy view on the device!");
+        } else if let Some(kernel) = strided_kernel::<T>(dst.device(), "strided") {
+            launch(kernel, layout, self, dst)
+                .expect("Failed to copy view on the device!");
+        } else if layout.strides[3] == 1 {
+            let w = layout.shape[3];
+
+            for (row, offset) in layout.rows().enumerate() {
+                cu::mem::cpy_async::<T>(&dst.as_ptr().add::<T>(row * w), &self.as_ptr().add::<T>(offset), w, stream)
+                    .expect("Failed to copy view on the device!");
+            }
+        } else {
+            let src = self.as_ndarray();
+            let src = src.as_slice().unwrap();
+            let host: Vec<T> = layout.offsets().map(|i| src[i]).collect();
+            dst.clone_from(&host);
+        }
+    }
+
+    fn scatter(&mut self, layout: &Layout, src: &Self) {
+        self.device().bind_to_thread()
+            .expect("Failed to bind the context of the device!");
+
+        let stream = &self.stream().stream;
+        src.record_stream(self.stream());
+
+        if layout.is_contiguous() {
+            cu::mem::cpy_async::<T>(&self.as_ptr().add::<T>(layout.offset), &src.as_ptr(), layout.len(), stream)
+                .expect("Failed to copy view on the device!");
+        } else if let Some(kernel) = strided_kernel::<T>(self.device(), "strided_scatter") {
+            launch(kernel, layout, src, self)
+                .expect("Failed to copy view on the device!");
+        } else if layout.strides[3] == 1 {
+            let w = layout.shape[3];
+
+            for (row, offset) in layout.rows().enumerate() {
+                cu::mem::cpy_async::<T>(&self.as_ptr().add::<T>(offset), &src.as_ptr().add::<T>(row * w), w, stream)
+                    .expect("Failed to copy view on the device!");
+            }
+        } else {
+            let mut host = self.as_ndarray();
+            let dst = host.as_slice_mut().unwrap();
+
+            for (x, i) in src.as_ndarray().iter().zip(layout.offsets()) {
+                dst[i] = *x;
+            }
+
+            self.clone_from(dst);
+        }
+    }
+}
+
+/// The kernel `<kernel>_<dtype>` of the `strided` module, or None if it was not embedded for `T`.
+/// Its function is looked up in the driver once, and cached in the loaded module after.
+fn strided_kernel<T: Element>(device: &Arc<Device>, kernel: &str) -> Option<Kernel> {
+    let name = format!("{}_{}", kernel, T::NAME);
+
+    modules::with_kernel("strided", &name, device.sm())?;
+
+    let kernel = device.get_kernel("strided", &name)
+        .unwrap_or_else(|e| panic!("Failed to load kernel {} from module strided: {}", name, e));
+
+    Some(kernel)
+}
+
+/// Launch `kernel` of the `strided` module for the elements of `y`, 
+/// on its stream, with the layout of the strided tensor.
+fn launch<T: Element>(kernel: Kernel, layout: &Layout, x: &Gpu<T>, y: &Gpu<T>) -> Result<()> {
+    let [n, c, h, w] = layout.shape.map(|d| d as u64);
+    let [sn, sc, sh, sw] = layout.strides.map(|s| s as u64);
+
+    kernel.launch_n(layout.len(), y.stream(), (x.as_arg(), y.as_arg(), n, c, h, w, sn, sc, sh, sw, layout.offset as u64))
+}
+
+/// Storages whose elements can be summed through a Layout, 
+/// used to accumulate gradients from every consumer of a Var.
+pub trait Accumulate: Strided {
+    /// Add the elements of `src`, in NCHW order, into the elements of `self` selected by `layout`.
+    fn scatter_add(&mut self, layout: &Layout, src: &Self);
+}
+
+impl<T: Float> Accumulate for Cpu<T> {
+    fn scatter_add(&mut self, layout: &Layout, src: &Self) {
+        let dst = self.as_slice_mut();
+
+        if layout.is_contiguous() {
+            let start = layout.offset();
+            return parallel::assign(&mut dst[start..start + layout.len()], src.as_slice(), T::add_assign)
+        }
+
+        for (x, i) in src.as_slice().iter().zip(layout.offsets()) {
+            dst[i] = dst[i] + *x;
+        }
+    }
+}
+
+/// The sum is done on the device, on the stream of `self`.
+impl<T: Float> Accumulate for Gpu<T> {
+    fn scatter_add(&mut self, layout: &Layout, src: &Self) {
+        let kernel = strided_kernel::<T>(self.device(), "strided_add")
+            .unwrap_or_else(|| panic!("There is no kernel strided_add_{} to add a view on the device!", T::NAME));
+
+        src.record_stream(self.stream());
+        launch(kernel, layout, src, self)
+            .expect("Failed to add view on the device!");
+    }
+}
+
+/// A strided window into a parent storage, without copying.
+///
+/// Operators can read a view through `iter()` on the cpu,
+/// or pass `as_ptr()` and `strides()` to a kernel on the gpu.
+/// In a scope, the `layout()` of a view is passed to `as_strided`.
+pub struct View<'a, S: Storage> {
+    parent: &'a S,
+    layout: Layout,
+}
+
+impl<'a, S: Storage> Clone for View<'a, S> {
+    fn clone(&self) -> Self {
+        Self {
+            parent: self.parent,
+            layout: self.layout,
+        }
+    }
+}
+
+impl<'a, S: Storage> View<'a, S> {
+    /// A view of the whole parent.
+    pub fn new(parent: &'a S) -> Self {
+        Self {
+            parent,
+            layout: Layout::new(parent.shape()),
+        }
+    }
+
+    pub fn parent(&self) -> &'a S {
+        self.parent
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.layout.shape()
+    }
+
+    pub fn strides(&self) -> [usize; 4] {
+        self.layout.strides
+    }
+
+    /// Offset of the first element of the view into the parent.
+    pub fn offset(&self) -> usize {
+        self.layout.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.layout.len()
+    }
+
+    /// Offset into the parent of the element at `index`.
+    pub fn index_of(&self, index: [usize; 4]) -> usize {
+        self.layout.index_of(index)
+    }
+
+    pub fn is_contiguous(&self) -> bool {
+        self.layout.is_contiguous()
+    }
+
+    pub fn narrow(&self, axis: usize, start: usize, len: usize) -> Result<Self> {
+        self.with_layout(self.layout.narrow(axis, start, len)?)
+    }
+
+    pub fn slice(&self, axis: usize, range: Range<usize>) -> Result<Self> {
+        self.with_layout(self.layout.slice(axis, range)?)
+    }
+
+    pub fn select(&self, axis: usize, index: usize) -> Result<Self> {
+        self.with_layout(self.layout.select(axis, index)?)
+    }
+
+    pub fn transpose(&self, a: usize, b: usize) -> Result<Self> {
+        self.with_layout(self.layout.transpose(a, b)?)
+    }
+
+    pub fn permute(&self, axes: [usize; 4]) -> Result<Self> {
+        self.with_layout(self.layout.permute(axes)?)
+    }
+
+    pub fn reshape(&self, shape: Shape) -> Result<Self> {
+        self.with_layout(self.layout.reshape(&shape)?)
+    }
+
+    fn with_layout(&self, layout: Layout) -> Result<Self> {
+        Ok(Self {
+            parent: self.parent,
+            layout,
+        })
+    }
+}
+
+impl<'a, S: Strided> View<'a, S> 
+where
+    S: From<Shape>
+{
+    /// Copy the elements of the view into a new storage.
+    pub fn contiguous(&self) -> S {
+        let mut out = S::from(self.shape());
+        self.parent.gather(&self.layout, &mut out);
+        out
+    }
+}
+
+impl<'a, T: Element> View<'a, Cpu<T>> {
+    /// The elements of the view, in NCHW order.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        let data = self.parent.as_slice();
+        self.layout.offsets().map(move |i| data[i])
+    }
+
+    /// The elements of the view as a slice, if it is contiguous.
+    pub fn as_slice(&self) -> Option<&'a [T]> {
+        if !self.is_contiguous() {
+            return None
+        }
+
+        Some(&self.parent.as_slice()[self.offset()..self.offset() + self.len()])
+    }
+}
+
+impl<'a, T: Element> View<'a, Gpu<T>> {
+    /// Pointer to the first element of the view.
+    pub fn as_ptr(&self) -> cu::DevicePtr {
+        self.parent.as_ptr().add::<T>(self.offset())
+    }
+}
+
+/// Strides of a contiguous NCHW tensor with `shape`.
+pub fn contiguous_strides(shape: [usize; 4]) -> [usize; 4] {
+    [shape[1] * shape[2] * shape[3], shape[2] * shape[3], shape[3], 1]
+}
+
+fn check_axis(axis: usize) -> Result<()> {
+    if axis >= 4 {
+        return Err(anyhow!("Axis {} is out of range for a 4D tensor!", axis))
+    }
+
+    Ok(())
+}